@@ -0,0 +1,62 @@
+//! Helping the gps get a fix faster after it's been off.
+
+use crate::UtcDateTime;
+
+/// Whether EASY is on, see [`crate::Gps::set_easy`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EasyStatus {
+    pub enabled: bool,
+    /// How many days ahead the gps has predicted ephemeris for, from 0 to 3.
+    /// Zero until it's tracked satellites for a few minutes.
+    pub extended_days: u8,
+}
+
+/// What EPO data the gps has, see [`crate::Gps::epo_status`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpoStatus {
+    /// How many six hour sets of predictions the gps has
+    pub sets: u16,
+    /// When the first set starts, `None` if there are none
+    pub valid_from: Option<UtcDateTime>,
+    /// When the last set ends, `None` if there are none
+    pub valid_until: Option<UtcDateTime>,
+}
+
+/// How long each set of predictions in an EPO file covers
+pub(crate) const EPO_SET_SECS: u32 = 6 * 60 * 60;
+
+/// Each satellite's ephemeris in an EPO file is one record of this size
+pub(crate) const EPO_RECORD_LEN: usize = 60;
+/// How many records go in each packet
+pub(crate) const EPO_RECORDS_PER_PACKET: usize = 3;
+
+/// How far an EPO upload got, see [`crate::Gps::upload_epo`].
+///
+/// Start each upload with the default, and pass the same one back in to
+/// carry on from where a failed upload stopped.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EpoProgress {
+    pub(crate) next_seq: u16,
+}
+
+impl EpoProgress {
+    /// How many packets the gps has acked
+    pub fn packets_acked(&self) -> u16 {
+        self.next_seq
+    }
+}
+
+/// How many packets `epo` takes, or `None` if it isn't a whole number of
+/// records or is too long.
+pub(crate) fn epo_packet_count(epo: &[u8]) -> Option<u16> {
+    if epo.is_empty() || !epo.len().is_multiple_of(EPO_RECORD_LEN) {
+        return None;
+    }
+    let records = epo.len() / EPO_RECORD_LEN;
+    let packets = records.div_ceil(EPO_RECORDS_PER_PACKET);
+    // The last sequence number marks the end
+    u16::try_from(packets).ok().filter(|&n| n < u16::MAX)
+}
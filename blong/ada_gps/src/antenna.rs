@@ -0,0 +1,43 @@
+//! Reports of which antenna the gps is using, see
+//! [`crate::Gps::set_antenna_reports`].
+
+use alloc::vec::Vec;
+
+use crate::cmd;
+
+/// From a `$PGTOP,11,<status>` sentence.
+///
+/// The gps switches to its internal antenna when an external one is
+/// unplugged or open circuit, so that shows up as [`Self::Internal`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntennaStatus {
+    /// The external antenna's feed is shorted
+    Shorted,
+    Internal,
+    /// Using an external active antenna
+    Active,
+}
+
+impl AntennaStatus {
+    /// Parses a whole `$PGTOP` line, including the checksum.
+    pub fn parse(line: &[u8]) -> Option<Self> {
+        let (name, fields) = cmd::parse(line).ok()?;
+        if name != b"PGTOP" {
+            return None;
+        }
+        Self::from_fields(&fields)
+    }
+
+    pub(crate) fn from_fields(fields: &[Vec<u8>]) -> Option<Self> {
+        match fields {
+            [function, status, ..] if function == b"11" => match status.as_slice() {
+                b"1" => Some(Self::Shorted),
+                b"2" => Some(Self::Internal),
+                b"3" => Some(Self::Active),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
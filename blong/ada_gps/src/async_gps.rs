@@ -0,0 +1,800 @@
+//! A version of [`Gps`](crate::Gps) for async executors such as embassy.
+//!
+//! It reads and writes the serial port directly through `embedded-io-async`
+//! and waits with `embedded-hal-async`, so the executor can run something
+//! else, or sleep, while a reply is on its way instead of spinning through
+//! [`Gps`](crate::Gps)'s `delay_us(1)` loops.
+//!
+//! It covers configuring the gps, including [`AsyncGps::apply`], its power
+//! modes and restarts, and reading the logger. It doesn't have the queries
+//! for most settings, such as whether SBAS is on or which datum is used, EPO
+//! and the other aiding data, changing the baud rate, the Quectel commands,
+//! power cycling, or deadlines. For those, [`AsyncGps::release`] the serial
+//! port and drive the gps with [`Gps`](crate::Gps) instead.
+//!
+//! Nothing is forwarded while waiting for a reply, so NMEA output is turned
+//! off before the first command as [`Gps`](crate::Gps) does by default.
+
+use alloc::vec::Vec;
+#[cfg(feature = "locus")]
+use core::{cell::Cell, ops::ControlFlow};
+use core::{
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, Write};
+use lexical_core::FormattedSize;
+
+#[cfg(feature = "locus")]
+use crate::gps::{
+    check_locus_data, check_locus_end, feed_locus_data, locus_packet_count,
+    MAX_POINTS_PER_LOCUS_DATA_PACKET,
+};
+#[cfg(feature = "locus")]
+use crate::logger;
+use crate::{
+    cmd, debug, error,
+    exchange::{Action, Exchange, DELAY_BEFORE_RETRY_US, MAX_CMD_TRIES},
+    gps::{
+        u32_to_base10_ascii, BootWatch, MAX_CMD_TRIES_WITHOUT_NMEA_DISABLED, MAX_READ_CMD_US,
+        MAX_READ_SPURIOUS_AFTER_BOOT_READY, MAX_WRITE_CMD_US, UPDATE_RATE_MS,
+        WAIT_BEFORE_CHECKING_BOOT_READY_US,
+    },
+    info, trace, ConfigItem, Datum, DgpsMode, Error, ExtendedFix, FirmwareVersion,
+    NmeaOutputConfig, PowerMode,
+};
+
+/// Bytes to ask the serial port for at a time
+const READ_CHUNK_LEN: usize = 64;
+
+pub struct AsyncGps<Serial, Delay> {
+    disabled_nmea_output: bool,
+    standby: bool,
+    backup: bool,
+    serial: Serial,
+    delay: Delay,
+    /// Bytes read past the end of the last line
+    pending: Vec<u8>,
+}
+
+impl<Serial, Delay> AsyncGps<Serial, Delay>
+where
+    Serial: Read + Write,
+    Delay: DelayNs,
+{
+    pub fn new(serial: Serial, delay: Delay, already_disabled_nmea_output: bool) -> Self {
+        Self {
+            disabled_nmea_output: already_disabled_nmea_output,
+            standby: false,
+            backup: false,
+            serial,
+            delay,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Give back the serial port and delay, such as to hand them to a
+    /// blocking [`Gps`](crate::Gps) for a command only it has.
+    pub fn release(self) -> (Serial, Delay) {
+        (self.serial, self.delay)
+    }
+
+    pub async fn firmware_version(&mut self) -> Result<FirmwareVersion, Error<Serial::Error>> {
+        // PMTK_Q_RELEASE, answered by PMTK_DT_RELEASE
+        info!("Querying firmware version");
+        let fields = self.send_mtk_cmd_for_reply(b"605", &[], b"705", 2).await?;
+        Ok(FirmwareVersion::from_fields(fields))
+    }
+
+    /// Like [`Gps::apply`](crate::Gps::apply), but waits for each item to be
+    /// acked before sending the next.
+    pub async fn apply(&mut self, items: &[ConfigItem]) -> Vec<Result<(), Error<Serial::Error>>> {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            let result = self.apply_one(item).await;
+            if result.is_err() {
+                error!("Failed to apply {}", item);
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    async fn apply_one(&mut self, item: &ConfigItem) -> Result<(), Error<Serial::Error>> {
+        match *item {
+            ConfigItem::DisableNmeaOutput => self.ensure_nmea_output_disabled().await,
+            ConfigItem::NmeaOutput(config) => self.set_nmea_output(config).await,
+            ConfigItem::Sbas(enabled) => self.set_sbas(enabled).await,
+            ConfigItem::DgpsMode(mode) => self.set_dgps_mode(mode).await,
+            ConfigItem::PowerMode(ref mode) => self.set_power_mode(mode).await,
+            ConfigItem::Datum(datum) => self.set_datum(datum).await,
+            ConfigItem::UpdateRateMs(ms) => self.set_update_rate_ms(ms).await,
+            #[cfg(feature = "locus")]
+            ConfigItem::LoggerInterval(secs) => self.configure_logger_interval(secs).await,
+            #[cfg(feature = "locus")]
+            ConfigItem::LoggerTrigger(trigger) => self.configure_logger(trigger).await,
+            #[cfg(feature = "locus")]
+            ConfigItem::StartLogging => self.start_logging().await,
+            #[cfg(feature = "locus")]
+            ConfigItem::StopLogging => self.stop_logging().await,
+        }
+    }
+
+    pub async fn set_sbas(&mut self, enabled: bool) -> Result<(), Error<Serial::Error>> {
+        // PMTK_API_SET_SBAS_ENABLED
+        info!("Setting SBAS enabled to {}", enabled);
+        self.send_mtk_cmd(b"313", &[if enabled { b"1" } else { b"0" }])
+            .await
+    }
+
+    pub async fn set_dgps_mode(&mut self, mode: DgpsMode) -> Result<(), Error<Serial::Error>> {
+        // PMTK_API_SET_DGPS_MODE
+        info!("Setting DGPS mode to {}", mode);
+        self.send_mtk_cmd(b"301", &[mode.field()]).await
+    }
+
+    /// See [`Gps::set_datum`](crate::Gps::set_datum).
+    pub async fn set_datum(&mut self, datum: Datum) -> Result<(), Error<Serial::Error>> {
+        // PMTK_API_SET_DATUM
+        info!("Setting datum to {}", datum);
+        let index = datum.index().ok_or_else(|| {
+            error!("Datum index out of range");
+            Error::InvalidArgument
+        })?;
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let index_ascii = u32_to_base10_ascii(index as u32, &mut buf);
+        self.send_mtk_cmd(b"330", &[index_ascii]).await
+    }
+
+    /// Choose which NMEA sentences the gps sends, and how often.
+    ///
+    /// Nothing's forwarded, so the next command turns NMEA output off again.
+    /// Send this last before handing the serial port to something that reads
+    /// the sentences.
+    pub async fn set_nmea_output(
+        &mut self,
+        config: NmeaOutputConfig,
+    ) -> Result<(), Error<Serial::Error>> {
+        info!("Setting nmea output to {}", config);
+        if config.is_disabled() {
+            return self.ensure_nmea_output_disabled().await;
+        }
+        let fields = config.fields().ok_or_else(|| {
+            error!("Nmea output rate out of range");
+            Error::InvalidArgument
+        })?;
+        self.wake().await?;
+        // PMTK_API_SET_NMEA_OUTPUT
+        self.send_mtk_cmd_without_disabling_nmea(
+            b"314",
+            &fields,
+            MAX_CMD_TRIES_WITHOUT_NMEA_DISABLED,
+        )
+        .await?;
+        self.disabled_nmea_output = false;
+        Ok(())
+    }
+
+    /// See [`Gps::set_power_mode`](crate::Gps::set_power_mode).
+    pub async fn set_power_mode(&mut self, mode: &PowerMode) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_PERIODIC_MODE
+        info!("Setting power mode to {}", mode);
+
+        let times = match mode.times() {
+            Some(times) if !times.is_valid() => {
+                error!("Power mode times out of range");
+                return Err(Error::InvalidArgument);
+            }
+            Some(times) => times,
+            None => return self.send_mtk_cmd(b"225", &[mode.type_field()]).await,
+        };
+        if let Some(extended_fix) = &times.extended_fix {
+            self.set_extended_fix(extended_fix).await?;
+        }
+
+        let (second_run, second_sleep) = times.second.unwrap_or((0, 0));
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 4];
+        let [run_buf, sleep_buf, second_run_buf, second_sleep_buf] = &mut bufs;
+        self.send_mtk_cmd(
+            b"225",
+            &[
+                mode.type_field(),
+                u32_to_base10_ascii(times.run_ms, run_buf),
+                u32_to_base10_ascii(times.sleep_ms, sleep_buf),
+                u32_to_base10_ascii(second_run, second_run_buf),
+                u32_to_base10_ascii(second_sleep, second_sleep_buf),
+            ],
+        )
+        .await
+    }
+
+    async fn set_extended_fix(
+        &mut self,
+        extended_fix: &ExtendedFix,
+    ) -> Result<(), Error<Serial::Error>> {
+        // PMTK_SET_AL_DEE_CFG
+        debug!("Setting extended fix to {}", extended_fix);
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 4];
+        let [sats_buf, snr_buf, extension_buf, gap_buf] = &mut bufs;
+        self.send_mtk_cmd(
+            b"223",
+            &[
+                u32_to_base10_ascii(extended_fix.min_satellites as u32, sats_buf),
+                u32_to_base10_ascii(extended_fix.min_snr as u32, snr_buf),
+                u32_to_base10_ascii(extended_fix.extension_ms, extension_buf),
+                u32_to_base10_ascii(extended_fix.extension_gap_ms, gap_buf),
+            ],
+        )
+        .await
+    }
+
+    /// See [`Gps::enter_standby`](crate::Gps::enter_standby).
+    pub async fn enter_standby(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_STANDBY_MODE, 0 = stop mode
+        info!("Entering standby");
+        self.send_mtk_cmd(b"161", &[b"0"]).await?;
+        self.standby = true;
+        Ok(())
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    /// See [`Gps::wake`](crate::Gps::wake).
+    pub async fn wake(&mut self) -> Result<(), Error<Serial::Error>> {
+        if self.backup {
+            error!("In backup mode, cycle the gps's power to wake it");
+            return Err(Error::InBackupMode);
+        }
+        if !self.standby {
+            return Ok(());
+        }
+
+        info!("Waking from standby");
+        self.run_exchange(Exchange::wake()).await?;
+        self.standby = false;
+        Ok(())
+    }
+
+    /// See [`Gps::enter_backup_mode`](crate::Gps::enter_backup_mode).
+    pub async fn enter_backup_mode(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_PERIODIC_MODE, 4 = perpetual backup
+        info!("Entering backup mode");
+        self.wake().await?;
+        // Don't wait for an ack, it may power down before sending one.
+        self.write_cmd_raw(b"PMTK225", &[b"4"]).await?;
+        self.backup = true;
+        Ok(())
+    }
+
+    /// See [`Gps::is_reachable`](crate::Gps::is_reachable).
+    pub async fn is_reachable(&mut self) -> bool {
+        if self.check_ready(1).await.is_err() {
+            return false;
+        }
+        if self.backup {
+            info!("Left backup mode");
+            self.backup = false;
+            // Everything not saved to flash is lost
+            self.standby = false;
+            self.disabled_nmea_output = false;
+        }
+        true
+    }
+
+    /// Restart keeping all saved data.
+    pub async fn hot_restart(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_HOT_START
+        info!("Hot restarting");
+        self.send_reboot_cmd(b"PMTK101").await
+    }
+
+    /// Restart keeping everything but ephemeris.
+    pub async fn warm_restart(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_WARM_START
+        info!("Warm restarting");
+        self.send_reboot_cmd(b"PMTK102").await
+    }
+
+    /// Restart keeping everything but time, position, almanacs and ephemeris.
+    pub async fn cold_restart(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_COLD_START
+        info!("Cold restarting");
+        self.send_reboot_cmd(b"PMTK103").await
+    }
+
+    /// See [`Gps::factory_reset`](crate::Gps::factory_reset).
+    pub async fn factory_reset(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_CMD_FULL_COLD_START
+        info!("Factory resetting");
+        self.send_reboot_cmd(b"PMTK104").await
+    }
+
+    async fn send_reboot_cmd(&mut self, cmd: &[u8]) -> Result<(), Error<Serial::Error>> {
+        self.wake().await?;
+        let mut tries = 0;
+        loop {
+            tries += 1;
+            self.disabled_nmea_output = false;
+            let result = match self.write_cmd_raw(cmd, &[]).await {
+                Ok(()) => self.wait_for_boot().await,
+                Err(err) => Err(err),
+            };
+            let result = match result {
+                Ok(()) => self.ensure_nmea_output_disabled().await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => {
+                    debug!("Took {} tries to reboot with {=[u8]:a}", tries, cmd);
+                    return Ok(());
+                }
+                Err(err) if tries > MAX_CMD_TRIES => {
+                    error!("Failed to reboot with {=[u8]:a} after {} tries", cmd, tries);
+                    return Err(err);
+                }
+                Err(_) => self.delay.delay_us(DELAY_BEFORE_RETRY_US).await,
+            }
+        }
+    }
+
+    /// See `Gps::wait_for_boot`.
+    async fn wait_for_boot(&mut self) -> Result<(), Error<Serial::Error>> {
+        let mut boot = BootWatch::default();
+        while !boot.booted()? {
+            let read = self.run_exchange(Exchange::next_sentence()).await;
+            boot.saw(read.map(|mut fields| {
+                let name = fields.remove(0);
+                (name, fields)
+            }));
+        }
+
+        self.delay
+            .delay_us(WAIT_BEFORE_CHECKING_BOOT_READY_US)
+            .await;
+        // Anything else left from booting fails a try here
+        self.check_ready(MAX_READ_SPURIOUS_AFTER_BOOT_READY).await
+    }
+
+    async fn check_ready(&mut self, max_tries: usize) -> Result<(), Error<Serial::Error>> {
+        // PMTK_Q_RELEASE, answered by PMTK_DT_RELEASE
+        let exchange = Exchange::for_reply(b"605", &[], b"705", 2).max_tries(max_tries);
+        let fields = self.run_exchange(exchange).await?;
+        info!("Gps ready ({})", &FirmwareVersion::from_fields(fields));
+        Ok(())
+    }
+
+    pub async fn ensure_nmea_output_disabled(&mut self) -> Result<(), Error<Serial::Error>> {
+        self.wake().await?;
+        if self.disabled_nmea_output {
+            debug!("Nmea output already disabled");
+            return Ok(());
+        }
+
+        debug!("Disabling nmea output");
+        let fields = NmeaOutputConfig::default().fields().unwrap();
+        // PMTK_API_SET_NMEA_OUTPUT
+        self.send_mtk_cmd_without_disabling_nmea(
+            b"314",
+            &fields,
+            MAX_CMD_TRIES_WITHOUT_NMEA_DISABLED,
+        )
+        .await?;
+        self.disabled_nmea_output = true;
+        Ok(())
+    }
+
+    /// See [`Gps::set_update_rate_ms`](crate::Gps::set_update_rate_ms).
+    pub async fn set_update_rate_ms(&mut self, ms: u32) -> Result<(), Error<Serial::Error>> {
+        // PMTK_SET_NMEA_UPDATERATE
+        info!("Setting update rate to {}ms", ms);
+        if !UPDATE_RATE_MS.contains(&ms) {
+            error!("Update rate {}ms out of range", ms);
+            return Err(Error::InvalidArgument);
+        }
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let ms_ascii = u32_to_base10_ascii(ms, &mut buf);
+        self.send_mtk_cmd(b"220", &[ms_ascii]).await
+    }
+
+    pub async fn update_rate_ms(&mut self) -> Result<u32, Error<Serial::Error>> {
+        // PMTK_API_Q_FIX_CTL, answered by PMTK_DT_FIX_CTL
+        info!("Querying update rate");
+        let fields = self.send_mtk_cmd_for_reply(b"400", &[], b"500", 1).await?;
+        Ok(cmd::parse::integer_field(&fields[0])?)
+    }
+
+    #[cfg(feature = "locus")]
+    pub async fn configure_logger_interval(
+        &mut self,
+        secs: u32,
+    ) -> Result<(), Error<Serial::Error>> {
+        self.configure_logger(logger::Trigger::Interval(secs)).await
+    }
+
+    /// Choose what makes LOCUS record a point, see [`logger::Trigger`].
+    #[cfg(feature = "locus")]
+    pub async fn configure_logger(
+        &mut self,
+        trigger: logger::Trigger,
+    ) -> Result<(), Error<Serial::Error>> {
+        // PMTK_LOCUS_CONFIG
+        info!("Setting logger trigger to {}", trigger);
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let value_ascii = u32_to_base10_ascii(trigger.value(), &mut buf);
+        self.send_mtk_cmd(b"187", &[trigger.mode_field(), value_ascii])
+            .await
+    }
+
+    #[cfg(feature = "locus")]
+    pub async fn start_logging(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_LOCUS_STOP_LOGGER, 0 = start
+        info!("Starting logging");
+        self.send_mtk_cmd(b"185", &[b"0"]).await
+    }
+
+    #[cfg(feature = "locus")]
+    pub async fn stop_logging(&mut self) -> Result<(), Error<Serial::Error>> {
+        // PMTK_LOCUS_STOP_LOGGER, 1 = stop
+        info!("Stopping logging");
+        self.send_mtk_cmd(b"185", &[b"1"]).await
+    }
+
+    #[cfg(feature = "locus")]
+    pub async fn logger_status(&mut self) -> Result<logger::Status, Error<Serial::Error>> {
+        // PMTK_LOCUS_QUERY_STATUS
+        info!("Querying logger status");
+        let fields = self.send_mtk_cmd_for_reply(b"183", &[], b"LOG", 10).await?;
+        let status = logger::Status::from_fields(&fields)?;
+        info!("Got logger status: {:?}", &status);
+        Ok(status)
+    }
+
+    /// See [`Gps::read_logs`](crate::Gps::read_logs).
+    #[cfg(feature = "locus")]
+    pub async fn read_logs<F>(
+        &mut self,
+        on_point: F,
+    ) -> Result<(logger::Cursor, logger::ParseStats), Error<Serial::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        info!("Reading logs");
+        let mut cursor = logger::Cursor::default();
+        // PMTK_Q_LOCUS_DATA, 0 = full
+        let stats = self.read_locus_dump(b"0", &mut cursor, on_point).await?;
+        Ok((cursor, stats))
+    }
+
+    /// See [`Gps::read_new_logs`](crate::Gps::read_new_logs).
+    #[cfg(feature = "locus")]
+    pub async fn read_new_logs<F>(
+        &mut self,
+        cursor: &mut logger::Cursor,
+        on_point: F,
+    ) -> Result<logger::ParseStats, Error<Serial::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        info!("Reading logs after point {}", cursor.points_read);
+        // PMTK_Q_LOCUS_DATA, 1 = partial, only the sectors in use
+        self.read_locus_dump(b"1", cursor, on_point).await
+    }
+
+    /// See `Gps::read_locus_dump`. Each packet of the dump is its own
+    /// exchange, listening for the next.
+    #[cfg(feature = "locus")]
+    async fn read_locus_dump<F>(
+        &mut self,
+        kind: &[u8],
+        cursor: &mut logger::Cursor,
+        mut on_point: F,
+    ) -> Result<logger::ParseStats, Error<Serial::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        // NOTE: We don't retry because this is super expensive.
+
+        self.ensure_nmea_output_disabled().await?;
+        self.write_cmd_raw(b"PMTK622", &[kind]).await?;
+
+        let locus_start = self.run_exchange(Exchange::listen(b"PMTKLOX", 2)).await?;
+        let packet_count = locus_packet_count(&locus_start)?;
+        let max_points = packet_count * MAX_POINTS_PER_LOCUS_DATA_PACKET;
+
+        let skip_points = cursor.points_read;
+        let point_i = Cell::new(0);
+        let stopped = Cell::new(false);
+        let mut parser = logger::Parser::new(|point| {
+            if stopped.get() {
+                return;
+            }
+            let i = point_i.get();
+            point_i.set(i + 1);
+            if i >= skip_points {
+                cursor.points_read = i + 1;
+                if on_point(max_points, i, point).is_break() {
+                    stopped.set(true);
+                }
+            }
+        });
+
+        for n in 0..packet_count {
+            let locus_data = self.run_exchange(Exchange::listen(b"PMTKLOX", 2)).await?;
+            check_locus_data(n, &locus_data)?;
+            if !stopped.get() {
+                feed_locus_data(&locus_data, &mut parser)?;
+            }
+        }
+
+        let locus_end = self.run_exchange(Exchange::listen(b"PMTKLOX", 1)).await?;
+        check_locus_end(&locus_end)?;
+
+        let stats = parser.stats().clone();
+        info!("Read logs: {}", &stats);
+        if stopped.get() {
+            info!("Stopped early, after point {}", cursor.points_read);
+        } else {
+            cursor.points_read = point_i.get();
+        }
+        Ok(stats)
+    }
+
+    async fn send_mtk_cmd<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+    ) -> Result<(), Error<Serial::Error>> {
+        debug!("Trying to send PMTK {=[u8; 3]:a} for ack", num);
+        self.ensure_nmea_output_disabled().await?;
+        self.send_mtk_cmd_without_disabling_nmea(num, fields, MAX_CMD_TRIES)
+            .await
+    }
+
+    async fn send_mtk_cmd_without_disabling_nmea<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+        max_tries: usize,
+    ) -> Result<(), Error<Serial::Error>> {
+        let exchange = Exchange::for_ack(num, fields).max_tries(max_tries);
+        self.run_exchange(exchange).await.map(drop)
+    }
+
+    async fn send_mtk_cmd_for_reply<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+        reply_num: &'i [u8; 3],
+        reply_min_fields: usize,
+    ) -> Result<Vec<Vec<u8>>, Error<Serial::Error>> {
+        debug!(
+            "Trying to send PMTK {=[u8; 3]:a} for reply PMTK {=[u8; 3]:a}",
+            num, reply_num
+        );
+        self.ensure_nmea_output_disabled().await?;
+        self.run_exchange(Exchange::for_reply(
+            num,
+            fields,
+            reply_num,
+            reply_min_fields,
+        ))
+        .await
+    }
+
+    async fn run_exchange(
+        &mut self,
+        mut exchange: Exchange<Serial::Error>,
+    ) -> Result<Vec<Vec<u8>>, Error<Serial::Error>> {
+        loop {
+            match exchange.poll() {
+                Action::Write(bytes) => {
+                    if let Err(err) = self.write_raw(bytes).await {
+                        exchange.attempt_failed(err);
+                    }
+                }
+                Action::Read => self.read_into_exchange(&mut exchange).await,
+                // Never asked for
+                Action::Forward(_) | Action::Unsolicited(_) => {}
+                Action::Delay(us) => self.delay.delay_us(us).await,
+                Action::Done(result) => return result,
+            }
+        }
+    }
+
+    async fn write_cmd_raw<'i>(
+        &mut self,
+        name: &'i [u8],
+        fields: &'i [&'i [u8]],
+    ) -> Result<(), Error<Serial::Error>> {
+        let mut cmd = Vec::new();
+        cmd::serialize(name, fields, &mut cmd);
+
+        trace!("Sending {=[u8]:a}", &cmd);
+        self.write_raw(&cmd).await
+    }
+
+    async fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Error<Serial::Error>> {
+        let Self { serial, delay, .. } = self;
+        let write = async {
+            serial.write_all(bytes).await?;
+            serial.flush().await
+        };
+        match timeout(delay, MAX_WRITE_CMD_US, write).await {
+            Some(result) => result.map_err(Error::Transmit),
+            None => {
+                trace!("Write timed out");
+                Err(Error::WriteTimeout)
+            }
+        }
+    }
+
+    /// Push what's left from the last read into `exchange`, or failing that
+    /// wait for more.
+    ///
+    /// A serial port that says it's closed is treated as a timeout.
+    async fn read_into_exchange(&mut self, exchange: &mut Exchange<Serial::Error>) {
+        if self.pending.is_empty() {
+            let Self { serial, delay, .. } = self;
+            let mut chunk = [0_u8; READ_CHUNK_LEN];
+            match timeout(delay, MAX_READ_CMD_US, serial.read(&mut chunk)).await {
+                Some(Ok(len)) if len > 0 => self.pending.extend_from_slice(&chunk[..len]),
+                Some(Err(err)) => return exchange.attempt_failed(Error::Transmit(err)),
+                Some(Ok(_)) | None => return exchange.timed_out(),
+            }
+        }
+        let used = exchange.push_bytes(&self.pending);
+        self.pending.drain(..used);
+    }
+}
+
+/// Runs `fut` until it finishes, or until `us` have passed, whichever's first.
+async fn timeout<D, F>(delay: &mut D, us: u32, fut: F) -> Option<F::Output>
+where
+    D: DelayNs,
+    F: Future,
+{
+    let mut fut = pin!(fut);
+    let mut expired = pin!(delay.delay_us(us));
+    poll_fn(|cx| {
+        if let Poll::Ready(val) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Some(val));
+        }
+        if expired.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockAsyncSerial, MockDelay, MockTrans};
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_logger_status() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            // Cut off by output stopping, then split across reads
+            MockTrans::read_many(b"$GPRMC,064951.000,A,2307.12"),
+            MockTrans::read_many(b"$PMTKLOG,456,0,11,31,2,"),
+            MockTrans::read_many(b"0,0,0,3769,46*48\r\n"),
+        ];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, false);
+
+        let status = block_on(gps.logger_status()).unwrap();
+        assert_eq!(status.serial, 456);
+        assert_eq!(status.record_count, 3769);
+
+        gps.release().0.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_read_logs() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK622,0*28\r\n"),
+            MockTrans::read_many(include_bytes!(
+                "../test_assets/read_3819_log_records_inputs.txt"
+            )),
+        ];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        let mut points = Vec::new();
+        let (cursor, stats) = block_on(gps.read_logs(|_, i, point| {
+            assert_eq!(i, points.len());
+            points.push(point);
+            ControlFlow::Continue(())
+        }))
+        .unwrap();
+        let mut expected = Vec::new();
+        logger::parse_dump(
+            include_bytes!("../test_assets/3819_log_records.bin"),
+            |point| expected.push(point),
+        );
+        assert_eq!(points, expected);
+        assert_eq!(cursor.points_read, points.len());
+        assert_eq!(stats.invalid_packets, 0);
+
+        gps.release().0.done();
+    }
+
+    #[test]
+    fn test_hot_restart_from_capture() {
+        let expects = include!("../test_assets/hot_restart_mock.rs");
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        block_on(gps.hot_restart()).unwrap();
+
+        gps.release().0.done();
+    }
+
+    #[test]
+    fn test_standby_wakes_for_next_command() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK161,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,161,3*36\r\n"),
+            MockTrans::write_many(b"\r\n"),
+            MockTrans::read_many(b"$PMTK010,002*2D\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        block_on(gps.enter_standby()).unwrap();
+        assert!(gps.is_standby());
+        block_on(gps.set_sbas(true)).unwrap();
+        assert!(!gps.is_standby());
+
+        gps.release().0.done();
+    }
+
+    #[test]
+    fn test_apply_carries_on_past_failures() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,1*33\r\n"),
+            MockTrans::write_many(b"$PMTK220,200*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,220,3*30\r\n"),
+        ];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        let results = block_on(gps.apply(&[ConfigItem::Sbas(true), ConfigItem::UpdateRateMs(200)]));
+        assert_eq!(results, vec![Err(Error::GpsSaysUnsupportedCommand), Ok(())]);
+
+        gps.release().0.done();
+    }
+
+    #[test]
+    fn test_retries_after_timeout() {
+        let expects = [
+            // No answer, so times out
+            MockTrans::write_many(b"$PMTK220,200*2C\r\n"),
+            MockTrans::write_many(b"$PMTK220,200*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,220,3*30\r\n"),
+        ];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        block_on(gps.set_update_rate_ms(200)).unwrap();
+
+        gps.release().0.done();
+    }
+
+    #[test]
+    fn test_gives_up_eventually() {
+        let expects = vec![MockTrans::write_many(b"$PMTK400*36\r\n"); MAX_CMD_TRIES + 1];
+        let mut gps = AsyncGps::new(MockAsyncSerial::new(&expects), MockDelay, true);
+
+        assert_eq!(block_on(gps.update_rate_ms()), Err(Error::ReadTimeout));
+
+        gps.release().0.done();
+    }
+}
@@ -0,0 +1,45 @@
+//! Telling how long we've been waiting, for timeouts.
+
+/// Something that knows the time, so timeouts are real deadlines rather than
+/// a count of how many times we've delayed.
+///
+/// Any `FnMut() -> u64` giving microseconds works, such as a function
+/// returning `monotonics::AppMono::now().ticks()` with an
+/// `rp2040_monotonic::Rp2040Monotonic`, whose ticks are microseconds.
+pub trait Clock {
+    /// Microseconds since some fixed point. Allowed to wrap.
+    fn now_us(&mut self) -> u64;
+
+    /// Called with each delay the driver makes. Only [`CountedDelays`] needs
+    /// this.
+    fn delayed(&mut self, _us: u32) {}
+}
+
+impl<F> Clock for F
+where
+    F: FnMut() -> u64,
+{
+    fn now_us(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// Adds up the delays the driver makes instead of reading a clock, for when
+/// there isn't one.
+///
+/// This undercounts, as it leaves out the time between delays, and the
+/// driver has to keep delaying for a microsecond at a time to count at all.
+#[derive(Debug, Clone, Default)]
+pub struct CountedDelays {
+    us: u64,
+}
+
+impl Clock for CountedDelays {
+    fn now_us(&mut self) -> u64 {
+        self.us
+    }
+
+    fn delayed(&mut self, us: u32) {
+        self.us += us as u64;
+    }
+}
@@ -0,0 +1,71 @@
+//! MTK's binary packets, which the gps switches to for bulk transfers such
+//! as EPO data.
+//!
+//! ```text
+//! 0x04, 0x24, len: u16, id: u16, payload, checksum: u8, 0x0D, 0x0A
+//! ```
+//!
+//! Integers are little endian, `len` is the length of the whole packet and
+//! `checksum` is the xor of everything from `len` to the end of `payload`.
+
+use alloc::vec::Vec;
+
+use super::parse::Error;
+
+pub(crate) const PREAMBLE: [u8; 2] = [0x04, 0x24];
+const SUFFIX: [u8; 2] = [0x0D, 0x0A];
+/// Everything but the payload
+pub(crate) const OVERHEAD: usize = 2 + 2 + 2 + 1 + 2;
+
+pub(crate) fn serialize(id: u16, payload: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    out.extend_from_slice(&PREAMBLE);
+    out.extend_from_slice(&((payload.len() + OVERHEAD) as u16).to_le_bytes());
+    out.extend_from_slice(&id.to_le_bytes());
+    out.extend_from_slice(payload);
+    let checksum = checksum(&out[start + 2..]);
+    out.push(checksum);
+    out.extend_from_slice(&SUFFIX);
+}
+
+/// Returns (id, payload) from a whole packet.
+pub(crate) fn parse(packet: &[u8]) -> Result<(u16, &[u8]), Error> {
+    if packet.len() < OVERHEAD || packet[..2] != PREAMBLE {
+        return Err(Error::ExpectedPrefix);
+    }
+    let len = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+    if len != packet.len() {
+        return Err(Error::ExpectedEnd);
+    }
+    if packet[len - 2..] != SUFFIX {
+        return Err(Error::ExpectedSuffix);
+    }
+    if checksum(&packet[2..len - 3]) != packet[len - 3] {
+        return Err(Error::WrongChecksum);
+    }
+    let id = u16::from_le_bytes([packet[4], packet[5]]);
+    Ok((id, &packet[6..len - 3]))
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut packet = Vec::new();
+        serialize(723, &[0x01, 0x00, 0x01], &mut packet);
+        assert_eq!(
+            packet,
+            [0x04, 0x24, 0x0C, 0x00, 0xD3, 0x02, 0x01, 0x00, 0x01, 0xDD, 0x0D, 0x0A]
+        );
+        assert_eq!(parse(&packet), Ok((723, &[0x01, 0x00, 0x01][..])));
+
+        packet[6] = 0x02;
+        assert_eq!(parse(&packet), Err(Error::WrongChecksum));
+    }
+}
@@ -1,17 +1,41 @@
+#[cfg(feature = "commands")]
+pub(crate) mod binary;
 pub(crate) mod parse;
 pub(crate) mod serialize;
 
 pub(crate) use parse::parse;
 pub(crate) use serialize::serialize;
 
-use defmt::Format;
+use alloc::vec::Vec;
 use lexical_core::{FormattedSize, NumberFormatBuilder};
 
 // Terminology: Given "$PMTK183*38\r\n", the line is "PMTK183"
 
+/// A parsed command as (name, fields)
+pub(crate) type RawCmd = (Vec<u8>, Vec<Vec<u8>>);
+
+/// The longest a sentence can be, from the `$` to the line ending, by the
+/// NMEA 0183 standard
+pub(crate) const MAX_NMEA_LEN: usize = 82;
+/// The longest the gps's own `$P` sentences can be, which go well past the
+/// standard for LOCUS dumps
+pub(crate) const MAX_PROPRIETARY_LEN: usize = 255;
+
+/// Whether `line`, the start of a sentence, is already longer than any
+/// sentence of its kind, so must be noise.
+pub(crate) fn is_too_long(line: &[u8]) -> bool {
+    let max = if line.starts_with(b"$P") {
+        MAX_PROPRIETARY_LEN
+    } else {
+        MAX_NMEA_LEN
+    };
+    line.len() > max
+}
+
 const CHECKSUM_FORMAT: u128 = NumberFormatBuilder::hexadecimal();
 
-#[derive(Format, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Checksum(u8);
 
 impl Checksum {
@@ -77,4 +101,22 @@ mod tests {
         let actual = Checksum::compute_for(b"PMTK527,0.20");
         assert_eq!(actual, Checksum::parse(b"02").unwrap());
     }
+
+    #[test]
+    fn test_is_too_long() {
+        let mut nmea = b"$GPGSV".to_vec();
+        nmea.resize(MAX_NMEA_LEN, b'0');
+        assert!(!is_too_long(&nmea));
+        nmea.push(b'0');
+        assert!(is_too_long(&nmea));
+
+        let mut proprietary = b"$PMTKLOX".to_vec();
+        proprietary.resize(MAX_PROPRIETARY_LEN, b'0');
+        assert!(!is_too_long(&proprietary));
+        proprietary.push(b'0');
+        assert!(is_too_long(&proprietary));
+
+        // Noise before any `$` counts as standard
+        assert!(is_too_long(&[b'x'; MAX_NMEA_LEN + 1]));
+    }
 }
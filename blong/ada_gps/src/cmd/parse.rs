@@ -1,51 +1,48 @@
-use alloc::vec::Vec;
-use defmt::{Debug2Format, Format};
-
-use super::Checksum;
-use crate::{debug, IntegerPercent};
+use super::{Checksum, RawCmd};
+use crate::debug;
+#[cfg(feature = "locus")]
+use crate::IntegerPercent;
 
 /// Returns a tuple of (name, fields)
-pub(crate) fn parse(cmd: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>), Error> {
-    let mut raw = cmd.iter().peekable();
-    let mut name = Vec::new();
-    let mut fields = Vec::new();
+pub(crate) fn parse(cmd: &[u8]) -> Result<RawCmd, Error> {
+    let (name, fields) = split(cmd)?;
+    Ok((name.to_vec(), fields.map(<[u8]>::to_vec).collect()))
+}
 
+/// Like [`parse`], but borrowing the name and fields from `cmd` instead of
+/// copying them out.
+pub(crate) fn split(cmd: &[u8]) -> Result<(&[u8], Fields<'_>), Error> {
     // Prefix
-    if raw.next().ok_or(Error::ExpectedPrefix)? != &b'$' {
-        debug!("expected prefix, got different character");
-        return Err(Error::ExpectedPrefix);
-    }
+    let line = match cmd {
+        [b'$', line @ ..] => line,
+        _ => {
+            debug!("expected prefix, got different character");
+            return Err(Error::ExpectedPrefix);
+        }
+    };
 
     // Name
-    loop {
-        match raw.peek() {
-            Some(b',' | b'*') => {
-                if name.len() == 0 {
-                    debug!("got name of length zero");
-                    return Err(Error::ExpectedName);
-                } else {
-                    break;
-                }
-            }
-            _ => (),
-        }
-        let char = *raw.next().ok_or(Error::ExpectedName)?;
-        name.push(char);
+    let name_len = line
+        .iter()
+        .position(|&char| char == b',' || char == b'*')
+        .ok_or(Error::ExpectedName)?;
+    if name_len == 0 {
+        debug!("got name of length zero");
+        return Err(Error::ExpectedName);
     }
 
     // Fields
-    while raw.peek() != Some(&&b'*') {
-        let char = raw.next().ok_or(Error::ExpectedField)?;
-        if char == &b',' {
-            fields.push(Vec::new());
-        } else {
-            let field = fields.last_mut().unwrap();
-            field.push(*char);
-        }
-    }
+    let star = name_len
+        + line[name_len..]
+            .iter()
+            .position(|&char| char == b'*')
+            .ok_or(Error::ExpectedField)?;
+    let fields = Fields {
+        rest: (line[name_len] == b',').then(|| &line[name_len + 1..star]),
+    };
 
     // Checksum
-    let _ = raw.next(); // We already checked this is b'*'
+    let mut raw = line[star + 1..].iter();
     let checksum = [
         *raw.next().ok_or(Error::ExpectedChecksum)?,
         *raw.next().ok_or(Error::ExpectedChecksum)?,
@@ -69,32 +66,52 @@ pub(crate) fn parse(cmd: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>), Error> {
     }
 
     // Check checksum
-    let line = &cmd[1..cmd.len() - 5]; // between $ and *
-    if checksum != Checksum::compute_for(&line) {
+    if checksum != Checksum::compute_for(&line[..star]) {
         debug!("wrong checksum");
         return Err(Error::WrongChecksum);
     }
 
-    Ok((name, fields))
+    Ok((&line[..name_len], fields))
+}
+
+/// The fields of a sentence from [`split`], in order.
+#[derive(Debug, Clone)]
+pub(crate) struct Fields<'a> {
+    /// `None` once all fields are taken, or if the sentence had none
+    rest: Option<&'a [u8]>,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        match rest.iter().position(|&char| char == b',') {
+            Some(end) => {
+                self.rest = Some(&rest[end + 1..]);
+                Some(&rest[..end])
+            }
+            None => {
+                self.rest = None;
+                Some(rest)
+            }
+        }
+    }
 }
 
 pub(crate) fn integer_field(val: &[u8]) -> Result<u32, Error> {
-    lexical_core::parse(val).map_err(|err| {
-        debug!(
-            "Failed to parse field {=[u8]:a} as u32: {:?}",
-            val,
-            Debug2Format(&err),
-        );
+    lexical_core::parse(val).map_err(|_| {
+        debug!("Failed to parse field {=[u8]:a} as u32", val);
         Error::ParseField
     })
 }
 
+#[cfg(feature = "locus")]
 pub(crate) fn integer_percent_field(val: &[u8]) -> Result<IntegerPercent, Error> {
-    let val = lexical_core::parse::<u8>(val).map_err(|err| {
+    let val = lexical_core::parse::<u8>(val).map_err(|_| {
         debug!(
-            "Failed to parse field {=[u8]:a} as u8 (expecting integer percent): {:?}",
-            val,
-            Debug2Format(&err),
+            "Failed to parse field {=[u8]:a} as u8 (expecting integer percent)",
+            val
         );
         Error::ParseField
     })?;
@@ -121,7 +138,8 @@ pub(crate) fn bool_field(val: &[u8], truthy: &[u8], falsy: &[u8]) -> Result<bool
     }
 }
 
-#[derive(Format, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Error {
     ExpectedPrefix,
     ExpectedName,
@@ -137,6 +155,7 @@ pub enum Error {
 #[cfg(all(test, feature = "host-test"))]
 mod tests {
     use super::*;
+    use alloc::vec::Vec;
 
     #[test]
     fn test_valid() {
@@ -184,4 +203,14 @@ mod tests {
 
         assert_eq!(parse(b"$NAME,*0f\r\n"), Err(Error::WrongChecksum));
     }
+
+    #[test]
+    fn test_split_borrows_fields() {
+        let (name, fields) = split(b"$GPGSA,A,3,,*30\r\n").unwrap();
+        assert_eq!(name, b"GPGSA");
+        assert_eq!(fields.collect::<Vec<_>>(), [&b"A"[..], b"3", b"", b""]);
+
+        let (_, mut fields) = split(b"$PMTK183*38\r\n").unwrap();
+        assert_eq!(fields.next(), None);
+    }
 }
@@ -1,7 +1,9 @@
 use super::Checksum;
 use alloc::vec::Vec;
+use lexical_core::FormattedSize;
 
-pub(crate) fn serialize<'i, 'o>(name: &'i [u8], fields: &'i [&'i [u8]], out: &'o mut Vec<u8>) {
+/// Writes a complete sentence, with its checksum and line ending, to `out`.
+pub fn serialize<'i>(name: &'i [u8], fields: &'i [&'i [u8]], out: &mut Vec<u8>) {
     out.push(b'$');
 
     // Name
@@ -28,3 +30,44 @@ pub(crate) fn serialize<'i, 'o>(name: &'i [u8], fields: &'i [&'i [u8]], out: &'o
 
     out.extend_from_slice(b"\r\n");
 }
+
+/// Writes `val` with `decimals` digits after the point, rounded to the
+/// nearest, such as `-1.50` for `-1.499` to two decimals.
+pub(crate) fn float_field(val: f64, decimals: u32, out: &mut Vec<u8>) {
+    let scale = 10_u64.pow(decimals);
+    let scaled = libm::round(libm::fabs(val) * scale as f64) as u64;
+    if val.is_sign_negative() && scaled != 0 {
+        out.push(b'-');
+    }
+
+    let mut buf = [0_u8; u64::FORMATTED_SIZE_DECIMAL];
+    out.extend_from_slice(lexical_core::write(scaled / scale, &mut buf));
+    if decimals > 0 {
+        out.push(b'.');
+        let frac = lexical_core::write(scaled % scale, &mut buf);
+        for _ in frac.len()..decimals as usize {
+            out.push(b'0');
+        }
+        out.extend_from_slice(frac);
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_field() {
+        let write = |val, decimals| {
+            let mut out = Vec::new();
+            float_field(val, decimals, &mut out);
+            out
+        };
+        assert_eq!(write(24.772816, 6), b"24.772816");
+        assert_eq!(write(-121.0, 3), b"-121.000");
+        assert_eq!(write(-1.499, 2), b"-1.50");
+        assert_eq!(write(0.05, 1), b"0.1");
+        assert_eq!(write(-0.0001, 2), b"0.00");
+        assert_eq!(write(160.4, 0), b"160");
+    }
+}
@@ -0,0 +1,32 @@
+//! Configuring the gps in one go, see [`crate::Gps::apply`].
+
+use crate::{Datum, DgpsMode, NmeaOutputConfig, PowerMode};
+
+/// A single setting to apply to the gps.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigItem {
+    /// Stop the gps sending NMEA sentences. Commands do this anyway unless
+    /// they're forwarding NMEA, so this is only needed to do it up front.
+    DisableNmeaOutput,
+    /// See [`crate::Gps::set_nmea_output`]
+    NmeaOutput(NmeaOutputConfig),
+    /// Use corrections from SBAS satellites (WAAS, EGNOS, MSAS) for a DGPS
+    /// fix where available.
+    Sbas(bool),
+    DgpsMode(DgpsMode),
+    PowerMode(PowerMode),
+    Datum(Datum),
+    /// See [`crate::Gps::set_update_rate_ms`]
+    UpdateRateMs(u32),
+    /// Seconds between LOCUS log entries
+    #[cfg(feature = "locus")]
+    LoggerInterval(u32),
+    #[cfg(feature = "locus")]
+    LoggerTrigger(crate::logger::Trigger),
+    #[cfg(feature = "locus")]
+    StartLogging,
+    #[cfg(feature = "locus")]
+    StopLogging,
+}
@@ -0,0 +1,186 @@
+//! Converting positions between the forms they come in: NMEA's `ddmm.mmmm`
+//! with a hemisphere, decimal degrees, and integer microdegrees.
+//!
+//! Decimal degrees are positive north and east, like [`crate::Packet`].
+
+use core::fmt;
+
+/// Which coordinate a value is, as NMEA writes them differently.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Lat,
+    Lon,
+}
+
+impl Axis {
+    /// The hemisphere letters for positive and negative values
+    fn hemispheres(self) -> (u8, u8) {
+        match self {
+            Self::Lat => (b'N', b'S'),
+            Self::Lon => (b'E', b'W'),
+        }
+    }
+
+    fn max_degrees(self) -> f64 {
+        match self {
+            Self::Lat => 90.0,
+            Self::Lon => 180.0,
+        }
+    }
+
+    /// NMEA pads degrees to this many digits
+    fn degree_digits(self) -> usize {
+        match self {
+            Self::Lat => 2,
+            Self::Lon => 3,
+        }
+    }
+}
+
+/// Parses a value and its hemisphere as NMEA sends them, such as `2307.1256`
+/// and `N`, into decimal degrees.
+///
+/// `None` if either is empty, as before the gps has a fix, or malformed or
+/// out of range.
+pub fn parse_nmea(value: &[u8], hemisphere: &[u8], axis: Axis) -> Option<f64> {
+    let degrees = parse_ddmm(value)?;
+    if degrees > axis.max_degrees() {
+        return None;
+    }
+    let (positive, negative) = axis.hemispheres();
+    match *hemisphere {
+        [h] if h == positive => Some(degrees),
+        [h] if h == negative => Some(-degrees),
+        _ => None,
+    }
+}
+
+/// Parses `ddmm.mmmm` or `dddmm.mmmm` into decimal degrees, without a sign.
+///
+/// The minutes are parsed separately from the degrees, so they come out as
+/// exactly as an `f64` allows.
+pub fn parse_ddmm(value: &[u8]) -> Option<f64> {
+    let dot = value
+        .iter()
+        .position(|&char| char == b'.')
+        .unwrap_or(value.len());
+    let digits_ok = value[..dot].iter().all(u8::is_ascii_digit)
+        && value[dot..].iter().skip(1).all(u8::is_ascii_digit);
+    // At least both digits of the whole minutes
+    if dot < 2 || !digits_ok {
+        return None;
+    }
+
+    let (degrees, minutes) = value.split_at(dot - 2);
+    let degrees: u32 = if degrees.is_empty() {
+        0
+    } else {
+        lexical_core::parse(degrees).ok()?
+    };
+    let minutes: f64 = core::str::from_utf8(minutes).ok()?.parse().ok()?;
+    if minutes >= 60.0 {
+        return None;
+    }
+    Some(degrees as f64 + minutes / 60.0)
+}
+
+/// Writes `degrees` as NMEA does, such as `2307.1256,N` or `00516.0000,W`,
+/// to a ten-thousandth of a minute. Out of range values are clamped.
+pub fn write_nmea<W: fmt::Write>(out: &mut W, degrees: f64, axis: Axis) -> fmt::Result {
+    let (positive, negative) = axis.hemispheres();
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+    let degrees = degrees.abs().min(axis.max_degrees());
+
+    // Round in whole units of the last digit, so 59.99999 minutes carries
+    // into the degrees rather than being written as 60.0000
+    const UNITS_PER_MINUTE: u64 = 10_000;
+    const UNITS_PER_DEGREE: u64 = 60 * UNITS_PER_MINUTE;
+    let units = libm::round(degrees * UNITS_PER_DEGREE as f64) as u64;
+    let whole_degrees = units / UNITS_PER_DEGREE;
+    let units = units % UNITS_PER_DEGREE;
+
+    write!(
+        out,
+        "{:0width$}{:02}.{:04},{}",
+        whole_degrees,
+        units / UNITS_PER_MINUTE,
+        units % UNITS_PER_MINUTE,
+        hemisphere as char,
+        width = axis.degree_digits()
+    )
+}
+
+/// Decimal degrees as whole millionths of a degree, about 11cm at the
+/// equator, for storing positions without floats.
+pub fn to_microdegrees(degrees: f64) -> i32 {
+    libm::round(degrees * 1e6) as i32
+}
+
+pub fn from_microdegrees(microdegrees: i32) -> f64 {
+    microdegrees as f64 / 1e6
+}
+
+/// Writes decimal degrees for the export formats. An `f32` only has about a
+/// meter of precision at five places, so there's no point writing more.
+pub fn write_decimal<W: fmt::Write>(out: &mut W, degrees: f32) -> fmt::Result {
+    write!(out, "{:.5}", degrees)
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn nmea(degrees: f64, axis: Axis) -> String {
+        let mut out = String::new();
+        write_nmea(&mut out, degrees, axis).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_parse_nmea() {
+        let lat = parse_nmea(b"2307.1256", b"N", Axis::Lat).unwrap();
+        assert!((lat - 23.118_76).abs() < 1e-12, "{}", lat);
+        let lon = parse_nmea(b"12016.4438", b"W", Axis::Lon).unwrap();
+        assert!((lon - -120.274_063_333_333).abs() < 1e-12, "{}", lon);
+
+        assert_eq!(parse_nmea(b"0000.0000", b"S", Axis::Lat), Some(-0.0));
+        assert_eq!(parse_ddmm(b"30"), Some(0.5));
+        assert_eq!(parse_ddmm(b"130."), Some(1.5));
+
+        // Empty before the gps has a fix
+        assert_eq!(parse_nmea(b"", b"", Axis::Lat), None);
+        // The wrong hemisphere for the axis
+        assert_eq!(parse_nmea(b"2307.1256", b"E", Axis::Lat), None);
+        assert_eq!(parse_nmea(b"9100.0000", b"N", Axis::Lat), None);
+        assert_eq!(parse_ddmm(b"2360.0000"), None);
+        assert_eq!(parse_ddmm(b"5.1"), None);
+        assert_eq!(parse_ddmm(b"23-7.1256"), None);
+        assert_eq!(parse_ddmm(b"2307.1e3"), None);
+    }
+
+    #[test]
+    fn test_write_nmea() {
+        assert_eq!(nmea(23.118_76, Axis::Lat), "2307.1256,N");
+        assert_eq!(nmea(-5.266_666_67, Axis::Lon), "00516.0000,W");
+        assert_eq!(nmea(0.0, Axis::Lon), "00000.0000,E");
+        // Carries rather than writing 60 minutes
+        assert_eq!(nmea(-50.999_999_9, Axis::Lat), "5100.0000,S");
+
+        for (degrees, axis) in [(56.335_01, Axis::Lat), (-2.793_36, Axis::Lon)] {
+            let written = nmea(degrees, axis);
+            let (value, hemisphere) = written.split_once(',').unwrap();
+            let parsed = parse_nmea(value.as_bytes(), hemisphere.as_bytes(), axis).unwrap();
+            assert!((parsed - degrees).abs() < 1e-6, "{} {}", written, parsed);
+        }
+    }
+
+    #[test]
+    fn test_microdegrees() {
+        assert_eq!(to_microdegrees(56.335_01), 56_335_010);
+        assert_eq!(to_microdegrees(-2.793_360_4), -2_793_360);
+        assert_eq!(to_microdegrees(-180.0), -180_000_000);
+        assert_eq!(from_microdegrees(-2_793_360), -2.793_36);
+    }
+}
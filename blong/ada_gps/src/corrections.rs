@@ -0,0 +1,31 @@
+//! Where the gps gets corrections to its fix from, see
+//! [`crate::Gps::set_dgps_mode`] and [`crate::Gps::set_sbas`].
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DgpsMode {
+    None,
+    /// From RTCM messages sent to the gps's second UART
+    Rtcm,
+    /// From SBAS satellites (WAAS, EGNOS, MSAS), if SBAS is enabled
+    Sbas,
+}
+
+impl DgpsMode {
+    pub(crate) fn field(&self) -> &'static [u8] {
+        match self {
+            Self::None => b"0",
+            Self::Rtcm => b"1",
+            Self::Sbas => b"2",
+        }
+    }
+
+    pub(crate) fn from_field(field: &[u8]) -> Option<Self> {
+        match field {
+            b"0" => Some(Self::None),
+            b"1" => Some(Self::Rtcm),
+            b"2" => Some(Self::Sbas),
+            _ => None,
+        }
+    }
+}
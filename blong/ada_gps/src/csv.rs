@@ -0,0 +1,98 @@
+//! Writing packets out as CSV, one row each, for a quick look at a log in a
+//! spreadsheet or script. See the `gpx` module for something mapping tools
+//! understand.
+
+use core::fmt::{self, Write};
+
+use crate::{coords, Fix, Packet};
+
+const HEADER: &str = "time,lat,lon,alt,fix,hdop,sats\n";
+
+/// Fields the packet doesn't have are left empty. The altitude is above mean
+/// sea level.
+#[derive(Debug)]
+pub struct CsvWriter<W> {
+    out: W,
+}
+
+impl<W> CsvWriter<W>
+where
+    W: Write,
+{
+    /// Writes the header row.
+    pub fn new(mut out: W) -> Result<Self, fmt::Error> {
+        out.write_str(HEADER)?;
+        Ok(Self { out })
+    }
+
+    pub fn point(&mut self, packet: &Packet) -> fmt::Result {
+        if let Some(time) = packet.time {
+            time.write_iso8601(&mut self.out)?;
+        }
+        self.out.write_char(',')?;
+        if let Some(lat) = packet.lat {
+            coords::write_decimal(&mut self.out, lat)?;
+        }
+        self.out.write_char(',')?;
+        if let Some(lon) = packet.lon {
+            coords::write_decimal(&mut self.out, lon)?;
+        }
+        self.out.write_char(',')?;
+        if let Some(alt) = packet.height_msl {
+            write!(self.out, "{}", alt)?;
+        }
+        self.out.write_char(',')?;
+        if let Some(fix) = &packet.fix {
+            self.out.write_str(match fix {
+                Fix::No => "none",
+                Fix::GpsFix => "gps",
+                Fix::DGpsFix => "dgps",
+                Fix::DeadReckoning => "dr",
+            })?;
+        }
+        self.out.write_char(',')?;
+        if let Some(hdop) = packet.hdop {
+            write!(self.out, "{}.{:02}", hdop / 100, hdop % 100)?;
+        }
+        self.out.write_char(',')?;
+        if let Some(num_sat) = packet.num_sat {
+            write!(self.out, "{}", num_sat)?;
+        }
+        self.out.write_char('\n')
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::UtcDateTime;
+    use alloc::string::String;
+
+    #[test]
+    fn test_writes_rows() {
+        let mut csv = CsvWriter::new(String::new()).unwrap();
+        csv.point(&Packet {
+            time: UtcDateTime::new(2022, 1, 27, 22, 28, 30, 500),
+            fix: Some(Fix::DGpsFix),
+            lat: Some(56.33501),
+            lon: Some(-2.79336),
+            height_msl: Some(8),
+            hdop: Some(90),
+            num_sat: Some(9),
+            ..Packet::default()
+        })
+        .unwrap();
+        csv.point(&Packet::default()).unwrap();
+
+        assert_eq!(
+            csv.into_inner(),
+            "time,lat,lon,alt,fix,hdop,sats\n\
+             2022-01-27T22:28:30.500Z,56.33501,-2.79336,8,dgps,0.90,9\n\
+             ,,,,,,\n"
+        );
+    }
+}
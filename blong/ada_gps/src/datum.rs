@@ -0,0 +1,44 @@
+//! Which datum the gps reports positions in, see [`crate::Gps::set_datum`].
+
+/// Highest datum index PMTK330 accepts
+const MAX_INDEX: u8 = 222;
+
+/// A datum from the table in the PMTK command manual.
+///
+/// Only the common ones have their own variant, the rest are
+/// [`Self::Other`] with their index in the table.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Datum {
+    /// What GPS uses natively, and the default
+    Wgs84,
+    /// Tokyo, with the mean parameters for Japan, Korea and Okinawa
+    TokyoMean,
+    /// Tokyo, with the parameters for Japan alone
+    TokyoJapan,
+    /// Index into the datum table, from 3 to 222
+    Other(u8),
+}
+
+impl Datum {
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::Wgs84),
+            1 => Some(Self::TokyoMean),
+            2 => Some(Self::TokyoJapan),
+            index if index <= MAX_INDEX => Some(Self::Other(index)),
+            _ => None,
+        }
+    }
+
+    /// `None` if this is an [`Self::Other`] outside the table.
+    pub(crate) fn index(&self) -> Option<u8> {
+        match *self {
+            Self::Wgs84 => Some(0),
+            Self::TokyoMean => Some(1),
+            Self::TokyoJapan => Some(2),
+            Self::Other(index) if index <= MAX_INDEX => Some(index),
+            Self::Other(_) => None,
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! A compact encoding for storing months of points, several times smaller
+//! than LOCUS's own records.
+//!
+//! Each point is stored as the change from the one before: the position in
+//! steps of 0.00001 degrees (about a meter), the height in meters, and the
+//! time as the change in the gap between points, which is usually zero when
+//! logging on an interval. Small numbers are stored in fewer bytes.
+//!
+//! Every so often there's a keyframe with everything in full, so a damaged
+//! archive only loses the points up to the next one. Only the time, fix,
+//! position and height are kept, to the precision above.
+
+use alloc::vec::Vec;
+
+use crate::{Fix, Packet, UtcDateTime};
+
+/// Points between keyframes
+pub const KEYFRAME_INTERVAL: usize = 64;
+/// Fixed-point steps per degree
+const DEGREE_SCALE: f64 = 100_000.0;
+
+const KEYFRAME: u8 = 1 << 7;
+const HAS_TIME: u8 = 1 << 3;
+const HAS_POSITION: u8 = 1 << 4;
+const HAS_HEIGHT: u8 = 1 << 5;
+const FIX_MASK: u8 = 0b111;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data ends part way through a point
+    Truncated,
+    /// A point refers to one before it we haven't got, such as at the start
+    /// of damaged data. Skip ahead to the next keyframe.
+    NeedKeyframe,
+    /// Not something [`Encoder`] would have written
+    Invalid,
+}
+
+/// The last point written or read, which the next is stored relative to.
+#[derive(Debug, Clone, Default)]
+struct State {
+    since_keyframe: usize,
+    time: Option<i64>,
+    gap: i64,
+    lat: Option<i64>,
+    lon: Option<i64>,
+    height: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    last: State,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `packet` to `out`.
+    pub fn encode(&mut self, packet: &Packet, out: &mut Vec<u8>) {
+        let time = packet.time.map(UtcDateTime::to_unix);
+        let position = packet.lat.zip(packet.lon).map(|(lat, lon)| {
+            (
+                libm::round(lat as f64 * DEGREE_SCALE) as i64,
+                libm::round(lon as f64 * DEGREE_SCALE) as i64,
+            )
+        });
+        let height = packet.height_msl.map(i64::from);
+
+        // Anything new needs something to be relative to
+        let keyframe = self.last.since_keyframe.is_multiple_of(KEYFRAME_INTERVAL)
+            || (time.is_some() && self.last.time.is_none())
+            || (position.is_some() && self.last.lat.is_none())
+            || (height.is_some() && self.last.height.is_none());
+
+        let mut header = fix_code(packet.fix.as_ref());
+        if keyframe {
+            header |= KEYFRAME;
+        }
+        if time.is_some() {
+            header |= HAS_TIME;
+        }
+        if position.is_some() {
+            header |= HAS_POSITION;
+        }
+        if height.is_some() {
+            header |= HAS_HEIGHT;
+        }
+        out.push(header);
+
+        if keyframe {
+            self.last = State::default();
+            if let Some(time) = time {
+                out.extend_from_slice(&(time as u32).to_le_bytes());
+            }
+            if let Some((lat, lon)) = position {
+                out.extend_from_slice(&(lat as i32).to_le_bytes());
+                out.extend_from_slice(&(lon as i32).to_le_bytes());
+            }
+            if let Some(height) = height {
+                out.extend_from_slice(&(height as i16).to_le_bytes());
+            }
+        } else {
+            if let (Some(time), Some(last)) = (time, self.last.time) {
+                let gap = time - last;
+                write_varint(gap - self.last.gap, out);
+                self.last.gap = gap;
+            }
+            if let (Some((lat, lon)), Some(last_lat), Some(last_lon)) =
+                (position, self.last.lat, self.last.lon)
+            {
+                write_varint(lat - last_lat, out);
+                write_varint(lon - last_lon, out);
+            }
+            if let (Some(height), Some(last)) = (height, self.last.height) {
+                write_varint(height - last, out);
+            }
+        }
+
+        self.last.since_keyframe += 1;
+        // Keep the last of each, so a point missing one doesn't need a
+        // keyframe after it
+        self.last.time = time.or(self.last.time);
+        self.last.lat = position.map(|(lat, _)| lat).or(self.last.lat);
+        self.last.lon = position.map(|(_, lon)| lon).or(self.last.lon);
+        self.last.height = height.or(self.last.height);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    last: State,
+    /// Whether we've seen a keyframe to start from
+    synced: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the point at the start of `data`, returning it and how many
+    /// bytes it took.
+    pub fn decode(&mut self, data: &[u8]) -> Result<(Packet, usize), DecodeError> {
+        let (&header, mut rest) = data.split_first().ok_or(DecodeError::Truncated)?;
+        let keyframe = header & KEYFRAME != 0;
+
+        let mut packet = Packet {
+            fix: fix_from_code(header & FIX_MASK)?,
+            ..Packet::default()
+        };
+
+        if keyframe {
+            self.last = State::default();
+            self.synced = true;
+        } else if !self.synced {
+            return Err(DecodeError::NeedKeyframe);
+        }
+
+        if header & HAS_TIME != 0 {
+            let time = if keyframe {
+                u32::from_le_bytes(take(&mut rest)?) as i64
+            } else {
+                let last = self.last.time.ok_or(DecodeError::NeedKeyframe)?;
+                self.last.gap += read_varint(&mut rest)?;
+                last + self.last.gap
+            };
+            packet.time = Some(UtcDateTime::from_unix(time).ok_or(DecodeError::Invalid)?);
+            self.last.time = Some(time);
+        }
+
+        if header & HAS_POSITION != 0 {
+            let (lat, lon) = if keyframe {
+                (
+                    i32::from_le_bytes(take(&mut rest)?) as i64,
+                    i32::from_le_bytes(take(&mut rest)?) as i64,
+                )
+            } else {
+                let last_lat = self.last.lat.ok_or(DecodeError::NeedKeyframe)?;
+                let last_lon = self.last.lon.ok_or(DecodeError::NeedKeyframe)?;
+                (
+                    last_lat + read_varint(&mut rest)?,
+                    last_lon + read_varint(&mut rest)?,
+                )
+            };
+            packet.lat = Some((lat as f64 / DEGREE_SCALE) as f32);
+            packet.lon = Some((lon as f64 / DEGREE_SCALE) as f32);
+            self.last.lat = Some(lat);
+            self.last.lon = Some(lon);
+        }
+
+        if header & HAS_HEIGHT != 0 {
+            let height = if keyframe {
+                i16::from_le_bytes(take(&mut rest)?) as i64
+            } else {
+                let last = self.last.height.ok_or(DecodeError::NeedKeyframe)?;
+                last + read_varint(&mut rest)?
+            };
+            packet.height_msl = Some(i16::try_from(height).map_err(|_| DecodeError::Invalid)?);
+            self.last.height = Some(height);
+        }
+
+        Ok((packet, data.len() - rest.len()))
+    }
+}
+
+fn fix_code(fix: Option<&Fix>) -> u8 {
+    match fix {
+        None => 0,
+        Some(Fix::No) => 1,
+        Some(Fix::GpsFix) => 2,
+        Some(Fix::DGpsFix) => 3,
+        Some(Fix::DeadReckoning) => 4,
+    }
+}
+
+fn fix_from_code(code: u8) -> Result<Option<Fix>, DecodeError> {
+    Ok(match code {
+        0 => None,
+        1 => Some(Fix::No),
+        2 => Some(Fix::GpsFix),
+        3 => Some(Fix::DGpsFix),
+        4 => Some(Fix::DeadReckoning),
+        _ => return Err(DecodeError::Invalid),
+    })
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    if data.len() < N {
+        return Err(DecodeError::Truncated);
+    }
+    let (bytes, rest) = data.split_at(N);
+    *data = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+/// Zigzag, so small negative numbers are small too, then seven bits a byte
+/// with the top bit set on all but the last.
+fn write_varint(val: i64, out: &mut Vec<u8>) {
+    let mut zigzag = ((val << 1) ^ (val >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push(zigzag as u8 | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<i64, DecodeError> {
+    let mut zigzag = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let [byte] = take(data)?;
+        zigzag |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(DecodeError::Invalid)
+}
+
+#[cfg(all(test, feature = "host-test", feature = "locus"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_sample_dump() {
+        let mut points = Vec::new();
+        crate::logger::parse_dump(
+            include_bytes!("../test_assets/3819_log_records.bin"),
+            |point| points.push(point),
+        );
+
+        let mut encoder = Encoder::new();
+        let mut encoded = Vec::new();
+        for point in &points {
+            encoder.encode(point, &mut encoded);
+        }
+        // LOCUS takes 16 bytes a point in basic mode
+        assert!(encoded.len() * 3 < points.len() * 16);
+
+        let mut decoder = Decoder::new();
+        let mut data = &encoded[..];
+        for point in &points {
+            let (decoded, len) = decoder.decode(data).unwrap();
+            data = &data[len..];
+            assert_eq!(decoded.time, point.time);
+            assert_eq!(decoded.fix, point.fix);
+            assert_eq!(decoded.height_msl, point.height_msl);
+            assert!((decoded.lat.unwrap() - point.lat.unwrap()).abs() < 0.00001);
+            assert!((decoded.lon.unwrap() - point.lon.unwrap()).abs() < 0.00001);
+        }
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_resyncs_at_keyframe() {
+        let point = |secs| Packet {
+            time: UtcDateTime::from_unix(1_643_322_510 + secs),
+            lat: Some(56.33501),
+            lon: Some(-2.79336),
+            ..Packet::default()
+        };
+        let mut encoder = Encoder::new();
+        let mut encoded = Vec::new();
+        let mut starts = Vec::new();
+        for secs in 0..KEYFRAME_INTERVAL as i64 + 1 {
+            starts.push(encoded.len());
+            encoder.encode(&point(secs), &mut encoded);
+        }
+
+        // Starting part way through
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder.decode(&encoded[starts[1]..]),
+            Err(DecodeError::NeedKeyframe)
+        );
+        let keyframe = &encoded[starts[KEYFRAME_INTERVAL]..];
+        let (decoded, _) = decoder.decode(keyframe).unwrap();
+        assert_eq!(decoded.time, point(KEYFRAME_INTERVAL as i64).time);
+
+        assert_eq!(decoder.decode(&keyframe[..3]), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn test_varint() {
+        for val in [
+            0,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            1_000_000,
+            -1_000_000,
+            i64::MAX,
+            i64::MIN,
+        ] {
+            let mut out = Vec::new();
+            write_varint(val, &mut out);
+            assert_eq!(read_varint(&mut &out[..]), Ok(val));
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Sending a command and waiting for its reply, retrying if need be, with no
+//! serial port or delays of its own.
+//!
+//! [`Exchange`] says what it wants done through [`Exchange::poll`], and is
+//! told what came back through [`Exchange::push_bytes`]. [`crate::Gps`] and
+//! [`crate::AsyncGps`] are both thin loops around it, and anything else that
+//! can move bytes and wait, such as an interrupt handler, can drive one too.
+//!
+//! Replies that come as several sentences, such as a LOCUS dump, are read
+//! with an exchange that only listens, one per sentence after the first.
+
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::{cmd, debug, error, trace, Error, RawBytes, Stats};
+
+pub(crate) const MAX_CMD_TRIES: usize = 5;
+pub(crate) const DELAY_BEFORE_RETRY_US: u32 = 80_000;
+/// Maximum number of NMEA sentences to forward while waiting for a reply. At
+/// the default rate the gps sends about six a second.
+const MAX_FORWARDED_PER_REPLY: usize = 30;
+
+/// What an [`Exchange`] wants done next. Poll again once it's done.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action<'e, E> {
+    /// Send these bytes to the gps. Call [`Exchange::attempt_failed`] if
+    /// that fails.
+    Write(&'e [u8]),
+    /// Wait for bytes from the gps and [`Exchange::push_bytes`] them, or call
+    /// [`Exchange::timed_out`] if none come for a while.
+    Read,
+    /// An NMEA sentence that arrived in the middle, to pass on to whatever's
+    /// listening for fixes. Only given if [`Exchange::forward_nmea`] is set.
+    Forward(Vec<u8>),
+    /// A message the gps sent of its own accord, such as PMTK010 after it
+    /// restarts. Only given if [`Exchange::forward_system_messages`] is set.
+    Unsolicited(Vec<u8>),
+    /// Wait this many microseconds before the next try.
+    Delay(u32),
+    /// All done, with the fields of the reply. Don't poll after this.
+    Done(Result<Vec<Vec<u8>>, Error<E>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expect {
+    /// A PMTK_ACK for `num` saying it worked, then at least this many fields
+    Ack { num: [u8; 3], min_fields: usize },
+    /// A sentence with this name, with at least this many fields, the first
+    /// of them `first` if given
+    Reply {
+        name: Vec<u8>,
+        min_fields: usize,
+        first: Option<Vec<u8>>,
+    },
+    /// See [`Exchange::for_pq`]
+    Pq { name: Vec<u8>, mode: Vec<u8> },
+    /// Whatever sentence comes next
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State<E> {
+    Send,
+    Receive,
+    Failed(Error<E>),
+    Done,
+}
+
+/// One command and the reply to it, or just a reply.
+#[derive(Debug, Clone)]
+pub struct Exchange<E> {
+    /// What it's for, to log
+    name: Vec<u8>,
+    /// The whole sentence, ready to write. `None` if only listening.
+    cmd: Option<Vec<u8>>,
+    expect: Expect,
+    max_tries: usize,
+    tries: usize,
+    forward_nmea: bool,
+    forward_system_messages: bool,
+    forwarded: usize,
+    state: State<E>,
+    line: Vec<u8>,
+    last_is_carriage_return: bool,
+    /// A whole line waiting to be looked at
+    received: Option<Vec<u8>>,
+    stats: Stats,
+}
+
+impl<E> Exchange<E> {
+    /// `PMTK<num>`, answered by an ack.
+    pub fn for_ack(num: &[u8; 3], fields: &[&[u8]]) -> Self {
+        Self::for_ack_fields(num, fields, 0)
+    }
+
+    /// `PMTK<num>`, answered by an ack with at least `min_fields` after the
+    /// status. Those are the fields it finishes with.
+    pub fn for_ack_fields(num: &[u8; 3], fields: &[&[u8]], min_fields: usize) -> Self {
+        Self::new(
+            &pmtk_name(num),
+            fields,
+            Expect::Ack {
+                num: *num,
+                min_fields,
+            },
+        )
+    }
+
+    /// `PMTK<num>`, answered by `PMTK<reply_num>` with at least
+    /// `reply_min_fields`.
+    pub fn for_reply(
+        num: &[u8; 3],
+        fields: &[&[u8]],
+        reply_num: &[u8; 3],
+        reply_min_fields: usize,
+    ) -> Self {
+        Self::new(
+            &pmtk_name(num),
+            fields,
+            Expect::Reply {
+                name: pmtk_name(reply_num).to_vec(),
+                min_fields: reply_min_fields,
+                first: None,
+            },
+        )
+    }
+
+    /// Quectel's `$PQXXX`, whose first field is `W` to write a setting and
+    /// `Q` or `R` to query one. Answered by a sentence of the same name,
+    /// echoing that, and then `OK`, `ERROR` or the queried value.
+    pub fn for_pq(name: &[u8], fields: &[&[u8]]) -> Self {
+        Self::new(
+            name,
+            fields,
+            Expect::Pq {
+                name: name.to_vec(),
+                mode: fields[0].to_vec(),
+            },
+        )
+    }
+
+    /// Wakes the gps from standby. Any byte does, and an empty line is
+    /// ignored if it's already awake. Answered by PMTK_SYS_MSG 2, wakeup
+    /// from standby.
+    pub fn wake() -> Self {
+        let expect = Expect::Reply {
+            name: b"PMTK010".to_vec(),
+            min_fields: 1,
+            first: Some(b"002".to_vec()),
+        };
+        let mut exchange = Self::new(b"wakeup", &[], expect);
+        exchange.cmd = Some(b"\r\n".to_vec());
+        exchange
+    }
+
+    /// Sends nothing, just waits for the next sentence named `name`, with at
+    /// least `min_fields`. For the rest of a reply that comes as several
+    /// sentences. Not retried unless given [`Self::max_tries`].
+    pub fn listen(name: &[u8], min_fields: usize) -> Self {
+        Self::listen_for(
+            name,
+            Expect::Reply {
+                name: name.to_vec(),
+                min_fields,
+                first: None,
+            },
+        )
+    }
+
+    /// Sends nothing, just waits for the next sentence whatever it is,
+    /// finishing with its name and then its fields. Nothing is forwarded.
+    /// Not retried unless given [`Self::max_tries`].
+    pub fn next_sentence() -> Self {
+        Self::listen_for(b"any sentence", Expect::Any)
+    }
+
+    fn listen_for(name: &[u8], expect: Expect) -> Self {
+        let mut exchange = Self::new(name, &[], expect);
+        exchange.cmd = None;
+        exchange.max_tries = 0;
+        exchange
+    }
+
+    fn new(name: &[u8], fields: &[&[u8]], expect: Expect) -> Self {
+        let mut cmd = Vec::new();
+        cmd::serialize(name, fields, &mut cmd);
+        Self {
+            name: name.to_vec(),
+            cmd: Some(cmd),
+            expect,
+            max_tries: MAX_CMD_TRIES,
+            tries: 0,
+            forward_nmea: false,
+            forward_system_messages: false,
+            forwarded: 0,
+            state: State::Send,
+            line: Vec::new(),
+            last_is_carriage_return: false,
+            received: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Give up after this many retries. Defaults to five, and 0 sends the
+    /// command once.
+    pub fn max_tries(mut self, max_tries: usize) -> Self {
+        self.max_tries = max_tries;
+        self
+    }
+
+    /// Hand NMEA sentences that arrive while waiting back as
+    /// [`Action::Forward`], instead of treating them as a failed try.
+    pub fn forward_nmea(mut self) -> Self {
+        self.forward_nmea = true;
+        self
+    }
+
+    /// Hand PMTK system and text messages that arrive while waiting back as
+    /// [`Action::Unsolicited`], instead of treating them as a failed try.
+    pub fn forward_system_messages(mut self) -> Self {
+        self.forward_system_messages = true;
+        self
+    }
+
+    pub fn poll(&mut self) -> Action<'_, E> {
+        match mem::replace(&mut self.state, State::Done) {
+            State::Send => {
+                self.tries += 1;
+                self.state = State::Receive;
+                match &self.cmd {
+                    Some(cmd) => {
+                        trace!("Sending {=[u8]:a}", cmd);
+                        Action::Write(cmd)
+                    }
+                    None => Action::Read,
+                }
+            }
+            State::Receive => match self.received.take() {
+                Some(line) => self.check(line),
+                None => {
+                    self.state = State::Receive;
+                    Action::Read
+                }
+            },
+            State::Failed(err @ Error::GpsSaysUnsupportedCommand) => {
+                debug!("{=[u8]:a} unsupported, not retrying", &self.name);
+                Action::Done(Err(err))
+            }
+            State::Failed(err) if self.tries > self.max_tries => {
+                if self.cmd.is_some() {
+                    error!(
+                        "Failed to send {=[u8]:a} after {} tries",
+                        &self.name, self.tries
+                    );
+                } else {
+                    // Often expected, such as at the end of a stream
+                    debug!("Gave up waiting for {=[u8]:a}", &self.name);
+                }
+                Action::Done(Err(err))
+            }
+            State::Failed(_) => {
+                trace!("Delaying before retry");
+                self.stats.retries += 1;
+                self.state = State::Send;
+                Action::Delay(DELAY_BEFORE_RETRY_US)
+            }
+            State::Done => panic!("Polled exchange after it was done"),
+        }
+    }
+
+    /// Returns how many of `bytes` were used, which stops short after the end
+    /// of a line. Push the rest after polling again.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> usize {
+        if self.received.is_some() {
+            return 0;
+        }
+
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == b'$' && !self.line.is_empty() {
+                trace!("Resyncing");
+                self.stats.resyncs += 1;
+                self.line.clear();
+                self.line.push(byte);
+            } else if byte == b'\n' && self.last_is_carriage_return {
+                self.line.push(byte);
+                self.last_is_carriage_return = false;
+                let line = mem::take(&mut self.line);
+                trace!("Received {=[u8]:a}", &line);
+                self.received = Some(line);
+                return i + 1;
+            } else {
+                self.last_is_carriage_return = byte == b'\r';
+                self.line.push(byte);
+                if cmd::is_too_long(&self.line) {
+                    trace!("Sentence too long");
+                    let err = Error::SentenceTooLong(RawBytes::new(&self.line));
+                    self.attempt_failed(err);
+                    return i + 1;
+                }
+            }
+        }
+        bytes.len()
+    }
+
+    /// What's gone wrong so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Nothing's come from the gps for too long.
+    pub fn timed_out(&mut self) {
+        trace!("Read timed out");
+        self.attempt_failed(Error::ReadTimeout);
+    }
+
+    /// Something went wrong moving bytes, so give up on this try.
+    pub fn attempt_failed(&mut self, err: Error<E>) {
+        self.stats.count_failure(&err);
+        self.line.clear();
+        self.last_is_carriage_return = false;
+        self.received = None;
+        self.state = State::Failed(err);
+    }
+
+    fn check(&mut self, line: Vec<u8>) -> Action<'_, E> {
+        let (name, fields) = match cmd::parse(&line) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.attempt_failed(Error::Parse(err, RawBytes::new(&line)));
+                return self.poll();
+            }
+        };
+
+        let expected_name = match &self.expect {
+            Expect::Ack { .. } => &b"PMTK001"[..],
+            Expect::Reply { name, .. } | Expect::Pq { name, .. } => &name[..],
+            Expect::Any => {
+                let mut fields = fields;
+                fields.insert(0, name);
+                return Action::Done(Ok(fields));
+            }
+        };
+
+        if self.forwarded < MAX_FORWARDED_PER_REPLY {
+            if is_nmea(&name) && self.forward_nmea {
+                self.forwarded += 1;
+                self.state = State::Receive;
+                return Action::Forward(line);
+            }
+            // Unless it's the reply, such as waking from standby
+            if is_system_message(&name) && name != expected_name && self.forward_system_messages {
+                self.forwarded += 1;
+                self.state = State::Receive;
+                return Action::Unsolicited(line);
+            }
+        }
+
+        if name != expected_name {
+            self.stats.spurious += 1;
+        }
+
+        let result = match &self.expect {
+            Expect::Ack { num, min_fields } => check_reply(b"PMTK001", 2, &name, fields)
+                .and_then(|fields| check_pmtk_ack(num, fields))
+                .and_then(|fields| {
+                    if fields.len() < *min_fields {
+                        error!(
+                            "Expected at least {} fields after the ack status, got {}",
+                            min_fields,
+                            fields.len()
+                        );
+                        return Err(Error::Protocol(RawBytes::from_parsed(b"", &fields)));
+                    }
+                    Ok(fields)
+                }),
+            Expect::Reply {
+                name: reply_name,
+                min_fields,
+                first,
+            } => {
+                check_reply(reply_name, *min_fields, &name, fields).and_then(|fields| match first {
+                    Some(first) if fields[0] != *first => {
+                        debug!(
+                            "Expected {=[u8]:a} starting {=[u8]:a}, got {=[u8]:a}",
+                            reply_name, first, &fields[0]
+                        );
+                        Err(Error::Protocol(RawBytes::from_parsed(reply_name, &fields)))
+                    }
+                    _ => Ok(fields),
+                })
+            }
+            Expect::Pq {
+                name: reply_name,
+                mode,
+            } => check_reply(reply_name, 2, &name, fields).and_then(|fields| {
+                if fields[0] != *mode {
+                    debug!(
+                        "Expected reply to {=[u8]:a}, got reply to {=[u8]:a}",
+                        mode, &fields[0]
+                    );
+                    return Err(Error::Protocol(RawBytes::from_parsed(reply_name, &fields)));
+                }
+                if fields[1] == b"ERROR" {
+                    return Err(Error::GpsSaysActionFailed);
+                }
+                Ok(fields)
+            }),
+            // Finished above
+            Expect::Any => unreachable!(),
+        };
+
+        match result {
+            Ok(fields) => {
+                if self.cmd.is_some() {
+                    debug!("Sent {=[u8]:a} in {} tries", &self.name, self.tries);
+                }
+                Action::Done(Ok(fields))
+            }
+            Err(err) => {
+                self.attempt_failed(err);
+                self.poll()
+            }
+        }
+    }
+}
+
+pub(crate) fn pmtk_name(num: &[u8; 3]) -> [u8; 7] {
+    let mut name = *b"PMTK\0\0\0";
+    name[4..].clone_from_slice(num);
+    name
+}
+
+/// Proprietary sentences (PMTK, PGTOP, ...) start with P, anything else is a
+/// standard NMEA sentence. PGTOP antenna reports come out with the NMEA
+/// though, so go the same way.
+pub(crate) fn is_nmea(name: &[u8]) -> bool {
+    !name.starts_with(b"P") || name == b"PGTOP"
+}
+
+/// PMTK_SYS_MSG and PMTK_TXT_MSG, which the gps sends without being asked,
+/// such as when it starts up.
+fn is_system_message(name: &[u8]) -> bool {
+    name == b"PMTK010" || name == b"PMTK011"
+}
+
+/// Checks a sentence is the `name` we're waiting for, with at least
+/// `min_fields`, returning its fields.
+fn check_reply<E>(
+    name: &[u8],
+    min_fields: usize,
+    actual_name: &[u8],
+    fields: Vec<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, Error<E>> {
+    if name != actual_name {
+        // This is super common if the board is sending us something else
+        // and we request something at the same time. Disabling nmea output
+        // helps some. Still, retrying on this is expected.
+        debug!("Expected {=[u8]:a}, got {=[u8]:a}", name, actual_name);
+        return Err(Error::Protocol(RawBytes::from_parsed(actual_name, &fields)));
+    }
+
+    if fields.len() < min_fields {
+        // Failing after parse and validating command name is unexpected
+        error!(
+            "Expected {=[u8]:a} to have at least {} fields, got {}",
+            actual_name,
+            min_fields,
+            fields.len()
+        );
+        return Err(Error::Protocol(RawBytes::from_parsed(actual_name, &fields)));
+    }
+
+    if fields.len() > min_fields {
+        trace!(
+            "{=[u8]:a} has {} fields, more than min_fields {}",
+            actual_name,
+            fields.len(),
+            min_fields
+        );
+    }
+
+    Ok(fields)
+}
+
+/// Checks a PMTK_ACK is for `for_num` and says the command worked, returning
+/// the fields after the status.
+pub(crate) fn check_pmtk_ack<E>(
+    for_num: &[u8],
+    mut fields: Vec<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, Error<E>> {
+    let got_for = &fields[0];
+    let got_status = &fields[1];
+    if got_status.len() != 1 {
+        error!(
+            "Expected PMTK_ACK status field to have one char, got: {=[u8]:a}",
+            got_status
+        );
+        return Err(Error::Protocol(RawBytes::from_parsed(b"PMTK001", &fields)));
+    }
+    let got_status = got_status[0];
+
+    if for_num != got_for {
+        debug!(
+            "Got ack for {=[u8]:a}, expected ack for {=[u8]:a}",
+            got_for, for_num
+        );
+        return Err(Error::Protocol(RawBytes::from_parsed(b"PMTK001", &fields)));
+    }
+
+    match got_status {
+        b'0' => Err(Error::GpsSaysInvalidCommand),
+        b'1' => Err(Error::GpsSaysUnsupportedCommand),
+        b'2' => Err(Error::GpsSaysActionFailed),
+        b'3' => Ok(fields.split_off(2)),
+        val => {
+            error!("Unexpected PMTK_ACK flag {:a}", val);
+            Err(Error::Protocol(RawBytes::from_parsed(b"PMTK001", &fields)))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    const RMC: &[u8] =
+        b"$GPRMC,064951.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2C\r\n";
+
+    #[test]
+    fn test_forwards_nmea_then_acks() {
+        let mut exchange = Exchange::<Infallible>::for_ack(b"220", &[b"200"]).forward_nmea();
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK220,200*2C\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+
+        let mut incoming = RMC.to_vec();
+        incoming.extend_from_slice(b"$PMTK001,2");
+        let used = exchange.push_bytes(&incoming);
+        assert_eq!(used, RMC.len());
+        assert_eq!(exchange.poll(), Action::Forward(RMC.to_vec()));
+
+        assert_eq!(exchange.poll(), Action::Read);
+        assert_eq!(
+            exchange.push_bytes(&incoming[used..]),
+            incoming.len() - used
+        );
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"20,3*30\r\n");
+        assert_eq!(exchange.poll(), Action::Done(Ok(Vec::new())));
+    }
+
+    #[test]
+    fn test_retries_then_gives_up() {
+        let mut exchange = Exchange::<Infallible>::for_ack(b"220", &[b"200"]).max_tries(1);
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK220,200*2C\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.timed_out();
+
+        assert_eq!(exchange.poll(), Action::Delay(DELAY_BEFORE_RETRY_US));
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK220,200*2C\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        // Without forwarding, NMEA is a failed try too
+        exchange.push_bytes(RMC);
+        assert_eq!(
+            exchange.poll(),
+            Action::Done(Err(Error::Protocol(RawBytes::new(
+                b"GPRMC,064951.000,A,2307.1256,N,1"
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_overlong_line_fails_try() {
+        let mut exchange = Exchange::<Infallible>::for_ack(b"220", &[b"200"]);
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK220,200*2C\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+
+        // Stops taking bytes as soon as it's too long
+        let noise = [b'x'; 1000];
+        assert_eq!(exchange.push_bytes(&noise), cmd::MAX_NMEA_LEN + 1);
+        assert_eq!(exchange.poll(), Action::Delay(DELAY_BEFORE_RETRY_US));
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK220,200*2C\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+
+        // Then picks up from the next sentence
+        let mut incoming = vec![b'x'; 20];
+        incoming.extend_from_slice(b"$PMTK001,220,3*30\r\n");
+        exchange.push_bytes(&incoming);
+        assert_eq!(exchange.poll(), Action::Done(Ok(Vec::new())));
+        assert_eq!(exchange.stats().retries, 1);
+        assert_eq!(exchange.stats().resyncs, 1);
+    }
+    #[test]
+    fn test_unsupported_isnt_retried() {
+        let mut exchange = Exchange::<Infallible>::for_ack(b"386", &[b"0.40"]);
+        assert_eq!(exchange.poll(), Action::Write(b"$PMTK386,0.40*09\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PMTK001,386,1*3F\r\n");
+        assert_eq!(
+            exchange.poll(),
+            Action::Done(Err(Error::GpsSaysUnsupportedCommand))
+        );
+        assert_eq!(exchange.stats().retries, 0);
+    }
+
+    #[test]
+    fn test_listen_reads_without_writing_or_retrying() {
+        let mut exchange = Exchange::<Infallible>::listen(b"PMTKLOX", 2);
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PMTKLOX,0,43*6E\r\n");
+        assert_eq!(
+            exchange.poll(),
+            Action::Done(Ok(vec![b"0".to_vec(), b"43".to_vec()]))
+        );
+
+        let mut exchange = Exchange::<Infallible>::listen(b"PMTKLOX", 2);
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.timed_out();
+        assert_eq!(exchange.poll(), Action::Done(Err(Error::ReadTimeout)));
+    }
+
+    #[test]
+    fn test_next_sentence_gives_name_first() {
+        let mut exchange = Exchange::<Infallible>::next_sentence().forward_system_messages();
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PMTK011,MTKGPS*08\r\n");
+        assert_eq!(
+            exchange.poll(),
+            Action::Done(Ok(vec![b"PMTK011".to_vec(), b"MTKGPS".to_vec()]))
+        );
+    }
+
+    #[test]
+    fn test_wake_keeps_its_reply() {
+        let mut exchange = Exchange::<Infallible>::wake().forward_system_messages();
+        assert_eq!(exchange.poll(), Action::Write(b"\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        // Some other system message is passed on, then the wakeup is the reply
+        exchange.push_bytes(b"$PMTK011,MTKGPS*08\r\n");
+        assert_eq!(
+            exchange.poll(),
+            Action::Unsolicited(b"$PMTK011,MTKGPS*08\r\n".to_vec())
+        );
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PMTK010,002*2D\r\n");
+        assert_eq!(exchange.poll(), Action::Done(Ok(vec![b"002".to_vec()])));
+    }
+
+    #[test]
+    fn test_pq_retries_on_error() {
+        let mut exchange = Exchange::<Infallible>::for_pq(b"PQTXT", &[b"W", b"0", b"1"]);
+        assert_eq!(exchange.poll(), Action::Write(b"$PQTXT,W,0,1*23\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PQTXT,W,ERROR*56\r\n");
+        assert_eq!(exchange.poll(), Action::Delay(DELAY_BEFORE_RETRY_US));
+        assert_eq!(exchange.poll(), Action::Write(b"$PQTXT,W,0,1*23\r\n"));
+        assert_eq!(exchange.poll(), Action::Read);
+        exchange.push_bytes(b"$PQTXT,W,OK*0A\r\n");
+        assert_eq!(
+            exchange.poll(),
+            Action::Done(Ok(vec![b"W".to_vec(), b"OK".to_vec()]))
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! What the track formats have in common, so which one to write can be picked
+//! in one place.
+
+use core::fmt;
+
+use crate::Packet;
+
+/// A track being written out, see the `gpx` and `geojson` modules.
+pub trait TrackWriter<W> {
+    /// Packets without a position are skipped.
+    fn point(&mut self, packet: &Packet) -> fmt::Result;
+
+    /// End the current segment and start another, for a gap in the track.
+    fn new_segment(&mut self) -> fmt::Result;
+
+    /// Closes the track and hands back the output.
+    fn finish(self) -> Result<W, fmt::Error>;
+}
@@ -0,0 +1,231 @@
+//! Rejecting packets that can't be right.
+//!
+//! The gps occasionally reports a position far from the truth for a single
+//! fix, especially just after getting a fix. These show up as spikes in a
+//! track, which this filters out. LOCUS also logs points while it has no fix
+//! at all, which this can drop too.
+
+use crate::{geodesy::f32::distance_m, Fix, Packet};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterConfig {
+    /// Reject a packet if reaching it from the last accepted packet would need
+    /// a ground speed above this.
+    pub max_speed_mps: f32,
+    /// Reject a packet if reaching it from the last accepted packet would need
+    /// a vertical speed above this.
+    pub max_climb_mps: f32,
+    /// Reject packets at exactly 0,0, which the gps reports when it doesn't
+    /// have a position.
+    pub reject_null_island: bool,
+    /// Reject packets the gps says it had no satellite fix for, including
+    /// dead reckoning. Packets that don't say are kept.
+    pub require_fix: bool,
+    /// Reject packets with a HDOP above this, multiplied by 100 as in
+    /// [`Packet::hdop`]. Packets that don't say are kept.
+    pub max_hdop: Option<u16>,
+}
+
+impl Default for FilterConfig {
+    /// Generous enough for anything short of flying.
+    fn default() -> Self {
+        Self {
+            max_speed_mps: 100.0,
+            max_climb_mps: 50.0,
+            reject_null_island: true,
+            require_fix: true,
+            max_hdop: None,
+        }
+    }
+}
+
+/// How many packets were let through, and why the rest weren't.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    pub accepted: usize,
+    pub no_fix: usize,
+    pub poor_hdop: usize,
+    pub null_island: usize,
+    pub too_fast: usize,
+    pub altitude_spike: usize,
+}
+
+impl FilterStats {
+    pub fn rejected(&self) -> usize {
+        self.no_fix + self.poor_hdop + self.null_island + self.too_fast + self.altitude_spike
+    }
+}
+
+/// Checks each packet against the last one it accepted.
+///
+/// Packets need a time to be checked against the last one. Packets without a
+/// position or time are accepted, but aren't compared against.
+#[derive(Debug, Clone)]
+pub struct PacketFilter {
+    config: FilterConfig,
+    last: Option<Packet>,
+    pub stats: FilterStats,
+}
+
+impl PacketFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            stats: FilterStats::default(),
+        }
+    }
+
+    /// Wrap a callback, such as the one given to [`crate::logger::Parser`],
+    /// so it only gets the packets that should be kept.
+    ///
+    /// In [`crate::Gps::read_logs`], where the callback is also given the
+    /// point's index, call [`Self::check`] in it instead.
+    pub fn wrap<'a, F>(&'a mut self, mut on_packet: F) -> impl FnMut(Packet) + 'a
+    where
+        F: FnMut(Packet) + 'a,
+    {
+        move |packet| {
+            if self.check(&packet) {
+                on_packet(packet);
+            }
+        }
+    }
+
+    /// Returns true if `packet` should be kept.
+    pub fn check(&mut self, packet: &Packet) -> bool {
+        if self.config.require_fix && matches!(packet.fix, Some(Fix::No | Fix::DeadReckoning)) {
+            self.stats.no_fix += 1;
+            return false;
+        }
+
+        if let (Some(max_hdop), Some(hdop)) = (self.config.max_hdop, packet.hdop) {
+            if hdop > max_hdop {
+                self.stats.poor_hdop += 1;
+                return false;
+            }
+        }
+
+        let (lat, lon) = match (packet.lat, packet.lon) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => {
+                self.stats.accepted += 1;
+                return true;
+            }
+        };
+
+        if self.config.reject_null_island && lat == 0.0 && lon == 0.0 {
+            self.stats.null_island += 1;
+            return false;
+        }
+
+        if let Some(last) = &self.last {
+            if let Some(secs) = secs_between(last, packet) {
+                let last_lat = last.lat.unwrap();
+                let last_lon = last.lon.unwrap();
+
+                let distance = distance_m(last_lat, last_lon, lat, lon);
+                if distance > self.config.max_speed_mps * secs {
+                    self.stats.too_fast += 1;
+                    return false;
+                }
+
+                if let (Some(last_height), Some(height)) = (last.height_msl, packet.height_msl) {
+                    let climb = (height as f32 - last_height as f32).abs();
+                    if climb > self.config.max_climb_mps * secs {
+                        self.stats.altitude_spike += 1;
+                        return false;
+                    }
+                }
+            }
+        }
+
+        if packet.time.is_some() {
+            self.last = Some(packet.clone());
+        }
+        self.stats.accepted += 1;
+        true
+    }
+}
+
+/// Never zero, so packets with the same time get compared as if a second
+/// apart rather than rejected.
+fn secs_between(earlier: &Packet, later: &Packet) -> Option<f32> {
+    let millis = later.time?.millis_since(earlier.time?);
+    Some((millis.unsigned_abs() as f32 / 1000.0).max(1.0))
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::UtcDateTime;
+
+    fn packet(secs: u8, lat: f32, lon: f32, height: i16) -> Packet {
+        Packet {
+            time: UtcDateTime::new(2022, 1, 27, 22, 28, secs, 0),
+            lat: Some(lat),
+            lon: Some(lon),
+            height_msl: Some(height),
+            ..Packet::default()
+        }
+    }
+
+    #[test]
+    fn test_filters_implausible_packets() {
+        let mut filter = PacketFilter::new(FilterConfig::default());
+
+        // Walking pace
+        assert!(filter.check(&packet(0, 56.33501, -2.79336, 8)));
+        assert!(filter.check(&packet(2, 56.33502, -2.79337, 8)));
+        // ~11km in a second
+        assert!(!filter.check(&packet(3, 56.43502, -2.79337, 8)));
+        // Compared against the last accepted packet, not the spike
+        assert!(filter.check(&packet(4, 56.33503, -2.79338, 9)));
+        // 500m up in a second
+        assert!(!filter.check(&packet(5, 56.33503, -2.79338, 509)));
+        assert!(!filter.check(&packet(6, 0.0, 0.0, 0)));
+        // No position, so nothing to check
+        assert!(filter.check(&Packet::default()));
+
+        assert_eq!(
+            filter.stats,
+            FilterStats {
+                accepted: 4,
+                no_fix: 0,
+                poor_hdop: 0,
+                null_island: 1,
+                too_fast: 1,
+                altitude_spike: 1,
+            }
+        );
+        assert_eq!(filter.stats.rejected(), 3);
+    }
+
+    #[test]
+    fn test_filters_poor_quality_packets() {
+        let mut filter = PacketFilter::new(FilterConfig {
+            max_hdop: Some(500),
+            ..FilterConfig::default()
+        });
+        let with = |fix, hdop| Packet {
+            fix: Some(fix),
+            hdop: Some(hdop),
+            ..packet(0, 56.33501, -2.79336, 8)
+        };
+
+        let mut kept = Vec::new();
+        let mut on_packet = filter.wrap(|packet| kept.push(packet));
+        on_packet(with(Fix::GpsFix, 120));
+        on_packet(with(Fix::No, 120));
+        on_packet(with(Fix::DeadReckoning, 120));
+        on_packet(with(Fix::DGpsFix, 900));
+        on_packet(with(Fix::DGpsFix, 90));
+        drop(on_packet);
+
+        assert_eq!(kept, [with(Fix::GpsFix, 120), with(Fix::DGpsFix, 90)]);
+        assert_eq!(filter.stats.no_fix, 2);
+        assert_eq!(filter.stats.poor_hdop, 1);
+    }
+}
@@ -0,0 +1,45 @@
+//! What the gps says it's running, see [`crate::Gps::firmware_version`].
+
+use alloc::vec::Vec;
+#[cfg(feature = "defmt")]
+use defmt::{write, Format, Formatter};
+
+/// From `PMTK_DT_RELEASE`, such as
+/// `$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    /// Such as `AXN_2.31_3339_13101700`, which includes the chipset and the
+    /// date the firmware was built
+    pub release: Vec<u8>,
+    /// The module maker's build, such as `5632`
+    pub build_id: Vec<u8>,
+    /// Such as `PA6H` or `Quectel-L80`. Older firmware leaves it out.
+    pub model: Option<Vec<u8>>,
+}
+
+impl FirmwareVersion {
+    pub(crate) fn from_fields(mut fields: Vec<Vec<u8>>) -> Self {
+        let model = fields.get_mut(2).map(core::mem::take);
+        fields.truncate(2);
+        let build_id = fields.pop().unwrap_or_default();
+        let release = fields.pop().unwrap_or_default();
+        Self {
+            release,
+            build_id,
+            model,
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl Format for FirmwareVersion {
+    fn format(&self, f: Formatter) {
+        write!(
+            f,
+            "FirmwareVersion {{ release: {=[u8]:a}, build_id: {=[u8]:a}, model: {=[u8]:a} }}",
+            self.release,
+            self.build_id,
+            self.model.as_deref().unwrap_or(b"?")
+        )
+    }
+}
@@ -0,0 +1,181 @@
+//! Telling whether the gps has a fix from its FIX pin, without talking to it.
+//!
+//! While the gps is searching the pin blinks about once a second. Once it has
+//! a fix the pin stays low, pulsing high once every 15 seconds. A pin that
+//! stops pulsing altogether means the gps is off or wedged, not fixed.
+//!
+//! Feed [`FixPin`] the pin's rising edges from its interrupt, and it says
+//! when the fix is gained or lost, so this keeps working while the gps is
+//! busy with a command and its NMEA isn't being read.
+
+/// Longer than the gap between blinks while searching, with some slack.
+const SEARCHING_MAX_GAP_US: u64 = 2_500_000;
+/// Longer than the gap between pulses with a fix, with some slack.
+const FIX_MAX_GAP_US: u64 = 16_000_000;
+/// Far shorter than the pin's pulses are apart, but long enough to swallow
+/// ringing on a long wire
+const DEFAULT_DEBOUNCE_US: u64 = 50_000;
+
+/// Tracks the rising edges of the FIX pin. Timestamps are in microseconds
+/// from any fixed point, such as boot.
+#[derive(Debug, Clone)]
+pub struct FixPin {
+    last_rise_us: Option<u64>,
+    prev_rise_us: Option<u64>,
+    debounce_us: u64,
+    /// What [`Self::take_change`] last said
+    reported_fix: bool,
+}
+
+impl Default for FixPin {
+    fn default() -> Self {
+        Self::with_debounce_us(DEFAULT_DEBOUNCE_US)
+    }
+}
+
+impl FixPin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore rising edges within `debounce_us` of the last one.
+    pub fn with_debounce_us(debounce_us: u64) -> Self {
+        Self {
+            last_rise_us: None,
+            prev_rise_us: None,
+            debounce_us,
+            reported_fix: false,
+        }
+    }
+
+    /// Call from the pin's interrupt. Returns whether we now have a fix if
+    /// that's changed, as for [`Self::take_change`].
+    pub fn on_rising_edge(&mut self, now_us: u64) -> Option<bool> {
+        let bounce = self
+            .last_rise_us
+            .is_some_and(|last| now_us.saturating_sub(last) < self.debounce_us);
+        if !bounce {
+            self.prev_rise_us = self.last_rise_us;
+            self.last_rise_us = Some(now_us);
+        }
+        self.take_change(now_us)
+    }
+
+    /// Whether we now have a fix, if that's changed since we last said.
+    /// Gaining or losing a fix can be the pin going quiet, so call this again
+    /// at [`Self::check_again_at_us`] as well as on each edge.
+    pub fn take_change(&mut self, now_us: u64) -> Option<bool> {
+        let has_fix = self.has_fix(now_us);
+        if has_fix == self.reported_fix {
+            return None;
+        }
+        self.reported_fix = has_fix;
+        Some(has_fix)
+    }
+
+    /// Whether the pin's been quiet for longer than it ever is while
+    /// searching, either now or before its last pulse, but has still pulsed
+    /// as it does with a fix.
+    ///
+    /// False until the first rising edge, so a disconnected pin doesn't look
+    /// like a fix.
+    pub fn has_fix(&self, now_us: u64) -> bool {
+        let last = match self.last_rise_us {
+            Some(last) => last,
+            None => return false,
+        };
+        let since_last = now_us.saturating_sub(last);
+        if since_last > FIX_MAX_GAP_US {
+            return false;
+        }
+        let quiet_since_last = since_last > SEARCHING_MAX_GAP_US;
+        // A pulse after the pin went dead doesn't say anything yet
+        let quiet_before_last = self.prev_rise_us.is_some_and(|prev| {
+            (SEARCHING_MAX_GAP_US + 1..=FIX_MAX_GAP_US).contains(&(last - prev))
+        });
+        quiet_since_last || quiet_before_last
+    }
+
+    /// When after `now_us` to next check [`Self::has_fix`], as it can change
+    /// without an edge to wake us. `None` once only an edge can change it.
+    pub fn check_again_at_us(&self, now_us: u64) -> Option<u64> {
+        let last = self.last_rise_us?;
+        [SEARCHING_MAX_GAP_US, FIX_MAX_GAP_US]
+            .into_iter()
+            .map(|gap| last + gap + 1)
+            .find(|&at| at > now_us)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000_000;
+
+    #[test]
+    fn test_fix_pin() {
+        let mut pin = FixPin::new();
+        assert!(!pin.has_fix(10 * SEC));
+
+        // Searching
+        for i in 0..5 {
+            pin.on_rising_edge(i * SEC);
+            assert!(!pin.has_fix(i * SEC + SEC / 2));
+        }
+
+        // Got a fix after the last blink
+        let check_at = pin.check_again_at_us(4 * SEC).unwrap();
+        assert!(!pin.has_fix(check_at - 1));
+        assert!(pin.has_fix(check_at));
+
+        // The pulse every 15 seconds doesn't lose the fix
+        pin.on_rising_edge(20 * SEC);
+        assert!(pin.has_fix(20 * SEC));
+
+        // Blinking again means we've lost it
+        pin.on_rising_edge(21 * SEC);
+        assert!(!pin.has_fix(21 * SEC));
+    }
+
+    #[test]
+    fn test_reports_changes() {
+        let mut pin = FixPin::new();
+        assert_eq!(pin.on_rising_edge(0), None);
+        assert_eq!(pin.on_rising_edge(SEC), None);
+
+        let check_at = pin.check_again_at_us(SEC).unwrap();
+        assert_eq!(pin.take_change(check_at), Some(true));
+        assert_eq!(pin.take_change(check_at + SEC), None);
+        assert_eq!(pin.on_rising_edge(15 * SEC), None);
+
+        // Ringing after the edge isn't blinking
+        assert_eq!(pin.on_rising_edge(15 * SEC + 1_000), None);
+        assert!(pin.has_fix(15 * SEC + 1_000));
+
+        assert_eq!(pin.on_rising_edge(16 * SEC), Some(false));
+    }
+    #[test]
+    fn test_quiet_pin_loses_fix() {
+        let mut pin = FixPin::new();
+        pin.on_rising_edge(0);
+        pin.on_rising_edge(SEC);
+
+        let gain_at = pin.check_again_at_us(SEC).unwrap();
+        assert_eq!(pin.take_change(gain_at), Some(true));
+
+        // The gps was turned off, so the pulses every 15 seconds stop
+        let lose_at = pin.check_again_at_us(gain_at).unwrap();
+        assert!(pin.has_fix(lose_at - 1));
+        assert_eq!(pin.take_change(lose_at), Some(false));
+        assert!(!pin.has_fix(lose_at + 60 * SEC));
+        assert_eq!(pin.check_again_at_us(lose_at), None);
+
+        // Pulsing again after that is searching, until it goes quiet again
+        assert_eq!(pin.on_rising_edge(100 * SEC), None);
+        assert!(!pin.has_fix(100 * SEC));
+        assert_eq!(pin.on_rising_edge(101 * SEC), None);
+        let gain_at = pin.check_again_at_us(101 * SEC).unwrap();
+        assert_eq!(pin.take_change(gain_at), Some(true));
+    }
+}
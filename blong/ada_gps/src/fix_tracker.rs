@@ -0,0 +1,266 @@
+//! Whether we have a fix right now, and where, from the NMEA the gps sends.
+//!
+//! Feed [`FixTracker`] every [`Sentence`] as it arrives, such as from an
+//! [`NmeaStream`](crate::NmeaStream):
+//!
+//! ```ignore
+//! for line in &mut stream {
+//!     if let Ok(Some(sentence)) = Sentence::parse(&line) {
+//!         tracker.on_sentence(&sentence, now_us());
+//!     }
+//! }
+//! if tracker.has_fix(now_us()) {
+//!     start_logging();
+//! }
+//! ```
+
+use crate::{
+    nmea::{FixMode, Sentence, TimeOfDay},
+    Fix, UtcDateTime,
+};
+
+/// The most recent of everything the gps has said about its fix. Fields
+/// other than the position keep their last value until a sentence updates
+/// them, so may be from an earlier report.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedFix {
+    /// When the gps took the fix
+    pub time: TimeOfDay,
+    /// With the date, once an RMC or ZDA has given it
+    pub date_time: Option<UtcDateTime>,
+    /// When the position arrived, as passed to [`FixTracker::on_sentence`]
+    pub received_us: u64,
+    /// In decimal degrees, positive is north
+    pub lat: f64,
+    /// In decimal degrees, positive is east
+    pub lon: f64,
+    /// From GGA. `None` until one arrives.
+    pub fix: Option<Fix>,
+    /// Altitude above mean sea level in meters
+    pub height_msl: Option<f32>,
+    pub num_sat: Option<u8>,
+    pub speed_knots: Option<f32>,
+    /// In degrees clockwise from true north
+    pub course: Option<f32>,
+    pub hdop: Option<f32>,
+    pub vdop: Option<f32>,
+    pub pdop: Option<f32>,
+}
+
+/// Keeps track of the current fix, and notices when it's lost, either by
+/// the gps saying so or by it going quiet. Timestamps are in microseconds
+/// from any fixed point, such as boot.
+#[derive(Debug, Clone)]
+pub struct FixTracker {
+    stale_after_us: u64,
+    fix: Option<TrackedFix>,
+    /// The gps has said it has no fix since the last position
+    lost: bool,
+}
+
+impl FixTracker {
+    /// A fix counts as lost once no position has arrived for
+    /// `stale_after_us`. The gps sends one a second by default, so a few
+    /// seconds is plenty.
+    pub fn new(stale_after_us: u64) -> Self {
+        Self {
+            stale_after_us,
+            fix: None,
+            lost: false,
+        }
+    }
+
+    pub fn on_sentence(&mut self, sentence: &Sentence, now_us: u64) {
+        match sentence {
+            Sentence::Gga(gga) => {
+                if gga.fix == Some(Fix::No) {
+                    self.lost = true;
+                    return;
+                }
+                if let Some(fix) = self.update_position(gga.time, gga.lat, gga.lon, now_us) {
+                    fix.fix = gga.fix.clone().or(fix.fix.take());
+                    fix.height_msl = gga.height_msl.or(fix.height_msl);
+                    fix.num_sat = gga.num_sat.or(fix.num_sat);
+                    fix.hdop = gga.hdop.or(fix.hdop);
+                }
+            }
+            Sentence::Rmc(rmc) => {
+                if !rmc.active {
+                    self.lost = true;
+                    return;
+                }
+                if let Some(fix) = self.update_position(rmc.time, rmc.lat, rmc.lon, now_us) {
+                    fix.date_time = rmc.date_time.or(fix.date_time);
+                    fix.speed_knots = rmc.speed_knots.or(fix.speed_knots);
+                    fix.course = rmc.course.or(fix.course);
+                }
+            }
+            Sentence::Gll(gll) => {
+                if !gll.active {
+                    self.lost = true;
+                    return;
+                }
+                self.update_position(gll.time, gll.lat, gll.lon, now_us);
+            }
+            Sentence::Gsa(gsa) => {
+                if gsa.mode == Some(FixMode::No) {
+                    self.lost = true;
+                    return;
+                }
+                if let Some(fix) = &mut self.fix {
+                    fix.pdop = gsa.pdop.or(fix.pdop);
+                    fix.hdop = gsa.hdop.or(fix.hdop);
+                    fix.vdop = gsa.vdop.or(fix.vdop);
+                }
+            }
+            Sentence::Vtg(vtg) => {
+                if let Some(fix) = &mut self.fix {
+                    fix.speed_knots = vtg.speed_knots.or(fix.speed_knots);
+                    fix.course = vtg.course_true.or(fix.course);
+                }
+            }
+            Sentence::Zda(zda) => {
+                if let Some(fix) = self.fix.as_mut().filter(|fix| fix.time == zda.time) {
+                    fix.date_time = zda.date_time.or(fix.date_time);
+                }
+            }
+            Sentence::Gsv(_) => {}
+        }
+    }
+
+    fn update_position(
+        &mut self,
+        time: TimeOfDay,
+        lat: Option<f64>,
+        lon: Option<f64>,
+        now_us: u64,
+    ) -> Option<&mut TrackedFix> {
+        let (lat, lon) = (lat?, lon?);
+        self.lost = false;
+
+        let fix = self.fix.get_or_insert(TrackedFix {
+            time,
+            date_time: None,
+            received_us: now_us,
+            lat,
+            lon,
+            fix: None,
+            height_msl: None,
+            num_sat: None,
+            speed_knots: None,
+            course: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+        });
+        if fix.time != time {
+            // The date may have changed too, so wait to hear it again
+            fix.date_time = None;
+        }
+        fix.time = time;
+        fix.received_us = now_us;
+        fix.lat = lat;
+        fix.lon = lon;
+        Some(fix)
+    }
+
+    /// Whether the gps has a fix, as of its last report, and that report is
+    /// recent.
+    pub fn has_fix(&self, now_us: u64) -> bool {
+        self.current(now_us).is_some()
+    }
+
+    /// The fix, if we have one. See [`Self::has_fix`].
+    pub fn current(&self, now_us: u64) -> Option<&TrackedFix> {
+        if self.lost || self.is_stale(now_us) {
+            None
+        } else {
+            self.fix.as_ref()
+        }
+    }
+
+    /// The most recent fix, however old, such as to show where we last were.
+    pub fn last(&self) -> Option<&TrackedFix> {
+        self.fix.as_ref()
+    }
+
+    /// How long since the last position arrived. `None` if none has yet.
+    pub fn age_us(&self, now_us: u64) -> Option<u64> {
+        Some(now_us.saturating_sub(self.fix.as_ref()?.received_us))
+    }
+
+    /// No position has arrived for longer than the limit given to
+    /// [`Self::new`], including if none ever has.
+    pub fn is_stale(&self, now_us: u64) -> bool {
+        self.age_us(now_us)
+            .is_none_or(|age| age > self.stale_after_us)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000_000;
+
+    fn sentence(line: &[u8]) -> Sentence {
+        Sentence::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_tracks_fix() {
+        let mut tracker = FixTracker::new(3 * SEC);
+        assert!(!tracker.has_fix(0));
+        assert!(tracker.is_stale(0));
+
+        tracker.on_sentence(
+            &sentence(
+                b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n",
+            ),
+            SEC,
+        );
+        tracker.on_sentence(
+            &sentence(b"$GPGSA,A,3,29,21,26,15,18,09,06,10,,,,,2.32,0.95,2.11*00\r\n"),
+            SEC,
+        );
+        tracker.on_sentence(
+            &sentence(
+                b"$GPRMC,064951.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2C\r\n",
+            ),
+            SEC,
+        );
+
+        let fix = tracker.current(2 * SEC).unwrap();
+        assert_eq!(fix.fix, Some(Fix::GpsFix));
+        assert_eq!(fix.date_time, UtcDateTime::new(2006, 4, 26, 6, 49, 51, 0));
+        assert_eq!(fix.pdop, Some(2.32));
+        assert_eq!(fix.course, Some(165.48));
+        assert_eq!(tracker.age_us(2 * SEC), Some(SEC));
+
+        // The gps goes quiet
+        assert!(tracker.has_fix(4 * SEC));
+        assert!(!tracker.has_fix(4 * SEC + 1));
+        assert!(tracker.last().is_some());
+    }
+
+    #[test]
+    fn test_gps_reports_losing_fix() {
+        let mut tracker = FixTracker::new(3 * SEC);
+        tracker.on_sentence(
+            &sentence(b"$GPGLL,2307.1256,N,12016.4438,E,064951.000,A,A*5F\r\n"),
+            0,
+        );
+        assert!(tracker.has_fix(0));
+
+        tracker.on_sentence(&sentence(b"$GPGGA,235948.000,,,,,0,0,,,M,,M,,*49\r\n"), 0);
+        assert!(!tracker.has_fix(0));
+        assert_eq!(tracker.last().map(|fix| fix.lat > 23.0), Some(true));
+
+        tracker.on_sentence(
+            &sentence(b"$GPGLL,2307.1256,N,12016.4438,E,064951.000,A,A*5F\r\n"),
+            SEC,
+        );
+        assert!(tracker.has_fix(SEC));
+    }
+}
@@ -0,0 +1,249 @@
+//! Distances and bearings between points, treating the earth as a sphere.
+//!
+//! Good to about 0.5%, which is well under what the gps can tell apart over
+//! the distances we care about. Positions are in decimal degrees and bearings
+//! in degrees clockwise from north, like [`crate::Packet`].
+//!
+//! The same functions are in [`self::f32`] and [`self::f64`]. Use the `f32`
+//! ones on device, where there's no FPU and `f64` is much slower. For
+//! working with [`crate::Packet`]s, see [`self::track`].
+//!
+//! See <https://www.movable-type.co.uk/scripts/latlong.html> for the formulae.
+
+macro_rules! geodesy {
+    ($float:ident) => {
+        pub mod $float {
+            use libm::Libm;
+
+            type M = Libm<$float>;
+
+            /// Mean radius of the earth in meters
+            pub const EARTH_RADIUS_M: $float = 6_371_000.0;
+
+            /// Great-circle distance in meters, using the haversine formula.
+            pub fn distance_m(lat1: $float, lon1: $float, lat2: $float, lon2: $float) -> $float {
+                let lat1 = lat1.to_radians();
+                let lat2 = lat2.to_radians();
+                let d_lat = lat2 - lat1;
+                let d_lon = (lon2 - lon1).to_radians();
+
+                let sin_d_lat = M::sin(d_lat / 2.0);
+                let sin_d_lon = M::sin(d_lon / 2.0);
+                let a = sin_d_lat * sin_d_lat + M::cos(lat1) * M::cos(lat2) * sin_d_lon * sin_d_lon;
+                2.0 * EARTH_RADIUS_M * M::asin(M::sqrt(a.min(1.0)))
+            }
+
+            /// Bearing to set off on from the first point to follow the great
+            /// circle to the second, in the range 0 to 360.
+            pub fn initial_bearing(
+                lat1: $float,
+                lon1: $float,
+                lat2: $float,
+                lon2: $float,
+            ) -> $float {
+                let lat1 = lat1.to_radians();
+                let lat2 = lat2.to_radians();
+                let d_lon = (lon2 - lon1).to_radians();
+
+                let y = M::sin(d_lon) * M::cos(lat2);
+                let x = M::cos(lat1) * M::sin(lat2) - M::sin(lat1) * M::cos(lat2) * M::cos(d_lon);
+                normalize_bearing(M::atan2(y, x).to_degrees())
+            }
+
+            /// The point reached by travelling `distance_m` along the great
+            /// circle starting at `bearing`. Returns `(lat, lon)`, with lon in
+            /// the range -180 to 180.
+            pub fn destination(
+                lat: $float,
+                lon: $float,
+                bearing: $float,
+                distance_m: $float,
+            ) -> ($float, $float) {
+                let lat = lat.to_radians();
+                let lon = lon.to_radians();
+                let bearing = bearing.to_radians();
+                let angle = distance_m / EARTH_RADIUS_M;
+
+                let dest_lat = M::asin(
+                    M::sin(lat) * M::cos(angle) + M::cos(lat) * M::sin(angle) * M::cos(bearing),
+                );
+                let dest_lon = lon
+                    + M::atan2(
+                        M::sin(bearing) * M::sin(angle) * M::cos(lat),
+                        M::cos(angle) - M::sin(lat) * M::sin(dest_lat),
+                    );
+
+                let dest_lon = normalize_bearing(dest_lon.to_degrees() + 180.0) - 180.0;
+                (dest_lat.to_degrees(), dest_lon)
+            }
+
+            fn normalize_bearing(degrees: $float) -> $float {
+                let degrees = degrees % 360.0;
+                if degrees < 0.0 {
+                    degrees + 360.0
+                } else {
+                    degrees
+                }
+            }
+        }
+    };
+}
+
+geodesy!(f32);
+geodesy!(f64);
+
+/// The `f32` functions over [`Packet`](crate::Packet)s, skipping any without
+/// a position.
+#[cfg(any(feature = "locus", feature = "nmea"))]
+pub mod track {
+    use crate::Packet;
+
+    fn position(packet: &Packet) -> Option<(f32, f32)> {
+        Some((packet.lat?, packet.lon?))
+    }
+
+    /// `None` if either has no position.
+    pub fn distance_m(from: &Packet, to: &Packet) -> Option<f32> {
+        let ((lat1, lon1), (lat2, lon2)) = (position(from)?, position(to)?);
+        Some(super::f32::distance_m(lat1, lon1, lat2, lon2))
+    }
+
+    /// `None` if either has no position.
+    pub fn initial_bearing(from: &Packet, to: &Packet) -> Option<f32> {
+        let ((lat1, lon1), (lat2, lon2)) = (position(from)?, position(to)?);
+        Some(super::f32::initial_bearing(lat1, lon1, lat2, lon2))
+    }
+
+    /// Total distance in meters along the packets in order.
+    pub fn length_m<'a>(packets: impl IntoIterator<Item = &'a Packet>) -> f32 {
+        let mut odometer = Odometer::default();
+        for packet in packets {
+            odometer.push(packet);
+        }
+        odometer.total_m()
+    }
+
+    /// Adds up the distance along a track a packet at a time, such as for
+    /// trip statistics or logging every so many meters.
+    #[derive(Debug, Clone, Default)]
+    pub struct Odometer {
+        last: Option<(f32, f32)>,
+        total_m: f32,
+    }
+
+    impl Odometer {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Returns how far it is from the previous packet with a position.
+        /// `None` if this packet has no position or is the first that does.
+        pub fn push(&mut self, packet: &Packet) -> Option<f32> {
+            let (lat, lon) = position(packet)?;
+            let step = self
+                .last
+                .map(|(last_lat, last_lon)| super::f32::distance_m(last_lat, last_lon, lat, lon));
+            self.last = Some((lat, lon));
+            self.total_m += step.unwrap_or(0.0);
+            step
+        }
+
+        pub fn total_m(&self) -> f32 {
+            self.total_m
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    // Land's End to John o' Groats
+    fn lands_end() -> (f64, f64) {
+        (dms(50.0, 3.0, 59.0), -dms(5.0, 42.0, 53.0))
+    }
+    fn john_o_groats() -> (f64, f64) {
+        (dms(58.0, 38.0, 38.0), -dms(3.0, 4.0, 12.0))
+    }
+
+    #[test]
+    fn test_distance() {
+        let ((lat1, lon1), (lat2, lon2)) = (lands_end(), john_o_groats());
+
+        let distance = super::f64::distance_m(lat1, lon1, lat2, lon2);
+        assert!((distance - 968_900.0).abs() < 100.0, "{}", distance);
+
+        let distance = super::f32::distance_m(lat1 as f32, lon1 as f32, lat2 as f32, lon2 as f32);
+        assert!((distance - 968_900.0).abs() < 200.0, "{}", distance);
+
+        assert_eq!(super::f64::distance_m(lat1, lon1, lat1, lon1), 0.0);
+    }
+
+    #[test]
+    fn test_initial_bearing() {
+        let ((lat1, lon1), (lat2, lon2)) = (lands_end(), john_o_groats());
+        let expected = dms(9.0, 7.0, 11.0);
+
+        let bearing = super::f64::initial_bearing(lat1, lon1, lat2, lon2);
+        assert!((bearing - expected).abs() < 0.001, "{}", bearing);
+
+        let bearing =
+            super::f32::initial_bearing(lat1 as f32, lon1 as f32, lat2 as f32, lon2 as f32);
+        assert!((bearing - expected as f32).abs() < 0.01, "{}", bearing);
+
+        // Due west comes out positive
+        let bearing = super::f64::initial_bearing(0.0, 0.0, 0.0, -1.0);
+        assert!((bearing - 270.0).abs() < 0.001, "{}", bearing);
+    }
+
+    #[test]
+    fn test_destination() {
+        let (lat, lon) = (dms(53.0, 19.0, 14.0), -dms(1.0, 43.0, 47.0));
+        let bearing = dms(96.0, 1.0, 18.0);
+        let expected = (dms(53.0, 11.0, 18.0), dms(0.0, 8.0, 0.0));
+
+        let dest = super::f64::destination(lat, lon, bearing, 124_800.0);
+        assert!((dest.0 - expected.0).abs() < 0.001, "{:?}", dest);
+        assert!((dest.1 - expected.1).abs() < 0.001, "{:?}", dest);
+
+        let dest = super::f32::destination(lat as f32, lon as f32, bearing as f32, 124_800.0);
+        assert!((dest.0 - expected.0 as f32).abs() < 0.001, "{:?}", dest);
+        assert!((dest.1 - expected.1 as f32).abs() < 0.001, "{:?}", dest);
+
+        // Wraps across the antimeridian
+        let dest = super::f64::destination(0.0, 179.5, 90.0, 111_195.0);
+        assert!((dest.1 - -179.5).abs() < 0.001, "{:?}", dest);
+    }
+
+    #[cfg(any(feature = "locus", feature = "nmea"))]
+    #[test]
+    fn test_track() {
+        use super::track::{self, Odometer};
+        use crate::Packet;
+
+        let at = |lat, lon| Packet {
+            lat: Some(lat),
+            lon: Some(lon),
+            ..Packet::default()
+        };
+        // A degree of longitude along the equator, and back half way
+        let packets = [at(0.0, 0.0), Packet::default(), at(0.0, 1.0), at(0.0, 0.5)];
+
+        let degree = track::distance_m(&packets[0], &packets[2]).unwrap();
+        assert!((degree - 111_195.0).abs() < 10.0, "{}", degree);
+        assert_eq!(track::distance_m(&packets[0], &packets[1]), None);
+        let bearing = track::initial_bearing(&packets[2], &packets[3]).unwrap();
+        assert!((bearing - 270.0).abs() < 0.01, "{}", bearing);
+
+        let length = track::length_m(&packets);
+        assert!((length - degree * 1.5).abs() < 10.0, "{}", length);
+
+        let mut odometer = Odometer::new();
+        assert_eq!(odometer.push(&packets[0]), None);
+        assert_eq!(odometer.push(&packets[1]), None);
+        assert_eq!(odometer.push(&packets[2]), Some(degree));
+        assert_eq!(odometer.total_m(), degree);
+    }
+}
@@ -0,0 +1,131 @@
+//! Writing packets out as GeoJSON (RFC 7946), for web maps that take it
+//! directly.
+//!
+//! The track is a `FeatureCollection` with a `LineString` feature for each
+//! segment. Like the GPX writer, it's streamed through
+//! [`core::fmt::Write`].
+
+use core::fmt::{self, Write};
+
+use crate::{coords, export::TrackWriter, Packet};
+
+const FEATURE_START: &str =
+    r#"{"type":"Feature","properties":{},"geometry":{"type":"LineString","coordinates":["#;
+const FEATURE_END: &str = "]}}";
+
+#[derive(Debug)]
+pub struct GeoJsonWriter<W> {
+    out: W,
+    /// Whether the current segment has any points yet, for the commas
+    segment_has_points: bool,
+}
+
+impl<W> GeoJsonWriter<W>
+where
+    W: Write,
+{
+    /// Opens the collection and the first segment.
+    pub fn new(mut out: W) -> Result<Self, fmt::Error> {
+        out.write_str(r#"{"type":"FeatureCollection","features":["#)?;
+        out.write_str(FEATURE_START)?;
+        Ok(Self {
+            out,
+            segment_has_points: false,
+        })
+    }
+
+    /// Packets without a position are skipped. Positions have an altitude if
+    /// known, above the ellipsoid if the geoid separation is known, otherwise
+    /// above mean sea level as LOCUS logs it.
+    pub fn point(&mut self, packet: &Packet) -> fmt::Result {
+        let (lat, lon) = match (packet.lat, packet.lon) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Ok(()),
+        };
+
+        if self.segment_has_points {
+            self.out.write_char(',')?;
+        }
+        self.segment_has_points = true;
+
+        // GeoJSON puts longitude first
+        self.out.write_char('[')?;
+        coords::write_decimal(&mut self.out, lon)?;
+        self.out.write_char(',')?;
+        coords::write_decimal(&mut self.out, lat)?;
+        if let Some(ele) = packet.height_ellipsoid().or(packet.height_msl) {
+            write!(self.out, ",{}", ele)?;
+        }
+        self.out.write_char(']')
+    }
+
+    /// End the current segment and start another, for a gap in the track.
+    pub fn new_segment(&mut self) -> fmt::Result {
+        self.out.write_str(FEATURE_END)?;
+        self.out.write_char(',')?;
+        self.out.write_str(FEATURE_START)?;
+        self.segment_has_points = false;
+        Ok(())
+    }
+
+    /// Closes the collection and hands back the output.
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        self.out.write_str(FEATURE_END)?;
+        self.out.write_str("]}\n")?;
+        Ok(self.out)
+    }
+}
+
+impl<W> TrackWriter<W> for GeoJsonWriter<W>
+where
+    W: Write,
+{
+    fn point(&mut self, packet: &Packet) -> fmt::Result {
+        GeoJsonWriter::point(self, packet)
+    }
+
+    fn new_segment(&mut self) -> fmt::Result {
+        GeoJsonWriter::new_segment(self)
+    }
+
+    fn finish(self) -> Result<W, fmt::Error> {
+        GeoJsonWriter::finish(self)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn point(lat: f32, lon: f32, height_msl: Option<i16>) -> Packet {
+        Packet {
+            lat: Some(lat),
+            lon: Some(lon),
+            height_msl,
+            ..Packet::default()
+        }
+    }
+
+    #[test]
+    fn test_writes_collection() {
+        let mut json = GeoJsonWriter::new(String::new()).unwrap();
+        json.point(&point(56.33501, -2.79336, Some(8))).unwrap();
+        json.point(&Packet::default()).unwrap();
+        json.point(&point(56.33502, -2.79337, None)).unwrap();
+        json.new_segment().unwrap();
+        json.point(&point(56.33601, -2.79436, Some(9))).unwrap();
+
+        assert_eq!(
+            json.finish().unwrap(),
+            concat!(
+                r#"{"type":"FeatureCollection","features":["#,
+                r#"{"type":"Feature","properties":{},"geometry":{"type":"LineString","coordinates":["#,
+                r#"[-2.79336,56.33501,8],[-2.79337,56.33502]]}},"#,
+                r#"{"type":"Feature","properties":{},"geometry":{"type":"LineString","coordinates":["#,
+                r#"[-2.79436,56.33601,9]]}}]}"#,
+                "\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,3500 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::RangeInclusive;
+#[cfg(feature = "locus")]
+use core::{cell::Cell, ops::ControlFlow};
+use embedded_hal::{blocking::delay::DelayUs, serial};
+use lexical_core::FormattedSize;
+
+#[cfg(feature = "locus")]
+use crate::logger;
+#[cfg(feature = "quectel")]
+use crate::quectel::CommandSet;
+use crate::{
+    aiding,
+    clock::{Clock, CountedDelays},
+    cmd, debug, error,
+    exchange::{
+        check_pmtk_ack, is_nmea, pmtk_name, Action, Exchange, DELAY_BEFORE_RETRY_US, MAX_CMD_TRIES,
+    },
+    info,
+    split::{Commander, NmeaStream},
+    trace, warn, ConfigItem, Datum, DgpsMode, EasyStatus, EpoProgress, EpoStatus, ExtendedFix,
+    FirmwareVersion, NmeaOutputConfig, ParseError, PowerMode, PowerSwitch, RawBytes, RxConsumer,
+    RxProducer, SearchMode, Stats, TrafficObserver, UtcDateTime,
+};
+
+// NOTE: See PMTK_A11-datasheet.pdf
+
+// TODO: Avoid allocating
+
+// TODO: Figure out what to divide ticks by to have it be consistent across clock? speeds
+pub(crate) const MAX_CMD_TRIES_WITHOUT_NMEA_DISABLED: usize = 20;
+pub(crate) const MAX_READ_CMD_US: u32 = 500_000;
+pub(crate) const MAX_WRITE_CMD_US: u32 = 50_000;
+/// What PMTK220 accepts
+pub(crate) const UPDATE_RATE_MS: RangeInclusive<u32> = 100..=10_000;
+/// Give the gps time to switch after changing the baud rate
+const DELAY_AFTER_BAUD_CHANGE_US: u32 = 100_000;
+/// And after switching between NMEA and binary packets
+const DELAY_AFTER_MODE_CHANGE_US: u32 = 100_000;
+/// Longer than any binary packet the gps sends us
+const MAX_BIN_PACKET_LEN: usize = 64;
+const SUPPORTED_BAUD_RATES: [u32; 7] = [4800, 9600, 14400, 19200, 38400, 57600, 115200];
+const MAX_READ_ERRORS_ON_BOOT: usize = 50;
+/// Maximum number of undocumented packets before we get the documented boot
+/// indicator packets.
+const MAX_READ_SPURIOUS_BEFORE_BOOT: usize = 1_000;
+// This helps us avoid some spurious messages
+pub(crate) const WAIT_BEFORE_CHECKING_BOOT_READY_US: u32 = 50_000;
+/// Maximum number of undocumented packets after we get the documented boot
+/// indicator packets.
+pub(crate) const MAX_READ_SPURIOUS_AFTER_BOOT_READY: usize = 20;
+/// Long enough off for the module's capacitors to drain, so it really resets
+const POWER_OFF_US: u32 = 200_000;
+// max 24 chunks, in basic mode one point is 2 chunks
+#[cfg(feature = "locus")]
+pub(crate) const MAX_POINTS_PER_LOCUS_DATA_PACKET: usize = 12;
+/// The gps acks an erase before it's done, so we check on it every so often
+#[cfg(feature = "locus")]
+const DELAY_BETWEEN_ERASE_CHECKS_US: u32 = 250_000;
+/// Erasing the whole flash takes a few seconds
+#[cfg(feature = "locus")]
+const MAX_ERASE_CHECKS: usize = 40;
+
+/// Most commands [`Gps::apply`] sends before waiting for the acks. The gps
+/// only has so much room for what we send it.
+const MAX_PIPELINED: usize = 4;
+
+/// Ok or Err along with the number of tries taken
+type WithTries<T, E> = Result<(usize, T), (usize, E)>;
+
+// Send so the driver can be handed to another task, such as from RTIC's init
+// to idle
+type UnsolicitedHandler<'rx> = Box<dyn FnMut(&[u8]) + Send + 'rx>;
+
+type BoxedObserver<'rx> = Box<dyn TrafficObserver + Send + 'rx>;
+
+/// Sets up a [`Gps`], see [`Gps::builder`].
+///
+/// Anything not set is left as it is for [`Gps::new`].
+pub struct GpsBuilder<'rx, Tx, Delay, C = CountedDelays> {
+    rx: RxConsumer<'rx>,
+    tx: Tx,
+    delay: Delay,
+    clock: C,
+    already_disabled_nmea_output: bool,
+    max_cmd_retries: usize,
+    read_timeout_us: u32,
+    write_timeout_us: u32,
+    nmea_out: Option<RxProducer<'rx>>,
+    on_unsolicited: Option<UnsolicitedHandler<'rx>>,
+    observer: Option<BoxedObserver<'rx>>,
+}
+
+impl<'rx, Tx, Delay, C> GpsBuilder<'rx, Tx, Delay, C> {
+    /// Skip disabling NMEA output before the first command, as it's known to
+    /// be off already, such as after waking from standby.
+    pub fn already_disabled_nmea_output(mut self, already: bool) -> Self {
+        self.already_disabled_nmea_output = already;
+        self
+    }
+
+    /// Time out by `clock` rather than by adding up delays.
+    pub fn clock<C2: Clock>(self, clock: C2) -> GpsBuilder<'rx, Tx, Delay, C2> {
+        GpsBuilder {
+            rx: self.rx,
+            tx: self.tx,
+            delay: self.delay,
+            clock,
+            already_disabled_nmea_output: self.already_disabled_nmea_output,
+            max_cmd_retries: self.max_cmd_retries,
+            read_timeout_us: self.read_timeout_us,
+            write_timeout_us: self.write_timeout_us,
+            nmea_out: self.nmea_out,
+            on_unsolicited: self.on_unsolicited,
+            observer: self.observer,
+        }
+    }
+
+    /// How many times to retry most commands after the first try, so each is
+    /// sent at most `retries + 1` times. Commands sent while NMEA output may
+    /// still be on get more.
+    pub fn max_cmd_retries(mut self, retries: usize) -> Self {
+        self.max_cmd_retries = retries;
+        self
+    }
+
+    /// How long to wait for each line or packet from the gps.
+    pub fn read_timeout_us(mut self, us: u32) -> Self {
+        self.read_timeout_us = us;
+        self
+    }
+
+    /// How long to wait for room to write each command.
+    pub fn write_timeout_us(mut self, us: u32) -> Self {
+        self.write_timeout_us = us;
+        self
+    }
+
+    /// See [`Gps::forward_nmea_to`].
+    pub fn forward_nmea_to(mut self, nmea_out: RxProducer<'rx>) -> Self {
+        self.nmea_out = Some(nmea_out);
+        self
+    }
+
+    /// See [`Gps::on_unsolicited`].
+    pub fn on_unsolicited(mut self, handler: impl FnMut(&[u8]) + Send + 'rx) -> Self {
+        self.on_unsolicited = Some(Box::new(handler));
+        self
+    }
+
+    /// See [`Gps::observe_traffic`].
+    pub fn observe_traffic(mut self, observer: impl TrafficObserver + Send + 'rx) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    pub fn build(self) -> Gps<'rx, Tx, Delay, C> {
+        Gps {
+            disabled_nmea_output: self.already_disabled_nmea_output,
+            static_nav_needs_pmtk397: false,
+            standby: false,
+            backup: false,
+            rx: self.rx,
+            nmea_out: self.nmea_out,
+            on_unsolicited: self.on_unsolicited,
+            observer: self.observer,
+            stats: Stats::default(),
+            firmware: None,
+            #[cfg(feature = "locus")]
+            logger_recording_mode: logger::RecordingMode::Overlap,
+            #[cfg(feature = "locus")]
+            interrupted_dump: false,
+            deadline: None,
+            max_cmd_retries: self.max_cmd_retries,
+            read_timeout_us: self.read_timeout_us,
+            write_timeout_us: self.write_timeout_us,
+            tx: self.tx,
+            delay: self.delay,
+            clock: self.clock,
+        }
+    }
+}
+
+/// A PMTK command number and its fields
+type SingleCmd = ([u8; 3], Vec<Vec<u8>>);
+
+pub struct Gps<'rx, Tx, Delay, C = CountedDelays> {
+    disabled_nmea_output: bool,
+    /// Older firmware only has PMTK397 for the static navigation threshold
+    static_nav_needs_pmtk397: bool,
+    /// Set while the gps is asleep until sent a byte
+    standby: bool,
+    /// Set while the gps can't hear us until its power is cycled
+    backup: bool,
+    rx: RxConsumer<'rx>,
+    nmea_out: Option<RxProducer<'rx>>,
+    /// Given sentences that arrive while waiting for a reply that aren't it
+    on_unsolicited: Option<UnsolicitedHandler<'rx>>,
+    observer: Option<BoxedObserver<'rx>>,
+    stats: Stats,
+    /// What the gps reported the last time we checked it was ready
+    firmware: Option<FirmwareVersion>,
+    #[cfg(feature = "locus")]
+    logger_recording_mode: logger::RecordingMode,
+    /// Set while the gps is still sending a LOCUS dump we stopped reading
+    #[cfg(feature = "locus")]
+    interrupted_dump: bool,
+    /// When the budget given to [`Self::within_deadline`] started by `clock`,
+    /// and how long it is
+    deadline: Option<(u64, u64)>,
+    max_cmd_retries: usize,
+    read_timeout_us: u32,
+    write_timeout_us: u32,
+    tx: Tx,
+    delay: Delay,
+    clock: C,
+}
+
+impl<'rx, Tx, Delay> Gps<'rx, Tx, Delay>
+where
+    Tx: serial::Write<u8>,
+    Delay: DelayUs<u32>,
+{
+    /// Start setting up a driver reading from `rx` and writing to `tx`.
+    ///
+    /// Unless given a clock with [`GpsBuilder::clock`], timeouts are measured
+    /// by adding up delays, see [`CountedDelays`].
+    pub fn builder(rx: RxConsumer<'rx>, tx: Tx, delay: Delay) -> GpsBuilder<'rx, Tx, Delay> {
+        GpsBuilder {
+            rx,
+            tx,
+            delay,
+            clock: CountedDelays::default(),
+            already_disabled_nmea_output: false,
+            max_cmd_retries: MAX_CMD_TRIES,
+            read_timeout_us: MAX_READ_CMD_US,
+            write_timeout_us: MAX_WRITE_CMD_US,
+            nmea_out: None,
+            on_unsolicited: None,
+            observer: None,
+        }
+    }
+
+    /// Shorthand for [`Self::builder`] with everything else left as it is.
+    pub fn new(
+        rx: RxConsumer<'rx>,
+        tx: Tx,
+        delay: Delay,
+        already_disabled_nmea_output: bool,
+    ) -> Self {
+        Self::builder(rx, tx, delay)
+            .already_disabled_nmea_output(already_disabled_nmea_output)
+            .build()
+    }
+}
+
+impl<'rx, Tx, Delay, C> Gps<'rx, Tx, Delay, C>
+where
+    Tx: serial::Write<u8>,
+    Delay: DelayUs<u32>,
+    C: Clock,
+{
+    /// Pass NMEA sentences that arrive while we're waiting for a reply on to
+    /// `nmea_out`, instead of treating them as a failed attempt.
+    ///
+    /// This also stops commands from disabling NMEA output first, so
+    /// something like an `NmeaListener` on the other end of `nmea_out` keeps
+    /// getting fixes while the gps is being configured.
+    pub fn forward_nmea_to(&mut self, nmea_out: RxProducer<'rx>) {
+        self.nmea_out = Some(nmea_out);
+    }
+
+    /// Call `handler` with each whole sentence that arrives while we're
+    /// waiting for a reply and isn't it: NMEA, and the system messages the
+    /// gps sends on its own such as after it restarts. Without this they're
+    /// dropped and the attempt retried.
+    ///
+    /// Like [`Self::forward_nmea_to`] this leaves NMEA output enabled, and
+    /// NMEA goes to both if both are set.
+    pub fn on_unsolicited(&mut self, handler: impl FnMut(&[u8]) + Send + 'rx) {
+        self.on_unsolicited = Some(Box::new(handler));
+    }
+
+    /// Hand everything written to and read from the gps to `observer` as
+    /// well, replacing any observer already set.
+    pub fn observe_traffic(&mut self, observer: impl TrafficObserver + Send + 'rx) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Counts of retries, resyncs and the like, to tell how well the link to
+    /// the gps is working.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Run `op`, such as a call to [`Self::read_logs`] or a restart, giving up
+    /// with [`Error::DeadlineExceeded`] once `budget_us` has passed by the
+    /// clock. Nested calls can only shorten the budget.
+    ///
+    /// The deadline is only checked between sentences and tries, so `op` can
+    /// overrun by up to the read timeout. A LOCUS dump cut short carries on
+    /// arriving, and is skipped over before the next command is sent.
+    pub fn within_deadline<T, Op>(&mut self, budget_us: u64, op: Op) -> Result<T, Error<Tx::Error>>
+    where
+        Op: FnOnce(&mut Self) -> Result<T, Error<Tx::Error>>,
+    {
+        let now = self.now_us();
+        let outer = self.deadline;
+        let budget_us = match outer {
+            Some((start, outer_budget)) => {
+                let outer_left = outer_budget.saturating_sub(now.wrapping_sub(start));
+                budget_us.min(outer_left)
+            }
+            None => budget_us,
+        };
+        self.deadline = Some((now, budget_us));
+        let result = op(self);
+        self.deadline = outer;
+        result
+    }
+
+    /// Split into a [`Commander`] that sends commands and an [`NmeaStream`]
+    /// of the sentences that arrive in between, passed on through
+    /// `nmea_queue`, the two ends of another [`RxBuf`](crate::RxBuf).
+    ///
+    /// Replaces anywhere NMEA was already forwarded to.
+    pub fn split(
+        mut self,
+        nmea_queue: (RxProducer<'rx>, RxConsumer<'rx>),
+    ) -> (Commander<'rx, Tx, Delay, C>, NmeaStream<'rx>) {
+        let (nmea_out, nmea_in) = nmea_queue;
+        self.forward_nmea_to(nmea_out);
+        (Commander::new(self), NmeaStream::new(nmea_in))
+    }
+
+    /// Apply each item in turn, carrying on past any that fail.
+    ///
+    /// Items that are a single command are sent several at a time without
+    /// waiting for each ack, and any that fail are retried one by one
+    /// afterwards. The rest are sent on their own, so the order between
+    /// them and the others is kept.
+    ///
+    /// Returns the result of each item, in the same order.
+    pub fn apply(&mut self, items: &[ConfigItem]) -> Vec<Result<(), Error<Tx::Error>>> {
+        let mut results = Vec::with_capacity(items.len());
+        let mut rest = items;
+        while let Some(item) = rest.first() {
+            let batch: Vec<_> = rest
+                .iter()
+                .take(MAX_PIPELINED)
+                .map_while(single_cmd)
+                .collect();
+
+            if batch.is_empty() {
+                results.push(self.apply_one(item));
+                rest = &rest[1..];
+            } else {
+                results.extend(self.apply_pipelined(&batch));
+                rest = &rest[batch.len()..];
+            }
+        }
+
+        for (item, result) in items.iter().zip(&results) {
+            if result.is_err() {
+                error!("Failed to apply {}", item);
+            }
+        }
+        results
+    }
+
+    /// Sends every command before reading any acks.
+    fn apply_pipelined(&mut self, cmds: &[SingleCmd]) -> Vec<Result<(), Error<Tx::Error>>> {
+        debug!("Sending {} commands at once", cmds.len());
+        let mut results: Vec<Option<Result<(), Error<Tx::Error>>>> = Vec::new();
+        let handled = self.ensure_nmea_output_handled().is_ok();
+        if handled {
+            for (num, fields) in cmds {
+                let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+                let written = self.write_cmd_raw(&pmtk_name(num), &fields);
+                results.push(written.err().map(Err));
+            }
+        } else {
+            results.resize_with(cmds.len(), || None);
+        }
+
+        let mut strays = 0;
+        // Still reading the acks when there are no retries
+        let max_strays = self.max_cmd_retries.max(1);
+        while results.iter().any(Option::is_none) && strays < max_strays {
+            let fields = match self.read_reply_raw(b"PMTK001", 2) {
+                Ok(fields) => fields,
+                // The rest aren't coming
+                Err(Error::ReadTimeout) => break,
+                Err(_) => {
+                    strays += 1;
+                    continue;
+                }
+            };
+            let waiting = cmds
+                .iter()
+                .zip(&results)
+                .position(|((num, _), result)| result.is_none() && num == &fields[0][..]);
+            match waiting {
+                Some(i) => results[i] = Some(check_pmtk_ack(&cmds[i].0, fields).map(drop)),
+                None => strays += 1,
+            }
+        }
+
+        // One try each down, so these run out at the same total as usual
+        let max_tries = if handled {
+            self.max_cmd_retries.checked_sub(1)
+        } else {
+            Some(self.max_cmd_retries)
+        };
+        cmds.iter()
+            .zip(results)
+            .map(|((num, fields), result)| match (result, max_tries) {
+                (Some(Ok(())), _) => Ok(()),
+                (Some(Err(err @ Error::GpsSaysUnsupportedCommand)), _) => Err(err),
+                // The pipelined send was the only try
+                (Some(Err(err)), None) => Err(err),
+                (None, None) => Err(Error::ReadTimeout),
+                (_, Some(max_tries)) => {
+                    self.ensure_nmea_output_handled()?;
+                    let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+                    let exchange = Exchange::for_ack(num, &fields).max_tries(max_tries);
+                    self.run_exchange(exchange).map(drop)
+                }
+            })
+            .collect()
+    }
+
+    fn apply_one(&mut self, item: &ConfigItem) -> Result<(), Error<Tx::Error>> {
+        match *item {
+            ConfigItem::DisableNmeaOutput => self.ensure_nmea_output_disabled(),
+            ConfigItem::NmeaOutput(config) => self.set_nmea_output(config),
+            ConfigItem::Sbas(enabled) => self.set_sbas(enabled),
+            ConfigItem::DgpsMode(mode) => self.set_dgps_mode(mode),
+            ConfigItem::PowerMode(ref mode) => self.set_power_mode(mode),
+            ConfigItem::Datum(datum) => self.set_datum(datum),
+            ConfigItem::UpdateRateMs(ms) => self.set_update_rate_ms(ms),
+            #[cfg(feature = "locus")]
+            ConfigItem::LoggerInterval(secs) => self.configure_logger_interval(secs),
+            #[cfg(feature = "locus")]
+            ConfigItem::LoggerTrigger(trigger) => self.configure_logger(trigger),
+            #[cfg(feature = "locus")]
+            ConfigItem::StartLogging => self.start_logging(),
+            #[cfg(feature = "locus")]
+            ConfigItem::StopLogging => self.stop_logging(),
+        }
+    }
+
+    pub fn set_sbas(&mut self, enabled: bool) -> Result<(), Error<Tx::Error>> {
+        // PMTK_API_SET_SBAS_ENABLED
+        info!("Setting SBAS enabled to {}", enabled);
+        self.send_mtk_cmd(b"313", &[if enabled { b"1" } else { b"0" }])
+    }
+
+    /// Remembered from the last time we checked the gps was ready, so only
+    /// asks it the first time.
+    pub fn firmware_version(&mut self) -> Result<&FirmwareVersion, Error<Tx::Error>> {
+        if self.firmware.is_none() {
+            self.ensure_nmea_output_handled()?;
+            self.check_ready(self.max_cmd_retries)?;
+        }
+        // check_ready always sets it when it succeeds
+        Ok(self.firmware.as_ref().unwrap())
+    }
+
+    pub fn sbas_enabled(&mut self) -> Result<bool, Error<Tx::Error>> {
+        // PMTK_API_Q_SBAS_ENABLED, answered by PMTK_DT_SBAS_ENABLED
+        info!("Querying SBAS enabled");
+        let fields = self.send_mtk_cmd_for_reply(b"413", &[], b"513", 1)?;
+        Ok(cmd::parse::bool_field(&fields[0], b"1", b"0")?)
+    }
+
+    pub fn set_dgps_mode(&mut self, mode: DgpsMode) -> Result<(), Error<Tx::Error>> {
+        // PMTK_API_SET_DGPS_MODE
+        info!("Setting DGPS mode to {}", mode);
+        self.send_mtk_cmd(b"301", &[mode.field()])
+    }
+
+    pub fn dgps_mode(&mut self) -> Result<DgpsMode, Error<Tx::Error>> {
+        // PMTK_API_Q_DGPS_MODE, answered by PMTK_DT_DGPS_MODE
+        info!("Querying DGPS mode");
+        let fields = self.send_mtk_cmd_for_reply(b"401", &[], b"501", 1)?;
+        DgpsMode::from_field(&fields[0]).ok_or_else(|| {
+            error!("Unknown DGPS mode {=[u8]:a}", &fields[0]);
+            Error::Protocol(RawBytes::new(&fields[0]))
+        })
+    }
+
+    /// How often the gps works out a fix and sends NMEA sentences, from 100
+    /// (10 Hz) to 10,000 milliseconds.
+    ///
+    /// Faster rates need a faster baud rate than the default 9600 to fit all
+    /// the sentences in, see [`Self::set_baud_rate`].
+    pub fn set_update_rate_ms(&mut self, ms: u32) -> Result<(), Error<Tx::Error>> {
+        // PMTK_SET_NMEA_UPDATERATE
+        info!("Setting update rate to {}ms", ms);
+        if !UPDATE_RATE_MS.contains(&ms) {
+            error!("Update rate {}ms out of range", ms);
+            return Err(Error::InvalidArgument);
+        }
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let ms_ascii = u32_to_base10_ascii(ms, &mut buf);
+        self.send_mtk_cmd(b"220", &[ms_ascii])
+    }
+
+    pub fn update_rate_ms(&mut self) -> Result<u32, Error<Tx::Error>> {
+        // PMTK_API_Q_FIX_CTL, answered by PMTK_DT_FIX_CTL
+        info!("Querying update rate");
+        let fields = self.send_mtk_cmd_for_reply(b"400", &[], b"500", 1)?;
+        Ok(cmd::parse::integer_field(&fields[0])?)
+    }
+
+    /// Report positions in `datum` instead of WGS84. Saved while the gps has
+    /// backup power.
+    pub fn set_datum(&mut self, datum: Datum) -> Result<(), Error<Tx::Error>> {
+        // PMTK_API_SET_DATUM
+        info!("Setting datum to {}", datum);
+        let index = datum.index().ok_or_else(|| {
+            error!("Datum index out of range");
+            Error::InvalidArgument
+        })?;
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let index_ascii = u32_to_base10_ascii(index as u32, &mut buf);
+        self.send_mtk_cmd(b"330", &[index_ascii])
+    }
+
+    pub fn datum(&mut self) -> Result<Datum, Error<Tx::Error>> {
+        // PMTK_API_Q_DATUM, answered by PMTK_DT_DATUM
+        info!("Querying datum");
+        let fields = self.send_mtk_cmd_for_reply(b"430", &[], b"530", 1)?;
+        let index = cmd::parse::integer_field(&fields[0])?;
+        u8::try_from(index)
+            .ok()
+            .and_then(Datum::from_index)
+            .ok_or_else(|| Error::Protocol(RawBytes::new(&fields[0])))
+    }
+
+    /// Choose which satellite systems to track, see [`SearchMode`]. Saved
+    /// while the gps has backup power.
+    pub fn set_search_mode(&mut self, mode: SearchMode) -> Result<(), Error<Tx::Error>> {
+        // PMTK_API_SET_GNSS_SEARCH_MODE
+        info!("Setting search mode to {}", mode);
+        if !(mode.gps || mode.glonass || mode.galileo) {
+            error!("Search mode must track at least one system");
+            return Err(Error::InvalidArgument);
+        }
+        let flag = |enabled| if enabled { b"1" } else { b"0" };
+        // The last two are Galileo's full mode and BeiDou, which no module
+        // we know of supports
+        self.send_mtk_cmd(
+            b"353",
+            &[
+                flag(mode.gps),
+                flag(mode.glonass),
+                flag(mode.galileo),
+                b"0",
+                b"0",
+            ],
+        )
+    }
+
+    pub fn search_mode(&mut self) -> Result<SearchMode, Error<Tx::Error>> {
+        // PMTK_Q_GNSS_SEARCH_MODE, answered in the ack
+        info!("Querying search mode");
+        let fields = self.send_mtk_cmd_for_ack_fields(b"355", &[], 1)?;
+        // Older firmware only has fields for the systems it knows about
+        let flag = |i: usize| match fields.get(i) {
+            Some(field) => cmd::parse::bool_field(field, b"1", b"0"),
+            None => Ok(false),
+        };
+        let mode = SearchMode {
+            gps: flag(0)?,
+            glonass: flag(1)?,
+            galileo: flag(2)?,
+        };
+        info!("Got search mode: {}", &mode);
+        Ok(mode)
+    }
+
+    /// Have the gps report which antenna it's using with its NMEA output, as
+    /// `$PGTOP` sentences. See [`crate::AntennaStatus`], and
+    /// [`crate::NmeaListener::antenna_status`] for decoding them.
+    pub fn set_antenna_reports(&mut self, enabled: bool) -> Result<(), Error<Tx::Error>> {
+        info!("Setting antenna reports enabled to {}", enabled);
+        self.ensure_nmea_output_handled()?;
+        // The gps doesn't ack PGCMD
+        self.write_cmd_raw(b"PGCMD", &[b"33", if enabled { b"1" } else { b"0" }])?;
+        self.flush_tx()
+    }
+
+    /// Switch the gps's UART to `baud`, calling `reconfigure_uart` to switch
+    /// our end to match once the command has gone out.
+    ///
+    /// The gps may keep the new rate across restarts while it has backup
+    /// power, so be ready to find it at either rate.
+    pub fn set_baud_rate(
+        &mut self,
+        baud: u32,
+        reconfigure_uart: impl FnOnce(u32),
+    ) -> Result<(), Error<Tx::Error>> {
+        // PMTK_SET_NMEA_BAUDRATE
+        info!("Setting baud rate to {}", baud);
+        if !SUPPORTED_BAUD_RATES.contains(&baud) {
+            error!("Unsupported baud rate {}", baud);
+            return Err(Error::InvalidArgument);
+        }
+        self.ensure_nmea_output_handled()?;
+
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let baud_ascii = u32_to_base10_ascii(baud, &mut buf);
+        // The gps switches straight away, so there's no ack we could read at
+        // either rate. Instead we check it answers at the new one.
+        self.write_cmd_raw(b"PMTK251", &[baud_ascii])?;
+        self.flush_tx()?;
+        self.delay_us(DELAY_AFTER_BAUD_CHANGE_US);
+
+        reconfigure_uart(baud);
+        self.flush_rx_queue();
+        self.check_ready(self.max_cmd_retries)
+    }
+
+    #[cfg(feature = "locus")]
+    pub fn configure_logger_interval(&mut self, secs: u32) -> Result<(), Error<Tx::Error>> {
+        self.configure_logger(logger::Trigger::Interval(secs))
+    }
+
+    /// Choose what makes LOCUS record a point, see [`logger::Trigger`].
+    #[cfg(feature = "locus")]
+    pub fn configure_logger(&mut self, trigger: logger::Trigger) -> Result<(), Error<Tx::Error>> {
+        // PMTK_LOCUS_CONFIG
+        info!("Setting logger trigger to {}", trigger);
+        let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+        let value_ascii = u32_to_base10_ascii(trigger.value(), &mut buf);
+        self.send_mtk_cmd(b"187", &[trigger.mode_field(), value_ascii])
+    }
+
+    /// Record a point straight away, whatever the trigger.
+    #[cfg(feature = "locus")]
+    pub fn log_now(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_LOCUS_LOG_NOW
+        info!("Logging a point now");
+        self.send_mtk_cmd(b"186", &[b"1"])
+    }
+
+    /// Erase every logged point, returning once the flash is actually empty.
+    ///
+    /// The gps acks before it's done erasing, and tends to fail commands sent
+    /// in the meantime, so we check the status until it reports no records.
+    /// Returns [`Error::ReadTimeout`] if it never does.
+    #[cfg(feature = "locus")]
+    pub fn erase_logs(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_LOCUS_ERASE_FLASH
+        info!("Erasing logs");
+        self.send_mtk_cmd(b"184", &[b"1"])?;
+
+        for _ in 0..MAX_ERASE_CHECKS {
+            match self.logger_status() {
+                Ok(status) if status.record_count == 0 => {
+                    info!("Erased logs");
+                    return Ok(());
+                }
+                Ok(status) => {
+                    debug!("Still erasing, {} records left", status.record_count);
+                }
+                Err(_) => {
+                    debug!("Gps busy erasing");
+                }
+            }
+            self.delay_us(DELAY_BETWEEN_ERASE_CHECKS_US);
+        }
+
+        error!("Gps never finished erasing logs");
+        Err(Error::ReadTimeout)
+    }
+
+    #[cfg(feature = "locus")]
+    pub fn start_logging(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_LOCUS_STOP_LOGGER, 0 = start
+        info!("Starting logging");
+        self.send_mtk_cmd(b"185", &[b"0"])
+    }
+
+    #[cfg(feature = "locus")]
+    pub fn stop_logging(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_LOCUS_STOP_LOGGER, 1 = stop
+        info!("Stopping logging");
+        self.send_mtk_cmd(b"185", &[b"1"])
+    }
+
+    #[cfg(feature = "locus")]
+    pub fn logger_status(&mut self) -> Result<logger::Status, Error<Tx::Error>> {
+        // PMTK_LOCUS_QUERY_STATUS
+        info!("Querying logger status");
+
+        let fields = self.send_mtk_cmd_for_reply(b"183", &[], b"LOG", 10)?;
+
+        let status = logger::Status::from_fields(&fields)?;
+
+        info!("Got logger status: {:?}", &status);
+
+        Ok(status)
+    }
+
+    /// Choose what should happen once LOCUS fills its flash.
+    ///
+    /// The gps only reports which it does, in
+    /// [`logger::Status::recording_mode`], and there's no command to change
+    /// it. So if it overlaps and [`logger::RecordingMode::FullStop`] is
+    /// chosen, nothing stops it unless [`Self::enforce_recording_mode`] is
+    /// called regularly.
+    #[cfg(feature = "locus")]
+    pub fn set_logger_recording_mode(&mut self, mode: logger::RecordingMode) {
+        info!("Setting logger recording mode to {}", mode);
+        self.logger_recording_mode = mode;
+    }
+
+    /// Stops logging ahead of the flash filling up, if
+    /// [`logger::RecordingMode::FullStop`] was chosen but the gps overlaps.
+    ///
+    /// Call it at least every `secs_until_next_call`. It stops as soon as
+    /// [`logger::Status::estimated_time_remaining`] says the flash could
+    /// fill before the next call, going by the logging interval, or a point
+    /// a second when logging by distance or speed. So up to that long of
+    /// flash may go unused. Returns the status, with `is_on` cleared if it
+    /// stopped logging.
+    #[cfg(feature = "locus")]
+    pub fn enforce_recording_mode(
+        &mut self,
+        secs_until_next_call: u32,
+    ) -> Result<logger::Status, Error<Tx::Error>> {
+        let mut status = self.logger_status()?;
+
+        let overlapping = status.is_on
+            && status.recording_mode == logger::RecordingMode::Overlap
+            && self.logger_recording_mode == logger::RecordingMode::FullStop;
+        if !overlapping {
+            return Ok(status);
+        }
+
+        let secs_per_point = if status.mode.contains(logger::LogMode::INTERVAL) {
+            status.interval.max(1)
+        } else {
+            1
+        };
+        let full_soon = status
+            .estimated_time_remaining(secs_per_point)
+            .map_or(status.percent_full == 100, |secs| {
+                secs <= secs_until_next_call
+            });
+        if full_soon {
+            warn!("Logger nearly full, stopping it before it overwrites old points");
+            self.stop_logging()?;
+            status.is_on = false;
+        }
+
+        Ok(status)
+    }
+
+    /// Whether the gps understands Quectel's `$PQ` commands, going by the
+    /// model it reports. Only asks the gps the first time.
+    #[cfg(feature = "quectel")]
+    pub fn command_set(&mut self) -> Result<CommandSet, Error<Tx::Error>> {
+        let model = self.firmware_version()?.model.as_deref();
+        Ok(CommandSet::from_model(model.unwrap_or_default()))
+    }
+
+    /// Whether the gps sends `$GPTXT` sentences with its antenna status and
+    /// the like. Saved across restarts.
+    #[cfg(feature = "quectel")]
+    pub fn set_txt_output(&mut self, enabled: bool) -> Result<(), Error<Tx::Error>> {
+        info!("Setting TXT output enabled to {}", enabled);
+        let enabled: &[u8] = if enabled { b"1" } else { b"0" };
+        self.send_pq_cmd(b"PQTXT", &[b"W", enabled, b"1"])?;
+        Ok(())
+    }
+
+    /// Start the gps's own count of the distance travelled, from zero.
+    #[cfg(feature = "quectel")]
+    pub fn start_odometer(&mut self) -> Result<(), Error<Tx::Error>> {
+        info!("Starting odometer");
+        self.send_pq_cmd(b"PQODO", &[b"W", b"1"])?;
+        Ok(())
+    }
+
+    #[cfg(feature = "quectel")]
+    pub fn stop_odometer(&mut self) -> Result<(), Error<Tx::Error>> {
+        info!("Stopping odometer");
+        self.send_pq_cmd(b"PQODO", &[b"W", b"0"])?;
+        Ok(())
+    }
+
+    /// Meters travelled since [`Self::start_odometer`], rounded down.
+    #[cfg(feature = "quectel")]
+    pub fn odometer_m(&mut self) -> Result<u32, Error<Tx::Error>> {
+        info!("Querying odometer");
+        let fields = self.send_pq_cmd(b"PQODO", &[b"Q"])?;
+        let meters = fields[1].split(|&c| c == b'.').next().unwrap_or_default();
+        Ok(cmd::parse::integer_field(meters)?)
+    }
+
+    /// GLP (GNSS low power) mode, which roughly halves the power drawn while
+    /// tracking at the cost of some accuracy. Saved across restarts.
+    #[cfg(feature = "quectel")]
+    pub fn set_glp(&mut self, enabled: bool) -> Result<(), Error<Tx::Error>> {
+        info!("Setting GLP enabled to {}", enabled);
+        let enabled: &[u8] = if enabled { b"1" } else { b"0" };
+        self.send_pq_cmd(b"PQGLP", &[b"W", enabled, b"1"])?;
+        Ok(())
+    }
+
+    /// Dump everything LOCUS has recorded, which takes a few minutes at 9600
+    /// baud.
+    ///
+    /// `on_point` is called with `max_points`, `i`, and `point`. `max_points`
+    /// is the upper bound on the number of times `on_point` will be called.
+    /// `i` is the current point index (starting at zero). Return
+    /// [`ControlFlow::Break`] to stop after that point.
+    ///
+    /// Returns a cursor just past the last point handed to `on_point`, to pass
+    /// to [`Self::read_new_logs`] later, and how much of the dump made sense.
+    #[cfg(feature = "locus")]
+    pub fn read_logs<F>(
+        &mut self,
+        on_point: F,
+    ) -> Result<(logger::Cursor, logger::ParseStats), Error<Tx::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        info!("Reading logs");
+        let mut cursor = logger::Cursor::default();
+        // PMTK_Q_LOCUS_DATA, 0 = full
+        let stats = self.read_locus_dump(b"0", &mut cursor, on_point)?;
+        Ok((cursor, stats))
+    }
+
+    /// Like [`Self::read_logs`], but only calls `on_point` for points after
+    /// `cursor`. Start with a default cursor, and reset it after erasing the
+    /// logs.
+    ///
+    /// `cursor` is moved along as each point is handed over, even if this
+    /// fails or `on_point` breaks part way, so a dump that gets cut short can
+    /// pick up where it left off. Keep it somewhere that survives a reset if
+    /// that matters.
+    ///
+    /// The gps can't start a dump part way through, so this still reads the
+    /// older points, but it skips the unused part of the flash. The stats
+    /// returned cover all of it.
+    #[cfg(feature = "locus")]
+    pub fn read_new_logs<F>(
+        &mut self,
+        cursor: &mut logger::Cursor,
+        on_point: F,
+    ) -> Result<logger::ParseStats, Error<Tx::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        info!("Reading logs after point {}", cursor.points_read);
+        // PMTK_Q_LOCUS_DATA, 1 = partial, only the sectors in use
+        self.read_locus_dump(b"1", cursor, on_point)
+    }
+
+    /// Hands `on_point` the points after `cursor`, moving it along as it goes.
+    ///
+    /// There's no way to stop the gps once it's started a dump, so after a
+    /// break we still read the rest to leave it ready for commands, we just
+    /// don't parse it.
+    #[cfg(feature = "locus")]
+    fn read_locus_dump<F>(
+        &mut self,
+        kind: &[u8],
+        cursor: &mut logger::Cursor,
+        mut on_point: F,
+    ) -> Result<logger::ParseStats, Error<Tx::Error>>
+    where
+        F: FnMut(usize, usize, logger::Packet) -> ControlFlow<()>,
+    {
+        // NOTE: We don't retry because this is super expensive.
+
+        self.ensure_nmea_output_handled()?;
+        self.check_deadline()?;
+        self.write_cmd_raw(b"PMTK622", &[kind])?;
+
+        let locus_start = self.read_reply_raw(b"PMTKLOX", 2)?;
+        let packet_count = locus_packet_count(&locus_start)?;
+        let max_points = packet_count * MAX_POINTS_PER_LOCUS_DATA_PACKET;
+
+        let skip_points = cursor.points_read;
+        let point_i = Cell::new(0);
+        let stopped = Cell::new(false);
+        let mut parser = logger::Parser::new(|point| {
+            if stopped.get() {
+                return;
+            }
+            let i = point_i.get();
+            point_i.set(i + 1);
+            if i >= skip_points {
+                cursor.points_read = i + 1;
+                if on_point(max_points, i, point).is_break() {
+                    stopped.set(true);
+                }
+            }
+        });
+
+        for n in 0..packet_count {
+            let locus_data = self.read_locus_packet(2)?;
+            check_locus_data(n, &locus_data)?;
+            if !stopped.get() {
+                feed_locus_data(&locus_data, &mut parser)?;
+            }
+        }
+
+        let locus_end = self.read_locus_packet(1)?;
+        check_locus_end(&locus_end)?;
+
+        let stats = parser.stats().clone();
+        info!("Read logs: {}", &stats);
+        if stopped.get() {
+            info!("Stopped early, after point {}", cursor.points_read);
+        } else {
+            cursor.points_read = point_i.get();
+        }
+        Ok(stats)
+    }
+
+    /// Like [`Self::read_reply_raw`] for the rest of a LOCUS dump, noting
+    /// if the deadline cuts it short.
+    #[cfg(feature = "locus")]
+    fn read_locus_packet(&mut self, min_fields: usize) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        let result = self
+            .check_deadline()
+            .and_then(|()| self.read_reply_raw(b"PMTKLOX", min_fields));
+        if let Err(Error::DeadlineExceeded) = result {
+            warn!("Stopped reading logs at the deadline");
+            self.interrupted_dump = true;
+        }
+        result
+    }
+
+    /// The rest of a dump [`Self::within_deadline`] cut short would bury the
+    /// replies to the next command, so skip past it first.
+    #[cfg(feature = "locus")]
+    fn finish_interrupted_dump(&mut self) -> Result<(), Error<Tx::Error>> {
+        if !self.interrupted_dump {
+            return Ok(());
+        }
+        info!("Skipping the rest of an interrupted LOCUS dump");
+        loop {
+            match self.read_reply_raw(b"PMTKLOX", 1) {
+                Ok(fields) if fields[0] == b"2" => break,
+                // It's stopped sending, so must be done
+                Err(Error::ReadTimeout) => break,
+                Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+                // Part of the dump, or something else in the middle
+                Ok(_) | Err(_) => {}
+            }
+        }
+        self.interrupted_dump = false;
+        Ok(())
+    }
+
+    /// Restart keeping all saved data.
+    pub fn hot_restart(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_HOT_START
+        info!("Hot restarting");
+        self.send_reboot_cmd(b"PMTK101")
+    }
+
+    /// Restart keeping everything but ephemeris.
+    pub fn warm_restart(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_WARM_START
+        info!("Warm restarting");
+        self.send_reboot_cmd(b"PMTK102")
+    }
+
+    /// Restart keeping everything but time, position, almanacs and ephemeris.
+    pub fn cold_restart(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_COLD_START
+        info!("Cold restarting");
+        self.send_reboot_cmd(b"PMTK103")
+    }
+
+    /// Restart, clearing everything.
+    ///
+    /// It's essentially a cold restart, but additionally clear system/user
+    /// configurations at re-start.
+    pub fn factory_reset(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_FULL_COLD_START
+        info!("Factory resetting");
+        self.send_reboot_cmd(b"PMTK104")
+    }
+
+    /// Below this speed the gps reports the position as not changing, which
+    /// stops a stationary logger's track from wandering. From 10 to 200 cm/s
+    /// in steps of 10, or 0 to turn it off.
+    pub fn set_static_nav_threshold(&mut self, cm_per_sec: u16) -> Result<(), Error<Tx::Error>> {
+        info!("Setting static navigation threshold to {}cm/s", cm_per_sec);
+        if cm_per_sec > 200 || !cm_per_sec.is_multiple_of(10) {
+            error!("Static navigation threshold out of range");
+            return Err(Error::InvalidArgument);
+        }
+        let mut buf = [0_u8; 4];
+        let speed_ascii = centi_to_ascii(cm_per_sec, &mut buf);
+
+        if !self.static_nav_needs_pmtk397 {
+            // PMTK_API_SET_STATIC_NAV_THD
+            match self.send_mtk_cmd(b"386", &[speed_ascii]) {
+                Err(Error::GpsSaysUnsupportedCommand) => {
+                    warn!("PMTK386 unsupported, falling back to PMTK397");
+                    self.static_nav_needs_pmtk397 = true;
+                }
+                result => return result,
+            }
+        }
+        // PMTK_API_SET_NAV_SPEED_THRESHOLD, the same for older firmware
+        self.send_mtk_cmd(b"397", &[speed_ascii])
+    }
+
+    /// EASY predicts ephemeris for up to three days from what the gps
+    /// receives while tracking, for much faster fixes when it's been off for
+    /// a while. It's on by default, but only works while the gps has backup
+    /// power.
+    pub fn set_easy(&mut self, enabled: bool) -> Result<(), Error<Tx::Error>> {
+        // PMTK_EASY_ENABLE, 1 = set
+        info!("Setting EASY enabled to {}", enabled);
+        self.send_mtk_cmd(b"869", &[b"1", if enabled { b"1" } else { b"0" }])
+    }
+
+    pub fn easy_status(&mut self) -> Result<EasyStatus, Error<Tx::Error>> {
+        // PMTK_EASY_ENABLE, 0 = query, answered with 2 = result
+        info!("Querying EASY status");
+        let fields = self.send_mtk_cmd_for_reply(b"869", &[b"0"], b"869", 3)?;
+        if fields[0] != b"2" {
+            error!("Expected EASY query result, got {=[u8]:a}", &fields[0]);
+            return Err(Error::Protocol(RawBytes::from_parsed(b"PMTK869", &fields)));
+        }
+
+        let extended_days = cmd::parse::integer_field(&fields[2])?;
+        let status = EasyStatus {
+            enabled: cmd::parse::bool_field(&fields[1], b"1", b"0")?,
+            extended_days: extended_days.min(u8::MAX as u32) as u8,
+        };
+        info!("Got EASY status: {}", &status);
+        Ok(status)
+    }
+
+    /// Upload an EPO file, the ephemeris for every satellite predicted for
+    /// the next few days, which gets a first fix in seconds instead of
+    /// minutes. They're published by MediaTek for download.
+    ///
+    /// The gps is switched to binary mode for the transfer and back to NMEA
+    /// afterwards. Start with a default `progress`, and if this fails, call
+    /// again with the same one to carry on from the last acked packet.
+    pub fn upload_epo(
+        &mut self,
+        epo: &[u8],
+        progress: &mut EpoProgress,
+    ) -> Result<(), Error<Tx::Error>> {
+        let packets = aiding::epo_packet_count(epo).ok_or_else(|| {
+            error!("EPO data isn't a whole number of records");
+            Error::InvalidArgument
+        })?;
+        info!(
+            "Uploading EPO, {} packets from {}",
+            packets, progress.next_seq
+        );
+        self.ensure_nmea_output_handled()?;
+
+        // PMTK_SET_OUTPUT_FMT, 1 = binary, 0 = keep the baud rate. Not acked
+        // as the gps has already switched.
+        self.write_cmd_raw(b"PMTK253", &[b"1", b"0"])?;
+        self.flush_tx()?;
+        self.delay_us(DELAY_AFTER_MODE_CHANGE_US);
+        self.flush_rx_queue();
+
+        let result = self.send_epo_packets(epo, packets, progress);
+        // Even if that failed, so the gps is left usable
+        let to_nmea = self.switch_binary_to_nmea();
+        result?;
+        to_nmea?;
+        info!("Uploaded EPO");
+        *progress = EpoProgress::default();
+        Ok(())
+    }
+
+    /// Tell the gps roughly what the time is, such as from an RTC, so it can
+    /// work out which satellites should be overhead after a cold start. Only
+    /// useful with EPO data or a reference position.
+    pub fn set_reference_time(&mut self, time: UtcDateTime) -> Result<(), Error<Tx::Error>> {
+        // PMTK_DT_UTC
+        info!("Setting reference time to {}", time);
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 6];
+        self.send_mtk_cmd(b"740", &utc_fields(time, &mut bufs))
+    }
+
+    /// Tell the gps roughly where it is, such as where it last had a fix,
+    /// along with the current time. Along with EPO data this gets a fix
+    /// within seconds after a cold start, if it hasn't moved far.
+    ///
+    /// `lat` and `lon` are in degrees, positive north and east, and `alt_m`
+    /// is meters above mean sea level.
+    pub fn set_reference_position(
+        &mut self,
+        lat: f32,
+        lon: f32,
+        alt_m: f32,
+        time: UtcDateTime,
+    ) -> Result<(), Error<Tx::Error>> {
+        // PMTK_DT_POS
+        info!(
+            "Setting reference position to {}, {} at {}m, {}",
+            lat, lon, alt_m, time
+        );
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || !alt_m.is_finite()
+        {
+            error!("Reference position out of range");
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut lat_ascii = Vec::new();
+        cmd::serialize::float_field(lat as f64, 6, &mut lat_ascii);
+        let mut lon_ascii = Vec::new();
+        cmd::serialize::float_field(lon as f64, 6, &mut lon_ascii);
+        let mut alt_ascii = Vec::new();
+        cmd::serialize::float_field(alt_m as f64, 1, &mut alt_ascii);
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 6];
+        let [year, month, day, hour, minute, second] = utc_fields(time, &mut bufs);
+
+        self.send_mtk_cmd(
+            b"741",
+            &[
+                &lat_ascii, &lon_ascii, &alt_ascii, year, month, day, hour, minute, second,
+            ],
+        )
+    }
+
+    pub fn epo_status(&mut self) -> Result<EpoStatus, Error<Tx::Error>> {
+        // PMTK_Q_EPO_INFO, answered by PMTK_DT_EPO_INFO
+        info!("Querying EPO status");
+        let fields = self.send_mtk_cmd_for_reply(b"607", &[], b"707", 5)?;
+        let sets = cmd::parse::integer_field(&fields[0])?;
+        let mut times = [0; 4];
+        for (time, field) in times.iter_mut().zip(&fields[1..5]) {
+            *time = cmd::parse::integer_field(field)?;
+        }
+        let [first_week, first_tow, last_week, last_tow] = times;
+
+        let status = if sets == 0 {
+            EpoStatus {
+                sets: 0,
+                valid_from: None,
+                valid_until: None,
+            }
+        } else {
+            EpoStatus {
+                sets: sets.min(u16::MAX as u32) as u16,
+                valid_from: UtcDateTime::from_gps_time(first_week, first_tow),
+                // The last time is when its set starts
+                valid_until: UtcDateTime::from_gps_time(last_week, last_tow)
+                    .and_then(|start| start.checked_add_seconds(aiding::EPO_SET_SECS as i64)),
+            }
+        };
+        info!("Got EPO status: {}", &status);
+        Ok(status)
+    }
+
+    /// Forget any EPO data, such as when it's out of date.
+    pub fn clear_epo(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_CLEAR_EPO
+        info!("Clearing EPO data");
+        self.send_mtk_cmd(b"127", &[])
+    }
+
+    fn send_epo_packets(
+        &mut self,
+        epo: &[u8],
+        packets: u16,
+        progress: &mut EpoProgress,
+    ) -> Result<(), Error<Tx::Error>> {
+        let packet_len = aiding::EPO_RECORD_LEN * aiding::EPO_RECORDS_PER_PACKET;
+        let mut payload = Vec::with_capacity(2 + packet_len);
+        while progress.next_seq <= packets {
+            let seq = progress.next_seq;
+            payload.clear();
+            if seq < packets {
+                payload.extend_from_slice(&seq.to_le_bytes());
+                let start = seq as usize * packet_len;
+                let end = (start + packet_len).min(epo.len());
+                payload.extend_from_slice(&epo[start..end]);
+            } else {
+                // An empty packet numbered 0xFFFF marks the end
+                payload.extend_from_slice(&u16::MAX.to_le_bytes());
+            }
+            // Short packets are padded out with empty records
+            payload.resize(2 + packet_len, 0);
+
+            self.with_retries(self.max_cmd_retries, |gps| {
+                // PMTK_BIN_EPO_DATA
+                gps.write_bin_packet_raw(722, &payload)?;
+                gps.read_epo_ack_raw(if seq < packets { seq } else { u16::MAX })
+            })
+            .map_err(|(tries, err)| {
+                error!("Failed to send EPO packet {} after {} tries", seq, tries);
+                err
+            })?;
+            progress.next_seq += 1;
+        }
+        Ok(())
+    }
+
+    fn read_epo_ack_raw(&mut self, seq: u16) -> Result<(), Error<Tx::Error>> {
+        // PMTK_BIN_ACK_EPO
+        let (id, payload) = self.read_bin_packet_raw()?;
+        match *payload.as_slice() {
+            [seq_lo, seq_hi, result] if id == 723 => {
+                let got_seq = u16::from_le_bytes([seq_lo, seq_hi]);
+                if got_seq != seq {
+                    debug!("Got EPO ack for {}, expected {}", got_seq, seq);
+                    return Err(Error::Protocol(RawBytes::new(&payload)));
+                }
+                match result {
+                    1 => Ok(()),
+                    _ => Err(Error::GpsSaysActionFailed),
+                }
+            }
+            _ => {
+                debug!("Expected EPO ack, got binary packet {}", id);
+                Err(Error::Protocol(RawBytes::new(&payload)))
+            }
+        }
+    }
+
+    fn switch_binary_to_nmea(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_BIN_SET_OUTPUT_FMT, 0 = NMEA, then a baud rate of 0 to keep it
+        self.write_bin_packet_raw(253, &[0, 0, 0, 0, 0])?;
+        self.flush_tx()?;
+        self.delay_us(DELAY_AFTER_MODE_CHANGE_US);
+        self.flush_rx_queue();
+        // The gps goes back to its saved NMEA output
+        self.disabled_nmea_output = false;
+        self.check_ready(self.max_cmd_retries)
+    }
+
+    /// See [`PowerMode`]. Not saved, so the gps goes back to
+    /// [`PowerMode::Normal`] when restarted.
+    pub fn set_power_mode(&mut self, mode: &PowerMode) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_PERIODIC_MODE
+        info!("Setting power mode to {}", mode);
+
+        let times = match mode.times() {
+            Some(times) if !times.is_valid() => {
+                error!("Power mode times out of range");
+                return Err(Error::InvalidArgument);
+            }
+            Some(times) => times,
+            None => return self.send_mtk_cmd(b"225", &[mode.type_field()]),
+        };
+        if let Some(extended_fix) = &times.extended_fix {
+            self.set_extended_fix(extended_fix)?;
+        }
+
+        let (second_run, second_sleep) = times.second.unwrap_or((0, 0));
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 4];
+        let [run_buf, sleep_buf, second_run_buf, second_sleep_buf] = &mut bufs;
+        self.send_mtk_cmd(
+            b"225",
+            &[
+                mode.type_field(),
+                u32_to_base10_ascii(times.run_ms, run_buf),
+                u32_to_base10_ascii(times.sleep_ms, sleep_buf),
+                u32_to_base10_ascii(second_run, second_run_buf),
+                u32_to_base10_ascii(second_sleep, second_sleep_buf),
+            ],
+        )
+    }
+
+    fn set_extended_fix(&mut self, extended_fix: &ExtendedFix) -> Result<(), Error<Tx::Error>> {
+        // PMTK_SET_AL_DEE_CFG
+        debug!("Setting extended fix to {}", extended_fix);
+        let mut bufs = [[0_u8; u32::FORMATTED_SIZE_DECIMAL]; 4];
+        let [sats_buf, snr_buf, extension_buf, gap_buf] = &mut bufs;
+        self.send_mtk_cmd(
+            b"223",
+            &[
+                u32_to_base10_ascii(extended_fix.min_satellites as u32, sats_buf),
+                u32_to_base10_ascii(extended_fix.min_snr as u32, snr_buf),
+                u32_to_base10_ascii(extended_fix.extension_ms, extension_buf),
+                u32_to_base10_ascii(extended_fix.extension_gap_ms, gap_buf),
+            ],
+        )
+    }
+
+    /// Put the gps to sleep until the next command, drawing about 1mA
+    /// instead of about 20mA while tracking.
+    ///
+    /// It stops answering until sent a byte, which the next command sends
+    /// for us, or call [`Self::wake`] to wake it up sooner.
+    pub fn enter_standby(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_STANDBY_MODE, 0 = stop mode
+        info!("Entering standby");
+        self.send_mtk_cmd(b"161", &[b"0"])?;
+        self.standby = true;
+        Ok(())
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby
+    }
+
+    /// Wake the gps from [`Self::enter_standby`]. Does nothing if it isn't in
+    /// standby.
+    ///
+    /// Fails with [`Error::InBackupMode`] after [`Self::enter_backup_mode`],
+    /// as bytes can't wake it from that.
+    pub fn wake(&mut self) -> Result<(), Error<Tx::Error>> {
+        if self.backup {
+            error!("In backup mode, cycle the gps's power to wake it");
+            return Err(Error::InBackupMode);
+        }
+        if !self.standby {
+            return Ok(());
+        }
+
+        info!("Waking from standby");
+        self.run_exchange(Exchange::wake().max_tries(self.max_cmd_retries))?;
+        self.standby = false;
+        Ok(())
+    }
+
+    /// Put the gps into backup mode, where it only keeps its clock and saved
+    /// data, drawing a few tens of microamps.
+    ///
+    /// The gps stops responding entirely, and only leaves backup mode when
+    /// its power is cycled, either directly or by its EN pin (see
+    /// [`Self::power_cycle`]). Modules with a
+    /// WAKEUP or FORCE_ON pin also wake when it's pulled high, but the
+    /// Adafruit breakout doesn't have one.
+    ///
+    /// Commands fail with [`Error::InBackupMode`] until [`Self::is_reachable`]
+    /// finds the gps answering again.
+    pub fn enter_backup_mode(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_CMD_PERIODIC_MODE, 4 = perpetual backup
+        info!("Entering backup mode");
+        self.wake()?;
+        // Don't wait for an ack, it may power down before sending one.
+        self.write_cmd_raw(b"PMTK225", &[b"4"])?;
+        self.backup = true;
+        Ok(())
+    }
+
+    /// Whether the gps answers at all, such as after cycling its power to
+    /// leave backup mode. Quick, as it only tries twice.
+    pub fn is_reachable(&mut self) -> bool {
+        if self.check_ready(1).is_err() {
+            return false;
+        }
+        if self.backup {
+            info!("Left backup mode");
+            self.backup = false;
+            // Everything not saved to flash is lost
+            self.standby = false;
+            self.disabled_nmea_output = false;
+        }
+        true
+    }
+
+    /// Cut the gps's power with `power` and restore it, then wait for it to
+    /// boot. For when it's wedged and won't answer even a restart command,
+    /// and to leave [`Self::enter_backup_mode`].
+    ///
+    /// Everything not saved to flash is lost, as with leaving backup mode.
+    /// Without a backup battery so is the almanac, so the next fix is a cold
+    /// start.
+    pub fn power_cycle(&mut self, power: &mut impl PowerSwitch) -> Result<(), Error<Tx::Error>> {
+        info!("Power cycling");
+        power.set_power(false);
+        self.delay_us(POWER_OFF_US);
+
+        self.backup = false;
+        self.standby = false;
+        self.disabled_nmea_output = false;
+        #[cfg(feature = "locus")]
+        {
+            self.interrupted_dump = false;
+        }
+
+        power.set_power(true);
+        // Skips any garbage from as it lost power
+        self.wait_for_boot()?;
+        self.ensure_nmea_output_handled()
+    }
+
+    fn send_reboot_cmd(&mut self, cmd: &[u8]) -> Result<(), Error<Tx::Error>> {
+        self.wake()?;
+        self.with_retries(self.max_cmd_retries, |gps| {
+            gps.disabled_nmea_output = false;
+            gps.write_cmd_raw(cmd, &[])?;
+            gps.wait_for_boot()?;
+            #[cfg(feature = "locus")]
+            {
+                // Rebooting stops any dump
+                gps.interrupted_dump = false;
+            }
+            gps.ensure_nmea_output_handled()?;
+            Ok(())
+        })
+        .map(|(tries, ())| {
+            debug!("Took {} tries to reboot with {=[u8]:a}", tries, cmd);
+        })
+        .map_err(|(tries, err)| {
+            error!("Failed to reboot with {=[u8]:a} after {} tries", cmd, tries);
+            err
+        })
+    }
+
+    fn wait_for_boot(&mut self) -> Result<(), Error<Tx::Error>> {
+        // PMTK_A11.pdf:
+        //   In addition, when the GPS module is powered-on or restarted via
+        //   command, both "$PMTK010,001*2E<CR><LF>" and
+        //   $PMTK011,MTKGPS*08<CR><LF>" will be returned at the same time after
+        //   GPS engine has successfully completed boot-up stage.
+
+        let mut boot = BootWatch::default();
+        while !boot.booted()? {
+            self.check_deadline()?;
+            let read = self.read_cmd_raw();
+            boot.saw(read);
+        }
+
+        self.delay_us(WAIT_BEFORE_CHECKING_BOOT_READY_US);
+        self.flush_rx_queue();
+
+        // This has the beneficial side-effect of failing and retrying until
+        // we've gone through the undocumented boot messages, so we don't get
+        // those when we're expecting replies later.
+        //
+        // Because of those extra messages, we give ourselves extra tries here.
+        self.check_ready(MAX_READ_SPURIOUS_AFTER_BOOT_READY)?;
+
+        Ok(())
+    }
+
+    /// Send `$PMTK<num>` with `fields` and wait for it to be acked, for
+    /// commands this crate doesn't wrap yet. It's retried and NMEA output is
+    /// handled as for any other command.
+    ///
+    /// Fails with [`Error::InvalidArgument`] if `num` isn't three digits or
+    /// a field has a character that would end the field or sentence early.
+    pub fn send_raw(&mut self, num: &[u8; 3], fields: &[&[u8]]) -> Result<(), Error<Tx::Error>> {
+        check_raw_cmd(num, fields)?;
+        self.send_mtk_cmd(num, fields)
+    }
+
+    /// Like [`Self::send_raw`] for a query, returning the fields of the
+    /// `$PMTK<reply_num>` it's answered by. Fails if the reply has fewer than
+    /// `reply_min_fields`.
+    pub fn send_raw_for_reply(
+        &mut self,
+        num: &[u8; 3],
+        fields: &[&[u8]],
+        reply_num: &[u8; 3],
+        reply_min_fields: usize,
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        check_raw_cmd(num, fields)?;
+        if !reply_num.iter().all(u8::is_ascii_digit) {
+            error!("Invalid reply PMTK number {=[u8; 3]:a}", reply_num);
+            return Err(Error::InvalidArgument);
+        }
+        self.send_mtk_cmd_for_reply(num, fields, reply_num, reply_min_fields)
+    }
+
+    /// Check the gps is ready to receive commands.
+    ///
+    /// We do this by sending a cheap command and reading the reply.
+    ///
+    /// That means this is relatively expensive and so should only be used after
+    /// boot or before sending an especially long command we don't want to
+    /// retry.
+    ///
+    /// For cheap commands we may as well just retry the command itself.
+    fn check_ready(&mut self, max_tries: usize) -> Result<(), Error<Tx::Error>> {
+        // PMTK_Q_RELEASE, answered by PMTK_DT_RELEASE
+        let exchange = Exchange::for_reply(b"605", &[], b"705", 2).max_tries(max_tries);
+        let fields = self.run_exchange(exchange)?;
+        let firmware = FirmwareVersion::from_fields(fields);
+        info!("Gps ready ({})", &firmware);
+        self.firmware = Some(firmware);
+        Ok(())
+    }
+
+    fn send_mtk_cmd<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+    ) -> Result<(), Error<Tx::Error>> {
+        debug!("Trying to send PMTK {=[u8; 3]:a} for ack", num);
+        self.ensure_nmea_output_handled()?;
+        self.send_mtk_cmd_without_disabling_nmea(num, fields, self.max_cmd_retries)
+    }
+
+    fn send_mtk_cmd_without_disabling_nmea<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+        max_tries: usize,
+    ) -> Result<(), Error<Tx::Error>> {
+        let exchange = Exchange::for_ack(num, fields).max_tries(max_tries);
+        self.run_exchange(exchange).map(drop)
+    }
+
+    fn send_mtk_cmd_for_reply<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+        reply_num: &'i [u8; 3],
+        reply_min_fields: usize,
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        debug!(
+            "Trying to send PMTK {=[u8; 3]:a} for reply PMTK {=[u8; 3]:a}",
+            num, reply_num
+        );
+
+        self.ensure_nmea_output_handled()?;
+        let exchange = Exchange::for_reply(num, fields, reply_num, reply_min_fields)
+            .max_tries(self.max_cmd_retries);
+        self.run_exchange(exchange)
+    }
+
+    /// For queries answered with extra fields on the end of the ack. Returns
+    /// the fields after the status.
+    fn send_mtk_cmd_for_ack_fields<'i>(
+        &mut self,
+        num: &'i [u8; 3],
+        fields: &'i [&'i [u8]],
+        min_fields: usize,
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        debug!("Trying to send PMTK {=[u8; 3]:a} for ack with fields", num);
+        self.ensure_nmea_output_handled()?;
+        let exchange =
+            Exchange::for_ack_fields(num, fields, min_fields).max_tries(self.max_cmd_retries);
+        self.run_exchange(exchange)
+    }
+
+    /// Quectel's commands are `$PQXXX,W,...` to write a setting and
+    /// `$PQXXX,Q` or `R` to query one. They're answered by a sentence of the
+    /// same name, echoing the `W`, `Q` or `R`, and then either `OK`, `ERROR`
+    /// or the queried value.
+    #[cfg(feature = "quectel")]
+    fn send_pq_cmd<'i>(
+        &mut self,
+        name: &'i [u8],
+        fields: &'i [&'i [u8]],
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        debug!("Trying to send {=[u8]:a}", name);
+
+        if self.command_set()? != CommandSet::PmtkAndPq {
+            error!("{=[u8]:a} needs a Quectel module", name);
+            return Err(Error::NotSupported);
+        }
+
+        self.run_exchange(Exchange::for_pq(name, fields).max_tries(self.max_cmd_retries))
+    }
+
+    /// Get the gps ready to answer a command. It needs to be awake, and NMEA
+    /// output gets in the way of replies unless we're forwarding it.
+    fn ensure_nmea_output_handled(&mut self) -> Result<(), Error<Tx::Error>> {
+        self.wake()?;
+        #[cfg(feature = "locus")]
+        self.finish_interrupted_dump()?;
+        if self.forwards_nmea() {
+            trace!("Forwarding nmea output, leaving it enabled");
+            return Ok(());
+        }
+        self.ensure_nmea_output_disabled()
+    }
+
+    pub fn ensure_nmea_output_disabled(&mut self) -> Result<(), Error<Tx::Error>> {
+        self.wake()?;
+        if self.disabled_nmea_output {
+            debug!("Nmea output already disabled");
+            return Ok(());
+        }
+
+        debug!("Disabling nmea output");
+        self.send_nmea_output_cmd(&NmeaOutputConfig::default())?;
+        self.disabled_nmea_output = true;
+        Ok(())
+    }
+
+    /// Choose which NMEA sentences the gps sends, and how often.
+    ///
+    /// Commands turn NMEA output off again before sending unless it's being
+    /// forwarded with [`Self::forward_nmea_to`], so set that up first to keep
+    /// getting sentences while configuring the gps.
+    pub fn set_nmea_output(&mut self, config: NmeaOutputConfig) -> Result<(), Error<Tx::Error>> {
+        info!("Setting nmea output to {}", config);
+        if config.is_disabled() {
+            return self.ensure_nmea_output_disabled();
+        }
+        self.wake()?;
+        self.send_nmea_output_cmd(&config)?;
+        self.disabled_nmea_output = false;
+        Ok(())
+    }
+
+    fn send_nmea_output_cmd(&mut self, config: &NmeaOutputConfig) -> Result<(), Error<Tx::Error>> {
+        let fields = config.fields().ok_or_else(|| {
+            error!("Nmea output rate out of range");
+            Error::InvalidArgument
+        })?;
+        // PMTK_API_SET_NMEA_OUTPUT
+        self.send_mtk_cmd_without_disabling_nmea(
+            b"314",
+            &fields,
+            MAX_CMD_TRIES_WITHOUT_NMEA_DISABLED,
+        )
+    }
+
+    /// Drive `exchange` to the end, forwarding NMEA if we're set up to.
+    fn run_exchange(
+        &mut self,
+        exchange: Exchange<Tx::Error>,
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        let mut exchange = exchange;
+        if self.forwards_nmea() {
+            exchange = exchange.forward_nmea();
+        }
+        if self.on_unsolicited.is_some() {
+            exchange = exchange.forward_system_messages();
+        }
+        let result = loop {
+            match exchange.poll() {
+                Action::Write(bytes) => {
+                    // Each try starts with a write, so this is between tries
+                    if let Err(err) = self.check_deadline() {
+                        break Err(err);
+                    }
+                    if let Err(err) = self.write_raw(bytes) {
+                        exchange.attempt_failed(err);
+                    }
+                }
+                Action::Read => {
+                    if let Err(err) = self.read_into_exchange(&mut exchange) {
+                        break Err(err);
+                    }
+                }
+                Action::Forward(line) => self.forward_nmea(&line),
+                Action::Unsolicited(line) => self.deliver_unsolicited(&line),
+                Action::Delay(us) => self.delay_us(us),
+                Action::Done(result) => break result,
+            }
+        };
+        self.stats.add(exchange.stats());
+        result
+    }
+
+    /// Push whatever's in the rx queue into `exchange`, waiting for
+    /// something to arrive if it's empty. Only fails if the deadline passes
+    /// while waiting.
+    fn read_into_exchange(
+        &mut self,
+        exchange: &mut Exchange<Tx::Error>,
+    ) -> Result<(), Error<Tx::Error>> {
+        let start = self.now_us();
+        loop {
+            self.check_deadline()?;
+            if self.us_since(start) > self.read_timeout_us as u64 {
+                exchange.timed_out();
+                return Ok(());
+            }
+
+            // Getting a grant can fail if the queue is being written to
+            match self.rx.read() {
+                Ok(grant) => {
+                    let used = exchange.push_bytes(grant.buf());
+                    self.observe_rx(&grant.buf()[..used]);
+                    grant.release(used);
+                    return Ok(());
+                }
+                Err(_) => {
+                    self.delay_us(1);
+                }
+            }
+        }
+    }
+
+    /// Waits for the next sentence named `name`, without sending anything.
+    fn read_reply_raw(
+        &mut self,
+        name: &[u8],
+        min_fields: usize,
+    ) -> Result<Vec<Vec<u8>>, Error<Tx::Error>> {
+        self.run_exchange(Exchange::listen(name, min_fields))
+    }
+
+    fn write_cmd_raw<'i>(
+        &mut self,
+        name: &'i [u8],
+        fields: &'i [&'i [u8]],
+    ) -> Result<(), Error<Tx::Error>> {
+        let mut cmd = Vec::new();
+        cmd::serialize(name, fields, &mut cmd);
+
+        trace!("Sending {=[u8]:a}", &cmd);
+        self.write_raw(&cmd)
+    }
+
+    fn write_bin_packet_raw(&mut self, id: u16, payload: &[u8]) -> Result<(), Error<Tx::Error>> {
+        let mut packet = Vec::new();
+        cmd::binary::serialize(id, payload, &mut packet);
+        trace!("Sending binary packet {} ({} bytes)", id, packet.len());
+        self.write_raw(&packet)
+    }
+
+    /// Returns (id, payload), skipping anything before the next preamble.
+    fn read_bin_packet_raw(&mut self) -> Result<(u16, Vec<u8>), Error<Tx::Error>> {
+        let mut packet = Vec::new();
+        let start = self.now_us();
+
+        'outer: loop {
+            if self.us_since(start) > self.read_timeout_us as u64 {
+                trace!("Read timed out");
+                return Err(Error::ReadTimeout);
+            }
+
+            let grant = match self.rx.read() {
+                Ok(grant) => grant,
+                Err(_) => {
+                    self.delay_us(1);
+                    continue 'outer;
+                }
+            };
+
+            let mut grant_used = 0;
+            for &byte in grant.buf() {
+                grant_used += 1;
+                packet.push(byte);
+
+                let pos = packet.len() - 1;
+                if pos < 2 && byte != cmd::binary::PREAMBLE[pos] {
+                    packet.clear();
+                    if byte == cmd::binary::PREAMBLE[0] {
+                        packet.push(byte);
+                    }
+                } else if packet.len() >= 4 {
+                    let len = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+                    if !(cmd::binary::OVERHEAD..=MAX_BIN_PACKET_LEN).contains(&len) {
+                        trace!("Resyncing");
+                        self.stats.resyncs += 1;
+                        packet.clear();
+                    } else if packet.len() == len {
+                        self.observe_rx(&grant.buf()[..grant_used]);
+                        grant.release(grant_used);
+                        break 'outer;
+                    }
+                }
+            }
+            self.observe_rx(&grant.buf()[..grant_used]);
+            grant.release(grant_used);
+        }
+
+        let (id, payload) = cmd::binary::parse(&packet)?;
+        let took = self.us_since(start);
+        trace!("Received binary packet {} (took {=u64:us})", id, took);
+        Ok((id, payload.to_vec()))
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Error<Tx::Error>> {
+        if let Some(observer) = &mut self.observer {
+            observer.on_tx(bytes);
+        }
+
+        let start = self.now_us();
+        for &byte in bytes {
+            'byte: loop {
+                match self.tx.write(byte) {
+                    Ok(()) => break 'byte,
+                    Err(nb::Error::WouldBlock) => {
+                        if self.us_since(start) > self.write_timeout_us as u64 {
+                            trace!("Write timed out");
+                            return Err(Error::WriteTimeout);
+                        }
+                        self.delay_us(1);
+                    }
+                    Err(nb::Error::Other(err)) => {
+                        return Err(Error::Transmit(err));
+                    }
+                }
+            }
+        }
+
+        let took = self.us_since(start);
+        trace!("Wrote (took {=u64:us})", took);
+
+        Ok(())
+    }
+
+    /// Wait until everything written has been sent.
+    fn flush_tx(&mut self) -> Result<(), Error<Tx::Error>> {
+        let start = self.now_us();
+        loop {
+            match self.tx.flush() {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::WouldBlock) => {
+                    if self.us_since(start) > self.write_timeout_us as u64 {
+                        trace!("Flush timed out");
+                        return Err(Error::WriteTimeout);
+                    }
+                    self.delay_us(1);
+                }
+                Err(nb::Error::Other(err)) => return Err(Error::Transmit(err)),
+            }
+        }
+    }
+
+    pub fn flush_rx_queue(&mut self) {
+        loop {
+            match self.rx.split_read() {
+                Ok(grant) => {
+                    let (first, second) = grant.bufs();
+                    self.observe_rx(first);
+                    self.observe_rx(second);
+                    let len = grant.combined_len();
+                    grant.release(len);
+                    break;
+                }
+                // Already empty
+                Err(bbqueue::Error::InsufficientSize) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// See [`Commander::pump`].
+    pub(crate) fn forward_waiting(&mut self) {
+        let grant = match self.rx.split_read() {
+            Ok(grant) => grant,
+            // Empty, or being written to
+            Err(_) => return,
+        };
+        let (first, second) = grant.bufs();
+
+        let mut line = Vec::new();
+        let mut used = 0;
+        for (i, &byte) in first.iter().chain(second).enumerate() {
+            if byte == b'$' && !line.is_empty() {
+                trace!("Resyncing");
+                self.stats.resyncs += 1;
+                line.clear();
+            }
+            line.push(byte);
+            if cmd::is_too_long(&line) {
+                // Dropped, or it would never end and the queue would stay
+                // full
+                trace!("Sentence too long");
+                self.stats.too_long += 1;
+                line.clear();
+                used = i + 1;
+                continue;
+            }
+            if !line.ends_with(b"\r\n") {
+                continue;
+            }
+            used = i + 1;
+            match cmd::parse(&line) {
+                Ok((name, _)) if is_nmea(&name) => self.forward_nmea(&line),
+                Ok(_) => self.deliver_unsolicited(&line),
+                Err(err) => {
+                    debug!("Dropping unparsable sentence: {}", err);
+                    self.stats.count_parse_error(err);
+                }
+            }
+            line.clear();
+        }
+
+        self.observe_rx(&first[..used.min(first.len())]);
+        self.observe_rx(&second[..used.saturating_sub(first.len())]);
+        grant.release(used);
+    }
+
+    fn forwards_nmea(&self) -> bool {
+        self.nmea_out.is_some() || self.on_unsolicited.is_some()
+    }
+
+    fn forward_nmea(&mut self, line: &[u8]) {
+        self.deliver_unsolicited(line);
+        let nmea_out = match &mut self.nmea_out {
+            Some(nmea_out) => nmea_out,
+            None => return,
+        };
+        match nmea_out.grant_exact(line.len()) {
+            Ok(mut grant) => {
+                grant.buf().copy_from_slice(line);
+                grant.commit(line.len());
+            }
+            Err(_) => {
+                warn!("Nmea out queue full, dropping {=[u8]:a}", line);
+            }
+        }
+    }
+
+    fn deliver_unsolicited(&mut self, line: &[u8]) {
+        if let Some(handler) = &mut self.on_unsolicited {
+            handler(line);
+        }
+    }
+
+    fn observe_rx(&mut self, bytes: &[u8]) {
+        if let Some(observer) = &mut self.observer {
+            observer.on_rx(bytes);
+        }
+    }
+
+    fn read_cmd_raw(&mut self) -> Result<cmd::RawCmd, Error<Tx::Error>> {
+        let mut fields = self.run_exchange(Exchange::next_sentence())?;
+        let name = fields.remove(0);
+        Ok((name, fields))
+    }
+
+    fn with_retries<Op, T>(
+        &mut self,
+        max_tries: usize,
+        mut op: Op,
+    ) -> WithTries<T, Error<Tx::Error>>
+    where
+        Op: FnMut(&mut Self) -> Result<T, Error<Tx::Error>>,
+    {
+        let mut tries = 0;
+        loop {
+            if let Err(err) = self.check_deadline() {
+                break Err((tries, err));
+            }
+            tries += 1;
+            match op(self) {
+                Ok(val) => break Ok((tries, val)),
+                Err(err @ (Error::DeadlineExceeded | Error::GpsSaysUnsupportedCommand)) => {
+                    break Err((tries, err))
+                }
+                Err(err) if tries > max_tries => {
+                    self.stats.count_failure(&err);
+                    break Err((tries, err));
+                }
+                Err(err) => {
+                    self.stats.count_failure(&err);
+                    self.stats.retries += 1;
+                    trace!("Delaying before retry");
+                    self.delay_us(DELAY_BEFORE_RETRY_US);
+                }
+            }
+        }
+    }
+
+    fn check_deadline(&mut self) -> Result<(), Error<Tx::Error>> {
+        if let Some((start, budget_us)) = self.deadline {
+            if self.us_since(start) >= budget_us {
+                debug!("Deadline exceeded");
+                return Err(Error::DeadlineExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
+        self.clock.delayed(us);
+    }
+
+    fn now_us(&mut self) -> u64 {
+        self.clock.now_us()
+    }
+
+    fn us_since(&mut self, start: u64) -> u64 {
+        self.now_us().wrapping_sub(start)
+    }
+}
+
+/// The command for `item`, if it's one PMTK command answered by an ack that
+/// doesn't change anything [`Gps`] keeps track of, so it can be pipelined.
+fn single_cmd(item: &ConfigItem) -> Option<SingleCmd> {
+    let mut buf = [0_u8; u32::FORMATTED_SIZE_DECIMAL];
+    let (num, fields): (&[u8; 3], Vec<&[u8]>) = match *item {
+        // PMTK_API_SET_SBAS_ENABLED
+        ConfigItem::Sbas(enabled) => (b"313", vec![if enabled { b"1" } else { b"0" }]),
+        // PMTK_API_SET_DGPS_MODE
+        ConfigItem::DgpsMode(mode) => (b"301", vec![mode.field()]),
+        // PMTK_SET_NMEA_UPDATERATE
+        ConfigItem::UpdateRateMs(ms) if UPDATE_RATE_MS.contains(&ms) => {
+            (b"220", vec![u32_to_base10_ascii(ms, &mut buf)])
+        }
+        // PMTK_API_SET_DATUM
+        ConfigItem::Datum(datum) => (
+            b"330",
+            vec![u32_to_base10_ascii(datum.index()? as u32, &mut buf)],
+        ),
+        // PMTK_LOCUS_CONFIG
+        #[cfg(feature = "locus")]
+        ConfigItem::LoggerInterval(secs) => (
+            b"187",
+            vec![
+                logger::Trigger::Interval(secs).mode_field(),
+                u32_to_base10_ascii(secs, &mut buf),
+            ],
+        ),
+        #[cfg(feature = "locus")]
+        ConfigItem::LoggerTrigger(trigger) => (
+            b"187",
+            vec![
+                trigger.mode_field(),
+                u32_to_base10_ascii(trigger.value(), &mut buf),
+            ],
+        ),
+        // PMTK_LOCUS_STOP_LOGGER
+        #[cfg(feature = "locus")]
+        ConfigItem::StartLogging => (b"185", vec![b"0"]),
+        #[cfg(feature = "locus")]
+        ConfigItem::StopLogging => (b"185", vec![b"1"]),
+        _ => return None,
+    };
+    Some((*num, fields.into_iter().map(<[u8]>::to_vec).collect()))
+}
+
+/// Checks a command from outside the crate will serialize to one sentence.
+fn check_raw_cmd<E>(num: &[u8; 3], fields: &[&[u8]]) -> Result<(), Error<E>> {
+    if !num.iter().all(u8::is_ascii_digit) {
+        error!("Invalid PMTK number {=[u8; 3]:a}", num);
+        return Err(Error::InvalidArgument);
+    }
+    let breaks_framing = |byte: &u8| matches!(byte, b',' | b'*' | b'$' | b'\r' | b'\n');
+    if fields.iter().any(|field| field.iter().any(breaks_framing)) {
+        error!("Field of PMTK {=[u8; 3]:a} would break the sentence", num);
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Returns a subslice of the input buffer containing the written bytes,
+/// starting from the same address in memory as the input slice.
+/// What's been read while waiting for the gps to boot.
+///
+/// PMTK_A11.pdf:
+///   In addition, when the GPS module is powered-on or restarted via
+///   command, both "$PMTK010,001*2E<CR><LF>" and
+///   $PMTK011,MTKGPS*08<CR><LF>" will be returned at the same time after
+///   GPS engine has successfully completed boot-up stage.
+#[derive(Debug, Default)]
+pub(crate) struct BootWatch {
+    seen_boot_sys_msg: bool,
+    seen_mtkgps: bool,
+    read_errors: usize,
+    read_spurious: usize,
+}
+
+impl BootWatch {
+    /// Fails if it's taken too many reads.
+    pub(crate) fn booted<E>(&self) -> Result<bool, Error<E>> {
+        if self.seen_boot_sys_msg && self.seen_mtkgps {
+            info!("Booted");
+            return Ok(true);
+        }
+
+        if self.read_errors > MAX_READ_ERRORS_ON_BOOT {
+            error!("Exceeded MAX_READ_ERRORS_ON_BOOT");
+            return Err(Error::BootFailed);
+        }
+
+        if self.read_spurious > MAX_READ_SPURIOUS_BEFORE_BOOT {
+            error!("Exceeded MAX_READ_SPURIOUS_ON_BOOT");
+            return Err(Error::BootFailed);
+        }
+        Ok(false)
+    }
+
+    pub(crate) fn saw<E>(&mut self, read: Result<cmd::RawCmd, Error<E>>) {
+        match read {
+            Ok((name, fields)) => {
+                if name == b"PMTK010" && fields == [b"001"] {
+                    debug!("Saw boot sys msg");
+                    self.seen_boot_sys_msg = true;
+                } else if name == b"PMTK011" && fields == [b"MTKGPS"] {
+                    debug!("Saw boot mtkgps");
+                    self.seen_mtkgps = true;
+                } else {
+                    debug!("Read spurious on boot: {=[u8]:a}", name);
+                    self.read_spurious += 1;
+                }
+            }
+            Err(_) => {
+                debug!("Read error while waiting for boot");
+                self.read_errors += 1;
+            }
+        }
+    }
+}
+
+/// How many data packets a LOCUS dump's start packet says follow.
+#[cfg(feature = "locus")]
+pub(crate) fn locus_packet_count<E>(locus_start: &[Vec<u8>]) -> Result<usize, Error<E>> {
+    if locus_start[0] != b"0" {
+        error!("Expected LOCUS start packet");
+        return Err(Error::Protocol(RawBytes::from_parsed(
+            b"PMTKLOX",
+            locus_start,
+        )));
+    }
+    Ok(cmd::parse::integer_field(&locus_start[1])? as usize)
+}
+
+/// Checks `locus_data` is data packet number `n`.
+#[cfg(feature = "locus")]
+pub(crate) fn check_locus_data<E>(n: usize, locus_data: &[Vec<u8>]) -> Result<(), Error<E>> {
+    if locus_data[0] != b"1" {
+        error!("Expected LOCUS data packet");
+        return Err(Error::Protocol(RawBytes::from_parsed(
+            b"PMTKLOX", locus_data,
+        )));
+    }
+
+    let actual_n = cmd::parse::integer_field(&locus_data[1])? as usize;
+    if actual_n != n {
+        error!(
+            "Expected LOCUS data packet number {}, got number {}",
+            n, actual_n
+        );
+        return Err(Error::Protocol(RawBytes::from_parsed(
+            b"PMTKLOX", locus_data,
+        )));
+    }
+    Ok(())
+}
+
+/// Feeds the words of a data packet [`check_locus_data`] passed to `parser`.
+#[cfg(feature = "locus")]
+pub(crate) fn feed_locus_data<F, E>(
+    locus_data: &[Vec<u8>],
+    parser: &mut logger::Parser<F>,
+) -> Result<(), Error<E>>
+where
+    F: FnMut(logger::Packet),
+{
+    for word in &locus_data[2..] {
+        let mut bytes = [0_u8; 4];
+        hex::decode_to_slice(word, &mut bytes).map_err(|_| {
+            error!("Invalid LOCUS data word {=[u8]:a}", &word[..]);
+            Error::Parse(ParseError::ParseField, RawBytes::new(word))
+        })?;
+        parser.feed(&bytes);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "locus")]
+pub(crate) fn check_locus_end<E>(locus_end: &[Vec<u8>]) -> Result<(), Error<E>> {
+    if locus_end[0] != b"2" {
+        error!("Expected LOCUS end packet");
+        return Err(Error::Protocol(RawBytes::from_parsed(
+            b"PMTKLOX", locus_end,
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn u32_to_base10_ascii(val: u32, out: &mut [u8; u32::FORMATTED_SIZE_DECIMAL]) -> &[u8] {
+    lexical_core::write(val, out)
+}
+
+/// The year, month, day, hour, minute and second of `time`, as PMTK740 and
+/// PMTK741 take them.
+fn utc_fields(time: UtcDateTime, bufs: &mut [[u8; u32::FORMATTED_SIZE_DECIMAL]; 6]) -> [&[u8]; 6] {
+    let [year, month, day, hour, minute, second] = bufs;
+    [
+        u32_to_base10_ascii(time.year() as u32, year),
+        u32_to_base10_ascii(time.month() as u32, month),
+        u32_to_base10_ascii(time.day() as u32, day),
+        u32_to_base10_ascii(time.hour() as u32, hour),
+        u32_to_base10_ascii(time.minute() as u32, minute),
+        u32_to_base10_ascii(time.second() as u32, second),
+    ]
+}
+
+/// Writes hundredths as a decimal, such as 40 as `0.40`. `val` must be less
+/// than 1000.
+fn centi_to_ascii(val: u16, out: &mut [u8; 4]) -> &[u8] {
+    debug_assert!(val < 1000);
+    let digit = |n: u16| b'0' + (n % 10) as u8;
+    *out = [digit(val / 100), b'.', digit(val / 10), digit(val)];
+    out
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Error<TxError> {
+    /// The gps behaved in a way contrary to our understanding of the spec,
+    /// with the start of what it sent.
+    Protocol(RawBytes),
+    GpsSaysInvalidCommand,
+    /// Not retried, as asking again won't change its answer.
+    GpsSaysUnsupportedCommand,
+    GpsSaysActionFailed,
+    BootFailed,
+    ReadTimeout,
+    WriteTimeout,
+    Transmit(TxError),
+    /// With the start of the sentence or field that couldn't be parsed, if
+    /// there was one.
+    Parse(ParseError, RawBytes),
+    /// The gps doesn't have the command, so we didn't send it.
+    NotSupported,
+    /// Outside the range the command accepts, so we didn't send it.
+    InvalidArgument,
+    /// The gps can't hear commands, see [`Gps::enter_backup_mode`].
+    InBackupMode,
+    /// Gave up at the end of the budget given to [`Gps::within_deadline`].
+    DeadlineExceeded,
+    /// A line went on longer than any sentence can, with its start. Reading
+    /// carries on from the next `$`.
+    SentenceTooLong(RawBytes),
+}
+
+impl<TxError> From<ParseError> for Error<TxError> {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err, RawBytes::default())
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "locus")]
+    use crate::sim::Sim;
+    use crate::{
+        mock::{self, MockSerial, MockTrans},
+        power::{ExtendedFix, PeriodicTimes},
+        RxBuf,
+    };
+    #[cfg(feature = "locus")]
+    use std::rc::Rc;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    #[test]
+    fn test_hot_restart_from_capture() {
+        let expects = include!("../test_assets/hot_restart_mock.rs");
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.hot_restart().unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_other_restarts() {
+        check_restart(b"$PMTK102*31\r\n", |gps| gps.warm_restart());
+        check_restart(b"$PMTK103*30\r\n", |gps| gps.cold_restart());
+        check_restart(b"$PMTK104*37\r\n", |gps| gps.factory_reset());
+    }
+
+    /// Expecting the same boot as a hot restart after `cmd`.
+    fn check_restart<E: core::fmt::Debug>(
+        cmd: &[u8],
+        restart: impl FnOnce(&mut mock::MockGps) -> Result<(), E>,
+    ) {
+        let mut expects = include!("../test_assets/hot_restart_mock.rs").to_vec();
+        expects[0] = MockTrans::write_many(cmd);
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        restart(&mut gps).unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_times_out_by_clock() {
+        // No answer to any try
+        let expects = vec![MockTrans::write_many(b"$PMTK220,200*2C\r\n"); MAX_CMD_TRIES + 1];
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut mock = MockSerial::new(&expects, rx_producer);
+        // Each look at the clock is a tenth of a second later
+        let mut now = 0;
+        let mut reads = 0;
+        let clock = || {
+            now += 100_000;
+            reads += 1;
+            now
+        };
+        let mut gps = Gps::builder(rx_consumer, mock.clone(), mock.clone())
+            .already_disabled_nmea_output(true)
+            .clock(clock)
+            .build();
+
+        assert_eq!(gps.set_update_rate_ms(200), Err(Error::ReadTimeout));
+
+        mock.done();
+        // Rather than half a million one microsecond delays a try
+        assert!(reads < 100);
+    }
+
+    #[test]
+    fn test_builder_sets_retries_and_timeouts() {
+        let expects = vec![MockTrans::write_many(b"$PMTK220,200*2C\r\n"); 3];
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut mock = MockSerial::new(&expects, rx_producer);
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut gps = Gps::builder(rx_consumer, mock.clone(), mock.clone())
+            .already_disabled_nmea_output(true)
+            .max_cmd_retries(2)
+            .read_timeout_us(10)
+            .clock(CountedDelays::default())
+            .observe_traffic(CountWrites(writes.clone()))
+            .build();
+
+        assert_eq!(gps.set_update_rate_ms(200), Err(Error::ReadTimeout));
+
+        mock.done();
+        assert_eq!(writes.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_stats() {
+        const CMD: &[u8] = b"$PMTK220,200*2C\r\n";
+        let expects = [
+            MockTrans::write_many(CMD),
+            MockTrans::read_many(b"$PMTK001,220,3*31\r\n"),
+            MockTrans::write_many(CMD),
+            MockTrans::read_many(b"$PMTK0"),
+            MockTrans::read_many(b"$PMTK010,001*2E\r\n"),
+            // No answer
+            MockTrans::write_many(CMD),
+            MockTrans::write_many(CMD),
+            MockTrans::read_many(b"$PMTK001,220,3*30\r\n"),
+        ];
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut mock = MockSerial::new(&expects, rx_producer);
+        let mut gps = Gps::builder(rx_consumer, mock.clone(), mock.clone())
+            .already_disabled_nmea_output(true)
+            .read_timeout_us(1_000)
+            .build();
+
+        gps.set_update_rate_ms(200).unwrap();
+
+        mock.done();
+        assert_eq!(
+            *gps.stats(),
+            Stats {
+                retries: 3,
+                resyncs: 1,
+                checksum_failures: 1,
+                timeouts: 1,
+                spurious: 1,
+                too_long: 0,
+            }
+        );
+        gps.reset_stats();
+        assert_eq!(*gps.stats(), Stats::default());
+    }
+
+    struct CountWrites(Arc<AtomicUsize>);
+
+    impl TrafficObserver for CountWrites {
+        fn on_tx(&mut self, _bytes: &[u8]) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_rx(&mut self, _bytes: &[u8]) {}
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_apply_carries_on_past_failures() {
+        let failing_attempt = [
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,2*3F\r\n"),
+        ];
+        // Both sent before either ack
+        let mut expects = vec![
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,2*3F\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        // Then the failure is retried on its own until we give up
+        expects.extend(
+            failing_attempt
+                .iter()
+                .cycle()
+                .take(failing_attempt.len() * MAX_CMD_TRIES)
+                .cloned(),
+        );
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let results = gps.apply(&[ConfigItem::LoggerInterval(5), ConfigItem::Sbas(true)]);
+
+        assert_eq!(results, vec![Err(Error::GpsSaysActionFailed), Ok(())]);
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_apply_with_one_retry() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,2*3F\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            // The one retry left
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,2*3F\r\n"),
+        ];
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut mock = MockSerial::new(&expects, rx_producer);
+        let mut gps = Gps::builder(rx_consumer, mock.clone(), mock.clone())
+            .already_disabled_nmea_output(true)
+            .max_cmd_retries(1)
+            .build();
+
+        let results = gps.apply(&[ConfigItem::LoggerInterval(5), ConfigItem::Sbas(true)]);
+
+        assert_eq!(results, vec![Err(Error::GpsSaysActionFailed), Ok(())]);
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_apply_without_retries() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,2*3F\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            // Nothing sent again
+        ];
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut mock = MockSerial::new(&expects, rx_producer);
+        let mut gps = Gps::builder(rx_consumer, mock.clone(), mock.clone())
+            .already_disabled_nmea_output(true)
+            .max_cmd_retries(0)
+            .build();
+
+        let results = gps.apply(&[ConfigItem::LoggerInterval(5), ConfigItem::Sbas(true)]);
+
+        assert_eq!(results, vec![Err(Error::GpsSaysActionFailed), Ok(())]);
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_apply_matches_acks_out_of_order() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK187,1,15*09\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::write_many(b"$PMTK185,0*22\r\n"),
+            MockTrans::write_many(b"$PMTK220,200*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            MockTrans::read_many(b"$PMTK001,185,3*3C\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,3*3E\r\n"),
+            MockTrans::read_many(b"$PMTK001,220,3*30\r\n"),
+            // Changes what we keep track of, so waits until the others are done
+            MockTrans::write_many(b"$PMTK314,0,1,0,0,0,5,0,0,0,0,0,0,0,0,0,0,0,0,0*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let results = gps.apply(&[
+            ConfigItem::LoggerInterval(15),
+            ConfigItem::Sbas(true),
+            ConfigItem::StartLogging,
+            ConfigItem::UpdateRateMs(200),
+            ConfigItem::NmeaOutput(NmeaOutputConfig {
+                rmc: 1,
+                gsv: 5,
+                ..NmeaOutputConfig::default()
+            }),
+        ]);
+
+        assert_eq!(results, vec![Ok(()); 5]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_enter_backup_mode() {
+        let expects = [MockTrans::write_many(b"$PMTK225,4*2F\r\n")];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.enter_backup_mode().unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_update_rate() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK220,200*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,220,3*30\r\n"),
+            MockTrans::write_many(b"$PMTK400*36\r\n"),
+            MockTrans::read_many(b"$PMTK500,200,0,0,0,0*29\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_update_rate_ms(200).unwrap();
+        assert_eq!(gps.update_rate_ms(), Ok(200));
+        assert_eq!(gps.set_update_rate_ms(50), Err(Error::InvalidArgument));
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_static_nav_threshold_falls_back_to_pmtk397() {
+        let expects = [
+            // Only asked once, as it won't change its mind
+            MockTrans::write_many(b"$PMTK386,0.40*09\r\n"),
+            MockTrans::read_many(b"$PMTK001,386,1*3F\r\n"),
+            MockTrans::write_many(b"$PMTK397,0.40*09\r\n"),
+            MockTrans::read_many(b"$PMTK001,397,3*3D\r\n"),
+            // Remembered for next time
+            MockTrans::write_many(b"$PMTK397,0.00*0D\r\n"),
+            MockTrans::read_many(b"$PMTK001,397,3*3D\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_static_nav_threshold(40).unwrap();
+        gps.set_static_nav_threshold(0).unwrap();
+        assert_eq!(
+            gps.set_static_nav_threshold(45),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_datum() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK330,1*2F\r\n"),
+            MockTrans::read_many(b"$PMTK001,330,3*30\r\n"),
+            MockTrans::write_many(b"$PMTK430*35\r\n"),
+            MockTrans::read_many(b"$PMTK530,1*29\r\n"),
+            MockTrans::write_many(b"$PMTK330,100*2F\r\n"),
+            MockTrans::read_many(b"$PMTK001,330,3*30\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_datum(Datum::TokyoMean).unwrap();
+        assert_eq!(gps.datum(), Ok(Datum::TokyoMean));
+        gps.set_datum(Datum::Other(100)).unwrap();
+        assert_eq!(
+            gps.set_datum(Datum::Other(223)),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_search_mode() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK353,1,1,0,0,0*2B\r\n"),
+            MockTrans::read_many(b"$PMTK001,353,3*35\r\n"),
+            MockTrans::write_many(b"$PMTK355*31\r\n"),
+            MockTrans::read_many(b"$PMTK001,355,3,1,1*33\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let mode = SearchMode {
+            gps: true,
+            glonass: true,
+            galileo: false,
+        };
+        gps.set_search_mode(mode).unwrap();
+        assert_eq!(gps.search_mode(), Ok(mode));
+        assert_eq!(
+            gps.set_search_mode(SearchMode {
+                gps: false,
+                glonass: false,
+                galileo: false,
+            }),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_antenna_reports() {
+        let expects = [MockTrans::write_many(b"$PGCMD,33,1*6C\r\n")];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_antenna_reports(true).unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_firmware_version() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let expected = FirmwareVersion {
+            release: b"AXN_2.31_3339_13101700".to_vec(),
+            build_id: b"5632".to_vec(),
+            model: Some(b"PA6H".to_vec()),
+        };
+        assert_eq!(gps.firmware_version(), Ok(&expected));
+        // Without asking again
+        assert_eq!(gps.firmware_version(), Ok(&expected));
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_nmea_output() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK314,0,1,0,0,0,5,0,0,0,0,0,0,0,0,0,0,0,0,0*2C\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+            // Commands turn it off again
+            MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_nmea_output(NmeaOutputConfig {
+            rmc: 1,
+            gsv: 5,
+            ..NmeaOutputConfig::default()
+        })
+        .unwrap();
+        gps.set_sbas(true).unwrap();
+        // Already off
+        gps.set_nmea_output(NmeaOutputConfig::default()).unwrap();
+        assert_eq!(
+            gps.set_nmea_output(NmeaOutputConfig {
+                gga: 6,
+                ..NmeaOutputConfig::default()
+            }),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_upload_epo() {
+        let bin = |id, payload: &[u8]| {
+            let mut packet = Vec::new();
+            cmd::binary::serialize(id, payload, &mut packet);
+            packet
+        };
+        let epo_packet = |seq: u16, records: &[u8]| {
+            let mut payload = seq.to_le_bytes().to_vec();
+            payload.extend_from_slice(records);
+            payload.resize(2 + 180, 0);
+            bin(722, &payload)
+        };
+        // Four records, so the second packet is padded
+        let epo: Vec<u8> = (0..240).map(|i| i as u8).collect();
+
+        let expects = [
+            MockTrans::write_many(b"$PMTK253,1,0*37\r\n"),
+            MockTrans::write_many(&epo_packet(0, &epo[..180])),
+            MockTrans::read_many(&bin(723, &[0, 0, 1])),
+            MockTrans::write_many(&epo_packet(1, &epo[180..])),
+            MockTrans::read_many(&bin(723, &[1, 0, 0])),
+            // Resent as it failed
+            MockTrans::write_many(&epo_packet(1, &epo[180..])),
+            MockTrans::read_many(&bin(723, &[1, 0, 1])),
+            MockTrans::write_many(&epo_packet(u16::MAX, &[])),
+            MockTrans::read_many(&bin(723, &[0xFF, 0xFF, 1])),
+            MockTrans::write_many(&bin(253, &[0, 0, 0, 0, 0])),
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let mut progress = EpoProgress::default();
+        gps.upload_epo(&epo, &mut progress).unwrap();
+        assert_eq!(progress, EpoProgress::default());
+        assert_eq!(
+            gps.upload_epo(&epo[..59], &mut progress),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_epo_status() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK607*33\r\n"),
+            MockTrans::read_many(
+                b"$PMTK707,56,1468,172800,1470,151200,1468,259200,1468,259200*1F\r\n",
+            ),
+            MockTrans::write_many(b"$PMTK127*36\r\n"),
+            MockTrans::read_many(b"$PMTK001,127,3*34\r\n"),
+            MockTrans::write_many(b"$PMTK607*33\r\n"),
+            MockTrans::read_many(b"$PMTK707,0,0,0,0,0,0,0,0,0*2E\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        assert_eq!(
+            gps.epo_status(),
+            Ok(EpoStatus {
+                sets: 56,
+                valid_from: UtcDateTime::from_gps_time(1468, 172800),
+                valid_until: UtcDateTime::from_gps_time(1470, 151200 + 6 * 60 * 60),
+            })
+        );
+        gps.clear_epo().unwrap();
+        assert_eq!(
+            gps.epo_status(),
+            Ok(EpoStatus {
+                sets: 0,
+                valid_from: None,
+                valid_until: None,
+            })
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_reference_time() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK740,2022,3,4,5,6,7*00\r\n"),
+            MockTrans::read_many(b"$PMTK001,740,3*33\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let time = UtcDateTime::new(2022, 3, 4, 5, 6, 7, 890).unwrap();
+        gps.set_reference_time(time).unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_reference_position() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK741,24.772816,-121.022636,160.0,2022,3,4,5,6,7*13\r\n"),
+            MockTrans::read_many(b"$PMTK001,741,3*32\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let time = UtcDateTime::new(2022, 3, 4, 5, 6, 7, 0).unwrap();
+        gps.set_reference_position(24.772816, -121.02264, 160.0, time)
+            .unwrap();
+        assert_eq!(
+            gps.set_reference_position(91.0, 0.0, 0.0, time),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_corrections_read_back() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK413*34\r\n"),
+            MockTrans::read_many(b"$PMTK513,1*28\r\n"),
+            MockTrans::write_many(b"$PMTK301,2*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,301,3*32\r\n"),
+            MockTrans::write_many(b"$PMTK401*37\r\n"),
+            MockTrans::read_many(b"$PMTK501,2*28\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        assert_eq!(gps.sbas_enabled(), Ok(true));
+        gps.set_dgps_mode(DgpsMode::Sbas).unwrap();
+        assert_eq!(gps.dgps_mode(), Ok(DgpsMode::Sbas));
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_logger_triggers() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK187,1,5*38\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,3*3E\r\n"),
+            MockTrans::write_many(b"$PMTK187,2,50*0B\r\n"),
+            MockTrans::read_many(b"$PMTK001,187,3*3E\r\n"),
+            MockTrans::write_many(b"$PMTK186,1*20\r\n"),
+            MockTrans::read_many(b"$PMTK001,186,3*3F\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.configure_logger_interval(5).unwrap();
+        gps.configure_logger(logger::Trigger::Distance(50)).unwrap();
+        gps.log_now().unwrap();
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_start_and_stop_logging() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK185,0*22\r\n"),
+            MockTrans::read_many(b"$PMTK001,185,3*3C\r\n"),
+            MockTrans::write_many(b"$PMTK185,1*23\r\n"),
+            MockTrans::read_many(b"$PMTK001,185,3*3C\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.start_logging().unwrap();
+        gps.stop_logging().unwrap();
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_logger_full_stop() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,456,0,11,31,2,0,0,0,3769,46*48\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,457,0,11,31,2,0,0,0,8176,99*48\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,457,0,11,31,2,0,0,0,8176,99*48\r\n"),
+            MockTrans::write_many(b"$PMTK185,1*23\r\n"),
+            MockTrans::read_many(b"$PMTK001,185,3*3C\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_logger_recording_mode(logger::RecordingMode::FullStop);
+        assert_eq!(
+            gps.enforce_recording_mode(60),
+            Ok(logger::Status {
+                serial: 456,
+                recording_mode: logger::RecordingMode::Overlap,
+                mode: logger::LogMode::ALWAYS_LOCATE
+                    | logger::LogMode::FIX_ONLY
+                    | logger::LogMode::INTERVAL,
+                content: logger::ContentFlags::UTC
+                    | logger::ContentFlags::VALID
+                    | logger::ContentFlags::LAT
+                    | logger::ContentFlags::LON
+                    | logger::ContentFlags::HEIGHT,
+                interval: 2,
+                distance: 0,
+                speed: 0,
+                is_on: true,
+                record_count: 3769,
+                percent_full: crate::IntegerPercent::new(46),
+            })
+        );
+        // Only checking doesn't stop it
+        assert!(gps.logger_status().unwrap().is_on);
+        // Not enough room left to log for another minute
+        let nearly_full = gps.enforce_recording_mode(60).unwrap();
+        assert!(!nearly_full.is_on);
+        assert_eq!(nearly_full.percent_full, 99);
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_erase_logs_waits() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK184,1*22\r\n"),
+            MockTrans::read_many(b"$PMTK001,184,3*3D\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,456,0,11,31,2,0,0,0,3769,46*48\r\n"),
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,457,0,11,31,2,0,0,0,0,0*40\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.erase_logs().unwrap();
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    fn sample_dump_points() -> Vec<logger::Packet> {
+        let mut points = Vec::new();
+        logger::parse_dump(
+            include_bytes!("../test_assets/3819_log_records.bin"),
+            |point| points.push(point),
+        );
+        points
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_read_logs() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK622,0*28\r\n"),
+            MockTrans::read_many(include_bytes!(
+                "../test_assets/read_3819_log_records_inputs.txt"
+            )),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let mut points = Vec::new();
+        let (cursor, stats) = gps
+            .read_logs(|max_points, i, point| {
+                assert_eq!(i, points.len());
+                assert!(i < max_points);
+                points.push(point);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(points, sample_dump_points());
+        assert_eq!(cursor.points_read, points.len());
+        assert_eq!(stats.packets_parsed, points.len());
+        assert_eq!(stats.invalid_packets, 0);
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_read_new_logs() {
+        let dump = include_bytes!("../test_assets/read_3819_log_records_inputs.txt");
+        let expects = [
+            MockTrans::write_many(b"$PMTK622,1*29\r\n"),
+            MockTrans::read_many(dump),
+            MockTrans::write_many(b"$PMTK622,1*29\r\n"),
+            MockTrans::read_many(dump),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+        let all_points = sample_dump_points();
+
+        let mut cursor = logger::Cursor {
+            points_read: all_points.len() - 10,
+        };
+        let mut points = Vec::new();
+        gps.read_new_logs(&mut cursor, |_, _, point| {
+            points.push(point);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(points, all_points[all_points.len() - 10..]);
+        assert_eq!(cursor.points_read, all_points.len());
+
+        // Nothing new since
+        gps.read_new_logs(&mut cursor, |_, _, _| panic!("Already read"))
+            .unwrap();
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_read_new_logs_resumes_after_break() {
+        let dump = include_bytes!("../test_assets/read_3819_log_records_inputs.txt");
+        let expects = [
+            MockTrans::write_many(b"$PMTK622,1*29\r\n"),
+            MockTrans::read_many(dump),
+            // Still ready for commands after stopping early
+            MockTrans::write_many(b"$PMTK183*38\r\n"),
+            MockTrans::read_many(b"$PMTKLOG,456,0,11,31,2,0,0,0,3769,46*48\r\n"),
+            MockTrans::write_many(b"$PMTK622,1*29\r\n"),
+            MockTrans::read_many(dump),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+        let all_points = sample_dump_points();
+
+        let mut cursor = logger::Cursor::default();
+        let mut points = Vec::new();
+        gps.read_new_logs(&mut cursor, |_, _, point| {
+            points.push(point);
+            if points.len() == 100 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+        assert_eq!(cursor.points_read, 100);
+
+        gps.logger_status().unwrap();
+
+        gps.read_new_logs(&mut cursor, |_, _, point| {
+            points.push(point);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(points, all_points);
+        assert_eq!(cursor.points_read, all_points.len());
+
+        mock.done();
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_read_new_logs_within_deadline() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let now = Rc::new(Cell::new(0));
+        let clock = {
+            let now = now.clone();
+            move || now.get()
+        };
+        let mut gps = Gps::builder(rx_consumer, sim.clone(), sim.clone())
+            .clock(clock)
+            .build();
+        // Three sectors' worth
+        let all_points = &sample_dump_points()[..600];
+        sim.log_points(all_points);
+
+        let mut cursor = logger::Cursor::default();
+        let mut points = Vec::new();
+        let result = gps.within_deadline(1_000_000, |gps| {
+            gps.read_new_logs(&mut cursor, |_, _, point| {
+                points.push(point);
+                // Run out of time once the first sector is in
+                now.set(2_000_000);
+                ControlFlow::Continue(())
+            })
+        });
+        assert_eq!(result, Err(Error::DeadlineExceeded));
+        assert_eq!(cursor.points_read, points.len());
+        assert!(points.len() < all_points.len());
+
+        // Skips the rest of the old dump to pick up where it left off
+        gps.read_new_logs(&mut cursor, |_, _, point| {
+            points.push(point);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(points, all_points);
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_boots_through_noise() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), true);
+
+        // Waiting in the queue for when it starts reading
+        sim.send(&[b'x'; 1000]);
+        let mut lox = b"$PMTKLOX,1,0,".to_vec();
+        lox.resize(400, b'F');
+        sim.send(&lox);
+        let mut gga = b"$GPGGA,064951.000,".to_vec();
+        gga.resize(200, b'9');
+        sim.send(&gga);
+
+        gps.hot_restart().unwrap();
+        assert_eq!(
+            gps.firmware_version().unwrap().release,
+            b"AXN_2.51_3339_17112000"
+        );
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_noise_is_too_long() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), true);
+        sim.log_points(&sample_dump_points()[..10]);
+
+        // Arrives before the start of the dump, which isn't retried
+        sim.send(&[b'x'; 500]);
+        let mut cursor = logger::Cursor::default();
+        let result = gps.read_new_logs(&mut cursor, |_, _, _| ControlFlow::Continue(()));
+        assert_eq!(
+            result,
+            Err(Error::SentenceTooLong(RawBytes::new(&[b'x'; 32])))
+        );
+    }
+
+    #[cfg(feature = "locus")]
+    #[test]
+    fn test_reboot_within_deadline() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        // So it never boots
+        sim.unsupport(b"101");
+        // Each look at the clock is a hundredth of a second later
+        let now = Rc::new(Cell::new(0));
+        let clock = {
+            let now = now.clone();
+            move || {
+                now.set(now.get() + 10_000);
+                now.get()
+            }
+        };
+        let mut gps = Gps::builder(rx_consumer, sim.clone(), sim.clone())
+            .clock(clock)
+            .build();
+
+        let result = gps.within_deadline(1_000_000, |gps| gps.hot_restart());
+        assert_eq!(result, Err(Error::DeadlineExceeded));
+        // Rather than a read timeout for each of many read errors and tries
+        assert!(now.get() < 1_100_000);
+        assert_eq!(sim.received(), vec![b"$PMTK101*32\r\n".to_vec()]);
+
+        // Nested calls can't extend the budget
+        let start = now.get();
+        let result = gps.within_deadline(1_000_000, |gps| {
+            gps.within_deadline(2_000_000, |gps| gps.hot_restart())
+        });
+        assert_eq!(result, Err(Error::DeadlineExceeded));
+        assert!(now.get() - start < 1_100_000);
+    }
+
+    #[test]
+    fn test_easy() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK869,1,1*35\r\n"),
+            MockTrans::read_many(b"$PMTK001,869,3*37\r\n"),
+            MockTrans::write_many(b"$PMTK869,0*29\r\n"),
+            MockTrans::read_many(b"$PMTK869,2,1,3*29\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_easy(true).unwrap();
+        assert_eq!(
+            gps.easy_status(),
+            Ok(EasyStatus {
+                enabled: true,
+                extended_days: 3,
+            })
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_backup_mode_until_reachable() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK225,4*2F\r\n"),
+            // Power cycled
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+            MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.enter_backup_mode().unwrap();
+        assert_eq!(gps.set_sbas(true), Err(Error::InBackupMode));
+
+        assert!(gps.is_reachable());
+        gps.set_sbas(true).unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_power_cycle() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK225,4*2F\r\n"),
+            // Power restored
+            MockTrans::read_many(b"$PMTK011,MTKGPS*08\r\n"),
+            MockTrans::read_many(b"$PMTK010,001*2E\r\n"),
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+            MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        struct Switch(Vec<bool>);
+        impl PowerSwitch for Switch {
+            fn set_power(&mut self, on: bool) {
+                self.0.push(on);
+            }
+        }
+        let mut switch = Switch(Vec::new());
+
+        gps.enter_backup_mode().unwrap();
+        gps.power_cycle(&mut switch).unwrap();
+        assert_eq!(switch.0, [false, true]);
+        assert!(!gps.is_standby());
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_power_mode() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK223,1,25,180000,60000*38\r\n"),
+            MockTrans::read_many(b"$PMTK001,223,3*33\r\n"),
+            MockTrans::write_many(b"$PMTK225,2,3000,12000,18000,72000*15\r\n"),
+            MockTrans::read_many(b"$PMTK001,225,3*35\r\n"),
+            MockTrans::write_many(b"$PMTK225,8*23\r\n"),
+            MockTrans::read_many(b"$PMTK001,225,3*35\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.set_power_mode(&PowerMode::PeriodicStandby(PeriodicTimes {
+            run_ms: 3000,
+            sleep_ms: 12000,
+            second: Some((18000, 72000)),
+            extended_fix: Some(ExtendedFix {
+                min_snr: 25,
+                ..ExtendedFix::default()
+            }),
+        }))
+        .unwrap();
+        gps.set_power_mode(&PowerMode::AlwaysLocateStandby).unwrap();
+
+        let too_short = PowerMode::PeriodicBackup(PeriodicTimes {
+            run_ms: 500,
+            sleep_ms: 12000,
+            second: None,
+            extended_fix: None,
+        });
+        assert_eq!(gps.set_power_mode(&too_short), Err(Error::InvalidArgument));
+        let too_few_sats = PowerMode::PeriodicBackup(PeriodicTimes {
+            run_ms: 3000,
+            sleep_ms: 12000,
+            second: None,
+            extended_fix: Some(ExtendedFix {
+                min_satellites: 0,
+                ..ExtendedFix::default()
+            }),
+        });
+        assert_eq!(
+            gps.set_power_mode(&too_few_sats),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_standby_wakes_for_next_command() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK161,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,161,3*36\r\n"),
+            MockTrans::write_many(b"\r\n"),
+            MockTrans::read_many(b"$PMTK010,002*2D\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.enter_standby().unwrap();
+        assert!(gps.is_standby());
+        gps.set_sbas(true).unwrap();
+        assert!(!gps.is_standby());
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_set_baud_rate() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK251,115200*1F\r\n"),
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        let mut reconfigured = None;
+        gps.set_baud_rate(115200, |baud| reconfigured = Some(baud))
+            .unwrap();
+        assert_eq!(reconfigured, Some(115200));
+
+        assert_eq!(
+            gps.set_baud_rate(1234, |_| panic!("Shouldn't reconfigure")),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[cfg(feature = "quectel")]
+    #[test]
+    fn test_quectel_odometer() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_3.8_3333_16070500,0000,Quectel-L80,1.0*01\r\n"),
+            MockTrans::write_many(b"$PQODO,W,1*23\r\n"),
+            MockTrans::read_many(b"$PQODO,W,ERROR*4A\r\n"),
+            MockTrans::write_many(b"$PQODO,W,1*23\r\n"),
+            MockTrans::read_many(b"$PQODO,W,OK*16\r\n"),
+            MockTrans::write_many(b"$PQODO,Q*38\r\n"),
+            MockTrans::read_many(b"$PQODO,Q,1234.5*0B\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.start_odometer().unwrap();
+        assert_eq!(gps.odometer_m(), Ok(1234));
+        assert_eq!(gps.command_set(), Ok(CommandSet::PmtkAndPq));
+
+        mock.done();
+    }
+
+    #[cfg(feature = "quectel")]
+    #[test]
+    fn test_quectel_cmd_not_sent_to_other_modules() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        assert_eq!(gps.set_glp(true), Err(Error::NotSupported));
+        assert_eq!(gps.command_set(), Ok(CommandSet::Pmtk));
+
+        mock.done();
+    }
+
+    const GGA: &[u8] =
+        b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n";
+
+    #[test]
+    fn test_forwards_nmea_while_waiting_for_reply() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(GGA),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let nmea_buf = RxBuf::new();
+        let (nmea_producer, mut nmea_consumer) = nmea_buf.try_split().unwrap();
+        let (mut gps, mut mock) = mock::gps_with_nmea_output(&expects);
+        gps.forward_nmea_to(nmea_producer);
+
+        gps.check_ready(1).unwrap();
+
+        mock.done();
+        let grant = nmea_consumer.read().unwrap();
+        assert_eq!(grant.buf(), GGA);
+    }
+
+    #[test]
+    fn test_unsolicited_handler() {
+        const STARTUP: &[u8] = b"$PMTK010,001*2E\r\n";
+        let expects = [
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(GGA),
+            MockTrans::read_many(STARTUP),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        // Leaves NMEA output on, so doesn't turn it off first
+        let (mut gps, mut mock) = mock::gps_with_nmea_output(&expects);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler_seen = seen.clone();
+        gps.on_unsolicited(move |line| handler_seen.lock().unwrap().push(line.to_vec()));
+
+        gps.set_sbas(true).unwrap();
+
+        mock.done();
+        assert_eq!(*seen.lock().unwrap(), [GGA, STARTUP]);
+    }
+
+    #[test]
+    fn test_nmea_output_disabled_before_first_cmd_only() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+            MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps_with_nmea_output(&expects);
+
+        gps.set_sbas(true).unwrap();
+        gps.set_sbas(true).unwrap();
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_ack_statuses() {
+        let expects = [
+            // Invalid, so tried again
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,0*32\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            // Unsupported, which won't change
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,1*33\r\n"),
+            // An ack for something else isn't ours
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,185,3*3C\r\n"),
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        assert_eq!(gps.set_sbas(true), Ok(()));
+        assert_eq!(gps.set_sbas(true), Err(Error::GpsSaysUnsupportedCommand));
+        assert_eq!(gps.set_sbas(true), Ok(()));
+
+        mock.done();
+    }
+
+    #[test]
+    fn test_send_raw() {
+        let expects = [
+            // PMTK_SET_AL_DEE_CFG, not wrapped
+            MockTrans::write_many(b"$PMTK223,1,25,180000,60000*38\r\n"),
+            MockTrans::read_many(b"$PMTK001,223,3*33\r\n"),
+            MockTrans::write_many(b"$PMTK400*36\r\n"),
+            MockTrans::read_many(b"$PMTK500,1000,0,0,0.0,0.0*1A\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+
+        gps.send_raw(b"223", &[b"1", b"25", b"180000", b"60000"])
+            .unwrap();
+        let fields = gps.send_raw_for_reply(b"400", &[], b"500", 1).unwrap();
+        assert_eq!(fields[0], b"1000");
+
+        // Nothing sent for these
+        assert_eq!(gps.send_raw(b"22a", &[]), Err(Error::InvalidArgument));
+        assert_eq!(gps.send_raw(b"223", &[b"1,2"]), Err(Error::InvalidArgument));
+        assert_eq!(
+            gps.send_raw_for_reply(b"400", &[], b"PQ5", 1),
+            Err(Error::InvalidArgument)
+        );
+
+        mock.done();
+    }
+
+    #[derive(Default)]
+    struct RecordedTraffic {
+        tx: Vec<u8>,
+        rx: Vec<u8>,
+    }
+
+    impl TrafficObserver for Arc<Mutex<RecordedTraffic>> {
+        fn on_tx(&mut self, bytes: &[u8]) {
+            self.lock().unwrap().tx.extend_from_slice(bytes);
+        }
+
+        fn on_rx(&mut self, bytes: &[u8]) {
+            self.lock().unwrap().rx.extend_from_slice(bytes);
+        }
+    }
+
+    #[test]
+    fn test_observes_traffic() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            // Dropped by resyncing, but still seen
+            MockTrans::read_many(b"$PMTK7"),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+            MockTrans::write_many(b"$PMTK400*36\r\n"),
+            MockTrans::read_many(b"$PMTK500,1000,0,0,0.0,0.0*1A\r\n"),
+        ];
+        let (mut gps, mut mock) = mock::gps(&expects);
+        let traffic = Arc::new(Mutex::new(RecordedTraffic::default()));
+        gps.observe_traffic(traffic.clone());
+
+        gps.firmware_version().unwrap();
+        gps.update_rate_ms().unwrap();
+
+        mock.done();
+        let traffic = traffic.lock().unwrap();
+        assert_eq!(traffic.tx, b"$PMTK605*31\r\n$PMTK400*36\r\n");
+        assert_eq!(
+            traffic.rx,
+            b"$PMTK7$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n\
+              $PMTK500,1000,0,0,0.0,0.0*1A\r\n"
+        );
+    }
+
+    #[test]
+    fn test_nmea_while_waiting_for_reply_is_error_without_forwarding() {
+        let expects = [
+            MockTrans::write_many(b"$PMTK605*31\r\n"),
+            MockTrans::read_many(GGA),
+            MockTrans::read_many(b"$PMTK705,AXN_2.31_3339_13101700,5632,PA6H,1.0*6B\r\n"),
+        ];
+        let (mut gps, _mock) = mock::gps(&expects);
+
+        gps.write_cmd_raw(b"PMTK605", &[]).unwrap();
+        assert_eq!(
+            gps.read_reply_raw(b"PMTK705", 2),
+            Err(Error::Protocol(RawBytes::new(
+                b"GPGGA,064951.000,2307.1256,N,120"
+            )))
+        );
+    }
+}
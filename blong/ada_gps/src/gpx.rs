@@ -0,0 +1,162 @@
+//! Writing packets out as a GPX 1.1 track, so the device can hand over a
+//! file that opens straight away in mapping tools.
+//!
+//! Everything goes through [`core::fmt::Write`], so the output can be
+//! streamed a point at a time rather than built up in memory.
+
+use core::fmt::{self, Write};
+
+use crate::{coords, export::TrackWriter, Fix, Packet};
+
+/// Writes one track, starting a new segment on [`Self::new_segment`].
+///
+/// Call [`Self::finish`] to close it, the output isn't valid GPX until then.
+#[derive(Debug)]
+pub struct GpxWriter<W> {
+    out: W,
+}
+
+impl<W> GpxWriter<W>
+where
+    W: Write,
+{
+    /// Writes the header and opens the first segment.
+    pub fn new(mut out: W) -> Result<Self, fmt::Error> {
+        out.write_str(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<gpx version="1.1" creator="ada_gps" xmlns="http://www.topografix.com/GPX/1/1">"#,
+            "\n<trk>\n<trkseg>\n",
+        ))?;
+        Ok(Self { out })
+    }
+
+    /// Packets without a position are skipped, as GPX requires one.
+    ///
+    /// The elevation is above the WGS84 ellipsoid when the geoid separation
+    /// is known. LOCUS doesn't log it, so for logged points it's above mean
+    /// sea level instead.
+    pub fn point(&mut self, packet: &Packet) -> fmt::Result {
+        let (lat, lon) = match (packet.lat, packet.lon) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Ok(()),
+        };
+
+        self.out.write_str(r#"<trkpt lat=""#)?;
+        coords::write_decimal(&mut self.out, lat)?;
+        self.out.write_str(r#"" lon=""#)?;
+        coords::write_decimal(&mut self.out, lon)?;
+        self.out.write_str(r#"">"#)?;
+        if let Some(ele) = packet.height_ellipsoid().or(packet.height_msl) {
+            write!(self.out, "<ele>{}</ele>", ele)?;
+        }
+        if let Some(time) = packet.time {
+            self.out.write_str("<time>")?;
+            time.write_iso8601(&mut self.out)?;
+            self.out.write_str("</time>")?;
+        }
+        if let Some(fix) = &packet.fix {
+            let fix = match fix {
+                Fix::No => "none",
+                Fix::GpsFix => "3d",
+                Fix::DGpsFix => "dgps",
+                // GPX has nothing closer
+                Fix::DeadReckoning => "none",
+            };
+            write!(self.out, "<fix>{}</fix>", fix)?;
+        }
+        if let Some(num_sat) = packet.num_sat {
+            write!(self.out, "<sat>{}</sat>", num_sat)?;
+        }
+        // In the order GPX expects
+        for (tag, dop) in [
+            ("hdop", packet.hdop),
+            ("vdop", packet.vdop),
+            ("pdop", packet.pdop),
+        ] {
+            if let Some(dop) = dop {
+                write!(self.out, "<{0}>{1}.{2:02}</{0}>", tag, dop / 100, dop % 100)?;
+            }
+        }
+        self.out.write_str("</trkpt>\n")
+    }
+
+    /// End the current segment and start another, for a gap in the track.
+    pub fn new_segment(&mut self) -> fmt::Result {
+        self.out.write_str("</trkseg>\n<trkseg>\n")
+    }
+
+    /// Closes the track and hands back the output.
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        self.out.write_str("</trkseg>\n</trk>\n</gpx>\n")?;
+        Ok(self.out)
+    }
+}
+
+impl<W> TrackWriter<W> for GpxWriter<W>
+where
+    W: Write,
+{
+    fn point(&mut self, packet: &Packet) -> fmt::Result {
+        GpxWriter::point(self, packet)
+    }
+
+    fn new_segment(&mut self) -> fmt::Result {
+        GpxWriter::new_segment(self)
+    }
+
+    fn finish(self) -> Result<W, fmt::Error> {
+        GpxWriter::finish(self)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::UtcDateTime;
+    use alloc::string::String;
+
+    #[test]
+    fn test_writes_track() {
+        let point = Packet {
+            time: UtcDateTime::new(2022, 1, 27, 22, 28, 30, 0),
+            fix: Some(Fix::GpsFix),
+            lat: Some(56.33501),
+            lon: Some(-2.79336),
+            height_msl: Some(8),
+            hdop: Some(125),
+            num_sat: Some(7),
+            ..Packet::default()
+        };
+
+        let mut gpx = GpxWriter::new(String::new()).unwrap();
+        gpx.point(&point).unwrap();
+        // No position
+        gpx.point(&Packet::default()).unwrap();
+        gpx.new_segment().unwrap();
+        gpx.point(&Packet {
+            height_msl: Some(10),
+            geoid_separation: Some(50),
+            pdop: Some(210),
+            ..point
+        })
+        .unwrap();
+        let gpx = gpx.finish().unwrap();
+
+        assert_eq!(
+            gpx,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="ada_gps" xmlns="http://www.topografix.com/GPX/1/1">
+<trk>
+<trkseg>
+<trkpt lat="56.33501" lon="-2.79336"><ele>8</ele><time>2022-01-27T22:28:30Z</time><fix>3d</fix><sat>7</sat><hdop>1.25</hdop></trkpt>
+</trkseg>
+<trkseg>
+<trkpt lat="56.33501" lon="-2.79336"><ele>60</ele><time>2022-01-27T22:28:30Z</time><fix>3d</fix><sat>7</sat><hdop>1.25</hdop><pdop>2.10</pdop></trkpt>
+</trkseg>
+</trk>
+</gpx>
+"#
+        );
+    }
+}
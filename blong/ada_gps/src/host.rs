@@ -0,0 +1,137 @@
+//! Running [`Gps`](crate::Gps) from a computer, against a gps on a USB
+//! serial adapter, for bring-up and debugging.
+//!
+//! ```no_run
+//! use ada_gps::{host, Gps, RxBuf};
+//!
+//! let rx_buf: &'static RxBuf = Box::leak(Box::new(RxBuf::new()));
+//! let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+//! let tx = host::open("/dev/ttyUSB0", 9600, rx_producer).unwrap();
+//! let mut gps = Gps::builder(rx_consumer, tx, host::StdDelay)
+//!     .clock(host::StdClock::new())
+//!     .build();
+//! println!("{:?}", gps.firmware_version());
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_hal::{blocking::delay::DelayUs, serial};
+use serialport::SerialPort;
+
+use crate::{error, Clock, RxProducer};
+
+/// How long the reader thread waits on the port before checking again
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Open the serial port at `path`, with a thread feeding whatever the gps
+/// sends into `rx` in place of the uart interrupt. Returns the writing end.
+///
+/// The thread runs until the port fails, such as by being unplugged.
+pub fn open(path: &str, baud_rate: u32, rx: RxProducer<'static>) -> serialport::Result<SerialTx> {
+    let port = serialport::new(path, baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    let reader = port.try_clone()?;
+    thread::spawn(move || read_into(reader, rx));
+    Ok(SerialTx { port })
+}
+
+fn read_into(mut port: Box<dyn SerialPort>, mut rx: RxProducer<'static>) {
+    let mut chunk = [0_u8; 64];
+    loop {
+        let len = match port.read(&mut chunk) {
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => {
+                error!("Serial read failed: {}", err.kind());
+                return;
+            }
+        };
+        let mut rest = &chunk[..len];
+        while !rest.is_empty() {
+            match rx.grant_max_remaining(rest.len()) {
+                Ok(mut grant) => {
+                    let n = grant.buf().len();
+                    grant.buf().copy_from_slice(&rest[..n]);
+                    grant.commit(n);
+                    rest = &rest[n..];
+                }
+                // Full until the driver catches up
+                Err(_) => thread::sleep(READ_TIMEOUT),
+            }
+        }
+    }
+}
+
+/// The writing end of a port from [`open`].
+pub struct SerialTx {
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTx {
+    /// Such as after [`Gps::set_baud_rate`](crate::Gps::set_baud_rate). The
+    /// reader thread shares the port, so follows along.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.port.set_baud_rate(baud_rate)
+    }
+}
+
+impl serial::Write<u8> for SerialTx {
+    type Error = io::ErrorKind;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        match self.port.write(&[byte]) {
+            Ok(1) => Ok(()),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => Err(nb::Error::WouldBlock),
+            Err(err) => Err(nb::Error::Other(err.kind())),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.port
+            .flush()
+            .map_err(|err| nb::Error::Other(err.kind()))
+    }
+}
+
+/// Sleeps the thread. The OS will usually sleep longer than asked, so pair it
+/// with [`StdClock`] rather than counting delays.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdDelay;
+
+impl DelayUs<u32> for StdDelay {
+    fn delay_us(&mut self, us: u32) {
+        thread::sleep(Duration::from_micros(us as u64));
+    }
+}
+
+/// Microseconds since it was made.
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    start: Instant,
+}
+
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for StdClock {
+    fn now_us(&mut self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
@@ -1,6 +1,5 @@
-use defmt::Format;
-
-#[derive(Format, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct IntegerPercent(u8);
 
 impl IntegerPercent {
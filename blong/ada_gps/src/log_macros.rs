@@ -1,54 +1,110 @@
+//! Logging through defmt on targets without an OS, through `log` with the
+//! `log` feature, or nowhere.
+//!
+//! Format strings are defmt's. `log` gets them as they are, followed by the
+//! arguments' `Debug` output.
+
 #[macro_export]
 macro_rules! debug {
-    ($($arg:expr),+ $(,)?) => {
-        #[cfg(not(target_os = "none"))]
-        drop(($($arg),+));
+    ($fmt:literal $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::debug!($fmt);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::debug!("{}", $fmt);
+    };
+    ($fmt:literal, $($arg:expr),+ $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::debug!($fmt, $($arg),+);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::debug!("{} {:?}", $fmt, ($(&$arg,)+));
 
-        #[cfg(target_os = "none")]
-        defmt::debug!($($arg),+);
-    }
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($($arg,)+);
+    };
 }
 
 #[macro_export]
 macro_rules! error {
-    ($($arg:expr),+ $(,)?) => {
-        #[cfg(not(target_os = "none"))]
-        drop(($($arg),+));
+    ($fmt:literal $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::error!($fmt);
 
-        #[cfg(target_os = "none")]
-        defmt::error!($($arg),+);
-    }
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::error!("{}", $fmt);
+    };
+    ($fmt:literal, $($arg:expr),+ $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::error!($fmt, $($arg),+);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::error!("{} {:?}", $fmt, ($(&$arg,)+));
+
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($($arg,)+);
+    };
 }
 
 #[macro_export]
 macro_rules! info {
-    ($($arg:expr),+ $(,)?) => {
-        #[cfg(not(target_os = "none"))]
-        drop(($($arg),+));
+    ($fmt:literal $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::info!($fmt);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::info!("{}", $fmt);
+    };
+    ($fmt:literal, $($arg:expr),+ $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::info!($fmt, $($arg),+);
 
-        #[cfg(target_os = "none")]
-        defmt::info!($($arg),+);
-    }
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::info!("{} {:?}", $fmt, ($(&$arg,)+));
+
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($($arg,)+);
+    };
 }
 
 #[macro_export]
 macro_rules! trace {
-    ($($arg:expr),+ $(,)?) => {
-        #[cfg(not(target_os = "none"))]
-        drop(($($arg),+));
+    ($fmt:literal $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::trace!($fmt);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::trace!("{}", $fmt);
+    };
+    ($fmt:literal, $($arg:expr),+ $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::trace!($fmt, $($arg),+);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::trace!("{} {:?}", $fmt, ($(&$arg,)+));
 
-        #[cfg(target_os = "none")]
-        defmt::trace!($($arg),+);
-    }
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($($arg,)+);
+    };
 }
 
 #[macro_export]
 macro_rules! warn {
-    ($($arg:expr),+ $(,)?) => {
-        #[cfg(not(target_os = "none"))]
-        drop(($($arg),+));
+    ($fmt:literal $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::warn!($fmt);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::warn!("{}", $fmt);
+    };
+    ($fmt:literal, $($arg:expr),+ $(,)?) => {
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        defmt::warn!($fmt, $($arg),+);
+
+        #[cfg(all(feature = "log", not(all(feature = "defmt", target_os = "none"))))]
+        log::warn!("{} {:?}", $fmt, ($(&$arg,)+));
 
-        #[cfg(target_os = "none")]
-        defmt::warn!($($arg),+);
-    }
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($($arg,)+);
+    };
 }
@@ -0,0 +1,56 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// What LOCUS records for each point, which decides how big a point is.
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct ContentFlags: u32 {
+        const UTC = 1<<0;
+        const VALID = 1<<1;
+        const LAT = 1<<2;
+        const LON = 1<<3;
+        const HEIGHT = 1<<4;
+        const SPEED = 1<<5;
+        const TRK = 1<<6; // Heading
+        const HDOP = 1<<10;
+        const NUM_SAT = 1<<12;
+    }
+}
+
+impl ContentFlags {
+    /// Bytes each point takes up in flash, including its checksum.
+    pub fn point_size(self) -> u32 {
+        let mut size = 0;
+
+        if self.contains(Self::UTC) {
+            size += 4;
+        }
+        if self.contains(Self::VALID) {
+            size += 1;
+        }
+        if self.contains(Self::LAT) {
+            size += 4;
+        }
+        if self.contains(Self::LON) {
+            size += 4;
+        }
+        if self.contains(Self::HEIGHT) {
+            size += 2;
+        }
+        if self.contains(Self::TRK) {
+            size += 2;
+        }
+        if self.contains(Self::SPEED) {
+            size += 2;
+        }
+        if self.contains(Self::HDOP) {
+            size += 2;
+        }
+        if self.contains(Self::NUM_SAT) {
+            size += 1;
+        }
+
+        size += 1;
+
+        size
+    }
+}
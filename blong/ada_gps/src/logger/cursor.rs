@@ -0,0 +1,10 @@
+/// How far through the log we've read, see [`crate::Gps::read_new_logs`].
+///
+/// Keep it somewhere that survives restarts, such as flash, to carry on
+/// from the same point after one.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    /// How many points have already been passed on
+    pub points_read: usize,
+}
@@ -1,6 +1,27 @@
-mod packet;
-pub(crate) mod parser;
+mod content;
+mod cursor;
+mod parser;
 mod status;
+mod trigger;
 
-pub use packet::{Fix, Packet};
-pub use status::Status;
+pub use crate::packet::{Fix, Packet};
+pub use content::ContentFlags;
+pub use cursor::Cursor;
+pub use parser::{ParseStats, Parser, SECTOR_SIZE};
+pub use status::{LogMode, RecordingMode, Status, FLASH_SIZE};
+pub use trigger::Trigger;
+
+/// Parse a LOCUS dump saved earlier, such as one written by `cargo xtask
+/// traffic to-locus-bin`, calling `on_point` for each point. See [`Parser`]
+/// to parse one as it arrives instead.
+///
+/// This is the same parser [`crate::Gps::read_logs`] uses, so the points
+/// match what it would have handed over.
+pub fn parse_dump<F>(data: &[u8], on_point: F) -> ParseStats
+where
+    F: FnMut(Packet),
+{
+    let mut parser = Parser::new(on_point);
+    parser.feed(data);
+    parser.stats().clone()
+}
@@ -1,77 +1,108 @@
-use core::ops::BitXor;
+use alloc::vec::Vec;
+use core::{mem, ops::BitXor};
 
-use bitflags::bitflags;
-use defmt::Format;
+use super::{ContentFlags, Fix, Packet};
+use crate::UtcDateTime;
 
-use super::{Fix, Packet};
-use crate::{warn, UtcDateTime};
-
-// TODO NOTE: We're just guessing this is little-endian, as that's more common
-// half the checksums pass either way
+// Everything is little-endian, like the MT3339's ARM core. The checksums
+// can't tell us this, as they XOR bytes or same-order pairs and so come out
+// the same either way round. Instead it's checked against a real dump in the
+// tests: only read little-endian does it have the content flags of basic mode
+// and points at a plausible time and place.
 
 const MAX_HEADER2_BIT_NUM: u32 = 7;
 const HEADER_SIZE: usize = 64;
 const HEADER1_SIZE: usize = 16;
 const HEADER1_CS_BUF_SIZE: usize = 14;
 const HEADER2_SIZE: usize = 44;
-const DATA_SIZE: usize = 4032;
-const DATA_CHECKSUM_SIZE: usize = 1;
-const SECTOR_SIZE: usize = 4096;
-
-#[derive(Format, Debug)]
-pub(crate) struct Parser<F> {
+/// LOCUS dumps are made of sectors this long
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Turns a LOCUS dump into [`Packet`]s as it's fed in, in whatever size
+/// pieces it arrives, so the whole dump never has to be in memory at once.
+///
+/// Buffers at most one sector, [`SECTOR_SIZE`] bytes.
+#[derive(Debug)]
+pub struct Parser<F> {
     on_packet: F,
     active_sector: Option<SectorHeader>,
-    pub(crate) stats: Stats,
+    /// The part of the current sector fed in so far
+    partial_sector: Vec<u8>,
+    stats: ParseStats,
 }
 
-#[derive(Format, Debug, Clone)]
-pub(crate) struct Stats {
-    sector_count: usize,
-    invalid_sectors: usize,
-    empty_sectors: usize,
-    invalid_packets: usize,
-    packets_parsed: usize,
-    invalid_fields: usize,
+/// How much of a LOCUS dump made sense, see [`Parser::stats`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    pub sector_count: usize,
+    /// Sectors whose header checksum didn't match, skipped entirely
+    pub invalid_sectors: usize,
+    /// Sectors with no points in yet
+    pub empty_sectors: usize,
+    /// Points whose checksum didn't match, skipped
+    pub invalid_packets: usize,
+    pub packets_parsed: usize,
+    /// Fields out of range in otherwise valid points, left as `None`
+    pub invalid_fields: usize,
 }
 
 impl<F> Parser<F>
 where
     F: FnMut(Packet),
 {
-    pub(crate) fn new(on_packet: F) -> Self {
+    /// `on_packet` is called with each point as soon as the sector it's in has
+    /// been fed in.
+    pub fn new(on_packet: F) -> Self {
         Self {
             on_packet,
             active_sector: None,
-            stats: Stats {
-                sector_count: 0,
-                empty_sectors: 0,
-                invalid_sectors: 0,
-                invalid_packets: 0,
-                packets_parsed: 0,
-                invalid_fields: 0,
-            },
+            partial_sector: Vec::new(),
+            stats: ParseStats::default(),
         }
     }
 
+    /// Counts for everything fed in so far.
+    pub fn stats(&self) -> &ParseStats {
+        &self.stats
+    }
+
     fn on_packet(&mut self, packet: Packet) {
         (self.on_packet)(packet)
     }
 
-    // TODO: Make this streaming
-    pub(crate) fn parse(&mut self, data: &[u8]) {
-        let mut temp_packet_count = 0;
+    /// The next part of the dump. Sectors can be split across calls however
+    /// is convenient, but must be fed in order.
+    ///
+    /// A partial sector left at the end of the dump is never parsed.
+    pub fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Skip the copy when we're handed whole sectors
+            if self.partial_sector.is_empty() && data.len() >= SECTOR_SIZE {
+                let (sector, rest) = data.split_at(SECTOR_SIZE);
+                self.parse_sector(sector);
+                data = rest;
+                continue;
+            }
 
-        let sector_count = data.len() / SECTOR_SIZE;
-        self.stats.sector_count = sector_count;
-        for sector_i in 0..sector_count {
-            let data_i = sector_i * SECTOR_SIZE;
-            let sector = &data[data_i..data_i + SECTOR_SIZE];
-            self.parse_sector(sector);
+            let wanted = SECTOR_SIZE - self.partial_sector.len();
+            let (part, rest) = data.split_at(wanted.min(data.len()));
+            self.partial_sector.extend_from_slice(part);
+            data = rest;
+
+            if self.partial_sector.len() == SECTOR_SIZE {
+                let sector = mem::take(&mut self.partial_sector);
+                self.parse_sector(&sector);
+                // Keep the allocation for the next sector
+                self.partial_sector = sector;
+                self.partial_sector.clear();
+            }
         }
     }
 
+    /// `sector` must be [`SECTOR_SIZE`] bytes.
     fn parse_sector(&mut self, sector: &[u8]) {
+        self.stats.sector_count += 1;
         let header = &sector[..HEADER_SIZE];
         let header = match SectorHeader::parse(header) {
             Some(header) => header,
@@ -144,7 +175,7 @@ where
 
         if content_flags.contains(ContentFlags::LAT) {
             let lat = read_f32_at(data, addr);
-            if lat <= 90_f32 && lat >= -90_f32 {
+            if (-90_f32..=90_f32).contains(&lat) {
                 packet.lat = Some(lat);
             } else {
                 self.stats.invalid_fields += 1;
@@ -154,7 +185,7 @@ where
 
         if content_flags.contains(ContentFlags::LON) {
             let lon = read_f32_at(data, addr);
-            if lon <= 180_f32 && lon >= -180_f32 {
+            if (-180_f32..=180_f32).contains(&lon) {
                 packet.lon = Some(lon);
             } else {
                 self.stats.invalid_fields += 1;
@@ -163,7 +194,7 @@ where
         }
 
         if content_flags.contains(ContentFlags::HEIGHT) {
-            packet.height = Some(read_i16_at(data, addr));
+            packet.height_msl = Some(read_i16_at(data, addr));
             addr += 2;
         }
 
@@ -184,7 +215,6 @@ where
 
         if content_flags.contains(ContentFlags::NUM_SAT) {
             packet.num_sat = Some(data[addr]);
-            addr += 1;
         }
 
         self.stats.packets_parsed += 1;
@@ -192,28 +222,14 @@ where
     }
 }
 
-#[derive(Debug, Format, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone)]
 struct SectorHeader {
     content_flags: ContentFlags,
     packet_size: u32,
     packet_count: u32,
 }
 
-bitflags! {
-    #[derive(Format)]
-    struct ContentFlags: u32 {
-        const UTC = 1<<0;
-        const VALID = 1<<1;
-        const LAT = 1<<2;
-        const LON = 1<<3;
-        const HEIGHT = 1<<4;
-        const SPEED = 1<<5;
-        const TRK = 1<<6; // Heading
-        const HDOP = 1<<10;
-        const NUM_SAT = 1<<12;
-    }
-}
-
 impl SectorHeader {
     fn parse(header: &[u8]) -> Option<Self> {
         let expected_checksum = read_u16_at(header, HEADER1_CS_BUF_SIZE);
@@ -227,7 +243,7 @@ impl SectorHeader {
         // uses it.
         let content_flags = read_u32_at(header, 4);
         let content_flags = ContentFlags::from_bits_truncate(content_flags);
-        let packet_size = packet_size(content_flags);
+        let packet_size = content_flags.point_size();
 
         let packet_count = packet_count(header);
 
@@ -266,54 +282,17 @@ fn packet_count(header: &[u8]) -> u32 {
     }
 
     let mut num_byte: u32 = i;
-    let mut num_bit: u32 = 0;
-    if j == 0 {
+    let num_bit: u32 = if j == 0 {
         num_byte += 1;
-        num_bit = 0;
+        0
     } else {
-        num_bit = MAX_HEADER2_BIT_NUM + 1 - j;
-    }
+        MAX_HEADER2_BIT_NUM + 1 - j
+    };
 
     num_byte * 8 + num_bit
 }
 
 // uCalculateSize in reference
-fn packet_size(content: ContentFlags) -> u32 {
-    let mut size = 0;
-
-    if content.contains(ContentFlags::UTC) {
-        size += 4;
-    }
-    if content.contains(ContentFlags::VALID) {
-        size += 1;
-    }
-    if content.contains(ContentFlags::LAT) {
-        size += 4;
-    }
-    if content.contains(ContentFlags::LON) {
-        size += 4;
-    }
-    if content.contains(ContentFlags::HEIGHT) {
-        size += 2;
-    }
-    if content.contains(ContentFlags::TRK) {
-        size += 2;
-    }
-    if content.contains(ContentFlags::SPEED) {
-        size += 2;
-    }
-    if content.contains(ContentFlags::HDOP) {
-        size += 2;
-    }
-    if content.contains(ContentFlags::NUM_SAT) {
-        size += 1;
-    }
-
-    size += 1;
-
-    size
-}
-
 /// `u1Locus_Gen_Checksum` in reference.
 fn u8_checksum_for(bytes: &[u8]) -> u8 {
     bytes.iter().fold(0_u8, BitXor::bitxor)
@@ -322,7 +301,7 @@ fn u8_checksum_for(bytes: &[u8]) -> u8 {
 /// Interprets the byte slice as a slice of `u16`s, and computes their checksum.
 /// `u2Locus_Gen_Checksum` in reference.
 fn u16_checksum_for(bytes: &[u8]) -> u16 {
-    assert!(bytes.len() % 2 == 0);
+    assert!(bytes.len().is_multiple_of(2));
 
     bytes
         .chunks_exact(2)
@@ -364,9 +343,84 @@ mod tests {
         let mut parser = Parser::new(|packet| {
             packets.push(packet);
         });
-        parser.parse(sample);
+        parser.feed(sample);
 
-        assert_debug_snapshot!(parser.stats);
+        assert_debug_snapshot!(parser.stats());
         assert_debug_snapshot!(packets);
     }
+
+    #[test]
+    fn sample_dump_is_little_endian() {
+        let sample = include_bytes!("../../test_assets/3819_log_records.bin");
+        let basic = ContentFlags::UTC
+            | ContentFlags::VALID
+            | ContentFlags::LAT
+            | ContentFlags::LON
+            | ContentFlags::HEIGHT;
+
+        let content = &sample[4..8];
+        assert_eq!(read_u32_at(content, 0), basic.bits());
+        assert_ne!(
+            u32::from_be_bytes(content.try_into().unwrap()),
+            basic.bits()
+        );
+
+        let time = &sample[HEADER_SIZE..HEADER_SIZE + 4];
+        let le_time = UtcDateTime::from_unix(read_u32_at(time, 0) as i64).unwrap();
+        assert_eq!(le_time.year(), 2022);
+        let be_time = u32::from_be_bytes(time.try_into().unwrap()) as i64;
+        assert!(UtcDateTime::from_unix(be_time).is_none_or(|time| time.year() != 2022));
+
+        let lat = &sample[HEADER_SIZE + 5..HEADER_SIZE + 9];
+        assert!((56.0..57.0).contains(&read_f32_at(lat, 0)));
+        assert!(!(56.0..57.0).contains(&f32::from_be_bytes(lat.try_into().unwrap())));
+    }
+
+    #[test]
+    fn feeds_split_sectors() {
+        let sample = include_bytes!("../../test_assets/3819_log_records.bin");
+
+        let mut whole = Vec::new();
+        Parser::new(|packet| whole.push(packet)).feed(sample);
+
+        let mut split = Vec::new();
+        let mut parser = Parser::new(|packet| split.push(packet));
+        // Odd sizes so sectors straddle calls
+        for chunk in sample.chunks(1000) {
+            parser.feed(chunk);
+        }
+        // A trailing partial sector isn't parsed
+        parser.feed(&sample[..100]);
+        assert_eq!(parser.stats().sector_count, sample.len() / SECTOR_SIZE);
+
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn parses_every_basic_field_and_checks_checksums() {
+        let sample = include_bytes!("../../test_assets/3819_log_records.bin");
+        let mut sector = sample[..SECTOR_SIZE].to_vec();
+        let header = SectorHeader::parse(&sector[..HEADER_SIZE]).unwrap();
+        let packet_size = header.packet_size as usize;
+
+        let mut packets = Vec::new();
+        let mut parser = Parser::new(|packet| packets.push(packet));
+        parser.feed(&sector);
+        let first = packets[0].clone();
+        assert!(first.time.is_some());
+        assert!(first.fix.is_some());
+        assert!(first.lat.is_some());
+        assert!(first.lon.is_some());
+        assert!(first.height_msl.is_some());
+        let count = packets.len();
+
+        // Corrupt the checksum of the first point
+        sector[HEADER_SIZE + packet_size - 1] ^= 0xFF;
+        let mut packets = Vec::new();
+        let mut parser = Parser::new(|packet| packets.push(packet));
+        parser.feed(&sector);
+        assert_eq!(parser.stats().invalid_packets, 1);
+        assert_eq!(packets.len(), count - 1);
+        assert_ne!(packets[0], first);
+    }
 }
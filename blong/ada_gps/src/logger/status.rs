@@ -1,10 +1,169 @@
+#[cfg(feature = "commands")]
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
 use crate::IntegerPercent;
-use defmt::Format;
+#[cfg(feature = "commands")]
+use crate::{cmd::parse, debug, ParseError};
+
+use super::ContentFlags;
+
+/// Bytes of flash LOCUS logs to, 32 sectors of 4 KiB
+pub const FLASH_SIZE: u32 = 128 * 1024;
 
-#[derive(Format, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Everything PMTKLOG reports, see [`crate::Gps::logger_status`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Status {
+    /// Serial number of the logger
+    pub serial: u32,
+    /// What the gps itself does once its flash is full
+    pub recording_mode: RecordingMode,
+    pub mode: LogMode,
+    pub content: ContentFlags,
+    /// Seconds between points, or zero if not logging by interval
     pub interval: u32,
+    /// Meters between points, or zero if not logging by distance
+    pub distance: u32,
+    /// Meters per second to log above, or zero if not logging by speed
+    pub speed: u32,
     pub is_on: bool,
     pub record_count: u32,
     pub percent_full: IntegerPercent,
 }
+
+impl Status {
+    /// From the fields of a PMTKLOG reply, which has at least ten.
+    #[cfg(feature = "commands")]
+    pub(crate) fn from_fields(fields: &[Vec<u8>]) -> Result<Self, ParseError> {
+        let serial_field = &fields[0];
+        let logging_type_field = &fields[1];
+        let mode_field = &fields[2];
+        let content_field = &fields[3];
+        let interval_field = &fields[4];
+        let distance_field = &fields[5];
+        let speed_field = &fields[6];
+        let status_field = &fields[7];
+        let number_field = &fields[8];
+        let percent_field = &fields[9];
+
+        debug!(
+            "Raw status fields: serial={=[u8]:a}, logging_type={=[u8]:a}, mode={=[u8]:a}, content={=[u8]:a}, interval={=[u8]:a}, distance={=[u8]:a}, speed={=[u8]:a}, status={=[u8]:a}, number={=[u8]:a}, percent={=[u8]:a}",
+            serial_field,
+            logging_type_field,
+            mode_field,
+            content_field,
+            interval_field,
+            distance_field,
+            speed_field,
+            status_field,
+            number_field,
+            percent_field,
+        );
+
+        Ok(Self {
+            serial: parse::integer_field(serial_field)?,
+            recording_mode: match parse::bool_field(logging_type_field, b"1", b"0")? {
+                true => RecordingMode::FullStop,
+                false => RecordingMode::Overlap,
+            },
+            mode: LogMode::from_bits_truncate(parse::integer_field(mode_field)?),
+            content: ContentFlags::from_bits_truncate(parse::integer_field(content_field)?),
+            interval: parse::integer_field(interval_field)?,
+            distance: parse::integer_field(distance_field)?,
+            speed: parse::integer_field(speed_field)?,
+            is_on: parse::bool_field(status_field, b"0", b"1")?,
+            record_count: parse::integer_field(number_field)?,
+            percent_full: parse::integer_percent_field(percent_field)?,
+        })
+    }
+
+    /// Roughly how many points fit in the flash with the current content.
+    ///
+    /// Works it out from the size of each point, or if the gps didn't say
+    /// what it records from how full it says it is. `None` if neither helps.
+    pub fn estimated_capacity_points(&self) -> Option<u32> {
+        if !self.content.is_empty() {
+            Some(FLASH_SIZE / self.content.point_size())
+        } else if self.percent_full.as_u8() > 0 {
+            Some(self.record_count * 100 / self.percent_full.as_u8() as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Roughly how many seconds until the flash is full, recording a point
+    /// every `interval_secs`.
+    ///
+    /// Pass [`Self::interval`] when logging by interval, or a guess at how
+    /// often points come when logging by distance or speed.
+    pub fn estimated_time_remaining(&self, interval_secs: u32) -> Option<u32> {
+        let capacity = self.estimated_capacity_points()?;
+        let points_left = capacity.saturating_sub(self.record_count);
+        Some(points_left.saturating_mul(interval_secs))
+    }
+}
+
+/// What LOCUS does once its flash is full.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecordingMode {
+    /// Carry on, overwriting the oldest points
+    Overlap,
+    /// Stop logging
+    FullStop,
+}
+
+bitflags! {
+    /// When LOCUS records a point.
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct LogMode: u32 {
+        const ALWAYS_LOCATE = 1<<0;
+        /// Only record points with a fix
+        const FIX_ONLY = 1<<1;
+        const NORMAL = 1<<2;
+        const INTERVAL = 1<<3;
+        const DISTANCE = 1<<4;
+        const SPEED = 1<<5;
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    fn status(content: ContentFlags, record_count: u32, percent_full: u8) -> Status {
+        Status {
+            serial: 456,
+            recording_mode: RecordingMode::Overlap,
+            mode: LogMode::INTERVAL,
+            content,
+            interval: 2,
+            distance: 0,
+            speed: 0,
+            is_on: true,
+            record_count,
+            percent_full: IntegerPercent::new(percent_full),
+        }
+    }
+
+    #[test]
+    fn test_estimates() {
+        let basic = ContentFlags::UTC
+            | ContentFlags::VALID
+            | ContentFlags::LAT
+            | ContentFlags::LON
+            | ContentFlags::HEIGHT;
+        let known = status(basic, 3769, 46);
+        assert_eq!(known.estimated_capacity_points(), Some(8192));
+        assert_eq!(known.estimated_time_remaining(2), Some(8846));
+
+        let unknown = status(ContentFlags::empty(), 4000, 50);
+        assert_eq!(unknown.estimated_capacity_points(), Some(8000));
+        assert_eq!(unknown.estimated_time_remaining(1), Some(4000));
+
+        let empty = status(ContentFlags::empty(), 0, 0);
+        assert_eq!(empty.estimated_capacity_points(), None);
+        assert_eq!(empty.estimated_time_remaining(1), None);
+    }
+}
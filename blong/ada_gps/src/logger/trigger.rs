@@ -0,0 +1,31 @@
+/// What makes LOCUS record a point, see
+/// [`crate::Gps::configure_logger`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Every so many seconds
+    Interval(u32),
+    /// Every time the gps moves so many meters
+    Distance(u32),
+    /// Every fix while moving faster than so many meters per second
+    Speed(u32),
+}
+
+#[cfg(feature = "commands")]
+impl Trigger {
+    /// The mode field of PMTK187
+    pub(crate) fn mode_field(&self) -> &'static [u8] {
+        match self {
+            Self::Interval(_) => b"1",
+            Self::Distance(_) => b"2",
+            Self::Speed(_) => b"3",
+        }
+    }
+
+    /// The value field of PMTK187
+    pub(crate) fn value(&self) -> u32 {
+        match *self {
+            Self::Interval(value) | Self::Distance(value) | Self::Speed(value) => value,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! A fake gps for host tests.
+//!
+//! Expectations are given as a sequence of [`MockTrans`]. Reads are pushed
+//! into the rx queue as soon as every write before them has been seen, so
+//! tests read in the same order as the captured traffic they're built from.
+//! See `cargo xtask traffic to-mock` for generating expectations from a
+//! capture.
+
+use alloc::{boxed::Box, collections::VecDeque, rc::Rc, vec::Vec};
+use core::{cell::RefCell, convert::Infallible};
+use embedded_hal::{blocking::delay::DelayUs, serial};
+
+use crate::{Gps, RxBuf, RxProducer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MockTrans {
+    /// Bytes the gps sends us
+    Read(Vec<u8>),
+    /// Bytes we expect to send the gps
+    Write(Vec<u8>),
+}
+
+impl MockTrans {
+    pub(crate) fn read_many(bytes: &[u8]) -> Self {
+        Self::Read(bytes.to_vec())
+    }
+
+    pub(crate) fn write_many(bytes: &[u8]) -> Self {
+        Self::Write(bytes.to_vec())
+    }
+}
+
+/// Acts as both the `Tx` and the `Delay` of a [`crate::Gps`].
+#[derive(Clone)]
+pub(crate) struct MockSerial<'rx> {
+    state: Rc<RefCell<State<'rx>>>,
+}
+
+struct State<'rx> {
+    expected: VecDeque<MockTrans>,
+    /// How far through the front of `expected` we are
+    pos: usize,
+    rx: RxProducer<'rx>,
+}
+
+impl<'rx> MockSerial<'rx> {
+    pub(crate) fn new(expects: &[MockTrans], rx: RxProducer<'rx>) -> Self {
+        let mut state = State {
+            expected: expects.iter().cloned().collect(),
+            pos: 0,
+            rx,
+        };
+        state.pump();
+
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    /// Panics if any expectations haven't been met.
+    pub(crate) fn done(&mut self) {
+        let state = self.state.borrow();
+        assert!(
+            state.expected.is_empty(),
+            "Unmet expectations (at byte {} of the first): {:#?}",
+            state.pos,
+            state.expected
+        );
+    }
+}
+
+pub(crate) type MockGps = Gps<'static, MockSerial<'static>, MockSerial<'static>>;
+
+/// A [`Gps`] talking to a [`MockSerial`] expecting `expects`, which has
+/// already turned NMEA output off.
+pub(crate) fn gps(expects: &[MockTrans]) -> (MockGps, MockSerial<'static>) {
+    new_gps(expects, true)
+}
+
+/// Like [`gps`], but leaves NMEA output on, so the first command doesn't
+/// turn it off.
+pub(crate) fn gps_with_nmea_output(expects: &[MockTrans]) -> (MockGps, MockSerial<'static>) {
+    new_gps(expects, false)
+}
+
+fn new_gps(
+    expects: &[MockTrans],
+    already_disabled_nmea_output: bool,
+) -> (MockGps, MockSerial<'static>) {
+    // Leaked so the gps can outlive this, it's only a test
+    let rx_buf: &'static RxBuf = Box::leak(Box::new(RxBuf::new()));
+    let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+    let mock = MockSerial::new(expects, rx_producer);
+    let gps = Gps::new(
+        rx_consumer,
+        mock.clone(),
+        mock.clone(),
+        already_disabled_nmea_output,
+    );
+    (gps, mock)
+}
+
+impl State<'_> {
+    fn advance(&mut self, by: usize) {
+        self.pos += by;
+        let len = match self.expected.front() {
+            Some(MockTrans::Read(bytes) | MockTrans::Write(bytes)) => bytes.len(),
+            None => return,
+        };
+        if self.pos == len {
+            self.expected.pop_front();
+            self.pos = 0;
+        }
+    }
+
+    /// Push pending reads into the rx queue, as far as they'll fit.
+    fn pump(&mut self) {
+        while let Some(MockTrans::Read(bytes)) = self.expected.front() {
+            let remaining = &bytes[self.pos..];
+            let mut grant = match self.rx.grant_max_remaining(remaining.len()) {
+                Ok(grant) => grant,
+                Err(_) => return,
+            };
+            let count = grant.buf().len();
+            grant.buf().copy_from_slice(&remaining[..count]);
+            grant.commit(count);
+            self.advance(count);
+        }
+    }
+}
+
+impl serial::Write<u8> for MockSerial<'_> {
+    type Error = Infallible;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        state.pump();
+
+        let pos = state.pos;
+        match state.expected.front() {
+            Some(MockTrans::Write(bytes)) => assert_eq!(
+                bytes[pos] as char,
+                byte as char,
+                "Wrong byte {} of write {:?}",
+                pos,
+                core::str::from_utf8(bytes)
+            ),
+            Some(MockTrans::Read(bytes)) => panic!(
+                "Wrote {:?}, but expected the gps to send {:?}",
+                byte as char,
+                core::str::from_utf8(bytes)
+            ),
+            None => panic!("Wrote {:?} after all expectations met", byte as char),
+        }
+
+        state.advance(1);
+        state.pump();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl DelayUs<u32> for MockSerial<'_> {
+    fn delay_us(&mut self, _us: u32) {
+        self.state.borrow_mut().pump();
+    }
+}
+
+/// The serial port of a [`crate::AsyncGps`], for use with [`MockDelay`].
+///
+/// Reads wait forever until every write before them has been seen. As delays
+/// finish straight away, a read the gps wouldn't answer times out.
+#[cfg(feature = "async")]
+pub(crate) struct MockAsyncSerial {
+    expected: VecDeque<MockTrans>,
+    /// How far through the front of `expected` we are
+    pos: usize,
+}
+
+#[cfg(feature = "async")]
+impl MockAsyncSerial {
+    pub(crate) fn new(expects: &[MockTrans]) -> Self {
+        Self {
+            expected: expects.iter().cloned().collect(),
+            pos: 0,
+        }
+    }
+
+    /// Panics if any expectations haven't been met.
+    pub(crate) fn done(&self) {
+        assert!(
+            self.expected.is_empty(),
+            "Unmet expectations (at byte {} of the first): {:#?}",
+            self.pos,
+            self.expected
+        );
+    }
+
+    fn advance(&mut self, by: usize) {
+        self.pos += by;
+        let len = match self.expected.front() {
+            Some(MockTrans::Read(bytes) | MockTrans::Write(bytes)) => bytes.len(),
+            None => return,
+        };
+        if self.pos == len {
+            self.expected.pop_front();
+            self.pos = 0;
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::ErrorType for MockAsyncSerial {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Read for MockAsyncSerial {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let bytes = match self.expected.front() {
+            Some(MockTrans::Read(bytes)) => &bytes[self.pos..],
+            _ => core::future::pending().await,
+        };
+        let count = bytes.len().min(buf.len());
+        buf[..count].copy_from_slice(&bytes[..count]);
+        self.advance(count);
+        Ok(count)
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Write for MockAsyncSerial {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for &byte in buf {
+            let pos = self.pos;
+            match self.expected.front() {
+                Some(MockTrans::Write(bytes)) => assert_eq!(
+                    bytes[pos] as char,
+                    byte as char,
+                    "Wrong byte {} of write {:?}",
+                    pos,
+                    core::str::from_utf8(bytes)
+                ),
+                Some(MockTrans::Read(bytes)) => panic!(
+                    "Wrote {:?}, but expected the gps to send {:?}",
+                    byte as char,
+                    core::str::from_utf8(bytes)
+                ),
+                None => panic!("Wrote {:?} after all expectations met", byte as char),
+            }
+            self.advance(1);
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(feature = "async")]
+pub(crate) struct MockDelay;
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::delay::DelayNs for MockDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// Runs `fut` to completion on this thread. Enough for the mocks, which never
+/// need waking.
+#[cfg(feature = "async")]
+pub(crate) fn block_on<F: core::future::Future>(fut: F) -> F::Output {
+    use core::task::{Context, Poll, Waker};
+
+    let mut fut = core::pin::pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(val) = fut.as_mut().poll(&mut cx) {
+            return val;
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! Decoding of the NMEA sentences the gps outputs while tracking.
+//!
+//! [`NmeaListener`] only ever reads, so it's safe to use on a tap of a gps
+//! something else is driving. To decode sentences one at a time, see
+//! [`Sentence`].
+
+mod sentence;
+
+use alloc::vec::Vec;
+
+use crate::{
+    cmd::{self, parse::Fields},
+    debug,
+    satellites::{GsvAssembler, SatellitesInView},
+    trace, AntennaStatus, Packet, ParseError, RxConsumer,
+};
+
+pub use sentence::{FixMode, Gga, Gsa, Gsv, Rmc, Sentence, TimeOfDay};
+
+/// Checks `line` is a whole sentence, from the `$` through the `\r\n`, and
+/// matches its checksum, without decoding it.
+pub fn validate(line: &[u8]) -> Result<(), ParseError> {
+    cmd::parse::split(line).map(|_| ())
+}
+
+pub struct NmeaListener<'rx> {
+    rx: RxConsumer<'rx>,
+    line: Vec<u8>,
+    last_is_carriage_return: bool,
+    epoch: Option<Epoch>,
+    /// A GSA that came too late for the fix in progress, so is for the next
+    pending_gsa: Option<Gsa>,
+    gsv: GsvAssembler,
+    satellites: SatellitesInView,
+    antenna_status: Option<AntennaStatus>,
+}
+
+impl<'rx> NmeaListener<'rx> {
+    pub fn new(rx: RxConsumer<'rx>) -> Self {
+        Self {
+            rx,
+            line: Vec::new(),
+            last_is_carriage_return: false,
+            epoch: None,
+            pending_gsa: None,
+            gsv: GsvAssembler::default(),
+            satellites: SatellitesInView::default(),
+            antenna_status: None,
+        }
+    }
+
+    /// As of the most recent GSV report from each constellation.
+    pub fn satellites(&self) -> &SatellitesInView {
+        &self.satellites
+    }
+
+    /// As of the most recent `$PGTOP`, `None` until the first. The gps only
+    /// sends them once enabled with [`crate::Gps::set_antenna_reports`].
+    pub fn antenna_status(&self) -> Option<AntennaStatus> {
+        self.antenna_status
+    }
+
+    /// Decode everything currently in the rx queue, without blocking.
+    ///
+    /// The gps reports each fix over several sentences. `on_packet` is called
+    /// with the combination of all of them once the first sentence of the next
+    /// fix arrives.
+    pub fn poll<F>(&mut self, mut on_packet: F)
+    where
+        F: FnMut(Packet),
+    {
+        // Getting a grant can fail if the queue is being written to, in which
+        // case we'll pick up the rest next poll.
+        while let Ok(grant) = self.rx.read() {
+            let len = grant.buf().len();
+            for &byte in grant.buf() {
+                self.push_byte(byte, &mut on_packet);
+            }
+            grant.release(len);
+        }
+    }
+
+    fn push_byte<F>(&mut self, byte: u8, on_packet: &mut F)
+    where
+        F: FnMut(Packet),
+    {
+        if byte == b'$' && !self.line.is_empty() {
+            trace!("Resyncing");
+            self.line.clear();
+        } else if cmd::is_too_long(&self.line) {
+            trace!("Dropping overlong sentence");
+            self.line.clear();
+        }
+
+        self.line.push(byte);
+
+        if byte == b'\n' && self.last_is_carriage_return {
+            // Taken so we can borrow the fields while updating ourselves
+            let mut line = core::mem::take(&mut self.line);
+            match cmd::parse::split(&line) {
+                Ok((name, fields)) => self.on_sentence(name, fields, on_packet),
+                Err(err) => {
+                    debug!("Failed to parse nmea sentence: {:?}", err);
+                }
+            }
+            line.clear();
+            self.line = line;
+        }
+        self.last_is_carriage_return = byte == b'\r';
+    }
+
+    fn on_sentence<F>(&mut self, name: &[u8], fields: Fields<'_>, on_packet: &mut F)
+    where
+        F: FnMut(Packet),
+    {
+        if name == b"PGTOP" {
+            let fields: Vec<_> = fields.map(<[u8]>::to_vec).collect();
+            if let Some(status) = AntennaStatus::from_fields(&fields) {
+                self.antenna_status = Some(status);
+            }
+            return;
+        }
+
+        let sentence = match Sentence::decode(name, fields) {
+            Ok(Some(sentence)) => sentence,
+            Ok(None) => {
+                trace!("Ignoring nmea sentence {=[u8]:a}", name);
+                return;
+            }
+            Err(err) => {
+                debug!("Failed to decode {=[u8]:a}: {:?}", name, err);
+                return;
+            }
+        };
+
+        if let Sentence::Gsv(gsv) = &sentence {
+            if let Some((talker, sats)) = self.gsv.push(gsv) {
+                self.satellites.replace(talker, sats);
+            }
+            return;
+        }
+
+        if let Sentence::Gsa(gsa) = sentence {
+            self.satellites.set_active(gsa.active());
+            // The gps sends GSA between GGA and RMC, so if we've had this
+            // fix's RMC already it's about the next one
+            match &mut self.epoch {
+                Some(epoch) if !epoch.rmc_seen => epoch.merge(Sentence::Gsa(gsa)),
+                _ => self.pending_gsa = Some(gsa),
+            }
+            return;
+        }
+
+        if let Sentence::Vtg(_) = sentence {
+            // The gps sends VTG last, after the fix's other sentences
+            if let Some(epoch) = &mut self.epoch {
+                epoch.merge(sentence);
+            }
+            return;
+        }
+
+        let time = match sentence.time() {
+            Some(time) => time,
+            None => return,
+        };
+        if let Some(epoch) = &self.epoch {
+            if epoch.time != time {
+                let epoch = self.epoch.take().unwrap();
+                on_packet(epoch.packet);
+            }
+        }
+
+        let epoch = match &mut self.epoch {
+            Some(epoch) => epoch,
+            None => {
+                let mut epoch = Epoch {
+                    time,
+                    packet: Packet::default(),
+                    rmc_seen: false,
+                };
+                if let Some(gsa) = self.pending_gsa.take() {
+                    epoch.merge(Sentence::Gsa(gsa));
+                }
+                self.epoch.insert(epoch)
+            }
+        };
+        epoch.merge(sentence);
+    }
+}
+
+/// Everything we've heard about a single fix so far
+#[derive(Debug, Clone, PartialEq)]
+struct Epoch {
+    time: TimeOfDay,
+    packet: Packet,
+    rmc_seen: bool,
+}
+
+impl Epoch {
+    fn merge(&mut self, sentence: Sentence) {
+        let into = &mut self.packet;
+        match sentence {
+            Sentence::Gga(gga) => {
+                into.fix = into.fix.take().or(gga.fix);
+                into.lat = into.lat.or(gga.lat.map(|lat| lat as f32));
+                into.lon = into.lon.or(gga.lon.map(|lon| lon as f32));
+                into.height_msl = into.height_msl.or(gga.height_msl.map(round_to_i16));
+                into.geoid_separation = into
+                    .geoid_separation
+                    .or(gga.geoid_separation.map(round_to_i16));
+                into.hdop = into
+                    .hdop
+                    .or(gga.hdop.map(|hdop| (hdop * 100.0 + 0.5) as u16));
+                into.num_sat = into.num_sat.or(gga.num_sat);
+            }
+            Sentence::Rmc(rmc) => {
+                self.rmc_seen = true;
+                // Only RMC has the date, so if it's disabled we can't give a time
+                into.time = into.time.or(rmc.date_time);
+                into.lat = into.lat.or(rmc.lat.map(|lat| lat as f32));
+                into.lon = into.lon.or(rmc.lon.map(|lon| lon as f32));
+                into.speed = into.speed.or(rmc
+                    .speed_knots
+                    .map(|knots| round_to_i16(knots * KNOTS_TO_MPS)));
+                into.heading = into
+                    .heading
+                    .or(rmc.course.map(|degrees| (degrees + 0.5) as u16 % 360));
+            }
+            Sentence::Gsa(gsa) => {
+                let dop = |dop: f32| (dop * 100.0 + 0.5) as u16;
+                into.pdop = into.pdop.or(gsa.pdop.map(dop));
+                into.hdop = into.hdop.or(gsa.hdop.map(dop));
+                into.vdop = into.vdop.or(gsa.vdop.map(dop));
+            }
+            Sentence::Zda(zda) => {
+                into.time = into.time.or(zda.date_time);
+            }
+            Sentence::Gll(gll) => {
+                into.lat = into.lat.or(gll.lat.map(|lat| lat as f32));
+                into.lon = into.lon.or(gll.lon.map(|lon| lon as f32));
+            }
+            Sentence::Vtg(vtg) => {
+                into.speed = into.speed.or(vtg
+                    .speed_knots
+                    .map(|knots| round_to_i16(knots * KNOTS_TO_MPS)));
+                into.heading = into
+                    .heading
+                    .or(vtg.course_true.map(|degrees| (degrees + 0.5) as u16 % 360));
+            }
+            // Not about the fix
+            Sentence::Gsv(_) => {}
+        }
+    }
+}
+
+const KNOTS_TO_MPS: f32 = 0.514_444;
+
+fn round_to_i16(val: f32) -> i16 {
+    if val < 0.0 {
+        (val - 0.5) as i16
+    } else {
+        (val + 0.5) as i16
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::{
+        satellites::{Constellation, Satellite, SnrStats},
+        Fix, RxBuf, UtcDateTime,
+    };
+
+    fn listen(input: &[u8]) -> Vec<Packet> {
+        let rx_buf = RxBuf::new();
+        let (mut rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut listener = NmeaListener::new(rx_consumer);
+        let mut packets = Vec::new();
+
+        for chunk in input.chunks(100) {
+            let mut grant = rx_producer.grant_exact(chunk.len()).unwrap();
+            grant.buf().copy_from_slice(chunk);
+            grant.commit(chunk.len());
+            listener.poll(|packet| packets.push(packet));
+        }
+
+        packets
+    }
+
+    #[test]
+    fn test_combines_sentences_per_fix() {
+        let packets = listen(
+            b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n\
+              $GPRMC,064951.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2C\r\n\
+              $GPGGA,064952.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*60\r\n",
+        );
+
+        assert_eq!(
+            packets,
+            vec![Packet {
+                time: UtcDateTime::new(2006, 4, 26, 6, 49, 51, 0),
+                fix: Some(Fix::GpsFix),
+                lat: Some(23.118_76),
+                lon: Some(120.274_06),
+                height_msl: Some(40),
+                geoid_separation: Some(18),
+                speed: Some(0),
+                heading: Some(165),
+                hdop: Some(95),
+                vdop: None,
+                pdop: None,
+                num_sat: Some(8),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_gll_and_zda() {
+        let packets = listen(
+            b"$GPGLL,2307.1256,N,12016.4438,E,064951.000,A,A*5F\r\n\
+              $GPZDA,064951.000,26,04,2006,,*5D\r\n\
+              $GPZDA,064952.000,26,04,2006,,*5E\r\n",
+        );
+
+        assert_eq!(
+            packets,
+            vec![Packet {
+                time: UtcDateTime::new(2006, 4, 26, 6, 49, 51, 0),
+                lat: Some(23.118_76),
+                lon: Some(120.274_06),
+                ..Packet::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vtg_without_rmc() {
+        let packets = listen(
+            b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n\
+              $GPVTG,165.48,T,,M,10.5,N,19.4,K,A*3B\r\n\
+              $GPGGA,064952.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*60\r\n",
+        );
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].speed, Some(5));
+        assert_eq!(packets[0].heading, Some(165));
+        // Without RMC there's no date
+        assert_eq!(packets[0].time, None);
+    }
+
+    #[test]
+    fn test_gsa_without_gga() {
+        let packets = listen(
+            b"$GPGSA,A,3,29,21,26,15,18,09,06,10,,,,,2.32,0.95,2.11*00\r\n\
+              $GPRMC,064951.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2C\r\n\
+              $GPGSA,A,2,29,21,26,,,,,,,,,,3.10,2.50,1.83*00\r\n\
+              $GPRMC,064952.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2F\r\n\
+              $GPRMC,064953.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2E\r\n",
+        );
+
+        // Each GSA goes with the RMC after it
+        let dops: Vec<_> = packets
+            .iter()
+            .map(|packet| (packet.pdop, packet.hdop, packet.vdop))
+            .collect();
+        assert_eq!(
+            dops,
+            vec![
+                (Some(232), Some(95), Some(211)),
+                (Some(310), Some(250), Some(183)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_fix() {
+        let packets = listen(
+            b"$GPGGA,235947.000,,,,,0,0,,,M,,M,,*46\r\n\
+              $GPRMC,235947.000,V,,,,,0.00,0.00,060180,,,N*4C\r\n\
+              $GPGGA,235948.000,,,,,0,0,,,M,,M,,*49\r\n",
+        );
+
+        assert_eq!(
+            packets,
+            vec![Packet {
+                time: UtcDateTime::new(1980, 1, 6, 23, 59, 47, 0),
+                fix: Some(Fix::No),
+                speed: Some(0),
+                heading: Some(0),
+                num_sat: Some(0),
+                ..Packet::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_skips_corrupt_and_unknown_sentences() {
+        let packets = listen(
+            b"$GPGGA,064951.000,2307.1256,N,120$GPGSA,A,3,29,21,26,15,18,09,06,10,,,,,2.32,0.95,2.11*00\r\n\
+              $GPGGA,064951.000,2307.1256,N,12016.4438,W,1,8,0.95,39.9,M,17.8,M,,*71\r\n\
+              $GPGGA,064952.000,2307.1256,S,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*7D\r\n",
+        );
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].lon, Some(-120.274_06));
+        assert_eq!(packets[0].time, None);
+        assert_eq!(packets[0].height_ellipsoid(), Some(58));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(b"$PMTK001,605,3*33\r\n"), Ok(()));
+        assert_eq!(
+            validate(b"$PMTK001,605,3*32\r\n"),
+            Err(ParseError::WrongChecksum)
+        );
+        assert_eq!(
+            validate(b"$PMTK001,605,3*33"),
+            Err(ParseError::ExpectedSuffix)
+        );
+    }
+
+    #[test]
+    fn test_antenna_status() {
+        let rx_buf = RxBuf::new();
+        let (mut rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut listener = NmeaListener::new(rx_consumer);
+        assert_eq!(listener.antenna_status(), None);
+
+        let input: &[u8] = b"$PGTOP,11,3*6F\r\n$PGTOP,11,1*6D\r\n";
+        let mut grant = rx_producer.grant_exact(input.len()).unwrap();
+        grant.buf().copy_from_slice(input);
+        grant.commit(input.len());
+        listener.poll(|_| panic!("No fixes sent"));
+
+        assert_eq!(listener.antenna_status(), Some(AntennaStatus::Shorted));
+        assert_eq!(
+            AntennaStatus::parse(b"$PGTOP,11,3*6F\r\n"),
+            Some(AntennaStatus::Active)
+        );
+    }
+
+    #[test]
+    fn test_satellites_in_view() {
+        let rx_buf = RxBuf::new();
+        let (mut rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let mut listener = NmeaListener::new(rx_consumer);
+
+        let input: &[u8] =
+            b"$GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45*75\r\n\
+              $GPGSV,2,2,08,18,27,106,,22,70,058,44,29,15,173,36,42,32,236,33*7F\r\n\
+              $GLGSV,1,1,02,70,10,050,21,71,55,120,*62\r\n\
+              $GPGSV,2,2,08,18,27,106,,22,70,058,44,29,15,173,36,42,32,236,33*7F\r\n";
+        let mut grant = rx_producer.grant_exact(input.len()).unwrap();
+        grant.buf().copy_from_slice(input);
+        grant.commit(input.len());
+        listener.poll(|_| panic!("No fixes sent"));
+
+        let satellites = listener.satellites();
+        // The repeated second part is out of sequence, so ignored
+        assert_eq!(satellites.iter().count(), 10);
+        assert_eq!(
+            satellites.iter().next(),
+            Some(&Satellite {
+                constellation: Constellation::Gps,
+                prn: 1,
+                elevation: Some(40),
+                azimuth: Some(83),
+                snr: Some(46),
+            })
+        );
+
+        let stats = satellites.stats();
+        assert_eq!(stats.overall.tracked, 8);
+        assert_eq!(stats.overall.snr_max, Some(46));
+        assert_eq!(
+            stats.per_constellation[1],
+            (
+                Constellation::Sbas,
+                SnrStats {
+                    in_view: 1,
+                    tracked: 1,
+                    snr_min: Some(33),
+                    snr_avg: Some(33),
+                    snr_max: Some(33),
+                }
+            )
+        );
+    }
+}
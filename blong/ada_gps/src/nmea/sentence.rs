@@ -0,0 +1,707 @@
+//! Decoding single NMEA sentences into their own types, without allocating.
+//!
+//! [`super::NmeaListener`] combines these into [`crate::Packet`]s. Use
+//! [`Sentence::parse`] directly for sentences from somewhere else, such as
+//! forwarded by [`crate::NmeaStream`].
+
+use core::str::FromStr;
+
+use crate::{
+    cmd::{self, parse::Fields},
+    coords::{self, Axis},
+    debug,
+    satellites::{Constellation, Satellite},
+    trace, Fix, ParseError, UtcDateTime,
+};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsv(Gsv),
+    Gsa(Gsa),
+    Vtg(Vtg),
+    Zda(Zda),
+    Gll(Gll),
+}
+
+impl Sentence {
+    /// Parses a whole line, from the `$` through the `\r\n`, checking the
+    /// checksum. `Ok(None)` for a valid sentence of a kind we don't decode.
+    pub fn parse(line: &[u8]) -> Result<Option<Self>, ParseError> {
+        let (name, fields) = cmd::parse::split(line)?;
+        Self::decode(name, fields)
+    }
+
+    pub(crate) fn decode(name: &[u8], fields: Fields<'_>) -> Result<Option<Self>, ParseError> {
+        // Skip the talker, as the gps may report GPS, GLONASS or combined.
+        match *name {
+            [_, _, b'G', b'G', b'A'] => Gga::decode(fields).map(|gga| Some(Self::Gga(gga))),
+            [_, _, b'R', b'M', b'C'] => Rmc::decode(fields).map(|rmc| Some(Self::Rmc(rmc))),
+            [t0, t1, b'G', b'S', b'V'] => {
+                Gsv::decode([t0, t1], fields).map(|gsv| Some(Self::Gsv(gsv)))
+            }
+            [_, _, b'G', b'S', b'A'] => Gsa::decode(fields).map(|gsa| Some(Self::Gsa(gsa))),
+            [_, _, b'V', b'T', b'G'] => Vtg::decode(fields).map(|vtg| Some(Self::Vtg(vtg))),
+            [_, _, b'Z', b'D', b'A'] => Zda::decode(fields).map(|zda| Some(Self::Zda(zda))),
+            [_, _, b'G', b'L', b'L'] => Gll::decode(fields).map(|gll| Some(Self::Gll(gll))),
+            _ => Ok(None),
+        }
+    }
+
+    /// When the fix the sentence is about was taken, if it's about one.
+    pub fn time(&self) -> Option<TimeOfDay> {
+        match self {
+            Self::Gga(gga) => Some(gga.time),
+            Self::Rmc(rmc) => Some(rmc.time),
+            Self::Zda(zda) => Some(zda.time),
+            Self::Gll(gll) => Some(gll.time),
+            Self::Gsv(_) | Self::Gsa(_) | Self::Vtg(_) => None,
+        }
+    }
+}
+
+/// UTC. Only RMC has the date to go with it, see [`Rmc::date_time`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millisecond: u16,
+}
+
+/// Fix data: where we are and how well we know it.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gga {
+    pub time: TimeOfDay,
+    /// In decimal degrees, positive is north. `None` without a fix.
+    pub lat: Option<f64>,
+    /// In decimal degrees, positive is east. `None` without a fix.
+    pub lon: Option<f64>,
+    /// `None` for fix qualities we don't know, such as RTK
+    pub fix: Option<Fix>,
+    /// Used in the fix
+    pub num_sat: Option<u8>,
+    pub hdop: Option<f32>,
+    /// Altitude above mean sea level (the geoid) in meters
+    pub height_msl: Option<f32>,
+    /// Height of the geoid above the WGS84 ellipsoid in meters
+    pub geoid_separation: Option<f32>,
+}
+
+impl Gga {
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        let [time, lat, ns, lon, ew, quality, num_sat, hdop, height_msl, _, geoid_separation] =
+            take_fields(&mut fields, "GGA")?;
+        let (lat, lon) = position_fields(lat, ns, lon, ew);
+
+        Ok(Self {
+            time: time_of_day_field(time)?,
+            lat,
+            lon,
+            fix: match quality {
+                b"0" => Some(Fix::No),
+                b"1" => Some(Fix::GpsFix),
+                b"2" => Some(Fix::DGpsFix),
+                b"6" => Some(Fix::DeadReckoning),
+                _ => None,
+            },
+            num_sat: number_field(num_sat),
+            hdop: number_field(hdop),
+            height_msl: number_field(height_msl),
+            geoid_separation: number_field(geoid_separation),
+        })
+    }
+}
+
+/// Recommended minimum data: position, velocity and the date.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmc {
+    pub time: TimeOfDay,
+    /// [`Self::time`] on the reported date. `None` if the date is missing or
+    /// invalid.
+    ///
+    /// Before the gps has heard the time from a satellite it reports the GPS
+    /// epoch, 1980-01-06.
+    pub date_time: Option<UtcDateTime>,
+    /// Whether the gps has a valid position. If not, `lat` and `lon` are
+    /// `None` even if reported.
+    pub active: bool,
+    /// In decimal degrees, positive is north
+    pub lat: Option<f64>,
+    /// In decimal degrees, positive is east
+    pub lon: Option<f64>,
+    /// Speed over ground in knots
+    pub speed_knots: Option<f32>,
+    /// Course over ground in degrees clockwise from true north
+    pub course: Option<f32>,
+}
+
+impl Rmc {
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        let [time, status, lat, ns, lon, ew, speed_knots, course, date] =
+            take_fields(&mut fields, "RMC")?;
+        let time = time_of_day_field(time)?;
+        let active = status == b"A";
+        let (lat, lon) = if active {
+            position_fields(lat, ns, lon, ew)
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            time,
+            date_time: date_field(date).and_then(|(year, month, day)| {
+                UtcDateTime::new(
+                    year,
+                    month,
+                    day,
+                    time.hour,
+                    time.minute,
+                    time.second,
+                    time.millisecond,
+                )
+            }),
+            active,
+            lat,
+            lon,
+            speed_knots: number_field(speed_knots),
+            course: number_field(course),
+        })
+    }
+}
+
+/// Part of a report of the satellites in view. The gps splits each talker's
+/// report over as many sentences as it takes, four satellites to each.
+/// [`crate::NmeaListener::satellites`] puts them back together.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gsv {
+    pub talker: [u8; 2],
+    /// How many sentences the report is split over
+    pub total: u8,
+    /// Which of them this is, from 1
+    pub num: u8,
+    /// Over the whole report
+    pub in_view: Option<u8>,
+    satellites: [Option<Satellite>; 4],
+}
+
+impl Gsv {
+    pub fn satellites(&self) -> impl Iterator<Item = &Satellite> {
+        self.satellites.iter().flatten()
+    }
+
+    fn decode(talker: [u8; 2], mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        let [total, num, in_view] = take_fields(&mut fields, "GSV")?;
+        let mut gsv = Self {
+            talker,
+            total: small_int_field(total).ok_or(ParseError::ParseField)? as u8,
+            num: small_int_field(num).ok_or(ParseError::ParseField)? as u8,
+            in_view: small_int_field(in_view).map(|n| n as u8),
+            satellites: Default::default(),
+        };
+
+        for slot in &mut gsv.satellites {
+            // NMEA 4.10 adds a signal id after the satellites, which leaves
+            // too few fields for another
+            let (prn, elevation, azimuth, snr) =
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(prn), Some(elevation), Some(azimuth), Some(snr)) => {
+                        (prn, elevation, azimuth, snr)
+                    }
+                    _ => break,
+                };
+            let prn = match small_int_field(prn) {
+                Some(prn) if prn <= u8::MAX as u16 => prn as u8,
+                _ => {
+                    trace!("Skipping satellite with PRN {=[u8]:a}", prn);
+                    continue;
+                }
+            };
+            *slot = Some(Satellite {
+                constellation: Constellation::from_talker(&talker, prn),
+                prn,
+                elevation: small_int_field(elevation).map(|e| e as u8),
+                azimuth: small_int_field(azimuth),
+                snr: small_int_field(snr).map(|snr| snr as u8),
+            });
+        }
+
+        Ok(gsv)
+    }
+}
+
+/// The satellites used in the current fix, and the dilution of precision
+/// their geometry gives.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsa {
+    /// `None` if the gps sent one we don't know
+    pub mode: Option<FixMode>,
+    active: [Option<u8>; 12],
+    pub pdop: Option<f32>,
+    pub hdop: Option<f32>,
+    pub vdop: Option<f32>,
+}
+
+/// How many dimensions the gps has a fix in.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FixMode {
+    No,
+    /// Without altitude, from only three satellites
+    TwoD,
+    ThreeD,
+}
+
+impl Gsa {
+    /// PRNs of the satellites used in the fix, as in
+    /// [`Satellite::prn`](crate::satellites::Satellite::prn).
+    pub fn active(&self) -> impl Iterator<Item = u8> + '_ {
+        self.active.iter().flatten().copied()
+    }
+
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        // The first field is whether the mode is automatic, which it always
+        // is unless told otherwise with a command we don't send
+        let [_, mode] = take_fields(&mut fields, "GSA")?;
+        let prns: [&[u8]; 12] = take_fields(&mut fields, "GSA")?;
+        let [pdop, hdop, vdop] = take_fields(&mut fields, "GSA")?;
+
+        let mut active = [None; 12];
+        for (slot, prn) in active.iter_mut().zip(prns) {
+            *slot = small_int_field(prn)
+                .filter(|&prn| prn <= u8::MAX as u16)
+                .map(|prn| prn as u8);
+        }
+
+        Ok(Self {
+            mode: match mode {
+                b"1" => Some(FixMode::No),
+                b"2" => Some(FixMode::TwoD),
+                b"3" => Some(FixMode::ThreeD),
+                _ => None,
+            },
+            active,
+            pdop: number_field(pdop),
+            hdop: number_field(hdop),
+            vdop: number_field(vdop),
+        })
+    }
+}
+
+/// Course and speed over ground.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vtg {
+    /// In degrees clockwise from true north
+    pub course_true: Option<f32>,
+    /// In degrees clockwise from magnetic north. The gps doesn't know the
+    /// magnetic variation, so this is usually `None`.
+    pub course_magnetic: Option<f32>,
+    pub speed_knots: Option<f32>,
+    pub speed_kph: Option<f32>,
+}
+
+impl Vtg {
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        // Each value is followed by a field with its unit
+        let [course_true, _, course_magnetic, _, speed_knots, _, speed_kph, _] =
+            take_fields(&mut fields, "VTG")?;
+
+        Ok(Self {
+            course_true: number_field(course_true),
+            course_magnetic: number_field(course_magnetic),
+            speed_knots: number_field(speed_knots),
+            speed_kph: number_field(speed_kph),
+        })
+    }
+}
+
+/// The date and time, on their own.
+///
+/// The date has all four digits of the year, unlike RMC's, so this is the
+/// one to set a clock from.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zda {
+    pub time: TimeOfDay,
+    /// [`Self::time`] on the reported date. `None` if the date is missing or
+    /// invalid.
+    pub date_time: Option<UtcDateTime>,
+    /// How far ahead of UTC local time is, in minutes. The gps doesn't know
+    /// the time zone, so this is usually `None`.
+    pub local_offset_minutes: Option<i16>,
+}
+
+impl Zda {
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        let [time, day, month, year, local_hours, local_minutes] = take_fields(&mut fields, "ZDA")?;
+        let time = time_of_day_field(time)?;
+
+        // The minutes take the sign of the hours, which may be `-00`
+        let minutes: i16 = number_field(local_minutes).unwrap_or(0);
+        let local_offset_minutes = number_field(local_hours).map(|hours: i16| {
+            if local_hours.starts_with(b"-") {
+                hours * 60 - minutes
+            } else {
+                hours * 60 + minutes
+            }
+        });
+
+        Ok(Self {
+            time,
+            date_time: number_field(year).and_then(|year| {
+                UtcDateTime::new(
+                    year,
+                    two_digits(month)?,
+                    two_digits(day)?,
+                    time.hour,
+                    time.minute,
+                    time.second,
+                    time.millisecond,
+                )
+            }),
+            local_offset_minutes,
+        })
+    }
+}
+
+/// Position and time, without the rest of GGA or RMC.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gll {
+    pub time: TimeOfDay,
+    /// Whether the gps has a valid position. If not, `lat` and `lon` are
+    /// `None` even if reported.
+    pub active: bool,
+    /// In decimal degrees, positive is north
+    pub lat: Option<f64>,
+    /// In decimal degrees, positive is east
+    pub lon: Option<f64>,
+}
+
+impl Gll {
+    fn decode(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        let [lat, ns, lon, ew, time, status] = take_fields(&mut fields, "GLL")?;
+        let active = status == b"A";
+        let (lat, lon) = if active {
+            position_fields(lat, ns, lon, ew)
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            time: time_of_day_field(time)?,
+            active,
+            lat,
+            lon,
+        })
+    }
+}
+
+/// The first `N` fields, ignoring any after them.
+fn take_fields<'a, const N: usize>(
+    fields: &mut Fields<'a>,
+    kind: &str,
+) -> Result<[&'a [u8]; N], ParseError> {
+    let mut taken = [&[][..]; N];
+    for field in &mut taken {
+        *field = fields.next().ok_or_else(|| {
+            debug!("{} has too few fields", kind);
+            ParseError::ExpectedField
+        })?;
+    }
+    Ok(taken)
+}
+
+/// Takes the four fields lat, N/S, lon, E/W.
+fn position_fields(lat: &[u8], ns: &[u8], lon: &[u8], ew: &[u8]) -> (Option<f64>, Option<f64>) {
+    (
+        coords::parse_nmea(lat, ns, Axis::Lat),
+        coords::parse_nmea(lon, ew, Axis::Lon),
+    )
+}
+
+/// `None` if empty, as for values the gps doesn't know yet, or malformed.
+fn number_field<T: FromStr>(val: &[u8]) -> Option<T> {
+    if val.is_empty() {
+        return None;
+    }
+    let parsed = core::str::from_utf8(val)
+        .ok()
+        .and_then(|val| val.parse().ok());
+    if parsed.is_none() {
+        debug!("Failed to parse field {=[u8]:a} as a number", val);
+    }
+    parsed
+}
+
+/// GSV and GSA fields are mostly small non-negative integers, possibly empty.
+fn small_int_field(val: &[u8]) -> Option<u16> {
+    if val.is_empty() || val.len() > 3 {
+        return None;
+    }
+    let mut n = 0;
+    for &digit in val {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        n = n * 10 + (digit - b'0') as u16;
+    }
+    Some(n)
+}
+
+fn two_digits(val: &[u8]) -> Option<u8> {
+    match val {
+        [tens @ b'0'..=b'9', ones @ b'0'..=b'9'] => Some((tens - b'0') * 10 + (ones - b'0')),
+        _ => None,
+    }
+}
+
+/// Parses `hhmmss` or `hhmmss.sss`
+fn time_of_day_field(val: &[u8]) -> Result<TimeOfDay, ParseError> {
+    let parse = || {
+        let millisecond = match val.get(6..)? {
+            [] => 0,
+            [b'.', fraction @ ..] => {
+                let mut millisecond = 0;
+                for i in 0..3 {
+                    let digit = match fraction.get(i) {
+                        Some(digit @ b'0'..=b'9') => digit - b'0',
+                        Some(_) => return None,
+                        None => 0,
+                    };
+                    millisecond = millisecond * 10 + digit as u16;
+                }
+                millisecond
+            }
+            _ => return None,
+        };
+
+        Some(TimeOfDay {
+            hour: two_digits(&val[0..2])?,
+            minute: two_digits(&val[2..4])?,
+            second: two_digits(&val[4..6])?,
+            millisecond,
+        })
+    };
+    parse().ok_or_else(|| {
+        debug!("Failed to parse field {=[u8]:a} as a time", val);
+        ParseError::ParseField
+    })
+}
+
+/// Parses `ddmmyy` into (year, month, day)
+fn date_field(val: &[u8]) -> Option<(i32, u8, u8)> {
+    if val.len() != 6 {
+        return None;
+    }
+    let year = match two_digits(&val[4..6])? as i32 {
+        // Before the gps has the time it reports the GPS epoch, 1980
+        yy @ 80..=99 => 1900 + yy,
+        yy => 2000 + yy,
+    };
+    Some((year, two_digits(&val[2..4])?, two_digits(&val[0..2])?))
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    fn assert_degrees(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("Expected a position");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_gga() {
+        let gga = match Sentence::parse(
+            b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n",
+        ) {
+            Ok(Some(Sentence::Gga(gga))) => gga,
+            other => panic!("Expected GGA, got {:?}", other),
+        };
+        assert_degrees(gga.lat, 23.118_76);
+        assert_degrees(gga.lon, 120.274_063_333);
+        assert_eq!(
+            gga,
+            Gga {
+                time: TimeOfDay {
+                    hour: 6,
+                    minute: 49,
+                    second: 51,
+                    millisecond: 0,
+                },
+                fix: Some(Fix::GpsFix),
+                num_sat: Some(8),
+                hdop: Some(0.95),
+                height_msl: Some(39.9),
+                geoid_separation: Some(17.8),
+                ..gga
+            }
+        );
+    }
+
+    #[test]
+    fn test_rmc() {
+        let rmc = match Sentence::parse(
+            b"$GPRMC,064951.000,A,2307.1256,S,12016.4438,W,0.03,165.48,260406,3.05,W,A*23\r\n",
+        ) {
+            Ok(Some(Sentence::Rmc(rmc))) => rmc,
+            other => panic!("Expected RMC, got {:?}", other),
+        };
+        assert_eq!(rmc.date_time, UtcDateTime::new(2006, 4, 26, 6, 49, 51, 0));
+        assert!(rmc.active);
+        assert_degrees(rmc.lat, -23.118_76);
+        assert_degrees(rmc.lon, -120.274_063_333);
+        assert_eq!(rmc.speed_knots, Some(0.03));
+        assert_eq!(rmc.course, Some(165.48));
+
+        // No position until the gps has a fix, and the date's the GPS epoch
+        let rmc = match Sentence::parse(b"$GPRMC,235947.000,V,,,,,0.00,0.00,060180,,,N*4C\r\n") {
+            Ok(Some(Sentence::Rmc(rmc))) => rmc,
+            other => panic!("Expected RMC, got {:?}", other),
+        };
+        assert_eq!(rmc.date_time, UtcDateTime::new(1980, 1, 6, 23, 59, 47, 0));
+        assert!(!rmc.active);
+        assert_eq!((rmc.lat, rmc.lon), (None, None));
+    }
+
+    #[test]
+    fn test_gsv() {
+        let gsv = match Sentence::parse(b"$GPGSV,3,3,09,42,32,236,33,1*5D\r\n") {
+            Ok(Some(Sentence::Gsv(gsv))) => gsv,
+            other => panic!("Expected GSV, got {:?}", other),
+        };
+        assert_eq!((gsv.talker, gsv.total, gsv.num), (*b"GP", 3, 3));
+        assert_eq!(gsv.in_view, Some(9));
+        // The trailing signal id isn't a satellite
+        assert_eq!(
+            gsv.satellites().collect::<alloc::vec::Vec<_>>(),
+            [&Satellite {
+                constellation: Constellation::Sbas,
+                prn: 42,
+                elevation: Some(32),
+                azimuth: Some(236),
+                snr: Some(33),
+            }]
+        );
+
+        // Satellites with a malformed PRN are skipped, and an empty SNR is
+        // one in view but not tracked
+        let gsv = match Sentence::parse(b"$GLGSV,1,1,02,70,10,050,21,XX,55,120,*64\r\n") {
+            Ok(Some(Sentence::Gsv(gsv))) => gsv,
+            other => panic!("Expected GSV, got {:?}", other),
+        };
+        assert_eq!(
+            gsv.satellites()
+                .map(|sat| (sat.constellation, sat.prn, sat.snr))
+                .collect::<alloc::vec::Vec<_>>(),
+            [(Constellation::Glonass, 70, Some(21))]
+        );
+    }
+
+    #[test]
+    fn test_gsa() {
+        let gsa = match Sentence::parse(b"$GPGSA,A,2,29,21,26,,,,,,,,,,3.10,2.50,1.83*00\r\n") {
+            Ok(Some(Sentence::Gsa(gsa))) => gsa,
+            other => panic!("Expected GSA, got {:?}", other),
+        };
+        assert_eq!(gsa.mode, Some(FixMode::TwoD));
+        assert_eq!(gsa.active().collect::<alloc::vec::Vec<_>>(), [29, 21, 26]);
+        assert_eq!(
+            (gsa.pdop, gsa.hdop, gsa.vdop),
+            (Some(3.1), Some(2.5), Some(1.83))
+        );
+    }
+
+    #[test]
+    fn test_vtg() {
+        assert_eq!(
+            Sentence::parse(b"$GPVTG,165.48,T,,M,0.03,N,0.06,K,A*36\r\n"),
+            Ok(Some(Sentence::Vtg(Vtg {
+                course_true: Some(165.48),
+                course_magnetic: None,
+                speed_knots: Some(0.03),
+                speed_kph: Some(0.06),
+            })))
+        );
+    }
+
+    #[test]
+    fn test_zda() {
+        assert_eq!(
+            Sentence::parse(b"$GPZDA,064951.000,26,04,2006,,*5D\r\n"),
+            Ok(Some(Sentence::Zda(Zda {
+                time: TimeOfDay {
+                    hour: 6,
+                    minute: 49,
+                    second: 51,
+                    millisecond: 0,
+                },
+                date_time: UtcDateTime::new(2006, 4, 26, 6, 49, 51, 0),
+                local_offset_minutes: None,
+            })))
+        );
+
+        let zda = match Sentence::parse(b"$GPZDA,064951.000,26,04,2006,-00,30*73\r\n") {
+            Ok(Some(Sentence::Zda(zda))) => zda,
+            other => panic!("Expected ZDA, got {:?}", other),
+        };
+        assert_eq!(zda.local_offset_minutes, Some(-30));
+    }
+
+    #[test]
+    fn test_gll() {
+        let gll = match Sentence::parse(b"$GPGLL,2307.1256,N,12016.4438,E,064951.000,A,A*5F\r\n") {
+            Ok(Some(Sentence::Gll(gll))) => gll,
+            other => panic!("Expected GLL, got {:?}", other),
+        };
+        assert!(gll.active);
+        assert_degrees(gll.lat, 23.118_76);
+        assert_degrees(gll.lon, 120.274_063_333);
+
+        assert_eq!(
+            Sentence::parse(b"$GPGLL,,,,,235947.000,V,N*74\r\n"),
+            Ok(Some(Sentence::Gll(Gll {
+                time: TimeOfDay {
+                    hour: 23,
+                    minute: 59,
+                    second: 47,
+                    millisecond: 0,
+                },
+                active: false,
+                lat: None,
+                lon: None,
+            })))
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(Sentence::parse(b"$PGTOP,11,3*6F\r\n"), Ok(None));
+        assert_eq!(
+            Sentence::parse(b"$GPGGA,064951.000,2307.1256,N*0D\r\n"),
+            Err(ParseError::ExpectedField)
+        );
+        assert_eq!(
+            Sentence::parse(b"$GPRMC,0649,A,,,,,,,*2D\r\n"),
+            Err(ParseError::ParseField)
+        );
+        assert_eq!(
+            Sentence::parse(b"$GPRMC,064951.000,A,,,,,,,*00\r\n"),
+            Err(ParseError::WrongChecksum)
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! Which NMEA sentences the gps sends, see [`crate::Gps::set_nmea_output`].
+
+/// Highest rate PMTK314 accepts
+const MAX_RATE: u8 = 5;
+const RATE_FIELDS: [&[u8]; MAX_RATE as usize + 1] = [b"0", b"1", b"2", b"3", b"4", b"5"];
+
+/// How often to send each sentence, as once every so many fixes from 1 to
+/// 5, or 0 to not send it. The default sends nothing.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NmeaOutputConfig {
+    pub gll: u8,
+    pub rmc: u8,
+    pub vtg: u8,
+    pub gga: u8,
+    pub gsa: u8,
+    pub gsv: u8,
+    pub zda: u8,
+}
+
+impl NmeaOutputConfig {
+    pub fn is_disabled(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The fields of PMTK314, or `None` if a rate is out of range.
+    pub(crate) fn fields(&self) -> Option<[&'static [u8]; 19]> {
+        let rate = |rate: u8| RATE_FIELDS.get(rate as usize).copied();
+        let mut fields: [&[u8]; 19] = [b"0"; 19];
+        fields[0] = rate(self.gll)?;
+        fields[1] = rate(self.rmc)?;
+        fields[2] = rate(self.vtg)?;
+        fields[3] = rate(self.gga)?;
+        fields[4] = rate(self.gsa)?;
+        fields[5] = rate(self.gsv)?;
+        // 6 to 16 are reserved, and 18 is MTK's own channel status
+        fields[17] = rate(self.zda)?;
+        Some(fields)
+    }
+}
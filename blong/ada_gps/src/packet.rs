@@ -0,0 +1,129 @@
+use crate::UtcDateTime;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum Fix {
+    /// Fix not available.
+    ///
+    /// Corresponds to NMEA GGA quality 0.
+    No,
+    /// Normal GPS fix
+    ///
+    /// Corresponds to NMEA GGA quality 1.
+    GpsFix,
+    /// Differential GPS fix (enhanced quality).
+    ///
+    /// Corresponds to NMEA GGA quality 2.
+    DGpsFix,
+    /// Dead reckoning.
+    ///
+    /// Corresponds to NMEA GGA quality 6.
+    DeadReckoning,
+}
+
+impl Fix {
+    /// Rough horizontal accuracy in meters, as HDOP times the range error
+    /// expected for this kind of fix. `hdop` is multiplied by 100, as in
+    /// [`Packet::hdop`].
+    ///
+    /// `None` if there's no fix, or it's dead reckoning, where HDOP says
+    /// nothing about how far we've drifted.
+    pub fn est_accuracy_m(&self, hdop: u16, uere: &Uere) -> Option<f32> {
+        let uere_m = match self {
+            Fix::GpsFix => uere.gps_m,
+            Fix::DGpsFix => uere.dgps_m,
+            Fix::No | Fix::DeadReckoning => return None,
+        };
+        Some(hdop as f32 / 100.0 * uere_m)
+    }
+}
+
+/// User equivalent range error: how far off the range to each satellite is
+/// likely to be, in meters.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Uere {
+    pub gps_m: f32,
+    /// With corrections from SBAS
+    pub dgps_m: f32,
+}
+
+impl Default for Uere {
+    /// Typical figures for a consumer receiver under open sky
+    fn default() -> Self {
+        Self {
+            gps_m: 5.0,
+            dgps_m: 2.0,
+        }
+    }
+}
+
+/// A single position report, either logged by LOCUS or decoded from live NMEA
+/// output.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Packet {
+    pub time: Option<UtcDateTime>,
+    pub fix: Option<Fix>,
+    /// In decimal degrees, positive is north
+    pub lat: Option<f32>,
+    /// In decimal degrees, positive is east
+    pub lon: Option<f32>,
+    /// Altitude above mean sea level (the geoid) in meters. This is what GGA
+    /// and LOCUS report as the altitude.
+    pub height_msl: Option<i16>,
+    /// Height of the geoid above the WGS84 ellipsoid in meters. Only known
+    /// from live NMEA, LOCUS doesn't log it.
+    pub geoid_separation: Option<i16>,
+    /// In meters per second
+    pub speed: Option<i16>,
+    /// In degrees
+    pub heading: Option<u16>,
+    /// Horizontal dilution of precision, multiplied by 100
+    pub hdop: Option<u16>,
+    /// Vertical dilution of precision, multiplied by 100. Only known from
+    /// live NMEA, LOCUS doesn't log it.
+    pub vdop: Option<u16>,
+    /// Position (3D) dilution of precision, multiplied by 100. Only known
+    /// from live NMEA.
+    pub pdop: Option<u16>,
+    pub num_sat: Option<u8>,
+}
+
+impl Packet {
+    /// Altitude above the WGS84 ellipsoid in meters, which is what most
+    /// formats other than NMEA (GPX, for one) expect.
+    pub fn height_ellipsoid(&self) -> Option<i16> {
+        Some(self.height_msl?.saturating_add(self.geoid_separation?))
+    }
+
+    /// See [`Fix::est_accuracy_m`]
+    pub fn est_accuracy_m(&self, uere: &Uere) -> Option<f32> {
+        self.fix.as_ref()?.est_accuracy_m(self.hdop?, uere)
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_est_accuracy() {
+        let uere = Uere::default();
+        assert_eq!(Fix::GpsFix.est_accuracy_m(95, &uere), Some(4.75));
+        assert_eq!(Fix::DGpsFix.est_accuracy_m(150, &uere), Some(3.0));
+        assert_eq!(Fix::No.est_accuracy_m(95, &uere), None);
+
+        let packet = Packet {
+            fix: Some(Fix::GpsFix),
+            hdop: Some(200),
+            ..Packet::default()
+        };
+        assert_eq!(packet.est_accuracy_m(&uere), Some(10.0));
+        let packet = Packet {
+            hdop: None,
+            ..packet
+        };
+        assert_eq!(packet.est_accuracy_m(&uere), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! How the gps trades power for availability, see
+//! [`crate::Gps::set_power_mode`].
+
+/// Switches the gps's power, such as by its EN pin, for
+/// [`crate::Gps::power_cycle`].
+pub trait PowerSwitch {
+    /// Turn the gps on if `on`, otherwise cut its power entirely.
+    fn set_power(&mut self, on: bool);
+}
+
+/// Shortest run or sleep time PMTK225 accepts
+const MIN_PERIOD_MS: u32 = 1_000;
+/// Longest run or sleep time PMTK225 accepts, six days
+const MAX_PERIOD_MS: u32 = 518_400_000;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PowerMode {
+    /// Tracking all the time
+    Normal,
+    /// Alternate tracking with backup mode, where only the clock and saved
+    /// data are kept. Draws the least, but needs the longest to get a fix
+    /// again.
+    PeriodicBackup(PeriodicTimes),
+    /// Alternate tracking with standby, where the gps keeps its state but
+    /// stops tracking.
+    PeriodicStandby(PeriodicTimes),
+    /// Let the gps decide how long to sleep for based on how well it can
+    /// see the sky, sleeping in standby.
+    AlwaysLocateStandby,
+    /// Like [`Self::AlwaysLocateStandby`], but sleeping in backup mode.
+    AlwaysLocateBackup,
+}
+
+/// Run and sleep times for the periodic modes.
+///
+/// The gps uses the second pair, if given, when it can't get a fix during a
+/// run of `run_ms`, so it can search for longer without staying awake for
+/// long once it has a fix.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeriodicTimes {
+    pub run_ms: u32,
+    pub sleep_ms: u32,
+    pub second: Option<(u32, u32)>,
+    /// Sent before the mode itself if given, otherwise the gps keeps what it
+    /// had.
+    pub extended_fix: Option<ExtendedFix>,
+}
+
+/// How long the gps stays awake past a run to get a fix, from PMTK223.
+///
+/// Without enough time the gps often can't get a fix again within a short
+/// run, and never catches up.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedFix {
+    /// Satellites it must be tracking to count a fix as good, from 1 to 4
+    pub min_satellites: u8,
+    /// Signal strength each must have, from 25 to 30 dB-Hz
+    pub min_snr: u8,
+    /// Longest to stay awake trying, from 40,000 to 180,000 milliseconds
+    pub extension_ms: u32,
+    /// Time between extensions, from 0 to 3,600,000 milliseconds
+    pub extension_gap_ms: u32,
+}
+
+impl Default for ExtendedFix {
+    /// What the gps uses until told otherwise
+    fn default() -> Self {
+        Self {
+            min_satellites: 1,
+            min_snr: 30,
+            extension_ms: 180_000,
+            extension_gap_ms: 60_000,
+        }
+    }
+}
+
+impl PowerMode {
+    /// The type field of PMTK225
+    pub(crate) fn type_field(&self) -> &'static [u8] {
+        match self {
+            Self::Normal => b"0",
+            Self::PeriodicBackup(_) => b"1",
+            Self::PeriodicStandby(_) => b"2",
+            Self::AlwaysLocateStandby => b"8",
+            Self::AlwaysLocateBackup => b"9",
+        }
+    }
+
+    pub(crate) fn times(&self) -> Option<&PeriodicTimes> {
+        match self {
+            Self::PeriodicBackup(times) | Self::PeriodicStandby(times) => Some(times),
+            _ => None,
+        }
+    }
+}
+
+impl PeriodicTimes {
+    pub(crate) fn is_valid(&self) -> bool {
+        let in_range = |ms| (MIN_PERIOD_MS..=MAX_PERIOD_MS).contains(&ms);
+        let second_valid = match self.second {
+            Some((run, sleep)) => in_range(run) && in_range(sleep),
+            None => true,
+        };
+        let extended_fix_valid = self.extended_fix.as_ref().is_none_or(ExtendedFix::is_valid);
+        in_range(self.run_ms) && in_range(self.sleep_ms) && second_valid && extended_fix_valid
+    }
+}
+
+impl ExtendedFix {
+    pub(crate) fn is_valid(&self) -> bool {
+        (1..=4).contains(&self.min_satellites)
+            && (25..=30).contains(&self.min_snr)
+            && (40_000..=180_000).contains(&self.extension_ms)
+            && self.extension_gap_ms <= 3_600_000
+    }
+}
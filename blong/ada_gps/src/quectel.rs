@@ -0,0 +1,26 @@
+//! Quectel's `$PQ` commands.
+//!
+//! Quectel's L80, L86 and L76 modules are the same MTK chipset as the
+//! Adafruit gps, and understand the same PMTK commands. They add a few of
+//! their own on top, which a plain MTK module ignores. See
+//! [`crate::Gps::command_set`] for telling the two apart.
+
+/// Which commands the gps understands.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSet {
+    Pmtk,
+    PmtkAndPq,
+}
+
+impl CommandSet {
+    /// From the model field of `PMTK_DT_RELEASE`, such as `PA6H` or
+    /// `Quectel-L80`.
+    pub(crate) fn from_model(model: &[u8]) -> Self {
+        if model.starts_with(b"Quectel") {
+            Self::PmtkAndPq
+        } else {
+            Self::Pmtk
+        }
+    }
+}
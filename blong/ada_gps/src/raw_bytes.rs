@@ -0,0 +1,93 @@
+//! Keeping a bit of what the gps sent with an error about it.
+
+use core::fmt;
+
+/// The start of whatever the gps sent that we couldn't make sense of, so it
+/// shows up wherever the error is logged. Anything past
+/// [`RawBytes::CAPACITY`] is dropped.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawBytes {
+    len: u8,
+    buf: [u8; RawBytes::CAPACITY],
+}
+
+impl RawBytes {
+    pub const CAPACITY: usize = 32;
+
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut raw = Self::default();
+        raw.push(bytes);
+        raw
+    }
+
+    /// A sentence as it was parsed, joined back up with commas. `name` is
+    /// left off if empty.
+    pub(crate) fn from_parsed(name: &[u8], fields: &[impl AsRef<[u8]>]) -> Self {
+        let mut raw = Self::new(name);
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 || !name.is_empty() {
+                raw.push(b",");
+            }
+            raw.push(field.as_ref());
+        }
+        raw
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len as usize]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let start = self.len as usize;
+        let len = bytes.len().min(Self::CAPACITY - start);
+        self.buf[start..start + len].copy_from_slice(&bytes[..len]);
+        self.len += len as u8;
+    }
+}
+
+impl Default for RawBytes {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            buf: [0; Self::CAPACITY],
+        }
+    }
+}
+
+impl fmt::Debug for RawBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b\"{}\"", self.as_bytes().escape_ascii())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for RawBytes {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=[u8]:a}", self.as_bytes())
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use alloc::{format, vec, vec::Vec};
+
+    #[test]
+    fn test_from_parsed() {
+        let fields: Vec<&[u8]> = vec![b"605", b"3"];
+        let raw = RawBytes::from_parsed(b"PMTK001", &fields);
+        assert_eq!(raw.as_bytes(), b"PMTK001,605,3");
+        assert_eq!(format!("{:?}", raw), r#"b"PMTK001,605,3""#);
+
+        let raw = RawBytes::from_parsed(b"", &fields);
+        assert_eq!(raw.as_bytes(), b"605,3");
+    }
+
+    #[test]
+    fn test_truncates() {
+        let raw = RawBytes::new(&[b'A'; 40]);
+        assert_eq!(raw.as_bytes(), &[b'A'; RawBytes::CAPACITY]);
+        let raw = RawBytes::from_parsed(&[b'A'; 30], &[b"BCDE"]);
+        assert_eq!(raw.as_bytes(), b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAA,B");
+    }
+}
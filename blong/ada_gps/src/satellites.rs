@@ -0,0 +1,287 @@
+//! Which satellites the gps can see, and how well.
+//!
+//! Built up from the GSV sentences the gps sends every second or so, see
+//! [`crate::NmeaListener::satellites`].
+
+use alloc::vec::Vec;
+
+use crate::{nmea::Gsv, trace};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Constellation {
+    Gps,
+    /// Satellites broadcasting corrections (WAAS, EGNOS, MSAS)
+    Sbas,
+    Glonass,
+    Galileo,
+    BeiDou,
+    Unknown,
+}
+
+impl Constellation {
+    const ALL: [Self; 6] = [
+        Self::Gps,
+        Self::Sbas,
+        Self::Glonass,
+        Self::Galileo,
+        Self::BeiDou,
+        Self::Unknown,
+    ];
+
+    /// From the talker of the GSV sentence reporting the satellite. GPS
+    /// talkers report SBAS satellites too, distinguished by PRN.
+    pub(crate) fn from_talker(talker: &[u8], prn: u8) -> Self {
+        match talker {
+            b"GP" if (33..=64).contains(&prn) => Self::Sbas,
+            b"GP" => Self::Gps,
+            b"GL" => Self::Glonass,
+            b"GA" => Self::Galileo,
+            b"GB" | b"BD" => Self::BeiDou,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Satellite {
+    pub constellation: Constellation,
+    pub prn: u8,
+    /// In degrees above the horizon
+    pub elevation: Option<u8>,
+    /// In degrees clockwise from true north
+    pub azimuth: Option<u16>,
+    /// Signal to noise ratio in dB-Hz. `None` if the satellite is in view
+    /// but not being tracked.
+    pub snr: Option<u8>,
+}
+
+/// The most recent complete report of each talker.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SatellitesInView {
+    by_talker: Vec<([u8; 2], Vec<Satellite>)>,
+    /// PRNs, as of the most recent GSA
+    active: Vec<u8>,
+}
+
+impl SatellitesInView {
+    pub fn iter(&self) -> impl Iterator<Item = &Satellite> {
+        self.by_talker.iter().flat_map(|(_, sats)| sats.iter())
+    }
+
+    /// Those the gps is using for its fix.
+    pub fn active(&self) -> impl Iterator<Item = &Satellite> {
+        self.iter().filter(|sat| self.active.contains(&sat.prn))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub(crate) fn set_active(&mut self, prns: impl Iterator<Item = u8>) {
+        self.active.clear();
+        self.active.extend(prns);
+    }
+
+    pub(crate) fn replace(&mut self, talker: [u8; 2], satellites: Vec<Satellite>) {
+        match self.by_talker.iter_mut().find(|(t, _)| *t == talker) {
+            Some((_, sats)) => *sats = satellites,
+            None => self.by_talker.push((talker, satellites)),
+        }
+    }
+
+    pub fn stats(&self) -> SignalStats {
+        let overall = SnrStats::of(self.iter());
+        let mut stats = SignalStats {
+            quality: self.quality(&overall),
+            overall,
+            per_constellation: Vec::new(),
+        };
+        for constellation in Constellation::ALL {
+            let sats = self.iter().filter(|sat| sat.constellation == constellation);
+            let constellation_stats = SnrStats::of(sats);
+            if constellation_stats.in_view > 0 {
+                stats
+                    .per_constellation
+                    .push((constellation, constellation_stats));
+            }
+        }
+        stats
+    }
+
+    /// Satellites at least this strong are as good as it gets.
+    const STRONG_SNR: u8 = 35;
+    /// Enough strong satellites for a solid fix with a good geometry.
+    const ENOUGH_STRONG: usize = 8;
+
+    fn quality(&self, overall: &SnrStats) -> u8 {
+        let strong = self
+            .iter()
+            .filter(|sat| sat.snr.is_some_and(|snr| snr >= Self::STRONG_SNR))
+            .count()
+            .min(Self::ENOUGH_STRONG);
+        let count_score = strong * 50 / Self::ENOUGH_STRONG;
+
+        let snr_score = match overall.snr_avg {
+            Some(avg) => (avg.clamp(20, 45) as usize - 20) * 50 / 25,
+            None => 0,
+        };
+
+        (count_score + snr_score) as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalStats {
+    /// How good the antenna's view of the sky is, from 0 to 100.
+    ///
+    /// Half is from how many satellites are received strongly, and half from
+    /// the average SNR of the tracked satellites between 20 and 45 dB-Hz.
+    /// Only meant for comparing antenna placements with each other.
+    pub quality: u8,
+    pub overall: SnrStats,
+    /// Only constellations with at least one satellite in view
+    pub per_constellation: Vec<(Constellation, SnrStats)>,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnrStats {
+    pub in_view: u8,
+    /// In view and with an SNR
+    pub tracked: u8,
+    pub snr_min: Option<u8>,
+    /// Rounded down
+    pub snr_avg: Option<u8>,
+    pub snr_max: Option<u8>,
+}
+
+impl SnrStats {
+    fn of<'a>(satellites: impl Iterator<Item = &'a Satellite>) -> Self {
+        let mut stats = Self::default();
+        let mut sum: u32 = 0;
+        for sat in satellites {
+            stats.in_view = stats.in_view.saturating_add(1);
+            if let Some(snr) = sat.snr {
+                stats.tracked = stats.tracked.saturating_add(1);
+                sum += snr as u32;
+                stats.snr_min = Some(stats.snr_min.map_or(snr, |min| min.min(snr)));
+                stats.snr_max = Some(stats.snr_max.map_or(snr, |max| max.max(snr)));
+            }
+        }
+        if stats.tracked > 0 {
+            stats.snr_avg = Some((sum / stats.tracked as u32) as u8);
+        }
+        stats
+    }
+}
+
+/// Reassembles the several GSV sentences a talker splits its report over.
+#[derive(Debug, Default)]
+pub(crate) struct GsvAssembler {
+    talker: [u8; 2],
+    total: u8,
+    next: u8,
+    satellites: Vec<Satellite>,
+}
+
+impl GsvAssembler {
+    /// Returns the talker and its satellites once the last sentence of a
+    /// report arrives. A sentence out of sequence drops the report in
+    /// progress.
+    pub(crate) fn push(&mut self, gsv: &Gsv) -> Option<([u8; 2], Vec<Satellite>)> {
+        let Gsv {
+            talker, total, num, ..
+        } = *gsv;
+
+        if num == 0 || num > total {
+            return None;
+        } else if num == 1 {
+            self.talker = talker;
+            self.total = total;
+            self.next = 1;
+            self.satellites.clear();
+        } else if talker != self.talker || total != self.total || num != self.next {
+            trace!("GSV out of sequence, dropping report");
+            self.next = 0;
+            return None;
+        }
+
+        self.satellites.extend(gsv.satellites().cloned());
+
+        if num == total {
+            self.next = 0;
+            Some((talker, core::mem::take(&mut self.satellites)))
+        } else {
+            self.next += 1;
+            None
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    fn sat(constellation: Constellation, prn: u8, snr: Option<u8>) -> Satellite {
+        Satellite {
+            constellation,
+            prn,
+            elevation: None,
+            azimuth: None,
+            snr,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        use Constellation::*;
+
+        let mut view = SatellitesInView::default();
+        view.replace(
+            *b"GP",
+            vec![
+                sat(Gps, 1, Some(40)),
+                sat(Gps, 2, Some(30)),
+                sat(Gps, 3, None),
+                sat(Sbas, 42, Some(35)),
+            ],
+        );
+        view.replace(*b"GL", vec![sat(Glonass, 70, Some(21))]);
+
+        let stats = view.stats();
+        assert_eq!(
+            stats.overall,
+            SnrStats {
+                in_view: 5,
+                tracked: 4,
+                snr_min: Some(21),
+                snr_avg: Some(31),
+                snr_max: Some(40),
+            }
+        );
+        assert_eq!(
+            stats
+                .per_constellation
+                .iter()
+                .map(|(c, s)| (*c, s.in_view))
+                .collect::<Vec<_>>(),
+            vec![(Gps, 3), (Sbas, 1), (Glonass, 1)]
+        );
+        // 2 strong satellites, average 31
+        assert_eq!(stats.quality, 12 + 22);
+
+        // A new report replaces only that talker's satellites
+        view.replace(*b"GP", vec![]);
+        assert_eq!(view.stats().overall.in_view, 1);
+    }
+
+    #[test]
+    fn test_empty_stats() {
+        let view = SatellitesInView::default();
+        assert!(view.is_empty());
+        assert_eq!(view.stats().overall, SnrStats::default());
+        assert_eq!(view.stats().quality, 0);
+    }
+}
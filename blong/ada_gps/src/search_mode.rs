@@ -0,0 +1,25 @@
+//! Which satellite systems the gps tracks, see
+//! [`crate::Gps::set_search_mode`].
+
+/// Tracking more systems sees more satellites, so fixes are quicker and
+/// better in cities and valleys, but draws more power.
+///
+/// Only MT3333 based modules can track anything but GPS.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMode {
+    pub gps: bool,
+    pub glonass: bool,
+    pub galileo: bool,
+}
+
+impl Default for SearchMode {
+    /// GPS alone, what every module can do
+    fn default() -> Self {
+        Self {
+            gps: true,
+            glonass: false,
+            galileo: false,
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Splitting a stream of packets into separate tracks, so a log covering
+//! weeks doesn't come out as one line joining every trip.
+
+use crate::{Fix, Packet};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// Start a new segment if there's more than this between packets
+    pub max_gap_secs: u32,
+    /// Start a new segment if this many packets in a row have no fix
+    pub max_packets_without_fix: usize,
+}
+
+impl Default for SegmentConfig {
+    /// Long enough to ride out losing the sky under a bridge.
+    fn default() -> Self {
+        Self {
+            max_gap_secs: 5 * 60,
+            max_packets_without_fix: 10,
+        }
+    }
+}
+
+/// Decides where one segment ends and the next starts, a packet at a time.
+/// See [`segments`] to do this to an iterator.
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    config: SegmentConfig,
+    last: Option<Packet>,
+    packets_without_fix: usize,
+    /// Whether the current segment has run out, so the next packet with a fix
+    /// starts a new one
+    ended: bool,
+    /// Whether the first segment has any packets with a fix yet
+    started: bool,
+}
+
+impl Segmenter {
+    pub fn new(config: SegmentConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            packets_without_fix: 0,
+            ended: false,
+            started: false,
+        }
+    }
+
+    /// Returns true if `packet` starts a new segment. The first packet
+    /// doesn't, as it starts the first.
+    ///
+    /// Packets without a time are never compared against.
+    pub fn check(&mut self, packet: &Packet) -> bool {
+        if matches!(packet.fix, Some(Fix::No)) {
+            self.packets_without_fix += 1;
+            if self.packets_without_fix >= self.config.max_packets_without_fix {
+                self.ended = true;
+            }
+            return false;
+        }
+        self.packets_without_fix = 0;
+
+        if let (Some(last), Some(time)) = (self.last.as_ref().and_then(|p| p.time), packet.time) {
+            if !time.is_within_seconds_of(last, self.config.max_gap_secs) {
+                self.ended = true;
+            }
+        }
+
+        if packet.time.is_some() {
+            self.last = Some(packet.clone());
+        }
+
+        let starts_new = self.ended && self.started;
+        self.ended = false;
+        self.started = true;
+        starts_new
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentEvent {
+    /// The packets after this are a new segment
+    NewSegment,
+    Point(Packet),
+}
+
+/// Adds a [`SegmentEvent::NewSegment`] between packets wherever a new segment
+/// starts, see [`Segmenter`].
+pub fn segments<I>(packets: I, config: SegmentConfig) -> Segments<I::IntoIter>
+where
+    I: IntoIterator<Item = Packet>,
+{
+    Segments {
+        packets: packets.into_iter(),
+        segmenter: Segmenter::new(config),
+        pending: None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Segments<I> {
+    packets: I,
+    segmenter: Segmenter,
+    /// The packet to hand out after the boundary we just did
+    pending: Option<Packet>,
+}
+
+impl<I> Iterator for Segments<I>
+where
+    I: Iterator<Item = Packet>,
+{
+    type Item = SegmentEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(packet) = self.pending.take() {
+            return Some(SegmentEvent::Point(packet));
+        }
+
+        let packet = self.packets.next()?;
+        if self.segmenter.check(&packet) {
+            self.pending = Some(packet);
+            Some(SegmentEvent::NewSegment)
+        } else {
+            Some(SegmentEvent::Point(packet))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::UtcDateTime;
+    use alloc::vec::Vec;
+
+    fn packet(secs: u32, fix: Fix) -> Packet {
+        Packet {
+            time: UtcDateTime::from_unix(1_643_322_510 + secs as i64),
+            fix: Some(fix),
+            ..Packet::default()
+        }
+    }
+
+    #[test]
+    fn test_splits_on_gaps_and_lost_fix() {
+        let config = SegmentConfig {
+            max_gap_secs: 60,
+            max_packets_without_fix: 2,
+        };
+        let packets = [
+            // Nothing before the first segment to split from
+            packet(0, Fix::No),
+            packet(0, Fix::No),
+            packet(0, Fix::GpsFix),
+            packet(15, Fix::GpsFix),
+            // Long gap
+            packet(600, Fix::GpsFix),
+            // A short dropout doesn't split
+            packet(615, Fix::No),
+            packet(630, Fix::GpsFix),
+            // A long one does
+            packet(645, Fix::No),
+            packet(650, Fix::No),
+            packet(655, Fix::GpsFix),
+        ];
+
+        let events: Vec<_> = segments(packets.clone(), config).collect();
+        let point = |i: usize| SegmentEvent::Point(packets[i].clone());
+        assert_eq!(
+            events,
+            [
+                point(0),
+                point(1),
+                point(2),
+                point(3),
+                SegmentEvent::NewSegment,
+                point(4),
+                point(5),
+                point(6),
+                point(7),
+                point(8),
+                SegmentEvent::NewSegment,
+                point(9),
+            ]
+        );
+    }
+}
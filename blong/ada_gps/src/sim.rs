@@ -0,0 +1,547 @@
+//! A fake gps that answers commands the way an MTK3339 would, for tests
+//! that send more than a couple of commands.
+//!
+//! Unlike a scripted mock it doesn't need to be told every byte of the
+//! conversation up front. It keeps the state commands change, such as
+//! whether NMEA output is on or the logger is running, and builds its
+//! replies from it, including LOCUS dumps of whatever points it's given.
+//!
+//! ```
+//! use ada_gps::{sim::Sim, Gps, RxBuf};
+//!
+//! let rx_buf = RxBuf::new();
+//! let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+//! let sim = Sim::new(rx_producer);
+//! let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), false);
+//! gps.hot_restart().unwrap();
+//! assert!(!sim.nmea_output_enabled());
+//! ```
+
+use alloc::{collections::VecDeque, rc::Rc, vec, vec::Vec};
+use core::{cell::RefCell, convert::Infallible};
+use embedded_hal::{blocking::delay::DelayUs, serial};
+use lexical_core::FormattedSize;
+
+use crate::{
+    cmd,
+    logger::{self, ContentFlags, Fix, Packet, SECTOR_SIZE},
+    RxProducer,
+};
+
+/// Bytes at the start of each LOCUS sector before its points
+const HEADER_SIZE: usize = 64;
+/// Where the bitmap of used points starts in a sector header, and how long
+const BITMAP_START: usize = 16;
+const BITMAP_SIZE: usize = 44;
+const SECTOR_COUNT: usize = logger::FLASH_SIZE as usize / SECTOR_SIZE;
+/// Four byte words in each PMTKLOX data packet, as the MT3339 sends them
+const WORDS_PER_LOX: usize = 24;
+
+/// Acts as both the `Tx` and the `Delay` of a [`Gps`](crate::Gps), and
+/// clones share the one fake gps, so a test can keep one to check on it.
+///
+/// Starts booted, with NMEA output on and the logger stopped and empty,
+/// recording the basic content.
+///
+/// Commands it doesn't otherwise know are acked as valid, unless marked with
+/// [`Self::unsupport`]. Sentences with a bad checksum are ignored, as the
+/// real gps does.
+#[derive(Clone)]
+pub struct Sim<'rx> {
+    state: Rc<RefCell<State<'rx>>>,
+}
+
+struct State<'rx> {
+    rx: RxProducer<'rx>,
+    /// Sent but not yet fitted into the rx queue
+    outgoing: VecDeque<u8>,
+    /// The sentence being written to us
+    line: Vec<u8>,
+    received: Vec<Vec<u8>>,
+    unsupported: Vec<[u8; 3]>,
+    firmware: Vec<Vec<u8>>,
+    nmea_output: bool,
+    standby: bool,
+    logging: bool,
+    log_interval: u32,
+    content: ContentFlags,
+    points: Vec<Packet>,
+}
+
+impl<'rx> Sim<'rx> {
+    pub fn new(rx: RxProducer<'rx>) -> Self {
+        let state = State {
+            rx,
+            outgoing: VecDeque::new(),
+            line: Vec::new(),
+            received: Vec::new(),
+            unsupported: Vec::new(),
+            firmware: vec![
+                b"AXN_2.51_3339_17112000".to_vec(),
+                b"0004".to_vec(),
+                b"1616S".to_vec(),
+                b"1.0".to_vec(),
+            ],
+            nmea_output: true,
+            standby: false,
+            logging: false,
+            log_interval: 15,
+            content: ContentFlags::UTC
+                | ContentFlags::VALID
+                | ContentFlags::LAT
+                | ContentFlags::LON
+                | ContentFlags::HEIGHT,
+            points: Vec::new(),
+        };
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    /// Send the messages the gps does once it's powered on.
+    pub fn boot(&self) {
+        self.state.borrow_mut().boot();
+    }
+
+    /// Send `bytes` as they are, such as an NMEA sentence or line noise.
+    pub fn send(&self, bytes: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.outgoing.extend(bytes);
+        state.pump();
+    }
+
+    /// Answer `PMTK<num>` with an ack saying it's unsupported, like older
+    /// firmware does for newer commands.
+    pub fn unsupport(&self, num: &[u8; 3]) {
+        self.state.borrow_mut().unsupported.push(*num);
+    }
+
+    /// The fields of the PMTK705 the gps answers firmware queries with.
+    pub fn set_firmware(&self, fields: &[&[u8]]) {
+        self.state.borrow_mut().firmware = fields.iter().map(|field| field.to_vec()).collect();
+    }
+
+    /// What LOCUS records for points logged from now on. Any already logged
+    /// are erased, as their sectors would no longer parse.
+    pub fn set_log_content(&self, content: ContentFlags) {
+        let mut state = self.state.borrow_mut();
+        state.content = content;
+        state.points.clear();
+    }
+
+    /// Record points in the LOCUS flash, as if the gps had logged them.
+    /// Fields the content doesn't include are dropped, and missing ones are
+    /// recorded as zero. Points past what fits in the flash are dropped.
+    pub fn log_points(&self, points: &[Packet]) {
+        let mut state = self.state.borrow_mut();
+        let capacity = state.capacity_points();
+        let room = capacity.saturating_sub(state.points.len());
+        state
+            .points
+            .extend_from_slice(&points[..points.len().min(room)]);
+    }
+
+    /// Every sentence written to the gps so far, whole, including the
+    /// checksum and line ending.
+    pub fn received(&self) -> Vec<Vec<u8>> {
+        self.state.borrow().received.clone()
+    }
+
+    pub fn nmea_output_enabled(&self) -> bool {
+        self.state.borrow().nmea_output
+    }
+
+    pub fn in_standby(&self) -> bool {
+        self.state.borrow().standby
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.state.borrow().logging
+    }
+}
+
+impl State<'_> {
+    fn boot(&mut self) {
+        self.nmea_output = true;
+        self.standby = false;
+        self.send(b"PMTK011", &[b"MTKGPS"]);
+        self.send(b"PMTK010", &[b"001"]);
+    }
+
+    fn send(&mut self, name: &[u8], fields: &[&[u8]]) {
+        let mut out = Vec::new();
+        cmd::serialize(name, fields, &mut out);
+        self.outgoing.extend(out);
+    }
+
+    fn ack(&mut self, num: &[u8], flag: &[u8]) {
+        self.send(b"PMTK001", &[num, flag]);
+    }
+
+    /// Push what's waiting to be sent into the rx queue, as far as it'll fit.
+    fn pump(&mut self) {
+        while !self.outgoing.is_empty() {
+            let (waiting, _) = self.outgoing.as_slices();
+            let mut grant = match self.rx.grant_max_remaining(waiting.len()) {
+                Ok(grant) => grant,
+                Err(_) => return,
+            };
+            let count = grant.buf().len();
+            grant.buf().copy_from_slice(&waiting[..count]);
+            grant.commit(count);
+            self.outgoing.drain(..count);
+        }
+    }
+
+    fn on_byte(&mut self, byte: u8) {
+        if self.standby {
+            // Any byte wakes it, and is otherwise ignored
+            self.standby = false;
+            self.send(b"PMTK010", &[b"002"]);
+            return;
+        }
+
+        if byte == b'$' {
+            self.line.clear();
+        }
+        self.line.push(byte);
+        if self.line.ends_with(b"\r\n") {
+            let line = core::mem::take(&mut self.line);
+            if line.starts_with(b"$") {
+                self.received.push(line.clone());
+                if let Ok((name, fields)) = cmd::parse(&line) {
+                    self.on_cmd(&name, &fields);
+                }
+            }
+        }
+    }
+
+    fn on_cmd(&mut self, name: &[u8], fields: &[Vec<u8>]) {
+        let num = match name.strip_prefix(b"PMTK") {
+            Some(num) if num.len() == 3 => [num[0], num[1], num[2]],
+            _ => return,
+        };
+        if self.unsupported.contains(&num) {
+            self.ack(&num, b"1");
+            return;
+        }
+        let field = |i: usize| fields.get(i).map(Vec::as_slice).unwrap_or_default();
+
+        match &num {
+            // PMTK_CMD_HOT_START, WARM_START, COLD_START, FULL_COLD_START
+            b"101" | b"102" | b"103" | b"104" => self.boot(),
+            // PMTK_CMD_STANDBY_MODE
+            b"161" => {
+                self.ack(&num, b"3");
+                self.standby = true;
+            }
+            // PMTK_SET_NMEA_OUTPUT, where -1 restores the default of on
+            b"314" => {
+                self.nmea_output = fields.iter().any(|field| field != b"0");
+                self.ack(&num, b"3");
+            }
+            // PMTK_Q_RELEASE
+            b"605" => {
+                let firmware = self.firmware.clone();
+                let fields: Vec<&[u8]> = firmware.iter().map(Vec::as_slice).collect();
+                self.send(b"PMTK705", &fields);
+            }
+            // PMTK_LOCUS_QUERY_STATUS
+            b"183" => self.send_log_status(),
+            // PMTK_LOCUS_ERASE_FLASH
+            b"184" => {
+                self.points.clear();
+                self.ack(&num, b"3");
+            }
+            // PMTK_LOCUS_STOP_LOGGER, 0 = start
+            b"185" => {
+                self.logging = field(0) == b"0";
+                self.ack(&num, b"3");
+            }
+            // PMTK_LOCUS_CONFIG, 1 = by interval
+            b"187" => {
+                if field(0) == b"1" {
+                    if let Ok(secs) = cmd::parse::integer_field(field(1)) {
+                        self.log_interval = secs;
+                    }
+                }
+                self.ack(&num, b"3");
+            }
+            // PMTK_Q_LOCUS_DATA, 0 = the whole flash, 1 = only sectors in use
+            b"622" => self.send_locus_dump(field(0) == b"0"),
+            _ => self.ack(&num, b"3"),
+        }
+    }
+
+    fn capacity_points(&self) -> usize {
+        points_per_sector(self.content) * SECTOR_COUNT
+    }
+
+    fn send_log_status(&mut self) {
+        let percent = self.points.len() * 100 / self.capacity_points();
+        let numbers = [
+            // Serial, then 0 = overlap when full
+            456,
+            0,
+            logger::LogMode::INTERVAL.bits() as usize,
+            self.content.bits() as usize,
+            self.log_interval as usize,
+            // Distance and speed
+            0,
+            0,
+            // 0 = logging
+            if self.logging { 0 } else { 1 },
+            self.points.len(),
+            percent,
+        ];
+        let mut bufs = [[0_u8; usize::FORMATTED_SIZE_DECIMAL]; 10];
+        let mut fields: Vec<&[u8]> = Vec::new();
+        for (number, buf) in numbers.iter().zip(bufs.iter_mut()) {
+            fields.push(lexical_core::write(*number, buf));
+        }
+        self.send(b"PMTKLOG", &fields);
+    }
+
+    fn send_locus_dump(&mut self, whole_flash: bool) {
+        let mut flash = Vec::new();
+        let per_sector = points_per_sector(self.content);
+        for points in self.points.chunks(per_sector) {
+            write_sector(self.content, points, &mut flash);
+        }
+        if whole_flash {
+            // Unused sectors are left erased
+            flash.resize(SECTOR_COUNT * SECTOR_SIZE, 0xFF);
+        }
+
+        let packets: Vec<&[u8]> = flash.chunks(WORDS_PER_LOX * 4).collect();
+        let mut buf = [0_u8; usize::FORMATTED_SIZE_DECIMAL];
+        let count = lexical_core::write(packets.len(), &mut buf);
+        self.send(b"PMTKLOX", &[b"0", count]);
+
+        for (n, packet) in packets.iter().enumerate() {
+            let mut buf = [0_u8; usize::FORMATTED_SIZE_DECIMAL];
+            let n = lexical_core::write(n, &mut buf);
+            let words: Vec<[u8; 8]> = packet.chunks(4).map(hex_word).collect();
+            let mut fields: Vec<&[u8]> = vec![b"1", n];
+            fields.extend(words.iter().map(|word| &word[..]));
+            self.send(b"PMTKLOX", &fields);
+        }
+
+        self.send(b"PMTKLOX", &[b"2"]);
+    }
+}
+
+fn points_per_sector(content: ContentFlags) -> usize {
+    let fit = (SECTOR_SIZE - HEADER_SIZE) / content.point_size() as usize;
+    fit.min(BITMAP_SIZE * 8)
+}
+
+/// Append a sector holding `points`, which must fit, to `flash`.
+fn write_sector(content: ContentFlags, points: &[Packet], flash: &mut Vec<u8>) {
+    let start = flash.len();
+    flash.resize(start + SECTOR_SIZE, 0xFF);
+    let sector = &mut flash[start..];
+
+    sector[..14].fill(0);
+    sector[4..8].copy_from_slice(&content.bits().to_le_bytes());
+    let checksum = sector[..14].chunks_exact(2).fold(0_u16, |acc, pair| {
+        acc ^ u16::from_le_bytes([pair[0], pair[1]])
+    });
+    sector[14..16].copy_from_slice(&checksum.to_le_bytes());
+
+    // Each point in use clears a bit, from the most significant down
+    let bitmap = &mut sector[BITMAP_START..BITMAP_START + BITMAP_SIZE];
+    bitmap[..points.len() / 8].fill(0);
+    if !points.len().is_multiple_of(8) {
+        bitmap[points.len() / 8] = 0xFF >> (points.len() % 8);
+    }
+
+    let mut addr = HEADER_SIZE;
+    for point in points {
+        let record = point_record(content, point);
+        sector[addr..addr + record.len()].copy_from_slice(&record);
+        addr += record.len();
+    }
+}
+
+fn point_record(content: ContentFlags, point: &Packet) -> Vec<u8> {
+    let mut record = Vec::new();
+    if content.contains(ContentFlags::UTC) {
+        let time = point.time.map_or(0, |time| time.to_unix() as u32);
+        record.extend_from_slice(&time.to_le_bytes());
+    }
+    if content.contains(ContentFlags::VALID) {
+        record.push(match point.fix {
+            Some(Fix::DGpsFix) => 0x04,
+            Some(Fix::GpsFix) => 0x02,
+            Some(Fix::DeadReckoning) => 0x40,
+            Some(Fix::No) | None => 0x00,
+        });
+    }
+    if content.contains(ContentFlags::LAT) {
+        record.extend_from_slice(&point.lat.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::LON) {
+        record.extend_from_slice(&point.lon.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::HEIGHT) {
+        record.extend_from_slice(&point.height_msl.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::SPEED) {
+        record.extend_from_slice(&point.speed.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::TRK) {
+        record.extend_from_slice(&point.heading.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::HDOP) {
+        record.extend_from_slice(&point.hdop.unwrap_or_default().to_le_bytes());
+    }
+    if content.contains(ContentFlags::NUM_SAT) {
+        record.push(point.num_sat.unwrap_or_default());
+    }
+    let checksum = record.iter().fold(0, |acc, byte| acc ^ byte);
+    record.push(checksum);
+    record
+}
+
+/// Upper case, as the gps sends them
+fn hex_word(bytes: &[u8]) -> [u8; 8] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut word = [0_u8; 8];
+    for (i, &byte) in bytes.iter().enumerate() {
+        word[i * 2] = DIGITS[(byte >> 4) as usize];
+        word[i * 2 + 1] = DIGITS[(byte & 0xF) as usize];
+    }
+    word
+}
+
+impl serial::Write<u8> for Sim<'_> {
+    type Error = Infallible;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        state.on_byte(byte);
+        state.pump();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl DelayUs<u32> for Sim<'_> {
+    fn delay_us(&mut self, _us: u32) {
+        self.state.borrow_mut().pump();
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::{Gps, RxBuf};
+    use core::ops::ControlFlow;
+
+    fn sample_points() -> Vec<Packet> {
+        let mut points = Vec::new();
+        logger::parse_dump(
+            include_bytes!("../test_assets/3819_log_records.bin"),
+            |point| points.push(point),
+        );
+        points
+    }
+
+    #[test]
+    fn test_logs_across_commands() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), false);
+
+        gps.hot_restart().unwrap();
+        assert!(!sim.nmea_output_enabled());
+        assert_eq!(
+            gps.firmware_version().unwrap().release,
+            b"AXN_2.51_3339_17112000"
+        );
+
+        gps.configure_logger_interval(5).unwrap();
+        gps.start_logging().unwrap();
+        assert!(sim.is_logging());
+        let points = &sample_points()[..300];
+        sim.log_points(points);
+
+        let status = gps.logger_status().unwrap();
+        assert!(status.is_on);
+        assert_eq!(status.interval, 5);
+        assert_eq!(status.record_count, 300);
+
+        gps.enter_standby().unwrap();
+        assert!(sim.in_standby());
+
+        let mut read = Vec::new();
+        let (cursor, stats) = gps
+            .read_logs(|_, _, point| {
+                read.push(point);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(!sim.in_standby());
+        assert_eq!(read, points);
+        assert_eq!(cursor.points_read, 300);
+        // Two sectors with points in, and the rest erased
+        assert_eq!(stats.sector_count, SECTOR_COUNT);
+        assert_eq!(stats.empty_sectors, SECTOR_COUNT - 2);
+
+        gps.erase_logs().unwrap();
+        assert_eq!(gps.logger_status().unwrap().record_count, 0);
+        assert_eq!(
+            sim.received().last().unwrap().as_slice(),
+            b"$PMTK183*38\r\n"
+        );
+    }
+
+    #[test]
+    fn test_dumps_sample_points() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), false);
+        let points = sample_points();
+        sim.log_points(&points);
+
+        let mut read = Vec::new();
+        let mut cursor = logger::Cursor::default();
+        let stats = gps
+            .read_new_logs(&mut cursor, |_, _, point| {
+                read.push(point);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(read, points);
+        assert_eq!(stats.invalid_sectors, 0);
+        assert_eq!(stats.empty_sectors, 0);
+    }
+
+    #[test]
+    fn test_unsupported_and_bad_checksums() {
+        let rx_buf = RxBuf::new();
+        let (rx_producer, rx_consumer) = rx_buf.try_split().unwrap();
+        let sim = Sim::new(rx_producer);
+        let mut gps = Gps::new(rx_consumer, sim.clone(), sim.clone(), true);
+        sim.unsupport(b"386");
+
+        // Falls back to the older command
+        gps.set_static_nav_threshold(50).unwrap();
+        assert!(sim.received().ends_with(&[
+            b"$PMTK386,0.50*08\r\n".to_vec(),
+            b"$PMTK397,0.50*08\r\n".to_vec(),
+        ]));
+
+        let mut tx = sim.clone();
+        for &byte in b"$PMTK185,0*00\r\n" {
+            serial::Write::write(&mut tx, byte).unwrap();
+        }
+        assert!(!sim.is_logging());
+    }
+}
@@ -0,0 +1,190 @@
+//! Smoothing out jitter in a track.
+//!
+//! At 1 Hz in basic mode the reported position wanders by a few meters even
+//! when standing still, which makes tracks look scribbled and inflates their
+//! length. A centered moving average over position and speed hides most of
+//! that. Run [`crate::PacketFilter`] first, as this will smear a spike over
+//! its neighbours rather than remove it.
+
+use alloc::vec::Vec;
+
+use crate::{geodesy::track::Odometer, Packet};
+
+/// Summary of a track, for comparing before and after smoothing.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackStats {
+    /// Total length of the track in meters
+    pub distance_m: f32,
+    /// Longest distance between consecutive points in meters
+    pub max_step_m: f32,
+}
+
+impl TrackStats {
+    pub fn of(packets: &[Packet]) -> Self {
+        let mut stats = Self::default();
+        let mut odometer = Odometer::new();
+        for packet in packets {
+            if let Some(step) = odometer.push(packet) {
+                stats.max_step_m = stats.max_step_m.max(step);
+            }
+        }
+        stats.distance_m = odometer.total_m();
+        stats
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SmoothStats {
+    pub before: TrackStats,
+    pub after: TrackStats,
+}
+
+/// Replaces the position and speed of each packet with the average over the
+/// `radius` packets either side of it.
+///
+/// Packets without a position are left alone and don't count towards the
+/// window. Averages near the ends of the track are over fewer packets.
+/// Tracks crossing the antimeridian are averaged across it, not the long way
+/// round.
+pub fn smooth(packets: &mut [Packet], radius: usize) -> SmoothStats {
+    let before = TrackStats::of(packets);
+
+    let positioned: Vec<usize> = (0..packets.len())
+        .filter(|&i| packets[i].lat.is_some() && packets[i].lon.is_some())
+        .collect();
+
+    let smoothed: Vec<(f32, f32, Option<i16>)> = (0..positioned.len())
+        .map(|i| {
+            let window =
+                &positioned[i.saturating_sub(radius)..(i + radius + 1).min(positioned.len())];
+
+            let centre_lon = packets[positioned[i]].lon.unwrap();
+            let (mut lat, mut lon) = (0.0, 0.0);
+            let (mut speed, mut speed_count) = (0.0, 0);
+            for &j in window {
+                lat += packets[j].lat.unwrap();
+                lon += unwrap_lon(packets[j].lon.unwrap(), centre_lon);
+                if let Some(s) = packets[j].speed {
+                    speed += s as f32;
+                    speed_count += 1;
+                }
+            }
+
+            let n = window.len() as f32;
+            let speed = if speed_count > 0 && packets[positioned[i]].speed.is_some() {
+                Some(libm::roundf(speed / speed_count as f32) as i16)
+            } else {
+                packets[positioned[i]].speed
+            };
+            (lat / n, normalize_lon(lon / n), speed)
+        })
+        .collect();
+
+    for (&i, (lat, lon, speed)) in positioned.iter().zip(smoothed) {
+        packets[i].lat = Some(lat);
+        packets[i].lon = Some(lon);
+        packets[i].speed = speed;
+    }
+
+    SmoothStats {
+        before,
+        after: TrackStats::of(packets),
+    }
+}
+
+/// `lon`, give or take a turn, so it's within 180 degrees of `centre`.
+fn unwrap_lon(lon: f32, centre: f32) -> f32 {
+    if lon - centre > 180.0 {
+        lon - 360.0
+    } else if lon - centre < -180.0 {
+        lon + 360.0
+    } else {
+        lon
+    }
+}
+
+/// Back into the range -180 to 180, after [`unwrap_lon`].
+fn normalize_lon(lon: f32) -> f32 {
+    if lon > 180.0 {
+        lon - 360.0
+    } else if lon < -180.0 {
+        lon + 360.0
+    } else {
+        lon
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    fn packet(lat: f32, lon: f32, speed: i16) -> Packet {
+        Packet {
+            lat: Some(lat),
+            lon: Some(lon),
+            speed: Some(speed),
+            ..Packet::default()
+        }
+    }
+
+    #[test]
+    fn test_smooth_stationary_jitter() {
+        let mut packets = [
+            packet(56.335, -2.7933, 0),
+            packet(56.33504, -2.79336, 2),
+            Packet::default(),
+            packet(56.33497, -2.79328, 0),
+            packet(56.33503, -2.79335, 1),
+            packet(56.33499, -2.79331, 0),
+        ];
+
+        let stats = smooth(&mut packets, 1);
+
+        assert!(
+            stats.after.distance_m < stats.before.distance_m / 2.0,
+            "{:?}",
+            stats
+        );
+        assert!(
+            stats.after.max_step_m < stats.before.max_step_m,
+            "{:?}",
+            stats
+        );
+        assert_eq!(packets[2], Packet::default());
+        assert_eq!(packets[1].speed, Some(1));
+        // The first point only has a neighbour on one side
+        assert!((packets[0].lat.unwrap() - 56.33502).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_smooth_across_antimeridian() {
+        let mut packets = [
+            packet(-16.5, 179.9997, 1),
+            packet(-16.5, 179.9999, 1),
+            packet(-16.5, -179.9999, 1),
+            packet(-16.5, -179.9997, 1),
+        ];
+
+        let stats = smooth(&mut packets, 1);
+
+        for packet in &packets {
+            assert!(packet.lon.unwrap().abs() > 179.999, "{:?}", packet);
+        }
+        assert!((packets[1].lon.unwrap() - 179.9999).abs() < 5e-5);
+        assert!((packets[2].lon.unwrap() + 179.9999).abs() < 5e-5);
+        assert!(stats.after.distance_m < 100.0, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_smooth_radius_zero_is_noop() {
+        let mut packets = [packet(56.335, -2.7933, 0), packet(56.336, -2.7934, 3)];
+        let original = packets.clone();
+
+        let stats = smooth(&mut packets, 0);
+
+        assert_eq!(packets, original);
+        assert_eq!(stats.before, stats.after);
+    }
+}
@@ -0,0 +1,191 @@
+//! Sending commands while something else reads the NMEA the gps keeps
+//! outputting, see [`Gps::split`].
+//!
+//! The [`Commander`] is the only reader of the rx queue. It passes NMEA on to
+//! the [`NmeaStream`] through a second queue, both while waiting for replies
+//! and whenever it's [`pump`](Commander::pump)ed between commands. Each half
+//! only owns its end of a queue, so they can live in different tasks.
+
+use alloc::vec::Vec;
+use core::{
+    mem,
+    ops::{Deref, DerefMut},
+};
+use embedded_hal::{blocking::delay::DelayUs, serial};
+
+use crate::{cmd, trace, Clock, CountedDelays, Gps, RxConsumer};
+
+/// The half of a [`Gps`] that sends commands, which are the [`Gps`]'s own
+/// through `Deref`.
+///
+/// Commands leave NMEA output on. Turning it off with
+/// [`Gps::ensure_nmea_output_disabled`] leaves the stream with nothing.
+pub struct Commander<'rx, Tx, Delay, C = CountedDelays> {
+    gps: Gps<'rx, Tx, Delay, C>,
+}
+
+impl<'rx, Tx, Delay, C> Commander<'rx, Tx, Delay, C>
+where
+    Tx: serial::Write<u8>,
+    Delay: DelayUs<u32>,
+    C: Clock,
+{
+    pub(crate) fn new(gps: Gps<'rx, Tx, Delay, C>) -> Self {
+        Self { gps }
+    }
+
+    /// Pass the whole sentences waiting in the rx queue on to the stream,
+    /// leaving any partial one for the next read.
+    ///
+    /// Call this regularly when not sending commands, as nothing else reads
+    /// the rx queue.
+    pub fn pump(&mut self) {
+        self.gps.forward_waiting();
+    }
+
+    /// Put the halves back together.
+    pub fn unsplit(self) -> Gps<'rx, Tx, Delay, C> {
+        self.gps
+    }
+}
+
+impl<'rx, Tx, Delay, C> Deref for Commander<'rx, Tx, Delay, C> {
+    type Target = Gps<'rx, Tx, Delay, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.gps
+    }
+}
+
+impl<'rx, Tx, Delay, C> DerefMut for Commander<'rx, Tx, Delay, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.gps
+    }
+}
+
+/// The half of a [`Gps`] that reads NMEA, as an iterator over whole
+/// sentences that ends whenever the queue runs dry.
+pub struct NmeaStream<'rx> {
+    rx: RxConsumer<'rx>,
+    line: Vec<u8>,
+}
+
+impl<'rx> NmeaStream<'rx> {
+    pub(crate) fn new(rx: RxConsumer<'rx>) -> Self {
+        Self {
+            rx,
+            line: Vec::new(),
+        }
+    }
+
+    /// Hand the queue to something else, such as an
+    /// [`NmeaListener`](crate::NmeaListener). Drops any partial sentence
+    /// already taken off it.
+    pub fn into_inner(self) -> RxConsumer<'rx> {
+        self.rx
+    }
+}
+
+impl<'rx> Iterator for NmeaStream<'rx> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        // Getting a grant can fail if the queue is being written to, in which
+        // case we'll pick up the rest next time.
+        while let Ok(grant) = self.rx.read() {
+            let mut used = 0;
+            for &byte in grant.buf() {
+                used += 1;
+                if byte == b'$' && !self.line.is_empty() {
+                    trace!("Resyncing");
+                    self.line.clear();
+                } else if cmd::is_too_long(&self.line) {
+                    trace!("Dropping overlong sentence");
+                    self.line.clear();
+                }
+                self.line.push(byte);
+                if self.line.ends_with(b"\r\n") {
+                    grant.release(used);
+                    return Some(mem::take(&mut self.line));
+                }
+            }
+            grant.release(used);
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use embedded_hal::blocking::delay::DelayUs;
+
+    use crate::{
+        cmd,
+        mock::{self, MockTrans},
+        RxBuf, Stats,
+    };
+
+    const GGA: &[u8] =
+        b"$GPGGA,064951.000,2307.1256,N,12016.4438,E,1,8,0.95,39.9,M,17.8,M,,*63\r\n";
+    const RMC: &[u8] =
+        b"$GPRMC,064951.000,A,2307.1256,N,12016.4438,E,0.03,165.48,260406,3.05,W,A*2C\r\n";
+
+    #[test]
+    fn test_stream_gets_nmea_around_commands() {
+        let expects = [
+            MockTrans::read_many(GGA),
+            MockTrans::read_many(&RMC[..20]),
+            // Leaves NMEA output on, so doesn't turn it off first
+            MockTrans::write_many(b"$PMTK313,1*2E\r\n"),
+            MockTrans::read_many(&RMC[20..]),
+            MockTrans::read_many(b"$PMTK001,313,3*31\r\n"),
+            MockTrans::read_many(GGA),
+        ];
+        let (gps, mut mock) = mock::gps_with_nmea_output(&expects);
+        let nmea_buf = RxBuf::new();
+        let (mut commander, mut stream) = gps.split(nmea_buf.try_split().unwrap());
+
+        // The partial RMC is left for the command to finish
+        commander.pump();
+        assert_eq!(stream.next().as_deref(), Some(GGA));
+        assert_eq!(stream.next(), None);
+
+        commander.set_sbas(true).unwrap();
+        commander.pump();
+
+        mock.done();
+        assert_eq!(stream.next().as_deref(), Some(RMC));
+        assert_eq!(stream.next().as_deref(), Some(GGA));
+        assert_eq!(stream.next(), None);
+    }
+    #[test]
+    fn test_pump_drops_lines_too_long_to_be_sentences() {
+        let expects = [
+            // Fills the rx queue without a line ending
+            MockTrans::read_many(&[b'x'; 1024]),
+            MockTrans::read_many(GGA),
+        ];
+        let (gps, mut mock) = mock::gps_with_nmea_output(&expects);
+        let nmea_buf = RxBuf::new();
+        let (mut commander, mut stream) = gps.split(nmea_buf.try_split().unwrap());
+
+        commander.pump();
+        assert_eq!(stream.next(), None);
+        assert!(commander.stats().too_long > 0);
+
+        // There's room for what comes next
+        DelayUs::delay_us(&mut mock, 0);
+        commander.pump();
+        mock.done();
+        assert_eq!(stream.next().as_deref(), Some(GGA));
+        assert_eq!(
+            *commander.stats(),
+            Stats {
+                too_long: (1024 / (cmd::MAX_NMEA_LEN + 1)) as u32,
+                // The rest of the noise, cut short by the GGA
+                resyncs: 1,
+                ..Stats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! Counting what goes wrong on the wire, to tell a bad connection from a
+//! quiet one. See [`Gps::stats`](crate::Gps::stats).
+
+use crate::{Error, ParseError};
+
+/// Running totals since the driver was made or [`Stats`] last reset.
+///
+/// A few resyncs and spurious sentences are normal when NMEA output is on,
+/// but many checksum failures or timeouts point at the wiring or baud rate.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Commands sent again after a failed try
+    pub retries: u32,
+    /// Lines cut short by the start of another, such as after dropped bytes
+    pub resyncs: u32,
+    /// Sentences that didn't match their checksum
+    pub checksum_failures: u32,
+    /// Tries that gave up waiting to read or write
+    pub timeouts: u32,
+    /// Whole sentences that arrived while waiting for a reply, weren't it,
+    /// and weren't forwarded
+    pub spurious: u32,
+    /// Lines dropped for running longer than any sentence of their kind
+    /// without ending, such as noise from a floating rx pin
+    pub too_long: u32,
+}
+
+impl Stats {
+    /// Count `err` if it's one we keep track of, as the reason a try failed.
+    pub(crate) fn count_failure<E>(&mut self, err: &Error<E>) {
+        match err {
+            Error::ReadTimeout | Error::WriteTimeout => self.timeouts += 1,
+            Error::Parse(err, _) => self.count_parse_error(*err),
+            Error::SentenceTooLong(_) => self.too_long += 1,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn count_parse_error(&mut self, err: ParseError) {
+        if err == ParseError::WrongChecksum {
+            self.checksum_failures += 1;
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: &Stats) {
+        self.retries += other.retries;
+        self.resyncs += other.resyncs;
+        self.checksum_failures += other.checksum_failures;
+        self.timeouts += other.timeouts;
+        self.spurious += other.spurious;
+        self.too_long += other.too_long;
+    }
+}
@@ -0,0 +1,14 @@
+//! Watching the bytes that go to and from the gps, such as to mirror them to
+//! RTT, USB, or a black-box log in flash.
+
+/// Handed everything [`Gps`](crate::Gps) writes to and reads from the gps,
+/// see [`Gps::observe_traffic`](crate::Gps::observe_traffic).
+///
+/// Bytes come as they're written or taken off the rx queue, so a read can
+/// stop part way through a line and finish in the next call. Binary packets
+/// and anything dropped while resyncing are included.
+pub trait TrafficObserver {
+    fn on_tx(&mut self, bytes: &[u8]);
+
+    fn on_rx(&mut self, bytes: &[u8]);
+}
@@ -0,0 +1,182 @@
+//! Sending to the gps from an interrupt, so writing a command doesn't wait
+//! on the UART a byte at a time.
+//!
+//! [`QueuedTx`] is the `Tx` of a [`Gps`](crate::Gps). It puts what the
+//! driver writes into a [`TxBuf`], and kicks the transmit interrupt, which
+//! passes the queue on to the UART with [`drain_tx_queue`] as it has room.
+
+use core::convert::Infallible;
+use embedded_hal::serial;
+
+use crate::{TxConsumer, TxProducer};
+
+/// How [`QueuedTx`] gets the transmit interrupt to send what it's queued.
+pub trait TxKick {
+    /// Called after queueing bytes. Make sure the interrupt will run, such
+    /// as by pending it if it's not already waiting for room in the UART.
+    fn kick(&mut self);
+
+    /// Whether everything queued has gone out on the wire, for
+    /// [`Gps::set_baud_rate`](crate::Gps::set_baud_rate) to wait on.
+    fn is_idle(&mut self) -> bool;
+}
+
+/// A `Tx` that queues bytes for the transmit interrupt instead of writing
+/// them to the UART itself.
+///
+/// Writes only wait when the queue is full, such as when sending a command
+/// longer than it while the gps is at a slow baud rate.
+pub struct QueuedTx<'tx, K> {
+    queue: TxProducer<'tx>,
+    kick: K,
+}
+
+impl<'tx, K> QueuedTx<'tx, K>
+where
+    K: TxKick,
+{
+    pub fn new(queue: TxProducer<'tx>, kick: K) -> Self {
+        Self { queue, kick }
+    }
+}
+
+impl<K> serial::Write<u8> for QueuedTx<'_, K>
+where
+    K: TxKick,
+{
+    type Error = Infallible;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        match self.queue.grant_exact(1) {
+            Ok(mut grant) => {
+                grant.buf()[0] = byte;
+                grant.commit(1);
+                self.kick.kick();
+                Ok(())
+            }
+            // Full until the interrupt catches up
+            Err(_) => {
+                self.kick.kick();
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        if self.kick.is_idle() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// For the transmit interrupt: hand `write` what's queued, as much at a time
+/// as it'll take. `write` returns how many bytes it took, so fewer than it
+/// was given once the UART is full.
+///
+/// Returns true if the queue is empty, when the interrupt can be turned off
+/// until it's next kicked.
+pub fn drain_tx_queue<F>(queue: &mut TxConsumer<'_>, mut write: F) -> bool
+where
+    F: FnMut(&[u8]) -> usize,
+{
+    loop {
+        let grant = match queue.read() {
+            Ok(grant) => grant,
+            Err(_) => return true,
+        };
+        let len = grant.buf().len();
+        let written = write(grant.buf());
+        grant.release(written);
+        if written < len {
+            return false;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+    use crate::{Gps, TxBuf};
+    use alloc::vec::Vec;
+    use embedded_hal::{blocking::delay::DelayUs, serial::Write as _};
+    use std::{cell::Cell, rc::Rc};
+
+    #[derive(Default, Clone)]
+    struct CountKicks {
+        kicks: Rc<Cell<usize>>,
+        idle: Rc<Cell<bool>>,
+    }
+
+    impl TxKick for CountKicks {
+        fn kick(&mut self) {
+            self.kicks.set(self.kicks.get() + 1);
+        }
+
+        fn is_idle(&mut self) -> bool {
+            self.idle.get()
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayUs<u32> for NoDelay {
+        fn delay_us(&mut self, _us: u32) {}
+    }
+
+    #[test]
+    fn test_queues_for_interrupt() {
+        let tx_buf = TxBuf::new();
+        let (tx_producer, mut tx_consumer) = tx_buf.try_split().unwrap();
+        let kick = CountKicks::default();
+        let mut tx = QueuedTx::new(tx_producer, kick.clone());
+
+        for &byte in b"$PMTK605*31\r\n" {
+            tx.write(byte).unwrap();
+        }
+        assert_eq!(kick.kicks.get(), 13);
+        assert_eq!(tx.flush(), Err(nb::Error::WouldBlock));
+
+        // A UART with room for five bytes at a time
+        let mut sent = Vec::new();
+        let mut uart = |bytes: &[u8]| {
+            let len = bytes.len().min(5);
+            sent.extend_from_slice(&bytes[..len]);
+            len
+        };
+        assert!(!drain_tx_queue(&mut tx_consumer, &mut uart));
+        assert!(!drain_tx_queue(&mut tx_consumer, &mut uart));
+        assert!(drain_tx_queue(&mut tx_consumer, &mut uart));
+        assert_eq!(sent, b"$PMTK605*31\r\n");
+
+        kick.idle.set(true);
+        assert_eq!(tx.flush(), Ok(()));
+    }
+
+    #[test]
+    fn test_times_out_when_never_drained() {
+        let tx_buf = TxBuf::new();
+        let (tx_producer, mut tx_consumer) = tx_buf.try_split().unwrap();
+        let rx_buf = crate::RxBuf::new();
+        let (_, rx_consumer) = rx_buf.try_split().unwrap();
+        let tx = QueuedTx::new(tx_producer, CountKicks::default());
+        let mut gps = Gps::builder(rx_consumer, tx, NoDelay)
+            .already_disabled_nmea_output(true)
+            .max_cmd_retries(1)
+            .build();
+
+        // The first try fills the queue, and the retry can't fit
+        let long = [b'0'; 400];
+        assert_eq!(
+            gps.send_raw(b"000", &[&long]),
+            Err(crate::Error::WriteTimeout)
+        );
+        let mut queued = Vec::new();
+        drain_tx_queue(&mut tx_consumer, |bytes| {
+            queued.extend_from_slice(bytes);
+            bytes.len()
+        });
+        assert!(queued.starts_with(b"$PMTK000,000"));
+    }
+}
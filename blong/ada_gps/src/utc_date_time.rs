@@ -1,14 +1,133 @@
+/// 1980-01-06, the start of GPS week 0
+const GPS_EPOCH_UNIX: i64 = 315_964_800;
+const SECS_PER_WEEK: i64 = 7 * 24 * 60 * 60;
+/// How far GPS time is ahead of UTC, as of the leap second at the end of
+/// 2016. GPS time doesn't have leap seconds.
+const GPS_LEAP_SECS: i64 = 18;
+
 #[derive(Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct UtcDateTime(time::OffsetDateTime);
 
 impl UtcDateTime {
+    /// Returns `None` if any part is out of range.
+    pub fn new(
+        year: i32,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        millisecond: u16,
+    ) -> Option<Self> {
+        let month = time::Month::try_from(month).ok()?;
+        let date = time::Date::from_calendar_date(year, month, day).ok()?;
+        let time = time::Time::from_hms_milli(hour, minute, second, millisecond).ok()?;
+        Some(Self(time::PrimitiveDateTime::new(date, time).assume_utc()))
+    }
+
     pub fn from_unix(timestamp: i64) -> Option<Self> {
         time::OffsetDateTime::from_unix_timestamp(timestamp)
             .map(Self)
             .ok()
     }
+
+    /// From a GPS week number, counted from 1980 without rolling over, and
+    /// seconds into that week. Assumes the current number of leap seconds.
+    pub fn from_gps_time(week: u32, time_of_week_secs: u32) -> Option<Self> {
+        let gps_secs = week as i64 * SECS_PER_WEEK + time_of_week_secs as i64;
+        Self::from_unix(GPS_EPOCH_UNIX + gps_secs - GPS_LEAP_SECS)
+    }
+
+    pub fn year(self) -> i32 {
+        self.0.year()
+    }
+
+    /// From 1 to 12
+    pub fn month(self) -> u8 {
+        self.0.month() as u8
+    }
+
+    pub fn day(self) -> u8 {
+        self.0.day()
+    }
+
+    pub fn hour(self) -> u8 {
+        self.0.hour()
+    }
+
+    pub fn minute(self) -> u8 {
+        self.0.minute()
+    }
+
+    pub fn second(self) -> u8 {
+        self.0.second()
+    }
+
+    pub fn millisecond(self) -> u16 {
+        self.0.millisecond()
+    }
+
+    /// Like `2022-01-27T22:28:30Z`, with milliseconds only if there are any.
+    #[cfg(any(feature = "gpx", feature = "csv"))]
+    pub(crate) fn write_iso8601<W: core::fmt::Write>(self, out: &mut W) -> core::fmt::Result {
+        write!(
+            out,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year(),
+            self.month(),
+            self.day(),
+            self.hour(),
+            self.minute(),
+            self.second()
+        )?;
+        if self.millisecond() != 0 {
+            write!(out, ".{:03}", self.millisecond())?;
+        }
+        out.write_char('Z')
+    }
+
+    /// Whole seconds since the unix epoch, rounded down.
+    pub fn to_unix(self) -> i64 {
+        self.0.unix_timestamp()
+    }
+
+    /// Seconds from `earlier` to `self`, negative if `earlier` is actually
+    /// later. Fractions of a second are truncated towards zero.
+    pub fn seconds_since(self, earlier: Self) -> i64 {
+        (self.0 - earlier.0).whole_seconds()
+    }
+
+    /// Milliseconds from `earlier` to `self`, negative if `earlier` is actually
+    /// later. Fractions of a millisecond are truncated towards zero.
+    pub fn millis_since(self, earlier: Self) -> i64 {
+        (self.0 - earlier.0).whole_milliseconds() as i64
+    }
+
+    /// Returns `None` if the result is out of range.
+    pub fn checked_add_seconds(self, secs: i64) -> Option<Self> {
+        self.0.checked_add(time::Duration::seconds(secs)).map(Self)
+    }
+
+    /// Returns `None` if the result is out of range.
+    pub fn checked_sub_seconds(self, secs: i64) -> Option<Self> {
+        self.0.checked_sub(time::Duration::seconds(secs)).map(Self)
+    }
+
+    pub fn is_before(self, other: Self) -> bool {
+        self < other
+    }
+
+    pub fn is_after(self, other: Self) -> bool {
+        self > other
+    }
+
+    /// True if `self` and `other` are at most `secs` apart, in either order.
+    pub fn is_within_seconds_of(self, other: Self, secs: u32) -> bool {
+        self.millis_since(other).unsigned_abs() <= secs as u64 * 1000
+    }
 }
 
+#[cfg(feature = "defmt")]
 impl defmt::Format for UtcDateTime {
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(
@@ -46,3 +165,57 @@ impl core::fmt::Display for UtcDateTime {
         )
     }
 }
+
+#[cfg(all(test, feature = "host-test"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let start = UtcDateTime::new(2022, 1, 27, 23, 59, 30, 0).unwrap();
+        let end = UtcDateTime::new(2022, 1, 28, 0, 1, 0, 500).unwrap();
+
+        assert_eq!(end.seconds_since(start), 90);
+        assert_eq!(start.seconds_since(end), -90);
+        assert_eq!(end.millis_since(start), 90_500);
+
+        assert_eq!(
+            start.checked_add_seconds(90),
+            UtcDateTime::new(2022, 1, 28, 0, 1, 0, 0)
+        );
+        assert_eq!(
+            start.checked_sub_seconds(-90),
+            start.checked_add_seconds(90)
+        );
+        assert_eq!(start.checked_add_seconds(i64::MAX), None);
+
+        assert_eq!(
+            UtcDateTime::from_unix(start.to_unix()),
+            Some(start),
+            "round trips through unix timestamps"
+        );
+    }
+
+    #[test]
+    fn test_from_gps_time() {
+        // Week 2238 started on 2022-11-27
+        assert_eq!(
+            UtcDateTime::from_gps_time(2238, 18),
+            UtcDateTime::new(2022, 11, 27, 0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_comparison() {
+        let start = UtcDateTime::new(2022, 1, 27, 22, 28, 30, 0).unwrap();
+        let end = UtcDateTime::new(2022, 1, 27, 22, 28, 32, 0).unwrap();
+
+        assert!(start.is_before(end));
+        assert!(end.is_after(start));
+        assert!(!start.is_after(start));
+
+        assert!(start.is_within_seconds_of(end, 2));
+        assert!(end.is_within_seconds_of(start, 2));
+        assert!(!start.is_within_seconds_of(end, 1));
+    }
+}
@@ -0,0 +1,13 @@
+// Generated by `cargo xtask traffic to-mock ada_gps/test_assets/hot_restart_traffic.txt ada_gps/test_assets/hot_restart_mock.rs`
+[
+    MockTrans::write_many(b"$PMTK101*32\r\n"),
+    MockTrans::read_many(b"$CDACK,34,0*79\r\n"),
+    MockTrans::read_many(b"$CDACK,103*50\r\n"),
+    MockTrans::read_many(b"$CDACK,105*56\r\n"),
+    MockTrans::read_many(b"$PMTK011,MTKGPS*08\r\n"),
+    MockTrans::read_many(b"$PMTK010,001*2E\r\n"),
+    MockTrans::write_many(b"$PMTK605*31\r\n"),
+    MockTrans::read_many(b"$PMTK705,AXN_2.51_3339_17112000,0004,1616S,1.0*56\r\n"),
+    MockTrans::write_many(b"$PMTK314,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"),
+    MockTrans::read_many(b"$PMTK001,314,3*36\r\n"),
+]
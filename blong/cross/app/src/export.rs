@@ -0,0 +1,307 @@
+//! What the USB drive holds when the button's held at boot: the gps's log,
+//! filtered and staged in the external flash as it's dumped, then exported as
+//! GPX and CSV as the host reads it.
+//!
+//! Nothing is kept but the staged points, so each read formats them afresh.
+//! Hosts mostly read in order, so each file remembers where the last read
+//! got to and carries on from there.
+
+use ada_gps::{
+    csv::CsvWriter, delta, gpx::GpxWriter, FilterConfig, FilterStats, Packet, PacketFilter,
+};
+use alloc::vec::Vec;
+use board::{
+    embedded_storage::nor_flash::{NorFlash, ReadNorFlash},
+    fat_volume::Files,
+    spi_flash::SECTOR_SIZE,
+    ExternalFlash,
+};
+use core::{cell::Cell, fmt, ops::ControlFlow};
+use defmt::warn;
+
+/// The most a point takes staged, a header and four varints
+const MAX_POINT_LEN: usize = 1 + 4 * 10;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Gpx,
+    Csv,
+}
+
+const FILES: [(Format, [u8; 11]); 2] = [
+    (Format::Gpx, *b"TRACK   GPX"),
+    (Format::Csv, *b"TRACK   CSV"),
+];
+
+/// Writes points to the flash as they come from the gps, see
+/// `ada_gps::delta`, leaving out any `ada_gps::PacketFilter` rejects.
+pub struct Stager {
+    flash: ExternalFlash,
+    filter: PacketFilter,
+    encoder: delta::Encoder,
+    point: Vec<u8>,
+    len: u32,
+    /// Up to where the flash has been erased
+    erased: u32,
+    failed: bool,
+}
+
+impl Stager {
+    pub fn new(flash: ExternalFlash) -> Self {
+        Self {
+            flash,
+            filter: PacketFilter::new(FilterConfig::default()),
+            encoder: delta::Encoder::new(),
+            point: Vec::with_capacity(MAX_POINT_LEN),
+            len: 0,
+            erased: 0,
+            failed: false,
+        }
+    }
+
+    /// For `read_logs`, which it stops once the flash is full.
+    pub fn push(&mut self, packet: &Packet) -> ControlFlow<()> {
+        if !self.filter.check(packet) {
+            return ControlFlow::Continue(());
+        }
+
+        self.point.clear();
+        self.encoder.encode(packet, &mut self.point);
+        let end = self.len + self.point.len() as u32;
+        if end as usize > self.flash.capacity() {
+            warn!("Flash full, leaving out the rest of the log");
+            return ControlFlow::Break(());
+        }
+
+        while self.erased < end {
+            let sector_end = self.erased + SECTOR_SIZE as u32;
+            if self.flash.erase(self.erased, sector_end).is_err() {
+                self.failed = true;
+                return ControlFlow::Break(());
+            }
+            self.erased = sector_end;
+        }
+        if self.flash.write(self.len, &self.point).is_err() {
+            self.failed = true;
+            return ControlFlow::Break(());
+        }
+        self.len = end;
+        ControlFlow::Continue(())
+    }
+
+    /// How many points have been staged, and why the rest were left out.
+    pub fn filter_stats(&self) -> &FilterStats {
+        &self.filter.stats
+    }
+
+    /// `None` if writing to the flash failed.
+    pub fn finish(self) -> Option<Tracks> {
+        if self.failed {
+            return None;
+        }
+        Tracks::new(self.flash, self.len).ok()
+    }
+}
+
+/// Where a file's output had got to, to carry on from
+#[derive(Clone, Default)]
+struct Cursor {
+    /// Where the next point is staged
+    staged: u32,
+    decoder: delta::Decoder,
+    /// Where the next point's output starts in the file
+    pos: u32,
+}
+
+/// Keeps the output that lands in `buf`, which is `start` bytes into the
+/// file, counting how far in the output is. Shared by reference, so where
+/// it's up to can be checked while a writer holds it.
+struct Window<'a> {
+    pos: Cell<u32>,
+    start: u32,
+    buf: &'a [Cell<u8>],
+}
+
+impl<'a> Window<'a> {
+    fn new(pos: u32, start: u32, buf: &'a mut [u8]) -> Self {
+        Self {
+            pos: Cell::new(pos),
+            start,
+            buf: Cell::from_mut(buf).as_slice_of_cells(),
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.start + self.buf.len() as u32
+    }
+}
+
+impl fmt::Write for &Window<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let pos = self.pos.get();
+        let len = s.len() as u32;
+        let from = pos.max(self.start);
+        let to = (pos + len).min(self.end());
+        if from < to {
+            let src = &s.as_bytes()[(from - pos) as usize..(to - pos) as usize];
+            let dst = &self.buf[(from - self.start) as usize..(to - self.start) as usize];
+            for (dst, &src) in dst.iter().zip(src) {
+                dst.set(src);
+            }
+        }
+        self.pos.set(pos + len);
+        Ok(())
+    }
+}
+
+enum Writer<W> {
+    Gpx(GpxWriter<W>),
+    Csv(CsvWriter<W>),
+}
+
+impl<W: fmt::Write> Writer<W> {
+    fn new(format: Format, out: W) -> Result<Self, fmt::Error> {
+        Ok(match format {
+            Format::Gpx => Self::Gpx(GpxWriter::new(out)?),
+            Format::Csv => Self::Csv(CsvWriter::new(out)?),
+        })
+    }
+
+    fn point(&mut self, packet: &Packet) -> fmt::Result {
+        match self {
+            Self::Gpx(gpx) => gpx.point(packet),
+            Self::Csv(csv) => csv.point(packet),
+        }
+    }
+
+    fn finish(self) -> fmt::Result {
+        match self {
+            Self::Gpx(gpx) => gpx.finish().map(drop),
+            Self::Csv(_) => Ok(()),
+        }
+    }
+}
+
+/// The staged log, as files for `board::FatVolume`.
+pub struct Tracks {
+    flash: ExternalFlash,
+    /// How much of the flash holds points
+    len: u32,
+    header_lens: [u32; FILES.len()],
+    sizes: [u32; FILES.len()],
+    cursors: [Cursor; FILES.len()],
+}
+
+impl Tracks {
+    /// Goes through every point to size the files.
+    fn new(flash: ExternalFlash, len: u32) -> Result<Self, fmt::Error> {
+        let mut tracks = Self {
+            flash,
+            len,
+            header_lens: [0; FILES.len()],
+            sizes: [0; FILES.len()],
+            cursors: Default::default(),
+        };
+        for (file, &(format, _)) in FILES.iter().enumerate() {
+            let mut empty = [0; 0];
+            let window = Window::new(0, 0, &mut empty);
+            Writer::new(format, &window)?;
+            tracks.header_lens[file] = window.pos.get();
+            tracks.cursors[file] = tracks.start(file);
+            let (size, _) = tracks.render(file, tracks.start(file), u32::MAX, &mut empty)?;
+            tracks.sizes[file] = size;
+        }
+        Ok(tracks)
+    }
+
+    /// Just after the header
+    fn start(&self, file: usize) -> Cursor {
+        Cursor {
+            pos: self.header_lens[file],
+            ..Cursor::default()
+        }
+    }
+
+    /// The point at `cursor`, moving it on. `None` after the last.
+    fn next_point(&mut self, cursor: &mut Cursor) -> Result<Option<Packet>, fmt::Error> {
+        let len = (self.len - cursor.staged).min(MAX_POINT_LEN as u32) as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        let mut data = [0; MAX_POINT_LEN];
+        self.flash
+            .read(cursor.staged, &mut data[..len])
+            .map_err(|_| fmt::Error)?;
+        match cursor.decoder.decode(&data[..len]) {
+            Ok((packet, used)) => {
+                cursor.staged += used as u32;
+                Ok(Some(packet))
+            }
+            Err(err) => {
+                warn!("Staged points end early: {}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes the file from `offset` into `buf`, carrying on from `cursor`,
+    /// which must be at or before it. Returns how far the output got, and
+    /// the last cursor at or before the end of `buf`.
+    fn render(
+        &mut self,
+        file: usize,
+        mut cursor: Cursor,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(u32, Cursor), fmt::Error> {
+        // The header lands before `offset`, unless carrying on from the start
+        let window = Window::new(cursor.pos - self.header_lens[file], offset, buf);
+        let mut writer = Writer::new(FILES[file].0, &window)?;
+        let mut last = cursor.clone();
+        loop {
+            cursor.pos = window.pos.get();
+            if cursor.pos <= window.end() {
+                last = cursor.clone();
+            }
+            if cursor.pos >= window.end() {
+                break;
+            }
+            match self.next_point(&mut cursor)? {
+                Some(packet) => writer.point(&packet)?,
+                None => {
+                    writer.finish()?;
+                    break;
+                }
+            }
+        }
+        Ok((window.pos.get(), last))
+    }
+}
+
+impl Files for Tracks {
+    fn count(&self) -> usize {
+        FILES.len()
+    }
+
+    fn name(&self, file: usize) -> [u8; 11] {
+        FILES[file].1
+    }
+
+    fn size(&self, file: usize) -> u32 {
+        self.sizes[file]
+    }
+
+    fn read(&mut self, file: usize, offset: u32, buf: &mut [u8]) -> bool {
+        let cursor = match &self.cursors[file] {
+            cursor if cursor.pos <= offset => cursor.clone(),
+            _ => self.start(file),
+        };
+        match self.render(file, cursor, offset, buf) {
+            Ok((_, cursor)) => {
+                self.cursors[file] = cursor;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
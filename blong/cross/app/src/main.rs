@@ -1,81 +1,237 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+mod export;
+
 #[rtic::app(device = rp_pico::hal::pac, peripherals = true, dispatchers = [DMA_IRQ_0])]
 mod app {
     #[allow(unused)]
     pub use defmt::{debug, error, info, trace, warn};
 
-    use ada_gps::Gps;
+    use ada_gps::{
+        nmea::Sentence, Commander, ConfigItem, FixPin, Gps, NmeaStream, PowerMode, QueuedTx,
+        UtcDateTime,
+    };
+    use alloc::{boxed::Box, format, vec::Vec};
     use bbqueue::BBBuffer;
     use board::{
+        core1::Fifo,
         cortex_m,
         cortex_m::prelude::*,
-        embedded_hal::digital::v2::OutputPin,
+        embedded_hal::digital::v2::{InputPin, OutputPin},
         nb, rp2040_monotonic,
-        rp_pico::{self, hal::Watchdog, pac::Interrupt},
-        Board, GpsDelay, GpsUartReader, GpsUartWriter, StatusLed,
+        rp2040_monotonic::fugit::ExtU64,
+        rp_pico::{self, hal::gpio, pac::Interrupt},
+        rtc::{self, DayOfWeek},
+        rtt_transfer, Accelerometer, Battery, Board, Button, ExternalFlash, GpsDelay, GpsFixPin,
+        GpsUartReader, GpsUartWriter, PowerSource, PowerSourceSense, ProfileTrigger, Rtc,
+        RttTransfer, StatusLed, Uart1Writer, Usb, Watchdog, WatchdogConfig,
     };
 
+    /// Commands are sent from UART0's interrupt, so writing them doesn't
+    /// block idle. Split so the NMEA the gps sends until the RTC is set isn't
+    /// thrown away.
+    type AppGps = Commander<'static, QueuedTx<'static, crate::GpsTxKick>, GpsDelay, fn() -> u64>;
+
     #[monotonic(binds = TIMER_IRQ_0)]
     type AppMono = rp2040_monotonic::Rp2040Monotonic;
 
     const STATUS_BLINK_CYCLES: u32 = 5_000_000;
+    /// 4KB, for parsing NMEA
+    const CORE1_STACK_WORDS: usize = 1024;
+    /// How long to hold the button to enter or leave ship mode
+    const SHIP_MODE_HOLD_US: u64 = 3_000_000;
+    /// How long to hold the button to reboot into the USB bootloader, for
+    /// updating firmware without opening the case
+    const BOOTSEL_HOLD_US: u64 = 10_000_000;
+    /// Longer lines typed on the USB console are cut short
+    const CONSOLE_LINE_LEN: usize = 32;
+    const HEALTH_INTERVAL_US: u64 = 60_000_000;
+    const LOG_INTERVAL_SECS: u32 = 15;
+    /// Warn once the logger has less room than this left
+    const LOW_LOG_SPACE_SECS: u32 = 12 * 60 * 60;
+    /// How hard a shove counts as moving, see `Accelerometer`
+    const MOTION_THRESHOLD_MG: u16 = 100;
+    /// Until it hears the time from a satellite the gps counts from 1980, so
+    /// dates before this aren't the real time
+    const MIN_GPS_YEAR: i32 = 2022;
+
+    // Phases marked with the `profile-trigger` feature
+    const PHASE_GPS_CONFIG: u8 = 1;
+    const PHASE_START_LOGGING: u8 = 2;
+    const PHASE_HEALTH: u8 = 3;
+    const PHASE_SHIP_MODE: u8 = 4;
 
     #[shared]
-    struct Shared {}
+    struct Shared {
+        button: ButtonState,
+        fix_pin: FixPin,
+        power_source: PowerSource,
+        usb: Usb,
+    }
 
     #[local]
     struct Local {
-        gps: Gps<'static, GpsUartWriter, GpsDelay>,
+        gps: AppGps,
+        core1_fifo: Fifo,
+        rtc: Rtc,
         watchdog: Watchdog,
         status_led: StatusLed,
+        gps_fix_pin: GpsFixPin,
+        gps_power: crate::GpsPower,
+        debug_uart_writer: Uart1Writer,
+        battery: Battery,
+        power_source_sense: PowerSourceSense,
+        accelerometer: Accelerometer,
+        profile_trigger: ProfileTrigger,
+        rtt_transfer: RttTransfer,
+        /// Until the gps's log is staged in it, if the USB port is a drive
+        flash: Option<ExternalFlash>,
         gps_uart_reader: GpsUartReader,
         gps_rx_producer: ada_gps::RxProducer<'static>,
+        gps_uart_writer: GpsUartWriter,
+        gps_tx_consumer: ada_gps::TxConsumer<'static>,
     }
 
     #[init(
         local = [
             gps_rx_queue: ada_gps::RxBuf = BBBuffer::new(),
+            gps_tx_queue: ada_gps::TxBuf = BBBuffer::new(),
+            gps_nmea_queue: ada_gps::RxBuf = BBBuffer::new(),
+            core1_stack: [usize; CORE1_STACK_WORDS] = [0; CORE1_STACK_WORDS],
+            core1_nmea: Option<NmeaStream<'static>> = None,
         ]
     )]
     fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
-        info!("Initializing");
+        info!(
+            "Initializing app {=str} ({=str})",
+            env!("CARGO_PKG_VERSION"),
+            option_env!("BLONG_GIT_HASH").unwrap_or("unknown build"),
+        );
 
         defmt::timestamp!("{=u64:us}", monotonics::AppMono::now().ticks());
 
         let Board {
             delay: _delay,
             watchdog,
+            reboot_cause: _reboot_cause,
             status_led,
+            button,
+            gps_fix_pin,
             gps_uart_reader,
             gps_uart_writer,
+            gps_uart_baud: _gps_uart_baud,
+            gps_tx_irq,
             gps_delay,
+            gps_enable,
+            uart1_reader: _uart1_reader,
+            uart1_writer: debug_uart_writer,
+            battery,
+            flash,
+            usb,
+            #[cfg(feature = "sd-card")]
+                sd_card: _sd_card,
+            power_source: power_source_sense,
+            #[cfg(not(feature = "accelerometer"))]
+                i2c: _i2c,
+            mut accelerometer,
+            rtc,
+            core1,
+            profile_trigger,
+            rtt_transfer,
             mono,
-        } = Board::init(c.core, c.device);
+        } = Board::init(c.core, c.device, WatchdogConfig::default());
+        let power_source = power_source_sense.read();
+        info!("Running off {}", power_source);
+        if accelerometer.is_present() && !accelerometer.enable_motion_interrupt(MOTION_THRESHOLD_MG)
+        {
+            error!("Failed to set up the accelerometer's motion interrupt");
+        }
 
         let (gps_rx_producer, gps_rx_consumer) = c.local.gps_rx_queue.try_split().unwrap();
-        let gps = Gps::new(gps_rx_consumer, gps_uart_writer, gps_delay, false);
+        let (gps_tx_producer, gps_tx_consumer) = c.local.gps_tx_queue.try_split().unwrap();
+        let gps_tx = QueuedTx::new(gps_tx_producer, crate::GpsTxKick(gps_tx_irq));
+        // Only read once init returns the monotonic, as the gps is first used in idle
+        let gps = Gps::builder(gps_rx_consumer, gps_tx, gps_delay).clock(now_us as fn() -> u64);
+        #[cfg(feature = "rtt-print")]
+        let gps = gps.observe_traffic(crate::RttTraffic::default());
+        let (gps, nmea) = gps
+            .build()
+            .split(c.local.gps_nmea_queue.try_split().unwrap());
+        let nmea = c.local.core1_nmea.insert(nmea);
+        let core1_fifo = core1.launch(c.local.core1_stack, nmea, core1_main);
 
         (
-            Shared {},
+            Shared {
+                button: ButtonState {
+                    pin: button,
+                    pressed: false,
+                },
+                fix_pin: FixPin::new(),
+                power_source,
+                usb,
+            },
             Local {
                 gps,
+                core1_fifo,
+                rtc,
                 watchdog,
                 status_led,
+                gps_fix_pin,
+                gps_power: crate::GpsPower(gps_enable),
+                debug_uart_writer,
+                battery,
+                power_source_sense,
+                accelerometer,
+                profile_trigger,
+                rtt_transfer,
+                flash,
                 gps_uart_reader,
                 gps_rx_producer,
+                gps_uart_writer,
+                gps_tx_consumer,
             },
             init::Monotonics(mono),
         )
     }
 
-    #[idle(local = [watchdog, status_led, gps])]
+    #[idle(
+        local = [
+            watchdog,
+            status_led,
+            gps_power,
+            debug_uart_writer,
+            battery,
+            profile_trigger,
+            rtt_transfer,
+            flash,
+            gps,
+            core1_fifo,
+            rtc,
+        ],
+        shared = [button, fix_pin, power_source, usb]
+    )]
     fn idle(c: idle::Context) -> ! {
+        let idle::SharedResources {
+            mut button,
+            mut fix_pin,
+            mut power_source,
+            mut usb,
+        } = c.shared;
         let idle::LocalResources {
             gps,
             watchdog,
             status_led,
+            gps_power,
+            debug_uart_writer,
+            battery,
+            profile_trigger,
+            rtt_transfer,
+            flash,
+            core1_fifo,
+            rtc,
         } = c.local;
 
         // gps.hot_restart().unwrap();
@@ -84,22 +240,181 @@ mod app {
         blink_status_led_for(status_led, 100_000_000);
         cortex_m::asm::delay(50_000_000);
 
+        let config = [
+            ConfigItem::Sbas(true),
+            ConfigItem::LoggerInterval(LOG_INTERVAL_SECS),
+        ];
+        let mut gps_errors = profile_trigger
+            .phase(PHASE_GPS_CONFIG, || gps.apply(&config))
+            .iter()
+            .filter(|result| result.is_err())
+            .count() as u32;
+
+        match gps.firmware_version() {
+            Ok(firmware) => info!("Gps firmware: {}", firmware),
+            Err(_) => gps_errors += 1,
+        }
+
         gps.logger_status().unwrap();
+        if let Some(flash) = flash.take() {
+            if usb.lock(|usb| matches!(usb, Usb::Storage(_))) {
+                stage_log_for_usb(gps, flash, &mut usb);
+            }
+        }
         // gps.read_logs(|count_estimate, i, point| {
         //     // info!("Got point {}, expecting {}", point, count_estimate)
         //     let percent = i as f32 / count_estimate as f32 * 100_f32;
         //     info!("{}% ({}/{})", percent, i, count_estimate);
+        //     ControlFlow::Continue(())
         // })
         // .unwrap();
 
+        let mut logging = false;
+        // What the gps's power mode was last set for
+        let mut power_mode_for = None;
+        let mut next_health_at = monotonics::AppMono::now().ticks() + HEALTH_INTERVAL_US;
+        let mut last_health = Vec::new();
+        let mut console_line = Vec::new();
+        let _ = wake::spawn_after(HEALTH_INTERVAL_US.micros());
+
         loop {
-            cortex_m::asm::wfe();
-            watchdog.feed();
+            watchdog.sleep();
             info!("Woke up");
 
+            let now = monotonics::AppMono::now().ticks();
+            let has_fix = fix_pin.lock(|fix_pin| fix_pin.has_fix(now));
+
+            // Let the gps sleep between fixes on battery, but track
+            // continuously while USB is paying for it
+            let source = power_source.lock(|source| *source);
+            if power_mode_for != Some(source) {
+                let mode = match source {
+                    PowerSource::Usb => PowerMode::Normal,
+                    PowerSource::Battery => PowerMode::AlwaysLocateStandby,
+                };
+                match gps.set_power_mode(&mode) {
+                    Ok(()) => power_mode_for = Some(source),
+                    Err(_) => {
+                        error!("Failed to set power mode for {}, will retry", source);
+                        gps_errors += 1;
+                    }
+                }
+            }
+
+            // Points logged without a fix are useless
+            if has_fix && !logging {
+                match profile_trigger.phase(PHASE_START_LOGGING, || gps.start_logging()) {
+                    Ok(()) => logging = true,
+                    Err(_) => {
+                        error!("Failed to start logging, will retry");
+                        gps_errors += 1;
+                    }
+                }
+            }
+
+            if now >= next_health_at {
+                next_health_at = now + HEALTH_INTERVAL_US;
+                profile_trigger.begin(PHASE_HEALTH);
+                let _ = wake::spawn_after(HEALTH_INTERVAL_US.micros());
+
+                let record_count = match gps.logger_status() {
+                    Ok(status) => {
+                        match status.estimated_time_remaining(LOG_INTERVAL_SECS) {
+                            Some(secs) if secs < LOW_LOG_SPACE_SECS => {
+                                warn!("Logger fills up in about {} minutes", secs / 60);
+                            }
+                            _ => {}
+                        }
+                        Some(status.record_count)
+                    }
+                    Err(_) => {
+                        gps_errors += 1;
+                        // Only cutting its power revives it once it stops
+                        // answering entirely
+                        if gps_power.0.is_present() && !gps.is_reachable() {
+                            warn!("Gps wedged, power cycling it");
+                            match gps.power_cycle(gps_power) {
+                                // It may have forgotten it was logging,
+                                // and comes back sending NMEA
+                                Ok(()) => {
+                                    logging = false;
+                                    if rtc.is_set() && gps.ensure_nmea_output_disabled().is_err() {
+                                        gps_errors += 1;
+                                    }
+                                }
+                                Err(_) => {
+                                    error!("Gps didn't come back after power cycling");
+                                    gps_errors += 1;
+                                }
+                            }
+                        }
+                        None
+                    }
+                };
+                last_health = health_sentence(&Health {
+                    battery_mv: battery.read_mv(),
+                    has_fix,
+                    logging,
+                    record_count,
+                    gps_errors,
+                });
+                debug_uart_writer.write_full_blocking(&last_health);
+                usb.lock(|usb| usb.write(&last_health));
+                profile_trigger.end();
+            }
+
+            // RTT doesn't interrupt us, so requests wait until we're next
+            // woken, at least by the gps's FIX pin pulse every 15 seconds
+            while let Some(event) = rtt_transfer.poll(now) {
+                match event {
+                    rtt_transfer::Event::Pull(name) if name == b"health" => {
+                        rtt_transfer.send(last_health.clone());
+                    }
+                    rtt_transfer::Event::Pull(name) => {
+                        warn!("Host pulled unknown file {=[u8]:a}", name);
+                        rtt_transfer.send_error(b"No such file");
+                    }
+                    rtt_transfer::Event::Push(data) => {
+                        warn!("Ignoring {} bytes pushed by host", data.len());
+                    }
+                    rtt_transfer::Event::SendFailed => {
+                        warn!("Host stopped acking transfer");
+                    }
+                }
+            }
+            if let Some(at) = rtt_transfer.check_again_at_us() {
+                let _ = wake::spawn_after(at.saturating_sub(now).micros());
+            }
+
+            // Drive writes wait on this, being too slow for USBCTRL_IRQ
+            usb.lock(|usb| usb.flush());
+            read_console(&mut usb, &mut console_line);
+
+            if take_button_press(&mut button) {
+                let held = button_held_us(&mut button, watchdog, BOOTSEL_HOLD_US);
+                if held >= BOOTSEL_HOLD_US {
+                    info!("Rebooting to BOOTSEL");
+                    Board::reboot_to_bootsel();
+                } else if held >= SHIP_MODE_HOLD_US {
+                    // Left high for the whole of ship mode
+                    profile_trigger.begin(PHASE_SHIP_MODE);
+                    enter_ship_mode(gps, gps_power, watchdog, status_led, &mut button);
+                }
+            }
+
             // TODO: This is where we actually do things
 
-            gps.flush_rx_queue();
+            gps.pump();
+            // Get core1 parsing what was just passed on
+            cortex_m::asm::sev();
+            while let Some(unix) = core1_fifo.read() {
+                if !rtc.is_set() {
+                    sync_rtc(rtc, unix);
+                    if rtc.is_set() && gps.ensure_nmea_output_disabled().is_err() {
+                        gps_errors += 1;
+                    }
+                }
+            }
             // NOTE: watchdog hasn't actually been tested, because of a cargo-flash
             // bug. As such, I'm unsure if the watchdog ticks while we're asleep
             watchdog.feed();
@@ -108,21 +423,121 @@ mod app {
         }
     }
 
-    #[task(binds = UART0_IRQ, local=[gps_uart_reader, gps_rx_producer])]
-    fn uart0(c: uart0::Context) {
-        const MAX_BYTES_PER_INTERRUPT: usize = 1024;
+    /// Only here to wake idle
+    #[task(capacity = 4)]
+    fn wake(_: wake::Context) {}
 
+    #[task(binds = USBCTRL_IRQ, shared = [usb])]
+    fn usbctrl(mut c: usbctrl::Context) {
+        c.shared.usb.lock(|usb| usb.poll());
+    }
+
+    /// The gps's FIX pin, the button, VBUS sense and the accelerometer. The
+    /// fix is tracked here rather than in idle, so it's known even while idle
+    /// is blocked on a gps command.
+    #[task(
+        binds = IO_IRQ_BANK0,
+        local = [gps_fix_pin, power_source_sense, accelerometer],
+        shared = [button, fix_pin, power_source]
+    )]
+    fn io_bank0(c: io_bank0::Context) {
+        let io_bank0::SharedResources {
+            mut button,
+            mut fix_pin,
+            mut power_source,
+        } = c.shared;
+
+        if let Some(source) = c.local.power_source_sense.take_change() {
+            if power_source.lock(|current| core::mem::replace(current, source)) != source {
+                info!("Now running off {}", source);
+                // So idle changes the gps's power mode
+                let _ = wake::spawn();
+            }
+        }
+
+        // Idle takes the press, we only have to clear it to return
+        button.lock(|button| {
+            if button.pin.interrupt_status(gpio::Interrupt::EdgeLow) {
+                button.pin.clear_interrupt(gpio::Interrupt::EdgeLow);
+                button.pressed = true;
+            }
+        });
+
+        if c.local.accelerometer.take_motion() {
+            debug!("Moved");
+        }
+
+        if take_fix_pin_rise(c.local.gps_fix_pin) {
+            let now = now_us();
+            let change = fix_pin.lock(|fix_pin| fix_pin.on_rising_edge(now));
+            on_fix_change(change);
+            // Gaining a fix is the pin going quiet, so there's no edge to
+            // tell us
+            if let Some(at) = fix_pin.lock(|fix_pin| fix_pin.check_again_at_us(now)) {
+                let _ = check_fix::spawn_after(at.saturating_sub(now).micros());
+            }
+        }
+    }
+
+    #[task(capacity = 2, shared = [fix_pin])]
+    fn check_fix(mut c: check_fix::Context) {
+        let now = now_us();
+        let (change, again_at) = c
+            .shared
+            .fix_pin
+            .lock(|fix_pin| (fix_pin.take_change(now), fix_pin.check_again_at_us(now)));
+        on_fix_change(change);
+        // Then whether the pin's gone quiet for good
+        if let Some(at) = again_at {
+            let _ = check_fix::spawn_after(at.saturating_sub(now).micros());
+        }
+    }
+
+    fn on_fix_change(change: Option<bool>) {
+        if let Some(has_fix) = change {
+            info!("Fix {=str}", if has_fix { "acquired" } else { "lost" });
+            // So idle starts logging
+            let _ = wake::spawn();
+        }
+    }
+
+    #[task(
+        binds = UART0_IRQ,
+        local = [gps_uart_reader, gps_rx_producer, gps_uart_writer, gps_tx_consumer]
+    )]
+    fn uart0(c: uart0::Context) {
         let uart0::LocalResources {
             gps_uart_reader: reader,
             gps_rx_producer: producer,
+            gps_uart_writer: writer,
+            gps_tx_consumer: tx_queue,
         } = c.local;
 
+        read_gps_uart(reader, producer);
+
+        // Either kicked by `GpsTxKick`, or the UART has room again
+        let sent_all = ada_gps::drain_tx_queue(tx_queue, |bytes| match writer.write_raw(bytes) {
+            Ok(rest) => bytes.len() - rest.len(),
+            Err(nb::Error::WouldBlock) => 0,
+            Err(nb::Error::Other(never)) => match never {},
+        });
+        if sent_all {
+            writer.disable_tx_interrupt();
+        } else {
+            writer.enable_tx_interrupt();
+        }
+
+        Board::unpend(Interrupt::UART0_IRQ);
+    }
+
+    fn read_gps_uart(reader: &mut GpsUartReader, producer: &mut ada_gps::RxProducer<'static>) {
+        const MAX_BYTES_PER_INTERRUPT: usize = 1024;
+
         let mut grant = match producer.grant_max_remaining(MAX_BYTES_PER_INTERRUPT) {
             Ok(grant) => grant,
             Err(_) => {
                 // This means the queue is totally full. Nothing we can do here.
                 // When we catch up later we'll just need to retry.
-                Board::unpend(Interrupt::UART0_IRQ);
                 return;
             }
         };
@@ -145,8 +560,245 @@ mod app {
                 grant.commit(0)
             }
         }
+    }
 
-        Board::unpend(Interrupt::UART0_IRQ);
+    struct Health {
+        battery_mv: u32,
+        has_fix: bool,
+        logging: bool,
+        /// `None` if we couldn't ask the gps
+        record_count: Option<u32>,
+        /// Failed gps commands since boot
+        gps_errors: u32,
+    }
+
+    /// `$PBLNG,<battery mV>,<fix 0/1>,<logging 0/1>,<records>,<gps
+    /// errors>*<checksum>`, for anything listening on the debug UART or USB
+    /// serial, or pulling `health` over RTT.
+    fn health_sentence(health: &Health) -> Vec<u8> {
+        let fields = [
+            format!("{}", health.battery_mv),
+            format!("{}", health.has_fix as u8),
+            format!("{}", health.logging as u8),
+            health
+                .record_count
+                .map(|count| format!("{}", count))
+                .unwrap_or_default(),
+            format!("{}", health.gps_errors),
+        ];
+        let fields: Vec<&[u8]> = fields.iter().map(|field| field.as_bytes()).collect();
+
+        let mut sentence = Vec::new();
+        ada_gps::serialize_sentence(b"PBLNG", &fields, &mut sentence);
+        sentence
+    }
+
+    /// For transporting or shelving the device for months.
+    ///
+    /// Turns the gps off, or puts it into backup mode if we can't switch its
+    /// power, and stops the watchdog, then sleeps until the button is held
+    /// again, when we reset.
+    ///
+    /// Without the `gps-enable` feature the gps stays in backup mode until its
+    /// power is cycled, so after leaving ship mode it won't respond until it's
+    /// unplugged. With it `Board::init` turns the gps back on.
+    fn enter_ship_mode(
+        gps: &mut AppGps,
+        gps_power: &mut crate::GpsPower,
+        watchdog: &mut Watchdog,
+        status_led: &mut StatusLed,
+        button: &mut impl rtic::Mutex<T = ButtonState>,
+    ) -> ! {
+        info!("Entering ship mode");
+
+        if gps_power.0.is_present() {
+            gps_power.0.set(false);
+        } else if gps.enter_backup_mode().is_err() {
+            error!("Failed to put gps into backup mode");
+        }
+
+        for _ in 0..3 {
+            blink_status_led(status_led);
+            cortex_m::asm::delay(STATUS_BLINK_CYCLES);
+        }
+
+        // Feeding it would mean waking every second
+        watchdog.disable();
+
+        // Don't count the hold that got us here
+        while button.lock(|button| button.pin.is_low().unwrap()) {}
+        take_button_press(button);
+
+        loop {
+            // Nothing but the button matters now, so stop every clock until
+            // it's pressed
+            button.lock(|button| Board::dormant_until(&button.pin, gpio::Interrupt::EdgeLow));
+            if take_button_press(button)
+                && button_held_us(button, watchdog, SHIP_MODE_HOLD_US) >= SHIP_MODE_HOLD_US
+            {
+                break;
+            }
+        }
+
+        info!("Leaving ship mode");
+        blink_status_led_for(status_led, 100_000_000);
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    /// Parses the NMEA core0 passes on, and sends back the time from each RMC
+    /// with the real date, as seconds since the unix epoch. Parsing takes
+    /// long enough that it would hold up core0's interrupts.
+    fn core1_main(nmea: &'static mut NmeaStream<'static>, mut fifo: Fifo) -> ! {
+        loop {
+            for line in &mut *nmea {
+                if let Some(time) = rmc_time(&line) {
+                    // Dropped if core0 is behind, it only needs one
+                    fifo.write(time.to_unix() as u32);
+                }
+            }
+            cortex_m::asm::wfe();
+        }
+    }
+
+    fn rmc_time(line: &[u8]) -> Option<UtcDateTime> {
+        match Sentence::parse(line) {
+            Ok(Some(Sentence::Rmc(rmc))) => rmc.date_time,
+            _ => None,
+        }
+        .filter(|time| time.year() >= MIN_GPS_YEAR)
+    }
+
+    /// Set the RTC from a time core1 found.
+    fn sync_rtc(rtc: &mut Rtc, unix: u32) {
+        let time = match UtcDateTime::from_unix(unix as i64) {
+            Some(time) => time,
+            None => return,
+        };
+        match rtc.set(rtc_date_time(time)) {
+            Ok(()) => info!("Set the RTC from the gps: {}", time),
+            Err(_) => error!("RTC rejected gps time {}", time),
+        }
+    }
+
+    fn rtc_date_time(time: UtcDateTime) -> rtc::DateTime {
+        // 1970-01-01 was a Thursday
+        let days = time.to_unix().div_euclid(24 * 60 * 60);
+        let day_of_week = match (days + 4).rem_euclid(7) {
+            0 => DayOfWeek::Sunday,
+            1 => DayOfWeek::Monday,
+            2 => DayOfWeek::Tuesday,
+            3 => DayOfWeek::Wednesday,
+            4 => DayOfWeek::Thursday,
+            5 => DayOfWeek::Friday,
+            _ => DayOfWeek::Saturday,
+        };
+        rtc::DateTime {
+            year: time.year() as u16,
+            month: time.month(),
+            day: time.day(),
+            day_of_week,
+            hour: time.hour(),
+            minute: time.minute(),
+            second: time.second(),
+        }
+    }
+
+    /// For the gps's timeouts.
+    fn now_us() -> u64 {
+        monotonics::AppMono::now().ticks()
+    }
+
+    /// The button, and whether it's been pressed since idle last checked.
+    pub struct ButtonState {
+        pin: Button,
+        pressed: bool,
+    }
+
+    /// Returns true if the button has been pressed since the last call.
+    fn take_button_press(button: &mut impl rtic::Mutex<T = ButtonState>) -> bool {
+        button.lock(|button| core::mem::take(&mut button.pressed))
+    }
+
+    fn take_fix_pin_rise(pin: &mut GpsFixPin) -> bool {
+        let rose = pin.interrupt_status(gpio::Interrupt::EdgeHigh);
+        if rose {
+            pin.clear_interrupt(gpio::Interrupt::EdgeHigh);
+        }
+        rose
+    }
+
+    /// Waits while the button is held, up to `max_us`, returning how long it
+    /// was held.
+    fn button_held_us(
+        button: &mut impl rtic::Mutex<T = ButtonState>,
+        watchdog: &mut Watchdog,
+        max_us: u64,
+    ) -> u64 {
+        let start = monotonics::AppMono::now().ticks();
+        loop {
+            watchdog.feed();
+            let held = monotonics::AppMono::now().ticks() - start;
+            if held >= max_us || button.lock(|button| button.pin.is_high().unwrap()) {
+                return held.min(max_us);
+            }
+        }
+    }
+
+    /// Dumps the gps's log into the flash, for the USB drive to export.
+    fn stage_log_for_usb(
+        gps: &mut AppGps,
+        flash: ExternalFlash,
+        usb: &mut impl rtic::Mutex<T = Usb>,
+    ) {
+        info!("Staging the gps's log for the USB drive");
+        let mut stager = crate::export::Stager::new(flash);
+        if gps.read_logs(|_, _, point| stager.push(&point)).is_err() {
+            error!("Failed to read the gps's log for the USB drive");
+            return;
+        }
+        // The USB port is a drive rather than a console, so this only goes to
+        // the log
+        info!("Filtered the gps's log: {}", stager.filter_stats());
+        match stager.finish() {
+            Some(tracks) => usb.lock(|usb| usb.attach_files(Box::new(tracks))),
+            None => error!("Failed to stage the gps's log in flash"),
+        }
+    }
+
+    /// Runs any commands typed on the USB serial port, a line at a time.
+    fn read_console(usb: &mut impl rtic::Mutex<T = Usb>, line: &mut Vec<u8>) {
+        let mut buf = [0; 64];
+        loop {
+            let count = usb.lock(|usb| usb.read(&mut buf));
+            if count == 0 {
+                return;
+            }
+            for &byte in &buf[..count] {
+                match byte {
+                    b'\r' | b'\n' => {
+                        if !line.is_empty() {
+                            run_console_command(usb, line);
+                        }
+                        line.clear();
+                    }
+                    _ if line.len() < CONSOLE_LINE_LEN => line.push(byte),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn run_console_command(usb: &mut impl rtic::Mutex<T = Usb>, command: &[u8]) {
+        match command {
+            b"bootsel" => {
+                info!("Rebooting to BOOTSEL from the console");
+                Board::reboot_to_bootsel();
+            }
+            _ => {
+                warn!("Unknown console command {=[u8]:a}", command);
+                usb.lock(|usb| usb.write(b"Unknown command\r\n"));
+            }
+        }
     }
 
     fn blink_status_led(led: &mut StatusLed) {
@@ -159,3 +811,67 @@ mod app {
         led.set_low().unwrap();
     }
 }
+
+/// Kicks UART0's interrupt to send what the gps driver queues.
+struct GpsTxKick(board::GpsTxIrq);
+
+impl ada_gps::TxKick for GpsTxKick {
+    fn kick(&mut self) {
+        self.0.kick();
+    }
+
+    fn is_idle(&mut self) -> bool {
+        self.0.is_idle()
+    }
+}
+
+/// Lets the gps driver power cycle the gps when it wedges, see
+/// `board::GpsEnable`.
+struct GpsPower(board::GpsEnable);
+
+impl ada_gps::PowerSwitch for GpsPower {
+    fn set_power(&mut self, on: bool) {
+        self.0.set(on);
+    }
+}
+
+/// Prints gps traffic to the rtt print channel a sentence at a time, marked
+/// `>` if sent and `<` if received. This is what `cargo xtask traffic
+/// to-mock` reads, so anything that isn't a sentence is left out.
+#[cfg(feature = "rtt-print")]
+#[derive(Default)]
+struct RttTraffic {
+    rx_line: alloc::vec::Vec<u8>,
+}
+
+#[cfg(feature = "rtt-print")]
+impl RttTraffic {
+    fn print(prefix: char, line: &[u8]) {
+        if !line.starts_with(b"$") {
+            return;
+        }
+        if let Ok(line) = core::str::from_utf8(line) {
+            board::rtt_target::rprint!("{}{}", prefix, line);
+        }
+    }
+}
+
+#[cfg(feature = "rtt-print")]
+impl ada_gps::TrafficObserver for RttTraffic {
+    fn on_tx(&mut self, bytes: &[u8]) {
+        Self::print('>', bytes);
+    }
+
+    fn on_rx(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'$' {
+                self.rx_line.clear();
+            }
+            self.rx_line.push(byte);
+            if byte == b'\n' {
+                Self::print('<', &self.rx_line);
+                self.rx_line.clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! A LIS3DH accelerometer on I2C0, whose INT1 output tells us when we've
+//! been moved, so a tracker can sleep until it is.
+//!
+//! Enable the `accelerometer` feature, wire the breakout's SDA to GP20, SCL
+//! to GP21 and INT to GP19. Without the feature I2C0 is left for other
+//! devices, and [`Accelerometer`] does nothing.
+
+use core::fmt::Debug;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+#[cfg(feature = "accelerometer")]
+use {
+    crate::I2cBus,
+    rp_pico::hal::gpio::{self, bank0::Gpio19, Pin, PullDownInput},
+};
+
+/// With SDO pulled low, as on Adafruit's breakout, or high
+const ADDRESSES: [u8; 2] = [0x18, 0x19];
+const WHO_AM_I_VALUE: u8 = 0x33;
+/// Set on a register address to read or write several in a row
+const AUTO_INCREMENT: u8 = 0x80;
+
+const REG_WHO_AM_I: u8 = 0x0F;
+const REG_CTRL_REG1: u8 = 0x20;
+const REG_CTRL_REG2: u8 = 0x21;
+const REG_CTRL_REG3: u8 = 0x22;
+const REG_CTRL_REG4: u8 = 0x23;
+const REG_CTRL_REG5: u8 = 0x24;
+const REG_REFERENCE: u8 = 0x26;
+const REG_OUT_X_L: u8 = 0x28;
+const REG_INT1_CFG: u8 = 0x30;
+const REG_INT1_SRC: u8 = 0x31;
+const REG_INT1_THS: u8 = 0x32;
+const REG_INT1_DURATION: u8 = 0x33;
+
+/// 10Hz, low power (8 bit) mode, all axes
+const CTRL_REG1_10HZ_LOW_POWER: u8 = 0x2F;
+/// High pass filter the data INT1 looks at, so it sees changes, not gravity
+const CTRL_REG2_HP_INT1: u8 = 0x01;
+/// Route INT1's events to the INT1 pin
+const CTRL_REG3_I1_IA1: u8 = 0x40;
+/// +/-2g
+const CTRL_REG4_2G: u8 = 0x00;
+/// Hold INT1 high until INT1_SRC is read
+const CTRL_REG5_LIR_INT1: u8 = 0x08;
+/// Any axis going over the threshold
+const INT1_CFG_ANY_HIGH: u8 = 0x2A;
+/// Set in INT1_SRC when an event is latched
+const INT1_SRC_IA: u8 = 0x40;
+/// What each step of INT1_THS is at +/-2g
+const THRESHOLD_STEP_MG: u16 = 16;
+/// In low power mode only the top byte of each reading counts, in steps of
+/// this at +/-2g
+const LOW_POWER_STEP_MG: i16 = 16;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lis3dhError<E> {
+    I2c(E),
+    /// Nothing answered at either address with a LIS3DH's WHO_AM_I
+    NotPresent,
+}
+
+pub struct Lis3dh<I2c> {
+    i2c: I2c,
+    address: u8,
+}
+
+impl<I2c, E> Lis3dh<I2c>
+where
+    I2c: Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    /// Looks for the chip at both addresses, and starts it measuring at
+    /// 10Hz. Fails with [`Lis3dhError::NotPresent`] if it isn't there.
+    pub fn new(mut i2c: I2c) -> Result<Self, Lis3dhError<E>> {
+        let address = ADDRESSES
+            .into_iter()
+            .find(|&address| {
+                let mut id = [0];
+                i2c.write_read(address, &[REG_WHO_AM_I], &mut id).is_ok() && id[0] == WHO_AM_I_VALUE
+            })
+            .ok_or(Lis3dhError::NotPresent)?;
+        let mut accel = Self { i2c, address };
+        accel.write(REG_CTRL_REG1, CTRL_REG1_10HZ_LOW_POWER)?;
+        accel.write(REG_CTRL_REG4, CTRL_REG4_2G)?;
+        defmt::info!("LIS3DH at {=u8:#x}", address);
+        Ok(accel)
+    }
+
+    /// Latch INT1 high when any axis changes by more than `threshold_mg`,
+    /// until [`Self::take_motion`] is called.
+    pub fn enable_motion_interrupt(&mut self, threshold_mg: u16) -> Result<(), Lis3dhError<E>> {
+        let threshold = (threshold_mg / THRESHOLD_STEP_MG).clamp(1, 0x7F) as u8;
+        self.write(REG_CTRL_REG2, CTRL_REG2_HP_INT1)?;
+        self.write(REG_CTRL_REG3, CTRL_REG3_I1_IA1)?;
+        self.write(REG_CTRL_REG5, CTRL_REG5_LIR_INT1)?;
+        self.write(REG_INT1_THS, threshold)?;
+        self.write(REG_INT1_DURATION, 0)?;
+        // Reading it settles the high pass filter on where we are now
+        self.read(REG_REFERENCE, &mut [0])?;
+        self.write(REG_INT1_CFG, INT1_CFG_ANY_HIGH)?;
+        // Clear anything latched while we were setting up
+        self.take_motion()?;
+        Ok(())
+    }
+
+    pub fn disable_motion_interrupt(&mut self) -> Result<(), Lis3dhError<E>> {
+        self.write(REG_INT1_CFG, 0)?;
+        self.write(REG_CTRL_REG3, 0)
+    }
+
+    /// Whether it's moved since the last call, which lets INT1 go low again.
+    pub fn take_motion(&mut self) -> Result<bool, Lis3dhError<E>> {
+        let mut src = [0];
+        self.read(REG_INT1_SRC, &mut src)?;
+        Ok(src[0] & INT1_SRC_IA != 0)
+    }
+
+    /// X, Y and Z in milli-g.
+    pub fn acceleration_mg(&mut self) -> Result<[i16; 3], Lis3dhError<E>> {
+        let mut out = [0; 6];
+        self.read(REG_OUT_X_L | AUTO_INCREMENT, &mut out)?;
+        let axis = |i: usize| (out[i + 1] as i8) as i16 * LOW_POWER_STEP_MG;
+        Ok([axis(0), axis(2), axis(4)])
+    }
+
+    pub fn release(self) -> I2c {
+        self.i2c
+    }
+
+    fn write(&mut self, reg: u8, value: u8) -> Result<(), Lis3dhError<E>> {
+        self.i2c
+            .write(self.address, &[reg, value])
+            .map_err(Lis3dhError::I2c)
+    }
+
+    fn read(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Lis3dhError<E>> {
+        self.i2c
+            .write_read(self.address, &[reg], buf)
+            .map_err(Lis3dhError::I2c)
+    }
+}
+
+/// The accelerometer, if the feature's on and it answered. Its INT1 pin
+/// raises IO_IRQ_BANK0, which should call [`Self::take_motion`].
+pub struct Accelerometer {
+    #[cfg(feature = "accelerometer")]
+    lis3dh: Option<Lis3dh<I2cBus>>,
+    #[cfg(feature = "accelerometer")]
+    int: AccelerometerInt,
+}
+
+/// The accelerometer's INT1 output, see [`crate::Board::dormant_until`]
+#[cfg(feature = "accelerometer")]
+pub type AccelerometerInt = Pin<Gpio19, PullDownInput>;
+
+impl Accelerometer {
+    #[cfg(feature = "accelerometer")]
+    pub(crate) fn new(i2c: I2cBus, int: AccelerometerInt) -> Self {
+        let lis3dh = match Lis3dh::new(i2c) {
+            Ok(lis3dh) => Some(lis3dh),
+            Err(_) => {
+                defmt::info!("No accelerometer");
+                None
+            }
+        };
+        int.set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
+        Self { lis3dh, int }
+    }
+
+    #[cfg(not(feature = "accelerometer"))]
+    pub(crate) fn new() -> Self {
+        Self {}
+    }
+
+    pub fn is_present(&self) -> bool {
+        #[cfg(feature = "accelerometer")]
+        if self.lis3dh.is_some() {
+            return true;
+        }
+        false
+    }
+
+    #[cfg(feature = "accelerometer")]
+    pub fn int_pin(&self) -> &AccelerometerInt {
+        &self.int
+    }
+
+    /// Raise IO_IRQ_BANK0 when any axis changes by more than
+    /// `threshold_mg`. Returns whether that worked.
+    pub fn enable_motion_interrupt(&mut self, threshold_mg: u16) -> bool {
+        #[cfg(feature = "accelerometer")]
+        if let Some(lis3dh) = &mut self.lis3dh {
+            return lis3dh.enable_motion_interrupt(threshold_mg).is_ok();
+        }
+        let _ = threshold_mg;
+        false
+    }
+
+    /// Whether we've moved since the last call. Clears the interrupt.
+    pub fn take_motion(&mut self) -> bool {
+        #[cfg(feature = "accelerometer")]
+        if self.int.interrupt_status(gpio::Interrupt::EdgeHigh) {
+            self.int.clear_interrupt(gpio::Interrupt::EdgeHigh);
+            if let Some(lis3dh) = &mut self.lis3dh {
+                return lis3dh.take_motion().unwrap_or(false);
+            }
+        }
+        false
+    }
+
+    /// X, Y and Z in milli-g, `None` if there's no accelerometer or it
+    /// didn't answer.
+    pub fn acceleration_mg(&mut self) -> Option<[i16; 3]> {
+        #[cfg(feature = "accelerometer")]
+        if let Some(lis3dh) = &mut self.lis3dh {
+            return lis3dh.acceleration_mg().ok();
+        }
+        None
+    }
+}
@@ -0,0 +1,148 @@
+//! Running code on the second core, so long parses don't hold up core0's
+//! interrupts and watchdog feeding.
+//!
+//! Core1 is woken by handing its boot ROM a stack and an entry point over the
+//! inter-core FIFOs, which are then free for the cores to pass words to each
+//! other. It shares everything else with core0, so:
+//! - The heap is fine, it takes a hardware spinlock.
+//! - Logging from core1 isn't, as RTT is only guarded against interrupts on
+//!   the same core. Keep to levels below `DEFMT_LOG`.
+//! - Core1 runs from flash, so [`InternalFlash`] mustn't be erased or
+//!   programmed while it's running. It's only handed out by [`Core1`], until
+//!   it's launched.
+
+use crate::InternalFlash;
+use rp_pico::pac;
+
+/// Either core's end of the pair of FIFOs between them. Words written on one
+/// core are read on the other, and each way holds 8.
+pub struct Fifo {
+    _private: (),
+}
+
+impl Fifo {
+    fn sio() -> &'static pac::sio::RegisterBlock {
+        // SAFETY: Each core only sees its own end of the FIFOs through these
+        // registers, and there's one `Fifo` per core
+        unsafe { &*pac::SIO::ptr() }
+    }
+
+    /// Returns false if the other core hasn't made room.
+    pub fn write(&mut self, word: u32) -> bool {
+        let sio = Self::sio();
+        if sio.fifo_st.read().rdy().bit_is_clear() {
+            return false;
+        }
+        sio.fifo_wr.write(|w| unsafe { w.bits(word) });
+        // Wake the other core if it's waiting in `wfe`
+        cortex_m::asm::sev();
+        true
+    }
+
+    pub fn write_blocking(&mut self, word: u32) {
+        while !self.write(word) {}
+    }
+
+    pub fn read(&mut self) -> Option<u32> {
+        let sio = Self::sio();
+        if sio.fifo_st.read().vld().bit_is_clear() {
+            return None;
+        }
+        Some(sio.fifo_rd.read().bits())
+    }
+
+    pub fn read_blocking(&mut self) -> u32 {
+        loop {
+            if let Some(word) = self.read() {
+                return word;
+            }
+            cortex_m::asm::wfe();
+        }
+    }
+
+    fn drain(&mut self) {
+        while self.read().is_some() {}
+    }
+}
+
+/// Core1, until it's launched. [`Core1::launch`] takes it, so it can only be
+/// launched once.
+pub struct Core1 {
+    fifo: Fifo,
+    internal_flash: InternalFlash,
+}
+
+/// What [`core1_start`] runs, set just before launching
+static mut ENTRY: usize = 0;
+static mut DATA: usize = 0;
+
+impl Core1 {
+    pub(crate) fn new(internal_flash: InternalFlash) -> Self {
+        Self {
+            fifo: Fifo { _private: () },
+            internal_flash,
+        }
+    }
+
+    /// Only until core1 is launched, as it can't run while the flash is
+    /// erased or programmed.
+    pub fn internal_flash(&mut self) -> &mut InternalFlash {
+        &mut self.internal_flash
+    }
+
+    /// Start core1 running `entry` on `stack`, handing it `data`. Returns
+    /// core0's end of the FIFOs, and `entry` gets core1's.
+    pub fn launch<T: Send>(
+        mut self,
+        stack: &'static mut [usize],
+        data: &'static mut T,
+        entry: fn(&'static mut T, Fifo) -> !,
+    ) -> Fifo {
+        // SAFETY: Core1 isn't running yet, and only reads these once it is
+        unsafe {
+            ENTRY = entry as usize;
+            DATA = data as *mut T as usize;
+        }
+
+        // Reset it, in case anything left it running
+        // SAFETY: Only this touches core1's power
+        let psm = unsafe { &*pac::PSM::ptr() };
+        psm.frce_off.modify(|_, w| w.proc1().set_bit());
+        while psm.frce_off.read().proc1().bit_is_clear() {}
+        psm.frce_off.modify(|_, w| w.proc1().clear_bit());
+
+        // SAFETY: Only read
+        let vector_table = unsafe { (*pac::PPB::ptr()).vtor.read().bits() };
+        // The AAPCS needs the stack 8-byte aligned at entry
+        let stack_top = stack.as_mut_ptr_range().end as u32 & !7;
+        let start: extern "C" fn() -> ! = core1_start::<T>;
+
+        // The boot ROM echoes each word back, and we start over if it gets
+        // one wrong. See the datasheet's section on launching core1.
+        let sequence = [0, 0, 1, vector_table, stack_top, start as usize as u32];
+        let mut i = 0;
+        while i < sequence.len() {
+            let word = sequence[i];
+            if word == 0 {
+                self.fifo.drain();
+                cortex_m::asm::sev();
+            }
+            self.fifo.write_blocking(word);
+            let echo = self.fifo.read_blocking();
+            i = if echo == word { i + 1 } else { 0 };
+        }
+
+        self.fifo
+    }
+}
+
+/// Where core1 starts, on its new stack.
+extern "C" fn core1_start<T>() -> ! {
+    // SAFETY: Set by `Core1::launch` before core1 started, to an `entry` for
+    // this `T` and a `&'static mut T` only we have now
+    let (entry, data) = unsafe {
+        let entry: fn(&'static mut T, Fifo) -> ! = core::mem::transmute(ENTRY);
+        (entry, &mut *(DATA as *mut T))
+    };
+    entry(data, Fifo { _private: () })
+}
@@ -0,0 +1,70 @@
+//! Dormant mode, where the crystal oscillator and everything clocked from it
+//! stop until a GPIO edge, drawing well under a milliamp. `wfe` alone leaves
+//! the PLLs and clocks running, which costs several.
+//!
+//! The SDK's `sleep_goto_dormant_until_pin` does the same: run from the
+//! crystal alone, stop the PLLs, and write the magic word that stops the
+//! crystal. Once it starts again we bring the clocks back up as they were at
+//! boot.
+
+use rp_pico::{
+    hal::{
+        clocks::init_clocks_and_plls,
+        gpio::{self, Pin, PinId, PinMode, ValidPinMode},
+        Watchdog,
+    },
+    pac, XOSC_CRYSTAL_FREQ,
+};
+
+/// Written to XOSC's DORMANT register to stop it
+const XOSC_DORMANT: u32 = 0x636f_6d61;
+/// CLK_REF_SELECTED and CLK_SYS_SELECTED are one-hot
+const CLK_REF_SELECTED_XOSC: u32 = 1 << 2;
+const CLK_SYS_SELECTED_CLK_REF: u32 = 1 << 0;
+
+pub(crate) fn dormant_until<I, M>(wake_pin: &Pin<I, M>, edge: gpio::Interrupt)
+where
+    I: PinId,
+    M: PinMode + ValidPinMode<I>,
+{
+    // SAFETY: Nothing else is running while we swap clocks around, and we
+    // set everything back before returning
+    let device = unsafe { pac::Peripherals::steal() };
+
+    // Run the system from the crystal, so the PLLs can stop
+    let clocks = &device.CLOCKS;
+    clocks.clk_ref_ctrl.modify(|_, w| w.src().xosc_clksrc());
+    while clocks.clk_ref_selected.read().bits() != CLK_REF_SELECTED_XOSC {}
+    clocks.clk_sys_ctrl.modify(|_, w| w.src().clk_ref());
+    while clocks.clk_sys_selected.read().bits() != CLK_SYS_SELECTED_CLK_REF {}
+    // These run off the USB PLL
+    clocks.clk_usb_ctrl.modify(|_, w| w.enable().clear_bit());
+    clocks.clk_adc_ctrl.modify(|_, w| w.enable().clear_bit());
+    clocks.clk_rtc_ctrl.modify(|_, w| w.enable().clear_bit());
+    // Powered down is their reset state
+    device.PLL_SYS.pwr.reset();
+    device.PLL_USB.pwr.reset();
+
+    wake_pin.set_dormant_wake_enabled(edge, true);
+    device
+        .XOSC
+        .dormant
+        .write(|w| unsafe { w.bits(XOSC_DORMANT) });
+    // Stopped here until the edge
+    while device.XOSC.status.read().stable().bit_is_clear() {}
+    wake_pin.set_dormant_wake_enabled(edge, false);
+
+    let mut resets = device.RESETS;
+    let mut watchdog = Watchdog::new(device.WATCHDOG);
+    init_clocks_and_plls(
+        XOSC_CRYSTAL_FREQ,
+        device.XOSC,
+        device.CLOCKS,
+        device.PLL_SYS,
+        device.PLL_USB,
+        &mut resets,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+}
@@ -0,0 +1,245 @@
+//! A read-only FAT12 drive made up as the host reads it, so files can be
+//! handed over without keeping a filesystem on the flash.
+//!
+//! Only the files' contents come from elsewhere, through [`Files`]. Until
+//! [`FatVolume::attach`] is called the drive has no medium, like an empty
+//! card reader, so the host mounts it once the files are ready.
+//!
+//! The volume is always 8MB, of 4K clusters, each file laid out in one run
+//! of them in the order [`Files`] lists them.
+
+use crate::usb_storage::{Blocks, BLOCK_SIZE};
+use alloc::boxed::Box;
+
+/// The root directory is a single block, which also holds the volume label
+pub const MAX_FILES: usize = ROOT_ENTRIES - 1;
+
+const TOTAL_BLOCKS: u32 = 16 * 1024;
+const BLOCKS_PER_CLUSTER: u32 = 8;
+const CLUSTER_SIZE: u32 = BLOCKS_PER_CLUSTER * BLOCK_SIZE as u32;
+const FAT_COUNT: u32 = 2;
+const BLOCKS_PER_FAT: u32 = 6;
+const ROOT_ENTRIES: usize = BLOCK_SIZE / DIR_ENTRY_LEN;
+const DIR_ENTRY_LEN: usize = 32;
+
+const FAT_START: u32 = 1;
+const ROOT_START: u32 = FAT_START + FAT_COUNT * BLOCKS_PER_FAT;
+const DATA_START: u32 = ROOT_START + 1;
+const CLUSTERS: u32 = (TOTAL_BLOCKS - DATA_START) / BLOCKS_PER_CLUSTER;
+/// Clusters are numbered from 2, the first two FAT entries are reserved
+const FIRST_CLUSTER: u32 = 2;
+
+// FAT12 holds fewer than 4085 clusters, at one and a half bytes each
+const _: () = assert!(CLUSTERS < 4085);
+const _: () = assert!((CLUSTERS + FIRST_CLUSTER) * 3 / 2 <= BLOCKS_PER_FAT * BLOCK_SIZE as u32);
+
+const MEDIA_FIXED: u8 = 0xF8;
+const FAT_END_OF_CHAIN: u16 = 0xFFF;
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_LABEL: u8 = 0x08;
+/// 1980-01-01, the earliest FAT can say, as files aren't dated
+const DATE_EPOCH: u16 = (1 << 5) | 1;
+const LABEL: &[u8; 11] = b"BLONG      ";
+
+/// What the volume holds, provided by the app.
+pub trait Files {
+    /// How many files there are, at most [`MAX_FILES`]
+    fn count(&self) -> usize;
+    /// The file's 8.3 name, in upper case and padded with spaces, like
+    /// `*b"TRACK   GPX"`
+    fn name(&self, file: usize) -> [u8; 11];
+    fn size(&self, file: usize) -> u32;
+    /// Fills `buf` with the file from `offset`, which is always within it.
+    /// Called from USBCTRL_IRQ, so must be quick. Returns whether it worked.
+    fn read(&mut self, file: usize, offset: u32, buf: &mut [u8]) -> bool;
+}
+
+/// Where a file is, in clusters
+#[derive(Clone, Copy, Default)]
+struct Extent {
+    first: u32,
+    len: u32,
+}
+
+pub struct FatVolume {
+    files: Option<Box<dyn Files + Send>>,
+    extents: [Extent; MAX_FILES],
+}
+
+impl FatVolume {
+    pub fn new() -> Self {
+        Self {
+            files: None,
+            extents: [Extent::default(); MAX_FILES],
+        }
+    }
+
+    /// Insert the medium. Files that don't fit are left out.
+    pub fn attach(&mut self, files: Box<dyn Files + Send>) {
+        let mut next = FIRST_CLUSTER;
+        for (file, extent) in self.extents.iter_mut().enumerate() {
+            *extent = Extent::default();
+            if file >= files.count() {
+                continue;
+            }
+            let len = files.size(file).div_ceil(CLUSTER_SIZE);
+            if next + len > FIRST_CLUSTER + CLUSTERS {
+                defmt::warn!("File {} doesn't fit on the drive, leaving it out", file);
+                continue;
+            }
+            *extent = Extent { first: next, len };
+            next += len;
+        }
+        self.files = Some(files);
+    }
+
+    /// What the FAT says follows `cluster`
+    fn fat_entry(&self, cluster: u32) -> u16 {
+        match cluster {
+            0 => 0xF00 | MEDIA_FIXED as u16,
+            1 => FAT_END_OF_CHAIN,
+            _ => self
+                .extents
+                .iter()
+                .find(|extent| (extent.first..extent.first + extent.len).contains(&cluster))
+                .map_or(0, |extent| {
+                    if cluster + 1 == extent.first + extent.len {
+                        FAT_END_OF_CHAIN
+                    } else {
+                        cluster as u16 + 1
+                    }
+                }),
+        }
+    }
+
+    /// Entries are packed in pairs, two 12 bit entries in three bytes
+    fn fat_byte(&self, index: u32) -> u8 {
+        let pair = index / 3 * 2;
+        let (a, b) = (self.fat_entry(pair), self.fat_entry(pair + 1));
+        match index % 3 {
+            0 => a as u8,
+            1 => (a >> 8) as u8 & 0x0F | (b as u8 & 0x0F) << 4,
+            _ => (b >> 4) as u8,
+        }
+    }
+
+    fn boot_sector(block: &mut [u8; BLOCK_SIZE]) {
+        block[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        block[3..11].copy_from_slice(b"BLONG   ");
+        block[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        block[13] = BLOCKS_PER_CLUSTER as u8;
+        block[14..16].copy_from_slice(&(FAT_START as u16).to_le_bytes());
+        block[16] = FAT_COUNT as u8;
+        block[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+        block[19..21].copy_from_slice(&(TOTAL_BLOCKS as u16).to_le_bytes());
+        block[21] = MEDIA_FIXED;
+        block[22..24].copy_from_slice(&(BLOCKS_PER_FAT as u16).to_le_bytes());
+        // Sectors per track and heads, which nothing uses any more
+        block[24..26].copy_from_slice(&1_u16.to_le_bytes());
+        block[26..28].copy_from_slice(&1_u16.to_le_bytes());
+        // Extended boot signature, then the serial, label and type
+        block[38] = 0x29;
+        block[39..43].copy_from_slice(&0xb106_0001_u32.to_le_bytes());
+        block[43..54].copy_from_slice(LABEL);
+        block[54..62].copy_from_slice(b"FAT12   ");
+        block[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    fn root_dir(extents: &[Extent], files: &dyn Files, block: &mut [u8; BLOCK_SIZE]) {
+        let mut entries = block.chunks_exact_mut(DIR_ENTRY_LEN);
+        if let Some(entry) = entries.next() {
+            entry[0..11].copy_from_slice(LABEL);
+            entry[11] = ATTR_VOLUME_LABEL;
+        }
+        // Less any `attach` left out, as an empty entry ends the directory
+        let present = extents
+            .iter()
+            .enumerate()
+            .take(files.count())
+            .filter(|(file, extent)| extent.len > 0 || files.size(*file) == 0);
+        for (entry, (file, extent)) in entries.zip(present) {
+            let size = files.size(file);
+            entry[0..11].copy_from_slice(&files.name(file));
+            entry[11] = ATTR_READ_ONLY;
+            // Created, accessed and modified dates
+            for at in [16, 18, 24] {
+                entry[at..at + 2].copy_from_slice(&DATE_EPOCH.to_le_bytes());
+            }
+            entry[26..28].copy_from_slice(&(extent.first as u16).to_le_bytes());
+            entry[28..32].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+}
+
+impl Default for FatVolume {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blocks for FatVolume {
+    fn block_count(&self) -> u32 {
+        TOTAL_BLOCKS
+    }
+
+    fn is_ready(&self) -> bool {
+        self.files.is_some()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> bool {
+        block.fill(0);
+        let files = match &mut self.files {
+            Some(files) => files,
+            None => return false,
+        };
+
+        if lba == 0 {
+            Self::boot_sector(block);
+        } else if lba < ROOT_START {
+            // Each copy of the FAT is the same
+            let start = (lba - FAT_START) % BLOCKS_PER_FAT * BLOCK_SIZE as u32;
+            for (i, byte) in block.iter_mut().enumerate() {
+                *byte = self.fat_byte(start + i as u32);
+            }
+        } else if lba == ROOT_START {
+            Self::root_dir(&self.extents, &**files, block);
+        } else {
+            let cluster = (lba - DATA_START) / BLOCKS_PER_CLUSTER + FIRST_CLUSTER;
+            let found = self
+                .extents
+                .iter()
+                .position(|extent| (extent.first..extent.first + extent.len).contains(&cluster));
+            if let Some(file) = found {
+                let offset = (lba - DATA_START) * BLOCK_SIZE as u32
+                    - (self.extents[file].first - FIRST_CLUSTER) * CLUSTER_SIZE;
+                // The end of the last cluster is past the end of the file
+                let len = files
+                    .size(file)
+                    .saturating_sub(offset)
+                    .min(BLOCK_SIZE as u32);
+                if len > 0 {
+                    return files.read(file, offset, &mut block[..len as usize]);
+                }
+            }
+        }
+        true
+    }
+
+    fn can_write(&self, _lba: u32) -> bool {
+        false
+    }
+
+    fn write_block(&mut self, _lba: u32, _block: &[u8; BLOCK_SIZE]) {}
+
+    fn is_dirty(&self) -> bool {
+        false
+    }
+
+    fn flush(&mut self) -> bool {
+        true
+    }
+}
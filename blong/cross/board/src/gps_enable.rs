@@ -0,0 +1,47 @@
+//! Switching the gps's power with its EN pin, which turns off the breakout's
+//! regulator when pulled low. Cutting power entirely is the only way to get
+//! the gps to draw nothing, and to revive it when it stops answering.
+//!
+//! Enable the `gps-enable` feature and connect GP18 to the breakout's EN pin.
+//! Without the feature EN is left floating, where the breakout's pull up
+//! keeps the gps on, and this does nothing.
+
+#[cfg(feature = "gps-enable")]
+use embedded_hal::digital::v2::OutputPin;
+#[cfg(feature = "gps-enable")]
+use rp_pico::hal::gpio::{bank0::Gpio18, Pin, PushPullOutput};
+
+pub struct GpsEnable {
+    #[cfg(feature = "gps-enable")]
+    pin: Pin<Gpio18, PushPullOutput>,
+}
+
+impl GpsEnable {
+    #[cfg(feature = "gps-enable")]
+    pub(crate) fn new(mut pin: Pin<Gpio18, PushPullOutput>) -> Self {
+        pin.set_high().unwrap();
+        Self { pin }
+    }
+
+    #[cfg(not(feature = "gps-enable"))]
+    pub(crate) fn new() -> Self {
+        Self {}
+    }
+
+    /// Whether the pin is wired up, so [`Self::set`] does anything.
+    pub fn is_present(&self) -> bool {
+        cfg!(feature = "gps-enable")
+    }
+
+    /// Power the gps on or off. It boots from scratch when turned back on.
+    pub fn set(&mut self, on: bool) {
+        #[cfg(feature = "gps-enable")]
+        if on {
+            self.pin.set_high().unwrap();
+        } else {
+            self.pin.set_low().unwrap();
+        }
+        #[cfg(not(feature = "gps-enable"))]
+        let _ = on;
+    }
+}
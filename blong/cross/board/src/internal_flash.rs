@@ -0,0 +1,195 @@
+//! The end of the RP2040's own QSPI flash, reserved in `memory.x` for
+//! settings and small logs, so they don't need any extra hardware.
+//!
+//! Erasing and programming go through the boot ROM's flash functions, which
+//! take the flash out of XIP mode while they run, so nothing can execute
+//! from flash in the meantime. We mask interrupts, and run the sequence from
+//! a function placed in RAM. Core 1 must not be running from flash either,
+//! so this is borrowed from [`crate::Core1`] until it's launched.
+//!
+//! Afterwards we put XIP back by calling a copy of boot2 in RAM, like the
+//! SDK does, as the ROM's own way back in is a much slower read mode.
+
+use crate::{rom, watchdog};
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Where flash is mapped for reading
+const XIP_BASE: u32 = 0x1000_0000;
+/// The region, as offsets into flash. Must match `memory.x`.
+pub const REGION_START: u32 = 2048 * 1024 - REGION_LEN;
+pub const REGION_LEN: u32 = 256 * 1024;
+
+/// The smallest erase the flash can do
+pub const SECTOR_SIZE: usize = 4096;
+/// The ROM programs whole pages
+pub const PAGE_SIZE: usize = 256;
+/// The ROM erases in these where the range allows, though we only ever ask
+/// it for a sector
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+const BOOT2_WORDS: usize = 64;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternalFlashError {
+    NotAligned,
+    OutOfBounds,
+}
+
+impl NorFlashError for InternalFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+/// The boot ROM's flash functions, looked up while we can still run from
+/// flash.
+struct Rom {
+    connect_internal_flash: extern "C" fn(),
+    flash_exit_xip: extern "C" fn(),
+    flash_range_erase: extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: extern "C" fn(),
+}
+
+impl Rom {
+    fn lookup() -> Self {
+        // SAFETY: The tags are the ROM's documented flash functions, with
+        // the signatures the datasheet's bootrom chapter gives
+        unsafe {
+            Self {
+                connect_internal_flash: core::mem::transmute(rom::func(b"IF")),
+                flash_exit_xip: core::mem::transmute(rom::func(b"EX")),
+                flash_range_erase: core::mem::transmute(rom::func(b"RE")),
+                flash_range_program: core::mem::transmute(rom::func(b"RP")),
+                flash_flush_cache: core::mem::transmute(rom::func(b"FC")),
+            }
+        }
+    }
+}
+
+pub struct InternalFlash {
+    /// Run to put XIP back as it was at boot
+    boot2: [u32; BOOT2_WORDS],
+    /// What the watchdog was started with, to feed it between sectors
+    watchdog_timeout_us: u32,
+}
+
+impl InternalFlash {
+    pub(crate) fn new(watchdog_timeout_us: u32) -> Self {
+        let mut boot2 = [0; BOOT2_WORDS];
+        // SAFETY: boot2 is the first 256 bytes of flash, which is mapped
+        unsafe {
+            core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), BOOT2_WORDS);
+        }
+        Self {
+            boot2,
+            watchdog_timeout_us,
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), InternalFlashError> {
+        if offset as usize % align != 0 || len % align != 0 {
+            return Err(InternalFlashError::NotAligned);
+        }
+        if offset as usize + len > REGION_LEN as usize {
+            return Err(InternalFlashError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Erase, or program `data` if given, `len` bytes at `offset` into the
+    /// region.
+    fn with_xip_off(&mut self, offset: u32, len: usize, data: Option<&[u8; PAGE_SIZE]>) {
+        let rom = Rom::lookup();
+        let boot2: extern "C" fn() =
+            // SAFETY: Thumb code, so the address needs its low bit set
+            unsafe { core::mem::transmute(self.boot2.as_ptr() as usize + 1) };
+        let erase = data.is_none();
+        let data = data.map_or(core::ptr::null(), |data| data.as_ptr());
+        cortex_m::interrupt::free(|_| {
+            // SAFETY: Interrupts are masked, everything it calls is in ROM or
+            // RAM, and `data` is on the stack
+            unsafe { flash_op(&rom, boot2, REGION_START + offset, len, erase, data) };
+        });
+    }
+}
+
+/// Runs entirely from RAM, as flash can't be read until `boot2` returns, so
+/// it mustn't call anything but `rom` and `boot2`.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn flash_op(
+    rom: &Rom,
+    boot2: extern "C" fn(),
+    addr: u32,
+    len: usize,
+    erase: bool,
+    data: *const u8,
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    if erase {
+        (rom.flash_range_erase)(addr, len, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    } else {
+        (rom.flash_range_program)(addr, data, len);
+    }
+    (rom.flash_flush_cache)();
+    boot2();
+}
+
+impl ErrorType for InternalFlash {
+    type Error = InternalFlashError;
+}
+
+impl ReadNorFlash for InternalFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        let start = (XIP_BASE + REGION_START + offset) as *const u8;
+        // SAFETY: In bounds of the region, which is mapped
+        unsafe { core::ptr::copy_nonoverlapping(start, bytes.as_mut_ptr(), bytes.len()) };
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        REGION_LEN as usize
+    }
+}
+
+impl NorFlash for InternalFlash {
+    const WRITE_SIZE: usize = PAGE_SIZE;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    /// Takes up to 400ms a sector, with interrupts masked for each. Feeds
+    /// the watchdog between sectors, as nothing else can while this runs.
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(InternalFlashError::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, SECTOR_SIZE)?;
+        for sector in (from..to).step_by(SECTOR_SIZE) {
+            self.with_xip_off(sector, SECTOR_SIZE, None);
+            watchdog::feed_directly(self.watchdog_timeout_us);
+        }
+        Ok(())
+    }
+
+    /// Programs a page at a time, with interrupts masked for each.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), PAGE_SIZE)?;
+        for (i, chunk) in bytes.chunks(PAGE_SIZE).enumerate() {
+            // `bytes` may be in flash itself, which we can't read from while
+            // programming
+            let mut page = [0; PAGE_SIZE];
+            page.copy_from_slice(chunk);
+            self.with_xip_off(offset + (i * PAGE_SIZE) as u32, PAGE_SIZE, Some(&page));
+        }
+        Ok(())
+    }
+}
@@ -2,35 +2,117 @@
 #![feature(alloc_error_handler)]
 
 extern crate alloc;
-use core::alloc::Layout;
-use panic_probe as _;
+use core::alloc::{GlobalAlloc, Layout};
 
 pub use cortex_m;
 pub use embedded_hal;
+#[cfg(feature = "sd-card")]
+pub use embedded_sdmmc;
+pub use embedded_storage;
 pub use nb;
 pub use rp2040_monotonic;
 pub use rp_pico;
+pub use rtt_target;
+
+pub mod accelerometer;
+pub mod core1;
+mod dormant;
+pub mod fat_volume;
+mod gps_enable;
+pub mod internal_flash;
+mod profile_trigger;
+mod reboot_cause;
+mod rom;
+pub mod rtc;
+pub mod rtt_transfer;
+#[cfg(feature = "sd-card")]
+mod sd_card;
+pub mod spi_flash;
+mod usb;
+mod usb_serial;
+pub mod usb_storage;
+pub mod watchdog;
+mod watchdogged_delay;
+pub use accelerometer::Accelerometer;
+pub use core1::Core1;
+pub use fat_volume::FatVolume;
+pub use gps_enable::GpsEnable;
+pub use internal_flash::InternalFlash;
+pub use profile_trigger::ProfileTrigger;
+pub use reboot_cause::RebootCause;
+pub use rtc::Rtc;
+pub use rtt_transfer::RttTransfer;
+#[cfg(feature = "sd-card")]
+pub use sd_card::SdCard;
+pub use spi_flash::SpiFlash;
+pub use usb::Usb;
+pub use usb_serial::UsbSerial;
+pub use usb_storage::UsbStorage;
+pub use watchdog::{Watchdog, WatchdogConfig};
+pub use watchdogged_delay::WatchdoggedDelay;
 
 use alloc_cortex_m::CortexMHeap;
 use asm_delay::AsmDelay;
 use cortex_m::{delay::Delay, peripheral::NVIC};
-use embedded_hal::{digital::v2::OutputPin, watchdog::WatchdogEnable as _};
-use embedded_time::{duration::Extensions as _, fixed_point::FixedPoint as _};
+use embedded_hal::{
+    adc::OneShot as _,
+    digital::v2::{InputPin as _, OutputPin},
+};
+use embedded_time::{fixed_point::FixedPoint as _, rate::Extensions as _};
 use rp2040_monotonic::Rp2040Monotonic;
 use rp_pico::{
     hal::{
+        adc::Adc,
         clocks::init_clocks_and_plls,
-        gpio::{bank0::Gpio25, Pin, PushPullOutput},
+        gpio::{
+            self,
+            bank0::{Gpio13, Gpio14, Gpio15, Gpio20, Gpio21, Gpio29},
+            FloatingInput, FunctionI2C, FunctionSpi, Pin, PullUpInput, PushPullOutput,
+        },
+        i2c::I2C,
+        spi::{self, Spi},
         uart::{self, UartPeripheral},
-        Clock, Sio, Watchdog,
+        Clock, Sio,
     },
-    pac::{self, Interrupt, UART0},
-    Gp16Uart0Tx, Gp17Uart0Rx, XOSC_CRYSTAL_FREQ,
+    pac::{self, Interrupt, I2C0, SPI1, UART0, UART1},
+    Gp16Uart0Tx, Gp17Uart0Rx, Gp4Uart1Tx, Gp5Uart1Rx, XOSC_CRYSTAL_FREQ,
 };
 use rtt_target::rtt_init;
 
 #[global_allocator]
-static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
+static ALLOCATOR: SharedHeap = SharedHeap(CortexMHeap::empty());
+
+/// The SIO spinlock that keeps the cores out of the heap at the same time
+const HEAP_SPINLOCK: *mut u32 = (0xd000_0100 + 4 * 30) as *mut u32;
+
+/// `CortexMHeap` only keeps out interrupts on the core it's running on, so
+/// this also takes a spinlock to keep out the other core.
+struct SharedHeap(CortexMHeap);
+
+impl SharedHeap {
+    fn locked<T>(&self, f: impl FnOnce(&CortexMHeap) -> T) -> T {
+        cortex_m::interrupt::free(|_| {
+            // SAFETY: Reading claims the lock, or gives 0 if the other core
+            // has it, and writing releases it
+            unsafe {
+                while core::ptr::read_volatile(HEAP_SPINLOCK) == 0 {}
+                let result = f(&self.0);
+                core::ptr::write_volatile(HEAP_SPINLOCK, 1);
+                result
+            }
+        })
+    }
+}
+
+unsafe impl GlobalAlloc for SharedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.locked(|heap| heap.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.locked(|heap| heap.dealloc(ptr, layout))
+    }
+}
 
 // The pico has 264KB of SRAM
 
@@ -42,34 +124,222 @@ fn oom(_: Layout) -> ! {
 /// # Safety
 /// This function must be called exactly once.
 unsafe fn init_allocator() {
-    crate::ALLOCATOR.init(
+    crate::ALLOCATOR.0.init(
         cortex_m_rt::heap_start() as usize,
         2_usize.pow(15), // about 12% of the total memory
     );
 }
 
-pub type StatusLed = Pin<Gpio25, PushPullOutput>;
+#[cfg(not(feature = "pico-w"))]
+pub type StatusLed = Pin<rp_pico::hal::gpio::bank0::Gpio25, PushPullOutput>;
+/// On the Pico W the onboard LED hangs off the wireless chip, and driving it
+/// means bringing up the cyw43 driver, which needs embassy. Until we have
+/// that, wire an LED (with a resistor) from GP22 to ground instead.
+#[cfg(feature = "pico-w")]
+pub type StatusLed = Pin<rp_pico::hal::gpio::bank0::Gpio22, PushPullOutput>;
+#[cfg(not(feature = "pico-w"))]
+const STATUS_LED_GPIO: u32 = 25;
+#[cfg(feature = "pico-w")]
+const STATUS_LED_GPIO: u32 = 22;
+/// Momentary button from GP14 to ground
+pub type Button = Pin<Gpio14, PullUpInput>;
+/// The gps's FIX output, see `ada_gps::FixPin`
+pub type GpsFixPin = Pin<Gpio15, FloatingInput>;
 pub type GpsUartReader = uart::Reader<UART0, (Gp16Uart0Tx, Gp17Uart0Rx)>;
 pub type GpsUartWriter = uart::Writer<UART0, (Gp16Uart0Tx, Gp17Uart0Rx)>;
-pub type GpsDelay = AsmDelay;
+/// Feeds the watchdog, as the gps driver can block for seconds retrying
+pub type GpsDelay = WatchdoggedDelay<AsmDelay>;
+/// UART1 on GP4 (TX) and GP5 (RX), or GP8 and GP9 with the `uart1-gp8`
+/// feature, at 115200 baud. Free for a debug console, a radio or another gps.
+#[cfg(not(feature = "uart1-gp8"))]
+pub type Uart1Pins = (Gp4Uart1Tx, Gp5Uart1Rx);
+#[cfg(feature = "uart1-gp8")]
+pub type Uart1Pins = (
+    Pin<gpio::bank0::Gpio8, gpio::FunctionUart>,
+    Pin<gpio::bank0::Gpio9, gpio::FunctionUart>,
+);
+pub type Uart1Reader = uart::Reader<UART1, Uart1Pins>;
+pub type Uart1Writer = uart::Writer<UART1, Uart1Pins>;
+/// I2C0 on GP20 (SDA) and GP21 (SCL), at 400kHz. The `accelerometer`
+/// feature gives it to [`Accelerometer`].
+pub type I2cBus = I2C<I2C0, (Pin<Gpio20, FunctionI2C>, Pin<Gpio21, FunctionI2C>)>;
+/// A W25Qxx on SPI1: GP10 (SCK), GP11 (TX to DI), GP12 (RX from DO) and GP13
+/// (CS)
+pub type ExternalFlash = SpiFlash<Spi<spi::Enabled, SPI1, 8>, Pin<Gpio13, PushPullOutput>>;
+
+/// Reads VSYS, which is the battery voltage when running off a battery.
+///
+/// On the Pico W this pin is shared with the wireless chip, so readings are
+/// only meaningful while that's idle.
+pub struct Battery {
+    adc: Adc,
+    pin: Pin<Gpio29, FloatingInput>,
+}
+
+impl Battery {
+    pub fn read_mv(&mut self) -> u32 {
+        let raw: u16 = nb::block!(self.adc.read(&mut self.pin)).unwrap();
+        // VSYS is divided by 3 before the ADC, which is 12 bit with a 3.3V
+        // reference
+        raw as u32 * 3 * 3300 / 4096
+    }
+}
+
+/// What we're running off.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Usb,
+    Battery,
+}
+
+/// Senses VBUS on GP24, to tell whether USB is powering us. Its edges raise
+/// IO_IRQ_BANK0, which should call [`Self::take_change`].
+///
+/// On the Pico W VBUS sense is on the wireless chip instead, so this always
+/// says we're on battery, to err on the side of saving power.
+pub struct PowerSourceSense {
+    #[cfg(not(feature = "pico-w"))]
+    pin: Pin<rp_pico::hal::gpio::bank0::Gpio24, FloatingInput>,
+}
+
+impl PowerSourceSense {
+    pub fn read(&self) -> PowerSource {
+        #[cfg(not(feature = "pico-w"))]
+        if self.pin.is_high().unwrap() {
+            return PowerSource::Usb;
+        }
+        PowerSource::Battery
+    }
+
+    /// What we're running off now, if it might have changed since the last
+    /// call. Clears the interrupt.
+    pub fn take_change(&mut self) -> Option<PowerSource> {
+        #[cfg(not(feature = "pico-w"))]
+        {
+            let mut changed = false;
+            for edge in [gpio::Interrupt::EdgeHigh, gpio::Interrupt::EdgeLow] {
+                if self.pin.interrupt_status(edge) {
+                    self.pin.clear_interrupt(edge);
+                    changed = true;
+                }
+            }
+            // It may bounce as the cable goes in, so go by where it settled
+            if changed {
+                return Some(self.read());
+            }
+        }
+        None
+    }
+}
+
+/// Changes the baud rate of the gps's UART in place, for
+/// `ada_gps::Gps::set_baud_rate`. Only call once everything written has been
+/// sent.
+pub struct GpsUartBaud {
+    peripheral_freq_hz: u32,
+}
+
+impl GpsUartBaud {
+    pub fn set(&mut self, baud: u32) {
+        // SAFETY: Only touches the baud rate registers, which nothing else
+        // writes after init
+        let uart = unsafe { &*UART0::ptr() };
+
+        // See section 4.2.7.1 of the datasheet
+        let div = 8 * self.peripheral_freq_hz / baud;
+        let (int, frac) = match div >> 7 {
+            0 => (1, 0),
+            int if int >= 65535 => (65535, 0),
+            int => (int, ((div & 0x7f) + 1) / 2),
+        };
+        uart.uartibrd
+            .write(|w| unsafe { w.baud_divint().bits(int as u16) });
+        uart.uartfbrd
+            .write(|w| unsafe { w.baud_divfrac().bits(frac as u8) });
+        // The new divisors only take effect on a write to LCR_H
+        uart.uartlcr_h.modify(|_, w| w);
+    }
+}
+
+/// Gets UART0's interrupt to send what's queued for the gps, for
+/// `ada_gps::QueuedTx`. The interrupt owns the UART's writer, and leaves the
+/// UART's transmit interrupt on while it's waiting for room to send more.
+pub struct GpsTxIrq {
+    _private: (),
+}
+
+impl GpsTxIrq {
+    /// Pend the interrupt, unless it's already waiting for room.
+    pub fn kick(&mut self) {
+        // SAFETY: Only reads, the interrupt is the only writer
+        let uart = unsafe { &*UART0::ptr() };
+        if uart.uartimsc.read().txim().bit_is_clear() {
+            NVIC::pend(Interrupt::UART0_IRQ);
+        }
+    }
+
+    /// Whether the interrupt has sent everything queued, and the UART has
+    /// finished shifting it out.
+    pub fn is_idle(&self) -> bool {
+        // SAFETY: Only reads, the interrupt is the only writer
+        let uart = unsafe { &*UART0::ptr() };
+        uart.uartimsc.read().txim().bit_is_clear()
+            && !NVIC::is_pending(Interrupt::UART0_IRQ)
+            && uart.uartfr.read().busy().bit_is_clear()
+    }
+}
 
 pub struct Board {
     pub watchdog: Watchdog,
+    pub reboot_cause: RebootCause,
     pub delay: Delay,
     pub status_led: StatusLed,
+    pub button: Button,
+    pub gps_fix_pin: GpsFixPin,
     pub gps_uart_reader: GpsUartReader,
     pub gps_uart_writer: GpsUartWriter,
-    pub gps_delay: AsmDelay,
+    pub gps_uart_baud: GpsUartBaud,
+    pub gps_tx_irq: GpsTxIrq,
+    pub gps_delay: GpsDelay,
+    pub gps_enable: GpsEnable,
+    pub uart1_reader: Uart1Reader,
+    pub uart1_writer: Uart1Writer,
+    pub battery: Battery,
+    /// `None` if no chip answered. Needed for [`Usb::Storage`], which has
+    /// nothing to hold without it.
+    pub flash: Option<ExternalFlash>,
+    pub usb: Usb,
+    #[cfg(feature = "sd-card")]
+    pub sd_card: SdCard,
+    pub power_source: PowerSourceSense,
+    #[cfg(not(feature = "accelerometer"))]
+    pub i2c: I2cBus,
+    pub accelerometer: Accelerometer,
+    pub rtc: Rtc,
+    /// Also lends out the [`InternalFlash`] until it's launched.
+    pub core1: Core1,
+    pub profile_trigger: ProfileTrigger,
+    pub rtt_transfer: RttTransfer,
     pub mono: Rp2040Monotonic,
 }
 
 impl Board {
-    pub fn init(core: cortex_m::Peripherals, device: pac::Peripherals) -> Self {
+    pub fn init(
+        core: cortex_m::Peripherals,
+        device: pac::Peripherals,
+        watchdog_config: WatchdogConfig,
+    ) -> Self {
         unsafe {
             init_allocator();
         }
 
-        init_needed_rtt();
+        let rtt_transfer = init_needed_rtt();
+
+        let reboot_cause = reboot_cause::take();
+        match reboot_cause {
+            RebootCause::PowerOn => defmt::info!("Powered on"),
+            cause => defmt::warn!("Rebooted by {}", cause),
+        }
 
         // Causes all interrupts to fire an event, allowing us to use wfe (wait for event) in our
         // idle loop. Our idle loop is simple enough this isn't technically necessary (we could just)
@@ -78,9 +348,7 @@ impl Board {
 
         let mut resets = device.RESETS;
 
-        let mut watchdog = Watchdog::new(device.WATCHDOG);
-        // Set to watchdog to reset if it's not reloaded within 1.05 seconds
-        watchdog.start(1_050_000u32.microseconds());
+        let mut watchdog = Watchdog::new(device.WATCHDOG, watchdog_config);
 
         let clocks = init_clocks_and_plls(
             XOSC_CRYSTAL_FREQ,
@@ -89,7 +357,7 @@ impl Board {
             device.PLL_SYS,
             device.PLL_USB,
             &mut resets,
-            &mut watchdog,
+            watchdog.hal(),
         )
         .ok()
         .unwrap();
@@ -97,7 +365,10 @@ impl Board {
         // NOTE: I'm not sure this is the right frequency
         let cpu_freq_hz = clocks.system_clock.freq().integer();
         let delay = Delay::new(core.SYST, cpu_freq_hz);
-        let gps_delay = AsmDelay::new(asm_delay::bitrate::Hertz(cpu_freq_hz));
+        let gps_delay = WatchdoggedDelay::new(
+            AsmDelay::new(asm_delay::bitrate::Hertz(cpu_freq_hz)),
+            watchdog_config.timeout_us,
+        );
 
         // Causes all interrupts to fire an event, allowing us to use wfe (wait for event) in our
         // idle loop. Our idle loop is simple enough this isn't technically necessary (we could just)
@@ -112,9 +383,22 @@ impl Board {
             &mut resets,
         );
 
+        #[cfg(not(feature = "pico-w"))]
         let mut status_led = pins.led.into_push_pull_output();
+        // GPIO25 is the wireless chip's SPI chip select on the Pico W, so
+        // leave it alone
+        #[cfg(feature = "pico-w")]
+        let mut status_led = pins.gpio22.into_push_pull_output();
         status_led.set_low().unwrap();
 
+        let button = pins.gpio14.into_pull_up_input();
+        // Both raise IO_IRQ_BANK0, the app's handler clears them
+        button.set_interrupt_enabled(gpio::Interrupt::EdgeLow, true);
+
+        // Every blink, which `ada_gps::FixPin` debounces
+        let gps_fix_pin = pins.gpio15.into_floating_input();
+        gps_fix_pin.set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
+
         let (mut gps_uart_reader, gps_uart_writer) = UartPeripheral::new(
             device.UART0,
             (pins.gpio16.into_mode(), pins.gpio17.into_mode()),
@@ -127,41 +411,218 @@ impl Board {
         .unwrap()
         .split();
         gps_uart_reader.enable_rx_interrupt();
+        let gps_uart_baud = GpsUartBaud {
+            peripheral_freq_hz: clocks.peripheral_clock.freq().integer(),
+        };
+
+        #[cfg(not(feature = "uart1-gp8"))]
+        let uart1_pins: Uart1Pins = (pins.gpio4.into_mode(), pins.gpio5.into_mode());
+        #[cfg(feature = "uart1-gp8")]
+        let uart1_pins: Uart1Pins = (pins.gpio8.into_mode(), pins.gpio9.into_mode());
+        let (uart1_reader, uart1_writer) =
+            UartPeripheral::new(device.UART1, uart1_pins, &mut resets)
+                .enable(
+                    uart::common_configs::_115200_8_N_1,
+                    clocks.peripheral_clock.freq(),
+                )
+                .unwrap()
+                .split();
+
+        let _ = pins.gpio10.into_mode::<FunctionSpi>();
+        let _ = pins.gpio11.into_mode::<FunctionSpi>();
+        let _ = pins.gpio12.into_mode::<FunctionSpi>();
+        let flash_spi = Spi::<_, _, 8>::new(device.SPI1).init(
+            &mut resets,
+            clocks.peripheral_clock.freq(),
+            // Well under what any W25Q reads at with the plain read command
+            16_000_000_u32.Hz(),
+            &embedded_hal::spi::MODE_0,
+        );
+        let flash = match SpiFlash::new(flash_spi, pins.gpio13.into_push_pull_output()) {
+            Ok(flash) => Some(flash),
+            Err(_) => {
+                defmt::info!("No SPI flash");
+                None
+            }
+        };
+
+        #[cfg(feature = "sd-card")]
+        let sd_card = {
+            let _ = pins.gpio2.into_mode::<FunctionSpi>();
+            let _ = pins.gpio3.into_mode::<FunctionSpi>();
+            let _ = pins.gpio0.into_mode::<FunctionSpi>();
+            let spi = Spi::<_, _, 8>::new(device.SPI0).init(
+                &mut resets,
+                clocks.peripheral_clock.freq(),
+                sd_card::INIT_BAUD_HZ.Hz(),
+                &embedded_hal::spi::MODE_0,
+            );
+            let mut cs = pins.gpio1.into_push_pull_output();
+            cs.set_high().unwrap();
+            SdCard::new(spi, cs)
+        };
+
+        let usb_bus = usb::bus(
+            device.USBCTRL_REGS,
+            device.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            &mut resets,
+        );
+        // The button's pull up has long since settled
+        let usb = if flash.is_some() && button.is_low().unwrap() {
+            defmt::info!("Button held, presenting a USB drive");
+            Usb::Storage(UsbStorage::new(usb_bus, FatVolume::new()))
+        } else {
+            Usb::Serial(UsbSerial::new(usb_bus))
+        };
+
+        #[cfg(feature = "gps-enable")]
+        let gps_enable = GpsEnable::new(pins.gpio18.into_push_pull_output());
+        #[cfg(not(feature = "gps-enable"))]
+        let gps_enable = GpsEnable::new();
+
+        #[cfg(feature = "profile-trigger")]
+        let profile_trigger = ProfileTrigger::new(pins.gpio26.into_push_pull_output());
+        #[cfg(not(feature = "profile-trigger"))]
+        let profile_trigger = ProfileTrigger::new();
+
+        let battery = Battery {
+            adc: Adc::new(device.ADC, &mut resets),
+            pin: pins.voltage_monitor.into_floating_input(),
+        };
+
+        #[cfg(not(feature = "pico-w"))]
+        let power_source = {
+            let pin = pins.vbus_detect.into_floating_input();
+            pin.set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
+            pin.set_interrupt_enabled(gpio::Interrupt::EdgeLow, true);
+            PowerSourceSense { pin }
+        };
+        #[cfg(feature = "pico-w")]
+        let power_source = PowerSourceSense {};
+
+        let i2c = I2C::i2c0(
+            device.I2C0,
+            pins.gpio20.into_mode(),
+            pins.gpio21.into_mode(),
+            400_000_u32.Hz(),
+            &mut resets,
+            clocks.system_clock.freq(),
+        );
+        #[cfg(feature = "accelerometer")]
+        let accelerometer = Accelerometer::new(i2c, pins.gpio19.into_pull_down_input());
+        #[cfg(not(feature = "accelerometer"))]
+        let accelerometer = Accelerometer::new();
+
+        let rtc = Rtc::new(device.RTC, clocks.rtc_clock, &mut resets);
 
         let mono = Rp2040Monotonic::new(device.TIMER);
 
         Self {
             watchdog,
+            reboot_cause,
             delay,
             status_led,
+            button,
+            gps_fix_pin,
             gps_uart_reader,
             gps_uart_writer,
+            gps_uart_baud,
+            gps_tx_irq: GpsTxIrq { _private: () },
             gps_delay,
+            gps_enable,
+            uart1_reader,
+            uart1_writer,
+            battery,
+            flash,
+            usb,
+            #[cfg(feature = "sd-card")]
+            sd_card,
+            power_source,
+            #[cfg(not(feature = "accelerometer"))]
+            i2c,
+            accelerometer,
+            rtc,
+            core1: Core1::new(InternalFlash::new(watchdog_config.timeout_us)),
+            profile_trigger,
+            rtt_transfer,
             mono,
         }
     }
 
+    /// Power the gps on or off, see [`GpsEnable`].
+    pub fn gps_power(&mut self, on: bool) {
+        self.gps_enable.set(on);
+    }
+
+    /// Reboot into the boot ROM's USB bootloader, as if BOOTSEL were held,
+    /// so new firmware can be copied onto the drive it shows up as. The
+    /// status LED flickers while it's busy.
+    pub fn reboot_to_bootsel() -> ! {
+        // SAFETY: "UB" is reset_to_usb_boot, which takes the pin to show
+        // activity on and which of its USB interfaces to leave out
+        let reset_to_usb_boot: extern "C" fn(u32, u32) =
+            unsafe { core::mem::transmute(rom::func(b"UB")) };
+        reset_to_usb_boot(1 << STATUS_LED_GPIO, 0);
+        // It resets us through the watchdog, which takes a moment
+        loop {
+            cortex_m::asm::wfe();
+        }
+    }
+
+    /// Stop every clock until `wake_pin` sees `edge`, then start them again.
+    ///
+    /// Nothing runs meanwhile. The UARTs, USB and ADC are unclocked, the
+    /// watchdog and timer are paused, and the RTC loses the time spent
+    /// dormant. So only sleep like this when there's nothing to listen for
+    /// but the pin, and not while USB is connected. The edge is left for
+    /// IO_IRQ_BANK0 to see as usual, and must be cleared before going dormant
+    /// again or we wake straight away.
+    pub fn dormant_until<I, M>(wake_pin: &Pin<I, M>, edge: gpio::Interrupt)
+    where
+        I: gpio::PinId,
+        M: gpio::PinMode + gpio::ValidPinMode<I>,
+    {
+        dormant::dormant_until(wake_pin, edge);
+    }
+
     pub fn unpend(interrupt: Interrupt) {
         NVIC::unpend(interrupt)
     }
 }
 
 #[cfg(not(feature = "rtt-print"))]
-fn init_needed_rtt() {
+fn init_needed_rtt() -> RttTransfer {
     let channels = rtt_init! {
         up: {
             0: {
                 size: 1024
                 name: "defmt_rtt"
             }
+            1: {
+                size: 1024
+                name: "transfer"
+            }
+        }
+        down: {
+            // Unused, but keeps transfer's down channel numbered the same as
+            // its up channel
+            0: {
+                size: 16
+            }
+            1: {
+                size: 512
+                name: "transfer"
+            }
         }
     };
 
     defmt_rtt_target::init(channels.up.0);
+    RttTransfer::new(channels.up.1, channels.down.1)
 }
 
 #[cfg(feature = "rtt-print")]
-fn init_needed_rtt() {
+fn init_needed_rtt() -> RttTransfer {
     let channels = rtt_init! {
         up: {
             0: {
@@ -170,16 +631,30 @@ fn init_needed_rtt() {
                 name: "Defmt"
             }
             1: {
+                size: 1024
+                name: "transfer"
+            }
+            2: {
                 // We block on buffer full with a massive buffer (2^15
                 // bytes, 12% of total memory) because we use this for
                 // dumping complete traffic, where partial data is useless.
                 size: 32768
             }
         }
+        down: {
+            0: {
+                size: 16
+            }
+            1: {
+                size: 512
+                name: "transfer"
+            }
+        }
     };
 
     defmt_rtt_target::init(channels.up.0);
-    rtt_target::set_print_channel(channels.up.1);
+    rtt_target::set_print_channel(channels.up.2);
+    RttTransfer::new(channels.up.1, channels.down.1)
 }
 
 #[cfg(test)]
@@ -0,0 +1,64 @@
+//! Marking firmware phases on a GPIO, so an external power analyzer (PPK2,
+//! Joulescope) can line up current draw with what the firmware is doing.
+//!
+//! Enable the `profile-trigger` feature and connect GP26 to one of the
+//! analyzer's digital inputs. Without the feature this does nothing, so call
+//! sites don't need to be feature gated.
+
+#[cfg(feature = "profile-trigger")]
+use embedded_hal::digital::v2::OutputPin;
+#[cfg(feature = "profile-trigger")]
+use rp_pico::hal::gpio::{bank0::Gpio26, Pin, PushPullOutput};
+
+/// Long enough for a PPK2 sampling digital inputs at 100kHz to see, at our
+/// 125MHz clock.
+#[cfg(feature = "profile-trigger")]
+const BLIP_CYCLES: u32 = 6_250;
+
+pub struct ProfileTrigger {
+    #[cfg(feature = "profile-trigger")]
+    pin: Pin<Gpio26, PushPullOutput>,
+}
+
+impl ProfileTrigger {
+    #[cfg(feature = "profile-trigger")]
+    pub(crate) fn new(mut pin: Pin<Gpio26, PushPullOutput>) -> Self {
+        pin.set_low().unwrap();
+        Self { pin }
+    }
+
+    #[cfg(not(feature = "profile-trigger"))]
+    pub(crate) fn new() -> Self {
+        Self {}
+    }
+
+    /// Sets the pin high for the duration of a phase. It starts with `id`
+    /// short low blips, so phases can be told apart in the capture.
+    pub fn begin(&mut self, id: u8) {
+        #[cfg(feature = "profile-trigger")]
+        {
+            self.pin.set_high().unwrap();
+            for _ in 0..id {
+                cortex_m::asm::delay(BLIP_CYCLES);
+                self.pin.set_low().unwrap();
+                cortex_m::asm::delay(BLIP_CYCLES);
+                self.pin.set_high().unwrap();
+            }
+        }
+        #[cfg(not(feature = "profile-trigger"))]
+        let _ = id;
+    }
+
+    pub fn end(&mut self) {
+        #[cfg(feature = "profile-trigger")]
+        self.pin.set_low().unwrap();
+    }
+
+    /// Runs `f` as a phase.
+    pub fn phase<T>(&mut self, id: u8, f: impl FnOnce() -> T) -> T {
+        self.begin(id);
+        let out = f();
+        self.end();
+        out
+    }
+}
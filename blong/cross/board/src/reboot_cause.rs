@@ -0,0 +1,121 @@
+//! Why we last reset, for working out why units in the field reboot.
+//!
+//! Panics and hard faults record a cause in the watchdog's scratch
+//! registers, which survive everything but powering on and the RUN pin.
+//! Either way we then reset straight away, and [`crate::Board::init`] reads
+//! the cause back with [`take`]. Anything else is told apart by the
+//! watchdog's reason register. The boot ROM uses scratch 0, 1 and 4 to 7, so
+//! this keeps to 2 and 3.
+
+use core::{
+    fmt::{self, Write as _},
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use cortex_m_rt::{exception, ExceptionFrame};
+use rp_pico::pac;
+
+/// In the top of scratch 2, to tell a cause we recorded from leftovers
+const MAGIC: u32 = 0xb106_0000;
+const MAGIC_MASK: u32 = 0xffff_0000;
+const CODE_PANIC: u32 = 1;
+const CODE_HARD_FAULT: u32 = 2;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootCause {
+    /// Powering on, or the RUN pin
+    PowerOn,
+    /// The FNV-1a hash of what the panic printed, like
+    /// `panicked at 'oom', board/src/lib.rs:122:5`
+    Panic { hash: u32 },
+    /// A fault that wasn't a panic, at `pc`
+    HardFault { pc: u32 },
+    /// Nothing fed the watchdog in time
+    WatchdogTimeout,
+    /// Something asked the watchdog to reset us
+    WatchdogForced,
+}
+
+fn watchdog() -> &'static pac::watchdog::RegisterBlock {
+    // SAFETY: The hal's `Watchdog` doesn't touch the scratch registers, the
+    // reason register is only read, and CTRL is only written to reset
+    unsafe { &*pac::WATCHDOG::ptr() }
+}
+
+fn record(code: u32, detail: u32) {
+    let watchdog = watchdog();
+    watchdog.scratch3.write(|w| unsafe { w.bits(detail) });
+    watchdog.scratch2.write(|w| unsafe { w.bits(MAGIC | code) });
+}
+
+fn is_recorded() -> bool {
+    watchdog().scratch2.read().bits() & MAGIC_MASK == MAGIC
+}
+
+/// Why we reset, forgetting it so the next boot doesn't see it again.
+pub(crate) fn take() -> RebootCause {
+    let watchdog = watchdog();
+    let scratch = watchdog.scratch2.read().bits();
+    let detail = watchdog.scratch3.read().bits();
+    watchdog.scratch2.write(|w| unsafe { w.bits(0) });
+
+    if scratch & MAGIC_MASK == MAGIC {
+        match scratch & !MAGIC_MASK {
+            CODE_PANIC => return RebootCause::Panic { hash: detail },
+            CODE_HARD_FAULT => return RebootCause::HardFault { pc: detail },
+            _ => {}
+        }
+    }
+    let reason = watchdog.reason.read();
+    if reason.timer().bit_is_set() {
+        RebootCause::WatchdogTimeout
+    } else if reason.force().bit_is_set() {
+        RebootCause::WatchdogForced
+    } else {
+        RebootCause::PowerOn
+    }
+}
+
+/// Hashes what's written to it, see [`RebootCause::Panic`]
+struct Fnv1a(u32);
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.0 = (self.0 ^ byte as u32).wrapping_mul(0x0100_0193);
+        }
+        Ok(())
+    }
+}
+
+/// Like panic-probe's, but records the panic before faulting.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+
+    cortex_m::interrupt::disable();
+    // If logging it panics, just fault
+    if !PANICKED.load(Ordering::Relaxed) {
+        PANICKED.store(true, Ordering::Relaxed);
+        let mut hash = Fnv1a(0x811c_9dc5);
+        let _ = write!(hash, "{}", info);
+        record(CODE_PANIC, hash.0);
+        defmt::error!("{}", defmt::Display2Format(info));
+    }
+    // probe-run prints a backtrace on a hard fault
+    cortex_m::asm::udf()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    // Panics fault on purpose, and have recorded why already
+    if !is_recorded() {
+        record(CODE_HARD_FAULT, frame.pc());
+    }
+    // Now, rather than waiting for the watchdog to time out, as it may be
+    // paused or off for ship mode
+    watchdog().ctrl.modify(|_, w| w.trigger().set_bit());
+    loop {
+        cortex_m::asm::nop();
+    }
+}
@@ -0,0 +1,14 @@
+//! The RP2040's boot ROM, which has functions for things like programming
+//! flash and rebooting into the USB bootloader.
+
+/// Looks up a function in the boot ROM by its two letter tag.
+///
+/// # Safety
+/// The tag must be one the ROM has.
+pub(crate) unsafe fn func(tag: &[u8; 2]) -> *const () {
+    // The bootrom chapter of the datasheet describes the table
+    let table = core::ptr::read_volatile(0x14 as *const u16) as *const u16;
+    let lookup: extern "C" fn(*const u16, u32) -> *const () =
+        core::mem::transmute(core::ptr::read_volatile(0x18 as *const u16) as usize);
+    lookup(table, u16::from_le_bytes(*tag) as u32)
+}
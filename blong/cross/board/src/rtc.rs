@@ -0,0 +1,73 @@
+//! The RP2040's real time clock, for the date and time of events and points
+//! before the gps has a fix. It counts from an arbitrary date at boot until
+//! [`Rtc::set`] is called, and only keeps time while we're powered.
+//!
+//! Its alarm raises RTC_IRQ, which should call [`Rtc::clear_alarm`].
+
+use rp_pico::{
+    hal::{clocks::RtcClock, rtc::RealTimeClock},
+    pac::{RESETS, RTC},
+};
+
+pub use rp_pico::hal::rtc::{DateTime, DateTimeFilter, DayOfWeek, RtcError};
+
+/// What it counts from until it's set
+const UNSET: DateTime = DateTime {
+    year: 2000,
+    month: 1,
+    day: 1,
+    day_of_week: DayOfWeek::Saturday,
+    hour: 0,
+    minute: 0,
+    second: 0,
+};
+
+pub struct Rtc {
+    rtc: RealTimeClock,
+    is_set: bool,
+}
+
+impl Rtc {
+    pub(crate) fn new(rtc: RTC, clock: RtcClock, resets: &mut RESETS) -> Self {
+        let rtc = RealTimeClock::new(rtc, clock, resets, UNSET).unwrap();
+        Self { rtc, is_set: false }
+    }
+
+    /// Set the date and time. Fails if it isn't a real date.
+    pub fn set(&mut self, time: DateTime) -> Result<(), RtcError> {
+        self.rtc.set_datetime(time)?;
+        self.is_set = true;
+        Ok(())
+    }
+
+    /// Whether it's been set since boot, so [`Self::now`] is the real time.
+    pub fn is_set(&self) -> bool {
+        self.is_set
+    }
+
+    /// `None` until it's been set.
+    pub fn now(&self) -> Option<DateTime> {
+        if !self.is_set {
+            return None;
+        }
+        self.rtc.now().ok()
+    }
+
+    /// Raise RTC_IRQ whenever the time matches `when`, replacing any earlier
+    /// alarm.
+    pub fn set_alarm(&mut self, when: DateTimeFilter) {
+        self.rtc.schedule_alarm(when);
+        self.rtc.enable_interrupt();
+    }
+
+    pub fn cancel_alarm(&mut self) {
+        self.rtc.disable_interrupt();
+        self.rtc.disable_alarm();
+    }
+
+    /// Call from RTC_IRQ. The alarm stays set, so fires again the next time
+    /// the time matches.
+    pub fn clear_alarm(&mut self) {
+        self.rtc.clear_interrupt();
+    }
+}
@@ -0,0 +1,291 @@
+//! Moving whole files to and from the host over RTT, for when only a debug
+//! probe is attached. See `cargo xtask rtt pull` for the other end.
+//!
+//! Both directions are made of frames:
+//!
+//! ```text
+//! 0xB1, kind: u8, seq: u16, len: u16, payload: [u8; len], crc: u32
+//! ```
+//!
+//! Integers are little endian, and `crc` is the CRC-32 of everything from
+//! `kind` to the end of `payload`. Frames that fail the check are dropped,
+//! and the sender resends when it doesn't get an ack.
+//!
+//! The host pulls a file by name with [`PULL`]. We send it as [`DATA`]
+//! frames numbered from zero, then an [`END`] frame holding the total length
+//! and CRC-32 of the file, waiting for an [`ACK`] of each before sending the
+//! next. Pushes are the same the other way around, with [`PUSH`] frames and
+//! a [`PUSH_END`].
+//!
+//! The transfer channel is up and down channel 1 on every build, as some RTT
+//! servers pair up the two directions by number.
+
+use alloc::vec::Vec;
+use rtt_target::{DownChannel, UpChannel};
+
+const MAGIC: u8 = 0xB1;
+const HEADER_LEN: usize = 1 + 1 + 2 + 2;
+const CRC_LEN: usize = 4;
+/// Longest payload in a frame
+pub const MAX_PAYLOAD: usize = 256;
+
+// Host to device
+pub const PULL: u8 = b'P';
+pub const PUSH: u8 = b'C';
+pub const PUSH_END: u8 = b'F';
+// Device to host
+pub const DATA: u8 = b'D';
+pub const END: u8 = b'E';
+/// Payload is a message saying what went wrong. Not acked.
+pub const ERROR: u8 = b'X';
+// Both ways
+pub const ACK: u8 = b'A';
+pub const NACK: u8 = b'N';
+
+const ACK_TIMEOUT_US: u64 = 200_000;
+const MAX_TRIES: u8 = 10;
+
+pub enum Event {
+    /// The host wants the file with this name. Answer with
+    /// [`RttTransfer::send`] or [`RttTransfer::send_error`].
+    Pull(Vec<u8>),
+    /// The host sent a whole file.
+    Push(Vec<u8>),
+    /// The host stopped acking the file we were sending, so we gave up.
+    SendFailed,
+}
+
+pub struct RttTransfer {
+    up: UpChannel,
+    down: DownChannel,
+    rx: Vec<u8>,
+    pushed: Vec<u8>,
+    next_push_seq: u16,
+    sending: Option<Sending>,
+}
+
+struct Sending {
+    data: Vec<u8>,
+    /// The frame waiting to be acked. `chunks()` is the END frame.
+    seq: u16,
+    sent_at_us: Option<u64>,
+    tries: u8,
+}
+
+impl Sending {
+    fn chunks(&self) -> u16 {
+        ((self.data.len() + MAX_PAYLOAD - 1) / MAX_PAYLOAD) as u16
+    }
+}
+
+impl RttTransfer {
+    pub(crate) fn new(up: UpChannel, down: DownChannel) -> Self {
+        Self {
+            up,
+            down,
+            rx: Vec::new(),
+            pushed: Vec::new(),
+            next_push_seq: 0,
+            sending: None,
+        }
+    }
+
+    /// Start sending `data` in answer to a [`Event::Pull`], replacing
+    /// anything still being sent. Keep calling [`Self::poll`] to send it.
+    pub fn send(&mut self, data: Vec<u8>) {
+        assert!(data.len() / MAX_PAYLOAD < u16::MAX as usize);
+        self.sending = Some(Sending {
+            data,
+            seq: 0,
+            sent_at_us: None,
+            tries: 0,
+        });
+    }
+
+    pub fn send_error(&mut self, msg: &[u8]) {
+        self.sending = None;
+        self.write_frame(ERROR, 0, &msg[..msg.len().min(MAX_PAYLOAD)]);
+    }
+
+    /// Handles anything the host has sent and carries on sending. Call until
+    /// it returns `None` whenever awake, and again at
+    /// [`Self::check_again_at_us`].
+    pub fn poll(&mut self, now_us: u64) -> Option<Event> {
+        let mut buf = [0; 64];
+        loop {
+            let n = self.down.read(&mut buf);
+            if n == 0 {
+                break;
+            }
+            self.rx.extend_from_slice(&buf[..n]);
+        }
+
+        while let Some((kind, seq, payload)) = self.take_frame() {
+            if let Some(event) = self.on_frame(kind, seq, payload) {
+                return Some(event);
+            }
+        }
+
+        self.continue_sending(now_us)
+    }
+
+    /// When the frame being sent times out, if there is one.
+    pub fn check_again_at_us(&self) -> Option<u64> {
+        Some(self.sending.as_ref()?.sent_at_us? + ACK_TIMEOUT_US)
+    }
+
+    fn on_frame(&mut self, kind: u8, seq: u16, payload: Vec<u8>) -> Option<Event> {
+        match kind {
+            PULL => {
+                self.sending = None;
+                return Some(Event::Pull(payload));
+            }
+            PUSH => {
+                if seq == 0 {
+                    self.pushed.clear();
+                    self.next_push_seq = 0;
+                }
+                if seq == self.next_push_seq {
+                    self.pushed.extend_from_slice(&payload);
+                    self.next_push_seq += 1;
+                }
+                // Acks repeats too, in case our ack was lost
+                if seq < self.next_push_seq {
+                    self.write_frame(ACK, seq, &[]);
+                }
+            }
+            PUSH_END => {
+                let len = payload
+                    .get(..4)
+                    .map(|len| u32::from_le_bytes(len.try_into().unwrap()));
+                if len == Some(self.pushed.len() as u32) {
+                    self.write_frame(ACK, seq, &[]);
+                    self.next_push_seq = 0;
+                    return Some(Event::Push(core::mem::take(&mut self.pushed)));
+                }
+                self.write_frame(NACK, seq, &[]);
+            }
+            ACK => {
+                if let Some(sending) = &mut self.sending {
+                    if seq == sending.seq {
+                        sending.seq += 1;
+                        sending.sent_at_us = None;
+                        sending.tries = 0;
+                        if sending.seq > sending.chunks() {
+                            self.sending = None;
+                        }
+                    }
+                }
+            }
+            NACK => {
+                if let Some(sending) = &mut self.sending {
+                    if seq == sending.seq {
+                        sending.sent_at_us = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn continue_sending(&mut self, now_us: u64) -> Option<Event> {
+        let sending = self.sending.as_mut()?;
+        if let Some(sent_at) = sending.sent_at_us {
+            if now_us < sent_at + ACK_TIMEOUT_US {
+                return None;
+            }
+        }
+        if sending.tries >= MAX_TRIES {
+            self.sending = None;
+            return Some(Event::SendFailed);
+        }
+        sending.sent_at_us = Some(now_us);
+        sending.tries += 1;
+
+        let seq = sending.seq;
+        let (kind, payload) = if seq < sending.chunks() {
+            let start = seq as usize * MAX_PAYLOAD;
+            let end = (start + MAX_PAYLOAD).min(sending.data.len());
+            (DATA, sending.data[start..end].to_vec())
+        } else {
+            let mut end = Vec::with_capacity(8);
+            end.extend_from_slice(&(sending.data.len() as u32).to_le_bytes());
+            end.extend_from_slice(&crc32(&sending.data).to_le_bytes());
+            (END, end)
+        };
+        self.write_frame(kind, seq, &payload);
+        None
+    }
+
+    /// Takes the next valid frame off the front of `rx`, skipping anything
+    /// corrupt.
+    fn take_frame(&mut self) -> Option<(u8, u16, Vec<u8>)> {
+        loop {
+            let start = match self.rx.iter().position(|&b| b == MAGIC) {
+                Some(start) => start,
+                None => {
+                    self.rx.clear();
+                    return None;
+                }
+            };
+            self.rx.drain(..start);
+            if self.rx.len() < HEADER_LEN {
+                return None;
+            }
+
+            let len = u16::from_le_bytes([self.rx[4], self.rx[5]]) as usize;
+            if len > MAX_PAYLOAD {
+                self.rx.remove(0);
+                continue;
+            }
+            let frame_len = HEADER_LEN + len + CRC_LEN;
+            if self.rx.len() < frame_len {
+                return None;
+            }
+
+            let body = &self.rx[1..HEADER_LEN + len];
+            let crc = &self.rx[HEADER_LEN + len..frame_len];
+            if crc32(body).to_le_bytes() != crc {
+                self.rx.remove(0);
+                continue;
+            }
+
+            let kind = self.rx[1];
+            let seq = u16::from_le_bytes([self.rx[2], self.rx[3]]);
+            let payload = self.rx[HEADER_LEN..HEADER_LEN + len].to_vec();
+            self.rx.drain(..frame_len);
+            return Some((kind, seq, payload));
+        }
+    }
+
+    fn write_frame(&mut self, kind: u8, seq: u16, payload: &[u8]) {
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+        frame.push(MAGIC);
+        frame.push(kind);
+        frame.extend_from_slice(&seq.to_le_bytes());
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame.extend_from_slice(payload);
+        let crc = crc32(&frame[1..]);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        // Skipped whole if the buffer's full, in which case the host never
+        // sees it and we resend after the timeout
+        self.up.write(&frame);
+    }
+}
+
+/// CRC-32 as used by zip and ethernet
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,23 @@
+//! An SD card on SPI0, for deployments that log more than the soldered
+//! flash holds, or where it's easier to swap cards than to download.
+//!
+//! Enable the `sd-card` feature and wire the card's socket to GP2 (SCK),
+//! GP3 (TX to the card's DI), GP0 (RX from its DO) and GP1 (CS).
+//!
+//! Cards have to be initialized with the clock at 400kHz or less, and we
+//! stay there, which is still about 50KB/s. [`SdCard::acquire`] initializes
+//! the card and gives an `embedded_sdmmc::BlockDevice` for it, so a missing
+//! card only shows up then.
+
+use rp_pico::{
+    hal::{
+        gpio::{bank0::Gpio1, Pin, PushPullOutput},
+        spi::{self, Spi},
+    },
+    pac::SPI0,
+};
+
+/// Fastest clock cards accept before they're initialized
+pub(crate) const INIT_BAUD_HZ: u32 = 400_000;
+
+pub type SdCard = embedded_sdmmc::SdMmcSpi<Spi<spi::Enabled, SPI0, 8>, Pin<Gpio1, PushPullOutput>>;
@@ -0,0 +1,236 @@
+//! A driver for W25Qxx-style SPI NOR flash, for archiving tracks on the
+//! device. LOCUS only holds a couple of days, and the RP2040's own flash
+//! is mostly firmware.
+//!
+//! The chip is erased in 4KB sectors and programmed in pages of up to 256
+//! bytes, and programming can only clear bits, so a sector must be erased
+//! before it's written again. [`SpiFlash`] implements `embedded-storage`'s
+//! `NorFlash` on top of that.
+
+use core::fmt::Debug;
+use embedded_hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS_1: u8 = 0x05;
+const CMD_READ_DATA: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_JEDEC_ID: u8 = 0x9F;
+const CMD_RELEASE_POWER_DOWN: u8 = 0xAB;
+/// Set in status register 1 while programming or erasing
+const STATUS_BUSY: u8 = 0x01;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError<E> {
+    Spi(E),
+    /// Nothing answered the JEDEC ID, or it gave a size we don't understand
+    NotPresent,
+    NotAligned,
+    OutOfBounds,
+}
+
+impl<E: Debug> NorFlashError for FlashError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Spi(_) | Self::NotPresent => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// What the chip says it is.
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JedecId {
+    /// 0xEF for Winbond
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    /// The size is 2 to the power of this in bytes
+    pub capacity: u8,
+}
+
+pub struct SpiFlash<Spi, Cs> {
+    spi: Spi,
+    cs: Cs,
+    capacity: usize,
+}
+
+impl<Spi, Cs, E> SpiFlash<Spi, Cs>
+where
+    Spi: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    Cs: OutputPin,
+    Cs::Error: Debug,
+{
+    /// Wakes the chip in case it was powered down, and reads its size from
+    /// its JEDEC ID. Fails with [`FlashError::NotPresent`] if there isn't
+    /// one.
+    pub fn new(spi: Spi, mut cs: Cs) -> Result<Self, FlashError<E>> {
+        cs.set_high().unwrap();
+        let mut flash = Self {
+            spi,
+            cs,
+            capacity: 0,
+        };
+        flash.command(&[CMD_RELEASE_POWER_DOWN], &mut [])?;
+        // It ignores commands for 3us while waking, about 400 cycles at our
+        // 125MHz clock
+        cortex_m::asm::delay(1_000);
+
+        let id = flash.jedec_id()?;
+        // Anything from 64KB to 16MB, as we only send 24 bit addresses
+        if !(16..=24).contains(&id.capacity) || id.manufacturer == 0xFF {
+            return Err(FlashError::NotPresent);
+        }
+        flash.capacity = 1 << id.capacity;
+        defmt::info!("SPI flash {} ({} bytes)", id, flash.capacity);
+        Ok(flash)
+    }
+
+    pub fn jedec_id(&mut self) -> Result<JedecId, FlashError<E>> {
+        let mut id = [0; 3];
+        self.command(&[CMD_JEDEC_ID], &mut id)?;
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    /// Program at most a page, which mustn't cross into the next one, as the
+    /// chip would wrap around to the start of the page.
+    pub fn program_page(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError<E>> {
+        let page_offset = offset as usize % PAGE_SIZE;
+        if page_offset + bytes.len() > PAGE_SIZE {
+            return Err(FlashError::NotAligned);
+        }
+        self.check_bounds(offset, bytes.len())?;
+
+        self.command(&[CMD_WRITE_ENABLE], &mut [])?;
+        self.cs.set_low().unwrap();
+        let result = self
+            .spi
+            .write(&address_cmd(CMD_PAGE_PROGRAM, offset))
+            .and_then(|()| self.spi.write(bytes));
+        self.cs.set_high().unwrap();
+        result.map_err(FlashError::Spi)?;
+        self.wait_while_busy()
+    }
+
+    /// Erase the 4KB sector `offset` is the start of. Takes up to 400ms.
+    pub fn erase_sector(&mut self, offset: u32) -> Result<(), FlashError<E>> {
+        if offset as usize % SECTOR_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        self.check_bounds(offset, SECTOR_SIZE)?;
+
+        self.command(&[CMD_WRITE_ENABLE], &mut [])?;
+        self.command(&address_cmd(CMD_SECTOR_ERASE, offset), &mut [])?;
+        self.wait_while_busy()
+    }
+
+    fn wait_while_busy(&mut self) -> Result<(), FlashError<E>> {
+        loop {
+            let mut status = [0];
+            self.command(&[CMD_READ_STATUS_1], &mut status)?;
+            if status[0] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn check_bounds(&self, offset: u32, len: usize) -> Result<(), FlashError<E>> {
+        if offset as usize + len > self.capacity {
+            return Err(FlashError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Send `cmd`, then read into `response`.
+    fn command(&mut self, cmd: &[u8], response: &mut [u8]) -> Result<(), FlashError<E>> {
+        self.cs.set_low().unwrap();
+        let result = self.spi.write(cmd).and_then(|()| {
+            // Clocks out zeros as it reads
+            response.fill(0);
+            self.spi.transfer(response).map(|_| ())
+        });
+        self.cs.set_high().unwrap();
+        result.map_err(FlashError::Spi)
+    }
+}
+
+/// `cmd` followed by a 24 bit address, which covers chips up to 16MB.
+fn address_cmd(cmd: u8, offset: u32) -> [u8; 4] {
+    let [_, a2, a1, a0] = offset.to_be_bytes();
+    [cmd, a2, a1, a0]
+}
+
+impl<Spi, Cs, E> ErrorType for SpiFlash<Spi, Cs>
+where
+    Spi: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    E: Debug,
+{
+    type Error = FlashError<E>;
+}
+
+impl<Spi, Cs, E> ReadNorFlash for SpiFlash<Spi, Cs>
+where
+    Spi: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    Cs: OutputPin,
+    Cs::Error: Debug,
+    E: Debug,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, bytes.len())?;
+        self.command(&address_cmd(CMD_READ_DATA, offset), bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<Spi, Cs, E> NorFlash for SpiFlash<Spi, Cs>
+where
+    Spi: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    Cs: OutputPin,
+    Cs::Error: Debug,
+    E: Debug,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    /// Erases a sector at a time, each taking up to 400ms, so erase a few at
+    /// a time and feed the watchdog in between.
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to || to as usize % SECTOR_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        for sector in (from..to).step_by(SECTOR_SIZE) {
+            self.erase_sector(sector)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, mut offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, bytes.len())?;
+        while !bytes.is_empty() {
+            let room = PAGE_SIZE - offset as usize % PAGE_SIZE;
+            let (page, rest) = bytes.split_at(room.min(bytes.len()));
+            self.program_page(offset, page)?;
+            offset += page.len() as u32;
+            bytes = rest;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! The USB port, which is a serial port unless the button is held while
+//! booting, when it's a drive of files the app attaches instead.
+
+use crate::{fat_volume::Files, FatVolume, UsbSerial, UsbStorage};
+use alloc::boxed::Box;
+use rp_pico::{
+    hal::{clocks::UsbClock, usb::UsbBus},
+    pac::{self, RESETS},
+};
+use usb_device::class_prelude::UsbBusAllocator;
+
+pub enum Usb {
+    Serial(UsbSerial),
+    Storage(UsbStorage<FatVolume>),
+}
+
+impl Usb {
+    /// Call from USBCTRL_IRQ.
+    pub fn poll(&mut self) {
+        match self {
+            Self::Serial(serial) => serial.poll(),
+            Self::Storage(storage) => storage.poll(),
+        }
+    }
+
+    /// See [`FatVolume::attach`]. Does nothing unless we're a drive.
+    pub fn attach_files(&mut self, files: Box<dyn Files + Send>) {
+        if let Self::Storage(storage) = self {
+            storage.blocks_mut().attach(files);
+        }
+    }
+
+    /// See [`UsbStorage::flush`]. Call from idle.
+    pub fn flush(&mut self) {
+        if let Self::Storage(storage) = self {
+            storage.flush();
+        }
+    }
+
+    /// See [`UsbSerial::read`]. Nothing is read while we're a drive.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        match self {
+            Self::Serial(serial) => serial.read(buf),
+            Self::Storage(_) => 0,
+        }
+    }
+
+    /// See [`UsbSerial::write`]. Nothing is written while we're a drive.
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        match self {
+            Self::Serial(serial) => serial.write(bytes),
+            Self::Storage(_) => 0,
+        }
+    }
+}
+
+/// Can only be called once.
+pub(crate) fn bus(
+    regs: pac::USBCTRL_REGS,
+    dpram: pac::USBCTRL_DPRAM,
+    clock: UsbClock,
+    resets: &mut RESETS,
+) -> &'static UsbBusAllocator<UsbBus> {
+    cortex_m::singleton!(
+        : UsbBusAllocator<UsbBus> =
+            UsbBusAllocator::new(UsbBus::new(regs, dpram, clock, true, resets))
+    )
+    .unwrap()
+}
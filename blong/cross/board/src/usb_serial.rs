@@ -0,0 +1,113 @@
+//! A serial port over USB (CDC-ACM), for downloading logs and a console
+//! without a debug probe.
+//!
+//! USBCTRL_IRQ must call [`UsbSerial::poll`], which moves data between the
+//! USB peripheral and a buffer each way. Everything else reads and writes
+//! the buffers, so never waits on the host.
+
+use cortex_m::peripheral::NVIC;
+use heapless::Deque;
+use rp_pico::{hal::usb::UsbBus, pac::Interrupt};
+use usb_device::{
+    class_prelude::UsbBusAllocator,
+    device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+};
+use usbd_serial::SerialPort;
+
+const BUF_LEN: usize = 1024;
+/// pid.codes' shared VID and PID for CDC-ACM devices
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+
+pub struct UsbSerial {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+    rx: Deque<u8, BUF_LEN>,
+    tx: Deque<u8, BUF_LEN>,
+}
+
+impl UsbSerial {
+    pub(crate) fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        // The serial port has to be allocated before the device is built
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("blong")
+            .product("blong gps logger")
+            .serial_number("0")
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .build();
+
+        Self {
+            device,
+            serial,
+            rx: Deque::new(),
+            tx: Deque::new(),
+        }
+    }
+
+    /// Call from USBCTRL_IRQ.
+    pub fn poll(&mut self) {
+        if self.device.poll(&mut [&mut self.serial]) {
+            let mut buf = [0; 64];
+            while let Ok(count) = self.serial.read(&mut buf) {
+                if count == 0 {
+                    break;
+                }
+                for &byte in &buf[..count] {
+                    // Dropped if nobody's reading, like a UART overrunning
+                    let _ = self.rx.push_back(byte);
+                }
+            }
+        }
+        self.send();
+    }
+
+    fn send(&mut self) {
+        while !self.tx.is_empty() {
+            let (queued, _) = self.tx.as_slices();
+            let written = match self.serial.write(queued) {
+                Ok(written) => written,
+                // Full until the host next reads, which raises the interrupt
+                Err(_) => return,
+            };
+            for _ in 0..written {
+                self.tx.pop_front();
+            }
+        }
+    }
+
+    /// Whether the host has set us up, so there's anyone to hear writes.
+    pub fn is_connected(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured
+    }
+
+    /// Take what the host has sent, returning how many bytes were read.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        for slot in buf {
+            match self.rx.pop_front() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Queue `bytes` for the host, returning how many fit. Nothing is
+    /// queued while no host is connected.
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        if !self.is_connected() {
+            return 0;
+        }
+        let mut count = 0;
+        for &byte in bytes {
+            if self.tx.push_back(byte).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        // Get USBCTRL_IRQ to send it
+        NVIC::pend(Interrupt::USBCTRL_IRQ);
+        count
+    }
+}
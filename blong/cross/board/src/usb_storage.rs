@@ -0,0 +1,601 @@
+//! A USB drive (mass storage, bulk-only transport), so a host can copy
+//! files off without any tools of ours. What it holds is up to [`Blocks`],
+//! see [`crate::FatVolume`] and [`FlashBlocks`].
+//!
+//! The host speaks SCSI, we answer the handful of commands Linux, macOS and
+//! Windows need for a disk of 512 byte blocks. Like [`crate::UsbSerial`],
+//! USBCTRL_IRQ must call [`UsbStorage::poll`], which does all the work bar
+//! writing. Erasing flash takes tens of milliseconds a sector, too long for
+//! an interrupt, so writes are kept until idle calls [`UsbStorage::flush`].
+//! The host is held off meanwhile.
+
+use embedded_storage::nor_flash::NorFlash;
+use rp_pico::hal::usb::UsbBus;
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, RequestType},
+    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+};
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// pid.codes' test VID and PID, which is only meant for development
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+const PACKET_SIZE: usize = 64;
+/// The largest flash sector [`FlashBlocks`] can rewrite
+const MAX_SECTOR_SIZE: usize = 4096;
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+const REQ_GET_MAX_LUN: u8 = 0xFE;
+const REQ_BULK_ONLY_RESET: u8 = 0xFF;
+
+/// Command and status wrappers, see the bulk-only transport spec
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_LEN: usize = 13;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1A;
+const SCSI_PREVENT_ALLOW_REMOVAL: u8 = 0x1E;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2A;
+const SCSI_VERIFY_10: u8 = 0x2F;
+const SCSI_SYNCHRONIZE_CACHE_10: u8 = 0x35;
+
+/// Sense keys and additional sense codes, for why the last command failed
+const SENSE_NONE: (u8, u8) = (0x00, 0x00);
+const SENSE_NO_MEDIUM: (u8, u8) = (0x02, 0x3A);
+const SENSE_MEDIUM_ERROR: (u8, u8) = (0x03, 0x11);
+const SENSE_INVALID_COMMAND: (u8, u8) = (0x05, 0x20);
+const SENSE_OUT_OF_RANGE: (u8, u8) = (0x05, 0x21);
+const SENSE_MEDIUM_CHANGED: (u8, u8) = (0x06, 0x28);
+const SENSE_WRITE_PROTECTED: (u8, u8) = (0x07, 0x27);
+
+/// Storage the host sees as a disk of [`BLOCK_SIZE`] blocks.
+pub trait Blocks {
+    fn block_count(&self) -> u32;
+    /// Whether there's a medium, otherwise the host is told there isn't
+    /// until there is.
+    fn is_ready(&self) -> bool {
+        true
+    }
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    /// Returns whether it worked.
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> bool;
+    /// Whether [`Self::write_block`] can take `lba` before the next
+    /// [`Self::flush`].
+    fn can_write(&self, lba: u32) -> bool;
+    /// Keeps `block` for [`Self::flush`] to write. Only called if
+    /// [`Self::can_write`].
+    fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]);
+    /// Whether there are blocks [`Self::flush`] hasn't written yet.
+    fn is_dirty(&self) -> bool;
+    /// Writes the blocks kept by [`Self::write_block`]. Returns whether it
+    /// worked.
+    fn flush(&mut self) -> bool;
+}
+
+/// NOR flash as blocks. Flash is erased a sector at a time, so writes are
+/// kept until the host moves on to another sector, then the rest of the
+/// sector is read in, erased and programmed back.
+pub struct FlashBlocks<F> {
+    flash: F,
+    sector: [u8; MAX_SECTOR_SIZE],
+    /// The offset of the sector in `sector`, if it has blocks to write
+    pending: Option<u32>,
+    /// Which of its blocks were written, a bit each as there are at most 8
+    dirty: u8,
+}
+
+impl<F: NorFlash> FlashBlocks<F> {
+    pub fn new(flash: F) -> Self {
+        assert!(F::ERASE_SIZE <= MAX_SECTOR_SIZE && F::ERASE_SIZE % BLOCK_SIZE == 0);
+        Self {
+            flash,
+            sector: [0; MAX_SECTOR_SIZE],
+            pending: None,
+            dirty: 0,
+        }
+    }
+
+    /// The offset of the sector holding `lba`, and the block's index in it
+    fn locate(lba: u32) -> (u32, usize) {
+        let offset = lba as usize * BLOCK_SIZE;
+        let start = offset / F::ERASE_SIZE * F::ERASE_SIZE;
+        (start as u32, (offset - start) / BLOCK_SIZE)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: NorFlash> Blocks for FlashBlocks<F> {
+    fn block_count(&self) -> u32 {
+        (self.flash.capacity() / BLOCK_SIZE) as u32
+    }
+
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> bool {
+        let (start, index) = Self::locate(lba);
+        if self.pending == Some(start) && self.dirty & (1 << index) != 0 {
+            block.copy_from_slice(&self.sector[index * BLOCK_SIZE..][..BLOCK_SIZE]);
+            return true;
+        }
+        self.flash.read(lba * BLOCK_SIZE as u32, block).is_ok()
+    }
+
+    fn can_write(&self, lba: u32) -> bool {
+        let (start, _) = Self::locate(lba);
+        self.pending.map_or(true, |pending| pending == start)
+    }
+
+    fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) {
+        let (start, index) = Self::locate(lba);
+        self.pending = Some(start);
+        self.dirty |= 1 << index;
+        self.sector[index * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(block);
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn flush(&mut self) -> bool {
+        let start = match self.pending.take() {
+            Some(start) => start,
+            None => return true,
+        };
+        let dirty = core::mem::take(&mut self.dirty);
+        let Self { flash, sector, .. } = self;
+        let sector = &mut sector[..F::ERASE_SIZE];
+
+        let mut changed = false;
+        for (i, block) in sector.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            let offset = start + (i * BLOCK_SIZE) as u32;
+            if dirty & (1 << i) == 0 {
+                if flash.read(offset, block).is_err() {
+                    return false;
+                }
+            } else if !changed {
+                // Hosts often rewrite blocks unchanged, skip wearing the flash
+                let mut old = [0; BLOCK_SIZE];
+                if flash.read(offset, &mut old).is_err() {
+                    return false;
+                }
+                changed = old[..] != block[..];
+            }
+        }
+        if !changed {
+            return true;
+        }
+        let end = start + F::ERASE_SIZE as u32;
+        flash.erase(start, end).is_ok() && flash.write(start, sector).is_ok()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Waiting for a command
+    Idle,
+    /// Sending the first `len` bytes of `response`
+    Respond { len: usize },
+    /// Sending `block`, `sent` bytes in, then `remaining` more blocks from
+    /// `lba`
+    Read {
+        lba: u32,
+        remaining: u32,
+        sent: usize,
+    },
+    /// Receiving into `block`, `received` bytes in, to write to `lba`
+    Write {
+        lba: u32,
+        remaining: u32,
+        received: usize,
+    },
+    /// Waiting for [`UsbStorage::flush`] to write everything out
+    Flushing,
+    /// Waiting for the host to clear the stall ending a failed command's
+    /// data early, with CLEAR_FEATURE, which usb-device handles
+    Stalled,
+    /// Sending the command's status
+    Status,
+}
+
+/// The mass storage class, which usb-device calls for control requests. The
+/// bulk endpoints are all ours, and [`MassStorage::process`] drives them.
+struct MassStorage<'a, B: Blocks> {
+    blocks: B,
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, UsbBus>,
+    ep_out: EndpointOut<'a, UsbBus>,
+    state: State,
+    /// From the current command
+    tag: [u8; 4],
+    expected_len: u32,
+    /// Whether the host expects data from us rather than to send it
+    data_in: bool,
+    transferred: u32,
+    failed: bool,
+    /// Set by [`UsbStorage::flush`], for the command waiting on it
+    flush_failed: bool,
+    /// Whether the host has been told there's a medium, to tell it when one
+    /// arrives
+    was_ready: bool,
+    /// Why the last command failed, for REQUEST SENSE
+    sense: (u8, u8),
+    response: [u8; PACKET_SIZE],
+    block: [u8; BLOCK_SIZE],
+}
+
+impl<'a, B: Blocks> MassStorage<'a, B> {
+    fn new(bus: &'a UsbBusAllocator<UsbBus>, blocks: B) -> Self {
+        Self {
+            blocks,
+            interface: bus.interface(),
+            ep_in: bus.bulk(PACKET_SIZE as u16),
+            ep_out: bus.bulk(PACKET_SIZE as u16),
+            state: State::Idle,
+            tag: [0; 4],
+            expected_len: 0,
+            data_in: false,
+            transferred: 0,
+            failed: false,
+            flush_failed: false,
+            was_ready: true,
+            sense: SENSE_NONE,
+            response: [0; PACKET_SIZE],
+            block: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Move things along as far as the endpoints allow.
+    fn process(&mut self) {
+        while self.step() {}
+    }
+
+    /// Returns whether anything happened.
+    fn step(&mut self) -> bool {
+        match self.state {
+            State::Idle => {
+                let mut cbw = [0; PACKET_SIZE];
+                match self.ep_out.read(&mut cbw) {
+                    Ok(len) => {
+                        self.command(&cbw[..len]);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            State::Respond { len } => {
+                if self.ep_in.write(&self.response[..len]).is_err() {
+                    return false;
+                }
+                self.transferred = len as u32;
+                self.state = State::Status;
+                true
+            }
+            State::Read {
+                lba,
+                remaining,
+                sent,
+            } => {
+                let packet = &self.block[sent..sent + PACKET_SIZE];
+                if self.ep_in.write(packet).is_err() {
+                    return false;
+                }
+                self.transferred += PACKET_SIZE as u32;
+                let sent = sent + PACKET_SIZE;
+                if sent < BLOCK_SIZE {
+                    self.state = State::Read {
+                        lba,
+                        remaining,
+                        sent,
+                    };
+                } else if remaining == 0 {
+                    self.state = State::Status;
+                } else {
+                    self.start_read(lba, remaining);
+                }
+                true
+            }
+            State::Write {
+                lba,
+                remaining,
+                received,
+            } => {
+                // Leaving the packet unread holds the host off until then
+                if received == 0 && !self.blocks.can_write(lba) {
+                    return false;
+                }
+                let len = match self.ep_out.read(&mut self.block[received..]) {
+                    Ok(len) => len,
+                    Err(_) => return false,
+                };
+                self.transferred += len as u32;
+                let received = received + len;
+                if received < BLOCK_SIZE {
+                    self.state = State::Write {
+                        lba,
+                        remaining,
+                        received,
+                    };
+                    return true;
+                }
+                self.blocks.write_block(lba, &self.block);
+                if remaining == 1 {
+                    self.state = State::Flushing;
+                } else {
+                    self.state = State::Write {
+                        lba: lba + 1,
+                        remaining: remaining - 1,
+                        received: 0,
+                    };
+                }
+                true
+            }
+            State::Flushing => {
+                if self.blocks.is_dirty() {
+                    return false;
+                }
+                if core::mem::take(&mut self.flush_failed) {
+                    self.fail(SENSE_MEDIUM_ERROR);
+                } else {
+                    self.pass();
+                }
+                true
+            }
+            State::Stalled => {
+                let stalled = if self.data_in {
+                    self.ep_in.is_stalled()
+                } else {
+                    self.ep_out.is_stalled()
+                };
+                if stalled {
+                    return false;
+                }
+                self.state = State::Status;
+                true
+            }
+            State::Status => {
+                let mut csw = [0; CSW_LEN];
+                csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+                csw[4..8].copy_from_slice(&self.tag);
+                let residue = self.expected_len.saturating_sub(self.transferred);
+                csw[8..12].copy_from_slice(&residue.to_le_bytes());
+                csw[12] = self.failed as u8;
+                if self.ep_in.write(&csw).is_err() {
+                    return false;
+                }
+                self.state = State::Idle;
+                true
+            }
+        }
+    }
+
+    fn command(&mut self, cbw: &[u8]) {
+        if cbw.len() != CBW_LEN || cbw[0..4] != CBW_SIGNATURE.to_le_bytes() {
+            defmt::warn!("Ignoring invalid mass storage command");
+            return;
+        }
+        self.tag.copy_from_slice(&cbw[4..8]);
+        self.expected_len = u32::from_le_bytes([cbw[8], cbw[9], cbw[10], cbw[11]]);
+        self.data_in = cbw[12] & 0x80 != 0;
+        self.transferred = 0;
+        self.failed = false;
+        self.flush_failed = false;
+        let cb = &cbw[15..];
+
+        let ready = self.blocks.is_ready();
+        match cb[0] {
+            SCSI_TEST_UNIT_READY if !ready => {
+                self.was_ready = false;
+                self.fail(SENSE_NO_MEDIUM);
+            }
+            // So the host reads it afresh
+            SCSI_TEST_UNIT_READY if !self.was_ready => {
+                self.was_ready = true;
+                self.fail(SENSE_MEDIUM_CHANGED);
+            }
+            SCSI_READ_CAPACITY_10 | SCSI_READ_10 | SCSI_WRITE_10 if !ready => {
+                self.fail(SENSE_NO_MEDIUM);
+            }
+            SCSI_WRITE_10 if self.blocks.is_read_only() => self.fail(SENSE_WRITE_PROTECTED),
+            SCSI_TEST_UNIT_READY | SCSI_PREVENT_ALLOW_REMOVAL | SCSI_VERIFY_10 => self.pass(),
+            SCSI_SYNCHRONIZE_CACHE_10 => self.state = State::Flushing,
+            SCSI_REQUEST_SENSE => {
+                let (key, asc) = core::mem::replace(&mut self.sense, SENSE_NONE);
+                let mut sense = [0; 18];
+                // Current error, fixed format
+                sense[0] = 0x70;
+                sense[2] = key;
+                sense[7] = 10;
+                sense[12] = asc;
+                self.respond(&sense);
+            }
+            SCSI_INQUIRY => {
+                let mut inquiry = [0; 36];
+                // Removable direct access device, SPC-2
+                inquiry[1] = 0x80;
+                inquiry[2] = 0x04;
+                inquiry[3] = 0x02;
+                inquiry[4] = 31;
+                inquiry[8..16].copy_from_slice(b"blong   ");
+                inquiry[16..32].copy_from_slice(b"gps logger      ");
+                inquiry[32..36].copy_from_slice(b"0.1 ");
+                self.respond(&inquiry);
+            }
+            // No mode pages, just whether it's write protected
+            SCSI_MODE_SENSE_6 => {
+                let protected = if self.blocks.is_read_only() { 0x80 } else { 0 };
+                self.respond(&[3, 0, protected, 0]);
+            }
+            SCSI_READ_CAPACITY_10 => {
+                let last_lba = self.blocks.block_count().saturating_sub(1);
+                let mut capacity = [0; 8];
+                capacity[0..4].copy_from_slice(&last_lba.to_be_bytes());
+                capacity[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.respond(&capacity);
+            }
+            SCSI_READ_10 | SCSI_WRITE_10 => {
+                let lba = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]);
+                let count = u16::from_be_bytes([cb[7], cb[8]]) as u32;
+                if lba as u64 + count as u64 > self.blocks.block_count() as u64 {
+                    self.fail(SENSE_OUT_OF_RANGE);
+                } else if count == 0 {
+                    self.pass();
+                } else if cb[0] == SCSI_READ_10 {
+                    self.start_read(lba, count);
+                } else {
+                    self.state = State::Write {
+                        lba,
+                        remaining: count,
+                        received: 0,
+                    };
+                }
+            }
+            op => {
+                defmt::debug!("Unsupported SCSI command {=u8:#x}", op);
+                self.fail(SENSE_INVALID_COMMAND);
+            }
+        }
+    }
+
+    /// Load block `lba` and start sending it.
+    fn start_read(&mut self, lba: u32, count: u32) {
+        if !self.blocks.read_block(lba, &mut self.block) {
+            self.fail(SENSE_MEDIUM_ERROR);
+            return;
+        }
+        self.state = State::Read {
+            lba: lba + 1,
+            remaining: count - 1,
+            sent: 0,
+        };
+    }
+
+    /// Send `response`, cut short if the host asked for less.
+    fn respond(&mut self, response: &[u8]) {
+        let len = response.len().min(self.expected_len as usize);
+        if len == 0 {
+            self.pass();
+            return;
+        }
+        self.response[..len].copy_from_slice(&response[..len]);
+        self.state = State::Respond { len };
+    }
+
+    fn pass(&mut self) {
+        self.state = State::Status;
+    }
+
+    /// If the host was expecting more data, the endpoint it's on is stalled
+    /// first, so the status isn't mistaken for data.
+    fn fail(&mut self, sense: (u8, u8)) {
+        self.sense = sense;
+        self.failed = true;
+        if self.transferred < self.expected_len {
+            if self.data_in {
+                self.ep_in.stall();
+            } else {
+                self.ep_out.stall();
+            }
+            self.state = State::Stalled;
+        } else {
+            self.state = State::Status;
+        }
+    }
+}
+
+impl<B: Blocks> UsbClass<UsbBus> for MassStorage<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(
+            self.interface,
+            CLASS_MASS_STORAGE,
+            SUBCLASS_SCSI,
+            PROTOCOL_BULK_ONLY,
+        )?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<UsbBus>) {
+        let req = xfer.request();
+        if req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface) as u16
+            && req.request == REQ_GET_MAX_LUN
+        {
+            // Just the one disk
+            xfer.accept_with(&[0]).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<UsbBus>) {
+        let req = xfer.request();
+        if req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface) as u16
+            && req.request == REQ_BULK_ONLY_RESET
+        {
+            self.state = State::Idle;
+            xfer.accept().ok();
+        }
+    }
+}
+
+pub struct UsbStorage<B: Blocks> {
+    device: UsbDevice<'static, UsbBus>,
+    storage: MassStorage<'static, B>,
+}
+
+impl<B: Blocks> UsbStorage<B> {
+    pub(crate) fn new(bus: &'static UsbBusAllocator<UsbBus>, blocks: B) -> Self {
+        // The class has to be allocated before the device is built
+        let storage = MassStorage::new(bus, blocks);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("blong")
+            .product("blong gps logger")
+            .serial_number("0")
+            .build();
+        Self { device, storage }
+    }
+
+    /// Call from USBCTRL_IRQ.
+    pub fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.storage]);
+        self.storage.process();
+    }
+
+    pub fn blocks_mut(&mut self) -> &mut B {
+        &mut self.storage.blocks
+    }
+
+    /// Writes out what the host wrote, then carries on with the command
+    /// that was waiting on it. Call from idle, as it can take as long as
+    /// erasing a flash sector.
+    pub fn flush(&mut self) {
+        if !self.storage.blocks.is_dirty() {
+            return;
+        }
+        if !self.storage.blocks.flush() {
+            self.storage.flush_failed = true;
+        }
+        self.storage.process();
+    }
+}
@@ -0,0 +1,119 @@
+//! The watchdog, started by [`crate::Board::init`] with a [`WatchdogConfig`].
+//!
+//! It counts down on the same tick as the timer, so it can't be paused in
+//! sleep by the hardware. [`Watchdog::sleep`] disables it around `wfe`
+//! instead, when asked to. It's paused anyway while dormant, as the tick
+//! stops with the clocks.
+
+use embedded_hal::watchdog::{Watchdog as _, WatchdogDisable as _, WatchdogEnable as _};
+use embedded_time::duration::Extensions as _;
+use rp_pico::{hal, pac};
+
+/// The most the counter holds, as it ticks down twice per microsecond
+/// (erratum RP2040-E1)
+pub const MAX_TIMEOUT_US: u32 = 0xff_ffff / 2;
+
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// How long it waits to be fed before resetting, at most
+    /// [`MAX_TIMEOUT_US`]
+    pub timeout_us: u32,
+    /// Stop counting while a debugger has halted us
+    pub pause_on_debug: bool,
+    /// Stop counting in [`Watchdog::sleep`]. A hang in an interrupt while
+    /// idle sleeps then goes unnoticed.
+    pub pause_in_sleep: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            timeout_us: 1_050_000,
+            pause_on_debug: true,
+            pause_in_sleep: false,
+        }
+    }
+}
+
+/// Restarts the countdown from `timeout_us` by writing LOAD directly, like
+/// [`Watchdog::feed`] does, for code that can't borrow the [`Watchdog`].
+/// `timeout_us` must match what it was started with.
+pub(crate) fn feed_directly(timeout_us: u32) {
+    // SAFETY: Writing LOAD only restarts the countdown
+    let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+    // The counter ticks down twice per microsecond (erratum RP2040-E1),
+    // which the hal compensates for in the same way
+    watchdog.load.write(|w| unsafe { w.bits(timeout_us * 2) });
+}
+
+pub struct Watchdog {
+    inner: hal::Watchdog,
+    config: WatchdogConfig,
+}
+
+impl Watchdog {
+    pub(crate) fn new(watchdog: pac::WATCHDOG, config: WatchdogConfig) -> Self {
+        assert!(config.timeout_us <= MAX_TIMEOUT_US);
+        let mut watchdog = Self {
+            inner: hal::Watchdog::new(watchdog),
+            config,
+        };
+        watchdog.start();
+        watchdog
+    }
+
+    /// For setting up the clocks, which starts the tick it counts down on
+    pub(crate) fn hal(&mut self) -> &mut hal::Watchdog {
+        &mut self.inner
+    }
+
+    pub fn config(&self) -> WatchdogConfig {
+        self.config
+    }
+
+    /// Also sets the pause bits, as the hal's `start` may overwrite them.
+    fn start(&mut self) {
+        self.inner.start(self.config.timeout_us.microseconds());
+        let pause = self.config.pause_on_debug;
+        // SAFETY: Only the pause bits, and `inner` is ours so nothing else
+        // writes CTRL meanwhile
+        let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+        watchdog.ctrl.modify(|_, w| {
+            w.pause_dbg0()
+                .bit(pause)
+                .pause_dbg1()
+                .bit(pause)
+                .pause_jtag()
+                .bit(pause)
+        });
+    }
+
+    pub fn feed(&mut self) {
+        self.inner.feed();
+    }
+
+    /// Stop it for good, for when nothing will be around to feed it.
+    pub fn disable(&mut self) {
+        self.inner.disable();
+    }
+
+    /// How long until it resets us, in microseconds.
+    pub fn remaining_us(&self) -> u32 {
+        // SAFETY: Only read
+        let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+        watchdog.ctrl.read().time().bits() / 2
+    }
+
+    /// Wait for an event, pausing the watchdog if
+    /// [`WatchdogConfig::pause_in_sleep`] is set. Feeds it after waking.
+    pub fn sleep(&mut self) {
+        if self.config.pause_in_sleep {
+            self.inner.disable();
+            cortex_m::asm::wfe();
+            self.start();
+        } else {
+            cortex_m::asm::wfe();
+            self.feed();
+        }
+    }
+}
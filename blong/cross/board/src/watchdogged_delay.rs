@@ -0,0 +1,54 @@
+use crate::watchdog;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+/// Wraps a delay to feed the watchdog every so often while delaying.
+///
+/// The gps driver retries for several seconds when the gps is slow to
+/// answer, which would otherwise trip the watchdog while it's blocked.
+///
+/// Feeds by writing the watchdog's LOAD register directly, like
+/// `Watchdog::feed` does, so the app can keep its own `Watchdog`.
+pub struct WatchdoggedDelay<D> {
+    inner: D,
+    period_us: u32,
+    /// Longest we delay between feeds
+    chunk_us: u32,
+}
+
+impl<D> WatchdoggedDelay<D> {
+    /// `period_us` must match what the watchdog was started with.
+    pub fn new(inner: D, period_us: u32) -> Self {
+        Self {
+            inner,
+            period_us,
+            // Half, leaving the rest for whatever runs between delays
+            chunk_us: (period_us / 2).max(1),
+        }
+    }
+
+    fn feed(&self) {
+        watchdog::feed_directly(self.period_us);
+    }
+}
+
+impl<D: DelayUs<u32>> DelayUs<u32> for WatchdoggedDelay<D> {
+    fn delay_us(&mut self, mut us: u32) {
+        while us > self.chunk_us {
+            self.inner.delay_us(self.chunk_us);
+            self.feed();
+            us -= self.chunk_us;
+        }
+        self.inner.delay_us(us);
+        self.feed();
+    }
+}
+
+impl<D: DelayUs<u32>> DelayMs<u32> for WatchdoggedDelay<D> {
+    fn delay_ms(&mut self, ms: u32) {
+        // Split so large delays don't overflow
+        for _ in 0..ms / 1_000 {
+            self.delay_us(1_000_000);
+        }
+        self.delay_us(ms % 1_000 * 1_000);
+    }
+}
@@ -1,61 +1,139 @@
 #![no_main]
 #![no_std]
 
+use bbqueue::BBBuffer;
+use board::{
+    cortex_m::interrupt::{free, Mutex},
+    nb,
+    rp_pico::pac::{self, interrupt},
+    GpsUartReader,
+};
+use core::cell::RefCell;
 use defmt_rtt as _;
-use panic_probe as _;
+
+/// Moved here by `init` for `UART0_IRQ`, as there's no RTIC to share it
+static GPS_RX: Mutex<RefCell<Option<(GpsUartReader, ada_gps::RxProducer<'static>)>>> =
+    Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn UART0_IRQ() {
+    free(|cs| {
+        if let Some((reader, producer)) = GPS_RX.borrow(cs).borrow_mut().as_mut() {
+            // If the queue's full the gps will resync once it catches up
+            if let Ok(mut grant) = producer.grant_max_remaining(1024) {
+                let count = match reader.read_raw(grant.buf()) {
+                    Ok(count) => count,
+                    Err(nb::Error::WouldBlock | nb::Error::Other(_)) => 0,
+                };
+                grant.commit(count);
+            }
+        }
+    });
+}
 
 #[defmt_test::tests]
 mod tests {
-    use ada_gps::{IntegerPercent, LoggerStatus};
-    use board::Board;
+    use ada_gps::{
+        logger::{RecordingMode, Status as LoggerStatus},
+        Gps, IntegerPercent,
+    };
+    use board::{
+        cortex_m::delay::Delay, Board, GpsDelay, GpsUartWriter, ProfileTrigger, WatchdogConfig,
+    };
+    use core::ops::ControlFlow;
+
+    use super::*;
+
+    pub struct State {
+        gps: Gps<'static, GpsUartWriter, GpsDelay>,
+        delay: Delay,
+        profile_trigger: ProfileTrigger,
+    }
 
     #[init]
-    fn init() -> Board {
-        let device = rp_pico::pac::Peripherals::take().unwrap();
+    fn init() -> State {
+        static GPS_RX_QUEUE: ada_gps::RxBuf = BBBuffer::new();
+
+        let device = pac::Peripherals::take().unwrap();
         let core = cortex_m::Peripherals::take().unwrap();
-        Board::init(core, device)
+        let Board {
+            gps_uart_reader,
+            gps_uart_writer,
+            gps_delay,
+            delay,
+            profile_trigger,
+            ..
+        } = Board::init(core, device, WatchdogConfig::default());
+
+        let (gps_rx_producer, gps_rx_consumer) = GPS_RX_QUEUE.try_split().unwrap();
+        free(|cs| {
+            GPS_RX
+                .borrow(cs)
+                .replace(Some((gps_uart_reader, gps_rx_producer)))
+        });
+        // SAFETY: The handler only touches what's behind `GPS_RX`
+        unsafe { pac::NVIC::unmask(pac::Interrupt::UART0_IRQ) };
+
+        State {
+            gps: Gps::builder(gps_rx_consumer, gps_uart_writer, gps_delay).build(),
+            delay,
+            profile_trigger,
+        }
     }
 
     #[test]
-    fn test_logs(board: &mut Board) {
-        let gps = &mut board.gps;
+    fn test_logs(state: &mut State) {
+        state.profile_trigger.begin(1);
+        let gps = &mut state.gps;
         gps.stop_logging().unwrap();
         gps.erase_logs().unwrap();
 
         gps.configure_logger_interval(60 * 30).unwrap();
+        let status = gps.logger_status().unwrap();
         assert_eq!(
-            gps.logger_status().unwrap(),
+            status,
             LoggerStatus {
                 interval: 60 * 30,
                 is_on: false,
                 record_count: 0,
                 percent_full: IntegerPercent::zero(),
+                recording_mode: RecordingMode::Overlap,
+                // Not set by us
+                ..status.clone()
             }
         );
 
         gps.configure_logger_interval(1).unwrap();
+        let status = gps.logger_status().unwrap();
         assert_eq!(
-            gps.logger_status().unwrap(),
+            status,
             LoggerStatus {
                 interval: 1,
                 is_on: false,
                 record_count: 0,
                 percent_full: IntegerPercent::zero(),
+                recording_mode: RecordingMode::Overlap,
+                // Not set by us
+                ..status.clone()
             }
         );
 
         gps.start_logging().unwrap();
+        let status = gps.logger_status().unwrap();
         assert_eq!(
-            gps.logger_status().unwrap(),
+            status,
             LoggerStatus {
                 interval: 1,
                 is_on: true,
                 record_count: 0,
                 percent_full: IntegerPercent::zero(),
+                recording_mode: RecordingMode::Overlap,
+                // Not set by us
+                ..status.clone()
             }
         );
 
-        board.delay.delay_ms(2_100); // 2.1 secs
+        state.delay.delay_ms(2_100); // 2.1 secs
 
         let status_after_delay = gps.logger_status().unwrap();
         assert_eq!(status_after_delay.interval, 1);
@@ -67,7 +145,14 @@ mod tests {
         );
         assert_eq!(status_after_delay.percent_full, 0);
 
-        gps.read_logs().unwrap();
+        let mut points = 0;
+        gps.read_logs(|_, _, _| {
+            points += 1;
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(points >= 2);
+        state.profile_trigger.end();
         // TODO: Clear
         // TODO: Check on, storage empty
         // TODO: Turn off
@@ -77,8 +162,8 @@ mod tests {
     // NOTE: This test is commented out because it causes us to lose our fix,
     //   which breaks other tests.
     // #[test]
-    // fn test_restarts(board: &mut Board) {
-    //     let gps = &mut board.gps;
+    // fn test_restarts(state: &mut State) {
+    //     let gps = &mut state.gps;
 
     //     for _ in 0..10 {
     //         gps.factory_reset().unwrap();
@@ -99,16 +184,16 @@ mod tests {
 
     // NOTE: This test is commented out as it produces a lot of logs
     // #[test]
-    // fn test_sending_pmtk_commands_race_condition(board: &mut Board) {
+    // fn test_sending_pmtk_commands_race_condition(state: &mut State) {
     //     // This tries to check we properly retry on the random errors we should
     //     //   expect.
     //     for boot in 0..40 {
-    //         if let Err(err) = board.gps.hot_restart() {
+    //         if let Err(err) = state.gps.hot_restart() {
     //             panic!("Failed to factory reset on boot {} with {:?}", boot, err);
     //         }
 
     //         for rep in 0..60 {
-    //             if let Err(err) = board.gps.configure_logger_interval(10) {
+    //             if let Err(err) = state.gps.configure_logger_interval(10) {
     //                 panic!("Failed boot {} rep {} with {:?}", boot, rep, err)
     //             }
     //         }
@@ -0,0 +1,161 @@
+//! Turning a LOCUS dump, such as from `cargo xtask traffic to-locus-bin`,
+//! into a GPX or CSV track, as the device's USB drive does.
+
+use ada_gps::{
+    csv::CsvWriter, gpx::GpxWriter, logger::Parser, smooth::smooth, FilterConfig, FilterStats,
+    Packet, PacketFilter,
+};
+use anyhow::anyhow;
+use std::{
+    fs::{self, File},
+    io::Write,
+};
+
+use crate::root_dir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Gpx,
+    Csv,
+}
+
+/// Arguments after `cargo xtask export`.
+#[derive(Debug, PartialEq)]
+pub struct ExportOpts {
+    in_path: String,
+    /// Ends in `.gpx` or `.csv`, which picks the format
+    out_path: String,
+    format: Format,
+    /// Leave out points `ada_gps::PacketFilter` rejects
+    filter: bool,
+    /// Average each point with this many either side, see
+    /// `ada_gps::smooth`. After filtering, so spikes aren't smeared out.
+    smooth_radius: Option<usize>,
+}
+
+impl ExportOpts {
+    pub fn parse(args: &[&str]) -> Result<Self, anyhow::Error> {
+        let (in_path, out_path, rest) = match args {
+            [in_path, out_path, rest @ ..] => (in_path, out_path, rest),
+            _ => return Err(anyhow!("Expected an input and an output path")),
+        };
+        let format = if out_path.ends_with(".gpx") {
+            Format::Gpx
+        } else if out_path.ends_with(".csv") {
+            Format::Csv
+        } else {
+            return Err(anyhow!("Expected the output to end in .gpx or .csv"));
+        };
+        let mut opts = Self {
+            in_path: in_path.to_string(),
+            out_path: out_path.to_string(),
+            format,
+            filter: false,
+            smooth_radius: None,
+        };
+        let mut rest = rest.iter();
+        while let Some(&arg) = rest.next() {
+            match arg {
+                "--filter" => opts.filter = true,
+                "--smooth" => {
+                    let radius = rest
+                        .next()
+                        .ok_or_else(|| anyhow!("Expected a radius after --smooth"))?;
+                    opts.smooth_radius = Some(radius.parse()?);
+                }
+                _ => return Err(anyhow!("Unsupported option: {}", arg)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+pub fn export(opts: ExportOpts) -> Result<(), anyhow::Error> {
+    let dump = fs::read(root_dir().join(&opts.in_path))?;
+    let mut packets = parse(&dump);
+
+    if opts.filter {
+        println!("Filtered: {:?}", filter(&mut packets));
+    }
+    if let Some(radius) = opts.smooth_radius {
+        println!("Smoothed: {:?}", smooth(&mut packets, radius));
+    }
+
+    let track = write_track(opts.format, &packets)?;
+    let output = root_dir().join(&opts.out_path);
+    let mut output = File::options().create_new(true).write(true).open(output)?;
+    output.write_all(track.as_bytes())?;
+
+    Ok(())
+}
+
+fn parse(dump: &[u8]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut parser = Parser::new(|packet| packets.push(packet));
+    parser.feed(dump);
+    println!("Parsed: {:?}", parser.stats());
+    drop(parser);
+    packets
+}
+
+fn filter(packets: &mut Vec<Packet>) -> FilterStats {
+    let mut filter = PacketFilter::new(FilterConfig::default());
+    packets.retain(|packet| filter.check(packet));
+    filter.stats
+}
+
+fn write_track(format: Format, packets: &[Packet]) -> Result<String, anyhow::Error> {
+    Ok(match format {
+        Format::Gpx => {
+            let mut gpx = GpxWriter::new(String::new())?;
+            for packet in packets {
+                gpx.point(packet)?;
+            }
+            gpx.finish()?
+        }
+        Format::Csv => {
+            let mut csv = CsvWriter::new(String::new())?;
+            for packet in packets {
+                csv.point(packet)?;
+            }
+            csv.into_inner()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_opts() {
+        assert_eq!(
+            ExportOpts::parse(&["log.bin", "track.gpx", "--filter", "--smooth", "2"]).unwrap(),
+            ExportOpts {
+                in_path: "log.bin".to_string(),
+                out_path: "track.gpx".to_string(),
+                format: Format::Gpx,
+                filter: true,
+                smooth_radius: Some(2),
+            }
+        );
+        assert!(ExportOpts::parse(&["log.bin", "track.csv", "--smooth"]).is_err());
+        assert!(ExportOpts::parse(&["log.bin", "track.kml"]).is_err());
+        assert!(ExportOpts::parse(&["log.bin", "track.csv", "--fast"]).is_err());
+    }
+
+    #[test]
+    fn test_filter_drops_rejects() {
+        let dump = fs::read(root_dir().join("ada_gps/test_assets/3819_log_records.bin")).unwrap();
+        let mut packets = parse(&dump);
+        let parsed = packets.len();
+
+        let stats = filter(&mut packets);
+
+        assert_eq!(packets.len(), stats.accepted);
+        assert_eq!(parsed, stats.accepted + stats.rejected());
+        // One row per point, after the header
+        let csv = write_track(Format::Csv, &packets).unwrap();
+        assert_eq!(csv.lines().count(), packets.len() + 1);
+    }
+}
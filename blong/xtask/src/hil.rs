@@ -0,0 +1,399 @@
+//! Running the self-tests on a pico attached by a debug probe, and reporting
+//! the results in a form CI understands.
+//!
+//! The self-tests are built by cargo and flashed and run by probe-rs, which
+//! prints their defmt logs. defmt-test logs a line as it starts each test and
+//! one once they've all passed. A failing test panics, which ends the run, so
+//! any tests after it are reported as skipped.
+
+use anyhow::anyhow;
+use std::{
+    fs,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use xshell::cmd;
+
+use crate::{pushd_cross, root_dir, split_capture_line};
+
+/// Options after `cargo xtask hil`.
+#[derive(Debug, Default)]
+pub struct HilOpts {
+    /// Serial port wired to the pico's gps UART in place of the gps
+    gps_port: Option<String>,
+    /// Traffic capture to replay on `gps_port`, see `cargo xtask traffic`
+    gps_replay: Option<String>,
+}
+
+impl HilOpts {
+    pub fn parse(args: &[&str]) -> Result<Self, anyhow::Error> {
+        let mut opts = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(|s| s.to_string())
+                    .ok_or_else(|| anyhow!("Expected a value after {}", arg))
+            };
+            match *arg {
+                "--gps-port" => opts.gps_port = Some(value()?),
+                "--gps-replay" => opts.gps_replay = Some(value()?),
+                _ => return Err(anyhow!("Unsupported option: {}", arg)),
+            }
+        }
+        if opts.gps_replay.is_some() && opts.gps_port.is_none() {
+            return Err(anyhow!("--gps-replay needs --gps-port"));
+        }
+        Ok(opts)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug)]
+struct TestResult {
+    name: String,
+    outcome: Outcome,
+}
+
+pub fn hil(opts: HilOpts) -> Result<(), anyhow::Error> {
+    let out_dir = root_dir().join("target/hil");
+    fs::create_dir_all(&out_dir)?;
+
+    // Build first so the replay starts close to when the tests do
+    {
+        let _p = pushd_cross()?;
+        cmd!("cargo build --tests -p self-tests").run()?;
+    }
+
+    let replay = match (&opts.gps_port, &opts.gps_replay) {
+        (Some(port), Some(capture)) => Some(spawn_replay(port, &root_dir().join(capture))?),
+        (Some(port), None) => {
+            println!("Leaving {} alone, pass --gps-replay to drive it", port);
+            None
+        }
+        _ => None,
+    };
+
+    let started = Instant::now();
+    let output = {
+        let _p = pushd_cross()?;
+        cmd!("cargo test -p self-tests")
+            .env(
+                "CARGO_TARGET_THUMBV6M_NONE_EABI_RUNNER",
+                "probe-rs run --chip RP2040",
+            )
+            .ignore_status()
+            .output()?
+    };
+    let elapsed = started.elapsed();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    fs::write(out_dir.join("stdout.log"), stdout.as_bytes())?;
+    fs::write(out_dir.join("stderr.log"), stderr.as_bytes())?;
+
+    // Detached rather than joined, as the replay only stops at the end of the
+    // capture
+    drop(replay);
+
+    let results = parse_defmt_test(&stdout, output.status.success());
+    let junit = to_junit(&results, elapsed);
+    fs::write(out_dir.join("junit.xml"), junit)?;
+
+    for result in &results {
+        match &result.outcome {
+            Outcome::Passed => println!("PASS {}", result.name),
+            Outcome::Failed(msg) => println!("FAIL {}: {}", result.name, msg),
+            Outcome::Skipped => println!("SKIP {}", result.name),
+        }
+    }
+    println!("Wrote {}", out_dir.display());
+
+    let passed = results.iter().all(|r| r.outcome == Outcome::Passed);
+    if passed && output.status.success() {
+        Ok(())
+    } else if results.is_empty() {
+        Err(anyhow!(
+            "No tests ran, see {}",
+            out_dir.join("stderr.log").display()
+        ))
+    } else {
+        Err(anyhow!("Self-tests failed"))
+    }
+}
+
+/// Works out each test's outcome from the lines defmt-test logs, which look
+/// like `INFO  (1/3) running `test_logs`...`.
+fn parse_defmt_test(log: &str, success: bool) -> Vec<TestResult> {
+    let mut results = Vec::<TestResult>::new();
+    let mut total = 0;
+    let mut all_passed = false;
+    let mut panic = None;
+
+    for line in log.lines() {
+        if let Some((n, of, name)) = parse_running_line(line) {
+            // Starting a test means the one before it passed
+            if let Some(last) = results.last_mut() {
+                last.outcome = Outcome::Passed;
+            }
+            total = of;
+            results.push(TestResult {
+                name: name.to_string(),
+                outcome: Outcome::Skipped,
+            });
+            if n != results.len() {
+                eprintln!(
+                    "Warning: defmt-test ran test {} as test {}, did the pico reset?",
+                    n,
+                    results.len()
+                );
+            }
+        } else if line.contains("all tests passed!") {
+            all_passed = true;
+        } else if panic.is_none() && line.contains("panicked at") {
+            panic = Some(line.trim().to_string());
+        }
+    }
+
+    if let Some(last) = results.last_mut() {
+        last.outcome = if all_passed && success {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(panic.unwrap_or_else(|| "Run ended without passing".to_string()))
+        };
+    }
+
+    // The names of tests after a failure are never logged
+    for i in results.len()..total {
+        results.push(TestResult {
+            name: format!("test {} of {}", i + 1, total),
+            outcome: Outcome::Skipped,
+        });
+    }
+
+    results
+}
+
+fn parse_running_line(line: &str) -> Option<(usize, usize, &str)> {
+    let rest = &line[line.find('(')? + 1..];
+    let (n, rest) = rest.split_once('/')?;
+    let (of, rest) = rest.split_once(") running `")?;
+    let (name, _) = rest.split_once('`')?;
+    Some((n.parse().ok()?, of.parse().ok()?, name))
+}
+
+fn to_junit(results: &[TestResult], elapsed: Duration) -> String {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let failures = count(|o| matches!(o, Outcome::Failed(_)));
+    let skipped = count(|o| *o == Outcome::Skipped);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"self-tests\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failures,
+        skipped,
+        elapsed.as_secs_f64()
+    ));
+    for result in results {
+        let name = xml_escape(&result.name);
+        match &result.outcome {
+            Outcome::Passed => {
+                xml.push_str(&format!("  <testcase classname=\"ada_gps\" name=\"{}\"/>\n", name))
+            }
+            Outcome::Failed(msg) => xml.push_str(&format!(
+                "  <testcase classname=\"ada_gps\" name=\"{}\">\n    <failure message=\"{}\"/>\n  </testcase>\n",
+                name,
+                xml_escape(msg)
+            )),
+            Outcome::Skipped => xml.push_str(&format!(
+                "  <testcase classname=\"ada_gps\" name=\"{}\">\n    <skipped/>\n  </testcase>\n",
+                name
+            )),
+        }
+    }
+    xml.push_str("  <system-out>See stdout.log</system-out>\n");
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Plays back what the gps sent in a traffic capture onto `port`, keeping the
+/// capture's timing. Commands sent by the pico aren't answered.
+fn spawn_replay(port: &str, capture: &Path) -> Result<thread::JoinHandle<()>, anyhow::Error> {
+    cmd!("stty -F {port} 9600 raw -echo").run()?;
+    let mut out = File::options().write(true).open(port)?;
+
+    let mut lines = Vec::new();
+    for line in BufReader::new(File::open(capture)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (time, line) = split_capture_line(&line)
+            .ok_or_else(|| anyhow!("Expected a timestamp in the capture: {}", line))?;
+        if let Some(line) = line.strip_prefix('<') {
+            lines.push((parse_timestamp(time.trim())?, line.to_string()));
+        }
+    }
+
+    Ok(thread::spawn(move || {
+        let start = Instant::now();
+        for (at, line) in lines {
+            if let Some(wait) = at.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+            if out.write_all(format!("{}\r\n", line).as_bytes()).is_err() {
+                eprintln!("Replay stopped, couldn't write to gps port");
+                return;
+            }
+        }
+    }))
+}
+
+/// Parses the `HH:MM:SS.mmm` at the start of each line of a traffic capture.
+fn parse_timestamp(time: &str) -> Result<Duration, anyhow::Error> {
+    let err = || anyhow!("Invalid timestamp: {}", time);
+    let (hms, ms) = time.split_once('.').ok_or_else(err)?;
+    let mut secs = 0;
+    for part in hms.split(':') {
+        secs = secs * 60 + part.parse::<u64>().map_err(|_| err())?;
+    }
+    let ms: u64 = ms.parse().map_err(|_| err())?;
+    Ok(Duration::from_millis(secs * 1000 + ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSED: &str = "\
+(HOST) INFO  flashing program (12 pages / 48.00 KiB)
+(HOST) INFO  success!
+────────────────────────────────────────────────────────────────────────────────
+0.000012 INFO  (1/2) running `test_logs`...
+└─ ada_gps::tests::__defmt_test_entry @ tests/ada_gps.rs:7
+2.500104 INFO  (2/2) running `test_power`...
+└─ ada_gps::tests::__defmt_test_entry @ tests/ada_gps.rs:7
+3.100020 INFO  all tests passed!
+└─ ada_gps::tests::__defmt_test_entry @ tests/ada_gps.rs:7
+";
+
+    const FAILED: &str = "\
+0.000012 INFO  (1/3) running `test_logs`...
+└─ ada_gps::tests::__defmt_test_entry @ tests/ada_gps.rs:7
+2.500104 INFO  (2/3) running `test_power`...
+└─ ada_gps::tests::__defmt_test_entry @ tests/ada_gps.rs:7
+2.600000 ERROR panicked at 'assertion failed: a < b', tests/ada_gps.rs:60:9
+└─ board::reboot_cause::panic @ board/src/reboot_cause.rs:104
+";
+
+    fn outcomes(results: &[TestResult]) -> Vec<(&str, &Outcome)> {
+        results
+            .iter()
+            .map(|r| (r.name.as_str(), &r.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_all_passed() {
+        let results = parse_defmt_test(PASSED, true);
+        assert_eq!(
+            outcomes(&results),
+            [
+                ("test_logs", &Outcome::Passed),
+                ("test_power", &Outcome::Passed)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_failure_skips_the_rest() {
+        let results = parse_defmt_test(FAILED, false);
+        assert_eq!(
+            outcomes(&results),
+            [
+                ("test_logs", &Outcome::Passed),
+                (
+                    "test_power",
+                    &Outcome::Failed(
+                        "2.600000 ERROR panicked at 'assertion failed: a < b', tests/ada_gps.rs:60:9"
+                            .to_string()
+                    )
+                ),
+                ("test 3 of 3", &Outcome::Skipped),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_run_failing_after_last_test() {
+        // All passed, but probe-rs saw something go wrong afterwards
+        let results = parse_defmt_test(PASSED, false);
+        assert_eq!(
+            results[1].outcome,
+            Outcome::Failed("Run ended without passing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_rerun_after_reset_doesnt_panic() {
+        let log = format!("{}{}", FAILED, PASSED);
+        let results = parse_defmt_test(&log, true);
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|r| r.outcome == Outcome::Passed));
+    }
+
+    #[test]
+    fn test_parse_nothing_ran() {
+        assert!(parse_defmt_test("Error: no probe was found\n", false).is_empty());
+    }
+
+    #[test]
+    fn test_junit() {
+        let results = parse_defmt_test(FAILED, false);
+        let xml = to_junit(&results, Duration::from_millis(2_600));
+        assert_eq!(
+            xml,
+            "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuite name=\"self-tests\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"2.600\">
+  <testcase classname=\"ada_gps\" name=\"test_logs\"/>
+  <testcase classname=\"ada_gps\" name=\"test_power\">
+    <failure message=\"2.600000 ERROR panicked at 'assertion failed: a &lt; b', tests/ada_gps.rs:60:9\"/>
+  </testcase>
+  <testcase classname=\"ada_gps\" name=\"test 3 of 3\">
+    <skipped/>
+  </testcase>
+  <system-out>See stdout.log</system-out>
+</testsuite>
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("01:02:03.004").unwrap(),
+            Duration::from_millis(3_723_004)
+        );
+        assert!(parse_timestamp("01:02:03").is_err());
+        assert!(parse_timestamp("aa:02:03.004").is_err());
+    }
+}
@@ -1,6 +1,11 @@
+mod export;
+mod hil;
+mod rtt;
+mod uf2;
+
 use anyhow::anyhow;
 use std::{
-    env,
+    env, fs,
     fs::File,
     io::{BufRead, BufReader, BufWriter, Write},
     path::PathBuf,
@@ -13,16 +18,29 @@ fn main() -> Result<(), anyhow::Error> {
 
     match &args[..] {
         ["flash"] => flash(),
+        ["dist"] => dist(),
         ["run"] => run_app(),
         ["check", "all"] => check_all(),
         ["test", "ada-gps"] => test_ada_gps(),
         ["test", "target"] => test_target(),
+        ["export", opts @ ..] => export::export(export::ExportOpts::parse(opts)?),
+        ["hil", opts @ ..] => hil::hil(hil::HilOpts::parse(opts)?),
+        ["rtt", args @ ..] => rtt::rtt(args),
         ["traffic", "to-raw-rx", in_path, out_path] => traffic_to_raw_rx(in_path, out_path),
         ["traffic", "to-locus-bin", in_path, out_path] => traffic_to_locus_bin(in_path, out_path),
+        ["traffic", "to-mock", in_path, out_path] => traffic_to_mock(in_path, out_path),
         _ => Err(anyhow!("Unsupported")),
     }
 }
 
+/// Splits a traffic capture line into its `HH:MM:SS.mmm` timestamp and the
+/// traffic after it, or `None` if it's too short to have both, as a blank
+/// line at the end is.
+fn split_capture_line(line: &str) -> Option<(&str, &str)> {
+    const TIMESTAMP_LEN: usize = "00:00:00.000 ".len();
+    Some((line.get(..TIMESTAMP_LEN)?, line.get(TIMESTAMP_LEN..)?))
+}
+
 fn traffic_to_raw_rx(in_path: &str, out_path: &str) -> Result<(), anyhow::Error> {
     let input = root_dir().join(in_path);
     let input = File::open(input)?;
@@ -34,11 +52,10 @@ fn traffic_to_raw_rx(in_path: &str, out_path: &str) -> Result<(), anyhow::Error>
 
     for line in input.lines() {
         let line = line?;
-        let line = &line["00:00:00.000 ".len()..];
-        if !line.starts_with("<") {
-            continue;
-        }
-        let line = &line[1..];
+        let line = match split_capture_line(&line).and_then(|(_, line)| line.strip_prefix('<')) {
+            Some(line) => line,
+            None => continue,
+        };
 
         output.write_all(line.as_bytes())?;
         output.write_all(b"\r\n")?;
@@ -60,13 +77,12 @@ fn traffic_to_locus_bin(in_path: &str, out_path: &str) -> Result<(), anyhow::Err
 
     for line in input.lines() {
         let line = line?;
-        let line = &line["00:00:00.000 ".len()..];
-        if !line.starts_with("<") {
-            continue;
-        }
-        let line = &line[1..];
+        let line = match split_capture_line(&line).and_then(|(_, line)| line.strip_prefix('<')) {
+            Some(line) => line,
+            None => continue,
+        };
 
-        check_nmea_checksum(line)?;
+        check_nmea_sentence(line)?;
 
         // Start
         if line.starts_with("$PMTKLOX,0") {
@@ -113,21 +129,70 @@ fn traffic_to_locus_bin(in_path: &str, out_path: &str) -> Result<(), anyhow::Err
     Ok(())
 }
 
-fn check_nmea_checksum(raw: &str) -> Result<(), anyhow::Error> {
-    let line = &raw[1..raw.len() - "*FF".len()];
-    let expected = &raw[raw.len() - "FF".len()..];
+/// Writes an array of `MockTrans` for ada_gps's host tests, to be used with
+/// `include!`.
+fn traffic_to_mock(in_path: &str, out_path: &str) -> Result<(), anyhow::Error> {
+    let input = root_dir().join(in_path);
+    let input = File::open(input)?;
+    let input = BufReader::new(input);
+
+    let output = root_dir().join(out_path);
+    let output = File::options().create_new(true).write(true).open(output)?;
+    let mut output = BufWriter::new(output);
 
-    let mut actual = 0;
-    for byte in line.as_bytes() {
-        actual ^= byte;
-    }
-    let actual = format!("{:02X}", actual);
+    writeln!(
+        output,
+        "// Generated by `cargo xtask traffic to-mock {} {}`",
+        in_path, out_path
+    )?;
+    writeln!(output, "[")?;
 
-    if expected == actual {
-        Ok(())
-    } else {
-        Err(anyhow!("{} failed checksum, actual: {}", raw, actual))
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (_, line) =
+            split_capture_line(&line).ok_or_else(|| anyhow!("Expected a timestamp: {}", line))?;
+
+        let (constructor, line) = if let Some(line) = line.strip_prefix('<') {
+            ("read_many", line)
+        } else if let Some(line) = line.strip_prefix('>') {
+            ("write_many", line)
+        } else {
+            return Err(anyhow!("Expected < or >, got: {}", line));
+        };
+
+        check_nmea_sentence(line)?;
+
+        writeln!(
+            output,
+            "    MockTrans::{}(b\"{}\\r\\n\"),",
+            constructor,
+            escape_bytes(line)
+        )?;
     }
+
+    writeln!(output, "]")?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Escapes `line` for a `b"..."` literal. Done a byte at a time, as
+/// `str::escape_default` gives `\u{..}` escapes those literals don't accept.
+fn escape_bytes(line: &str) -> String {
+    line.bytes()
+        .flat_map(core::ascii::escape_default)
+        .map(char::from)
+        .collect()
+}
+
+/// Checks the framing and checksum of a sentence from a traffic log, which
+/// has had its line ending stripped.
+fn check_nmea_sentence(raw: &str) -> Result<(), anyhow::Error> {
+    let line = format!("{}\r\n", raw);
+    ada_gps::nmea::validate(line.as_bytes()).map_err(|err| anyhow!("{}: {:?}", raw, err))
 }
 
 fn run_app() -> Result<(), anyhow::Error> {
@@ -154,6 +219,58 @@ fn flash() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Builds a release of the app and writes it to `target/dist/app-<version>`,
+/// including a UF2 that can be dragged onto a pico booted into BOOTSEL mode.
+fn dist() -> Result<(), anyhow::Error> {
+    let _p = pushd_app()?;
+
+    let git_hash = cmd!("git rev-parse --short HEAD").read()?;
+    let dirty = !cmd!("git status --porcelain").read()?.is_empty();
+    let git_hash = if dirty {
+        format!("{}-dirty", git_hash)
+    } else {
+        git_hash
+    };
+
+    let version = cmd!("cargo pkgid").read()?;
+    let version = version
+        .rsplit(['#', '@'])
+        .next()
+        .ok_or_else(|| anyhow!("Unexpected pkgid: {}", version))?
+        .to_string();
+
+    // Read by the app to log what it's running
+    cmd!("cargo build --release")
+        .env("BLONG_GIT_HASH", &git_hash)
+        .run()?;
+
+    let elf_path = root_dir()
+        .join("cross/target/thumbv6m-none-eabi/release")
+        .join("app");
+    let elf = fs::read(&elf_path)?;
+    let uf2 = uf2::elf_to_uf2(&elf)?;
+
+    let out_dir = root_dir()
+        .join("target/dist")
+        .join(format!("app-{}-{}", version, git_hash));
+    fs::create_dir_all(&out_dir)?;
+    fs::write(out_dir.join("app.elf"), &elf)?;
+    fs::write(out_dir.join("app.uf2"), &uf2)?;
+    fs::write(
+        out_dir.join("metadata.txt"),
+        format!(
+            "version: {}\ngit: {}\nelf bytes: {}\nuf2 blocks: {}\n",
+            version,
+            git_hash,
+            elf.len(),
+            uf2.len() / 512
+        ),
+    )?;
+
+    println!("Wrote {}", out_dir.display());
+    Ok(())
+}
+
 fn check_all() -> Result<(), anyhow::Error> {
     check_root()?;
     check_cross()?;
@@ -193,3 +310,26 @@ fn root_dir() -> PathBuf {
     xtask_dir.pop();
     xtask_dir
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_capture_line() {
+        assert_eq!(
+            split_capture_line("00:00:01.250 <$PMTK001,220,3*30"),
+            Some(("00:00:01.250 ", "<$PMTK001,220,3*30"))
+        );
+        assert_eq!(split_capture_line(""), None);
+        assert_eq!(split_capture_line("00:00:01"), None);
+        // Not a char boundary
+        assert_eq!(split_capture_line("00:00:01.250é<"), None);
+    }
+
+    #[test]
+    fn test_escape_bytes() {
+        assert_eq!(escape_bytes("$PMTK001,220,3*30"), "$PMTK001,220,3*30");
+        assert_eq!(escape_bytes("\"é\t"), "\\\"\\xc3\\xa9\\t");
+    }
+}
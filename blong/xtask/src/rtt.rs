@@ -0,0 +1,275 @@
+//! The host end of the board's RTT transfer channel, see
+//! `cross/board/src/rtt_transfer.rs` for the protocol.
+//!
+//! We talk to the channel through an RTT server that exposes it over TCP,
+//! such as OpenOCD's, started with something like:
+//!
+//! ```text
+//! openocd -f interface/cmsis-dap.cfg -f target/rp2040.cfg \
+//!     -c "init; rtt setup 0x20000000 0x42000 \"SEGGER RTT\"; rtt start; rtt server start 9091 1"
+//! ```
+//!
+//! The device only notices requests when it next wakes, so we keep asking for
+//! a while before giving up.
+
+use anyhow::anyhow;
+use std::{
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::root_dir;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:9091";
+
+const MAGIC: u8 = 0xB1;
+const HEADER_LEN: usize = 1 + 1 + 2 + 2;
+const CRC_LEN: usize = 4;
+const MAX_PAYLOAD: usize = 256;
+
+const PULL: u8 = b'P';
+const PUSH: u8 = b'C';
+const PUSH_END: u8 = b'F';
+const DATA: u8 = b'D';
+const END: u8 = b'E';
+const ERROR: u8 = b'X';
+const ACK: u8 = b'A';
+const NACK: u8 = b'N';
+
+/// How long to wait for the device to start answering a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const RESEND_AFTER: Duration = Duration::from_secs(1);
+/// Gaps longer than this once the device is answering mean it's gone
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn rtt(args: &[&str]) -> Result<(), anyhow::Error> {
+    let (args, addr) = match args {
+        [rest @ .., "--addr", addr] => (rest, *addr),
+        _ => (args, DEFAULT_ADDR),
+    };
+    match args {
+        ["pull", name, out_path] => pull(addr, name, out_path),
+        ["push", in_path] => push(addr, in_path),
+        _ => Err(anyhow!("Unsupported")),
+    }
+}
+
+fn pull(addr: &str, name: &str, out_path: &str) -> Result<(), anyhow::Error> {
+    let mut conn = Conn::connect(addr)?;
+    let mut data = Vec::new();
+    let started = Instant::now();
+    let mut answered = false;
+    let mut last_heard = Instant::now();
+    let mut last_asked = None::<Instant>;
+
+    loop {
+        if !answered {
+            if started.elapsed() > REQUEST_TIMEOUT {
+                return Err(anyhow!("Device didn't answer, is the app running?"));
+            }
+            if last_asked.is_none_or(|at| at.elapsed() > RESEND_AFTER) {
+                conn.write_frame(PULL, 0, name.as_bytes())?;
+                last_asked = Some(Instant::now());
+            }
+        } else if last_heard.elapsed() > STALL_TIMEOUT {
+            return Err(anyhow!("Device stopped sending"));
+        }
+
+        let (kind, seq, payload) = match conn.read_frame()? {
+            Some(frame) => frame,
+            None => continue,
+        };
+        answered = true;
+        last_heard = Instant::now();
+
+        let next_seq = (data.len() / MAX_PAYLOAD) as u16;
+        match kind {
+            DATA if seq == next_seq => {
+                data.extend_from_slice(&payload);
+                conn.write_frame(ACK, seq, &[])?;
+            }
+            // We've already got it, but our ack must have been lost
+            DATA if seq < next_seq => conn.write_frame(ACK, seq, &[])?,
+            DATA => conn.write_frame(NACK, next_seq, &[])?,
+            END => {
+                if payload.len() != 8 {
+                    return Err(anyhow!("Malformed end frame"));
+                }
+                let len = u32::from_le_bytes(payload[..4].try_into().unwrap());
+                let crc = u32::from_le_bytes(payload[4..].try_into().unwrap());
+                if len as usize != data.len() || crc != crc32(&data) {
+                    return Err(anyhow!(
+                        "Got {} bytes, device sent {}, or they were corrupted",
+                        data.len(),
+                        len
+                    ));
+                }
+                conn.write_frame(ACK, seq, &[])?;
+                break;
+            }
+            ERROR => {
+                return Err(anyhow!(
+                    "Device says: {}",
+                    String::from_utf8_lossy(&payload)
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let out_path = root_dir().join(out_path);
+    fs::write(&out_path, &data)?;
+    println!("Wrote {} bytes to {}", data.len(), out_path.display());
+    Ok(())
+}
+
+fn push(addr: &str, in_path: &str) -> Result<(), anyhow::Error> {
+    let data = fs::read(root_dir().join(in_path))?;
+    let mut conn = Conn::connect(addr)?;
+
+    let chunks = data.len().div_ceil(MAX_PAYLOAD);
+    if chunks >= u16::MAX as usize {
+        return Err(anyhow!("File too large"));
+    }
+    let mut frames: Vec<_> = data
+        .chunks(MAX_PAYLOAD)
+        .enumerate()
+        .map(|(seq, chunk)| (PUSH, seq as u16, chunk.to_vec()))
+        .collect();
+    frames.push((
+        PUSH_END,
+        chunks as u16,
+        (data.len() as u32).to_le_bytes().to_vec(),
+    ));
+
+    for (i, (kind, seq, payload)) in frames.into_iter().enumerate() {
+        // Give the device longer to notice the first frame
+        let timeout = if i == 0 {
+            REQUEST_TIMEOUT
+        } else {
+            STALL_TIMEOUT
+        };
+        conn.send_until_acked(kind, seq, &payload, timeout)?;
+    }
+
+    println!("Pushed {} bytes", data.len());
+    Ok(())
+}
+
+struct Conn {
+    stream: TcpStream,
+    rx: Vec<u8>,
+}
+
+impl Conn {
+    fn connect(addr: &str) -> Result<Self, anyhow::Error> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|err| anyhow!("Couldn't connect to RTT server at {}: {}", addr, err))?;
+        stream.set_read_timeout(Some(Duration::from_millis(50)))?;
+        Ok(Self {
+            stream,
+            rx: Vec::new(),
+        })
+    }
+
+    fn send_until_acked(
+        &mut self,
+        kind: u8,
+        seq: u16,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let started = Instant::now();
+        let mut last_sent = None::<Instant>;
+        loop {
+            if started.elapsed() > timeout {
+                return Err(anyhow!("Device didn't ack frame {}", seq));
+            }
+            if last_sent.is_none_or(|at| at.elapsed() > RESEND_AFTER) {
+                self.write_frame(kind, seq, payload)?;
+                last_sent = Some(Instant::now());
+            }
+            match self.read_frame()? {
+                Some((ACK, acked, _)) if acked == seq => return Ok(()),
+                Some((NACK, nacked, _)) if nacked == seq => last_sent = None,
+                _ => {}
+            }
+        }
+    }
+
+    fn write_frame(&mut self, kind: u8, seq: u16, payload: &[u8]) -> Result<(), anyhow::Error> {
+        let mut frame = vec![MAGIC, kind];
+        frame.extend_from_slice(&seq.to_le_bytes());
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame.extend_from_slice(payload);
+        let crc = crc32(&frame[1..]);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Returns `None` if no whole frame arrived before the read timeout.
+    fn read_frame(&mut self) -> Result<Option<(u8, u16, Vec<u8>)>, anyhow::Error> {
+        let mut buf = [0; 1024];
+        match self.stream.read(&mut buf) {
+            Ok(0) => return Err(anyhow!("RTT server closed the connection")),
+            Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        loop {
+            let start = match self.rx.iter().position(|&b| b == MAGIC) {
+                Some(start) => start,
+                None => {
+                    self.rx.clear();
+                    return Ok(None);
+                }
+            };
+            self.rx.drain(..start);
+            if self.rx.len() < HEADER_LEN {
+                return Ok(None);
+            }
+
+            let len = u16::from_le_bytes([self.rx[4], self.rx[5]]) as usize;
+            if len > MAX_PAYLOAD {
+                self.rx.remove(0);
+                continue;
+            }
+            let frame_len = HEADER_LEN + len + CRC_LEN;
+            if self.rx.len() < frame_len {
+                return Ok(None);
+            }
+
+            let crc = &self.rx[HEADER_LEN + len..frame_len];
+            if crc32(&self.rx[1..HEADER_LEN + len]).to_le_bytes() != crc {
+                self.rx.remove(0);
+                continue;
+            }
+
+            let kind = self.rx[1];
+            let seq = u16::from_le_bytes([self.rx[2], self.rx[3]]);
+            let payload = self.rx[HEADER_LEN..HEADER_LEN + len].to_vec();
+            self.rx.drain(..frame_len);
+            return Ok(Some((kind, seq, payload)));
+        }
+    }
+}
+
+/// Must match the board's
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,232 @@
+//! ELF to UF2 conversion for the RP2040, following elf2uf2 from the pico sdk.
+//!
+//! See <https://github.com/microsoft/uf2> for the format.
+
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+
+const MAGIC_START_0: u32 = 0x0A32_4655;
+const MAGIC_START_1: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+const FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+const RP2040_FAMILY_ID: u32 = 0xE48B_FF56;
+
+const BLOCK_SIZE: usize = 512;
+const BLOCK_DATA_SIZE: usize = 476;
+const PAGE_SIZE: u32 = 256;
+
+const FLASH_START: u32 = 0x1000_0000;
+const FLASH_END: u32 = 0x1100_0000;
+
+const PT_LOAD: u32 = 1;
+
+/// Converts a little-endian 32-bit ARM ELF into a UF2 image of the sections
+/// destined for flash.
+pub fn elf_to_uf2(elf: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let pages = flash_pages(elf)?;
+    if pages.is_empty() {
+        return Err(anyhow!("ELF has nothing to put in flash"));
+    }
+
+    let num_blocks = pages.len() as u32;
+    let mut out = Vec::with_capacity(pages.len() * BLOCK_SIZE);
+    for (block_no, (addr, data)) in pages.iter().enumerate() {
+        let start = out.len();
+        for word in [
+            MAGIC_START_0,
+            MAGIC_START_1,
+            FLAG_FAMILY_ID_PRESENT,
+            *addr,
+            PAGE_SIZE,
+            block_no as u32,
+            num_blocks,
+            RP2040_FAMILY_ID,
+        ] {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out.extend_from_slice(data);
+        out.resize(start + 32 + BLOCK_DATA_SIZE, 0);
+        out.extend_from_slice(&MAGIC_END.to_le_bytes());
+    }
+
+    Ok(out)
+}
+
+/// Page-aligned flash contents, keyed by address. Gaps within a page are
+/// zero-filled.
+fn flash_pages(elf: &[u8]) -> Result<BTreeMap<u32, Vec<u8>>, anyhow::Error> {
+    if elf.get(..4) != Some(b"\x7fELF") {
+        return Err(anyhow!("Not an ELF file"));
+    }
+    // EI_CLASS and EI_DATA
+    if elf.get(4..6) != Some(&[1, 1]) {
+        return Err(anyhow!("Expected a 32-bit little-endian ELF"));
+    }
+
+    let ph_offset = read_u32(elf, 0x1C)? as usize;
+    let ph_entry_size = read_u16(elf, 0x2A)? as usize;
+    let ph_count = read_u16(elf, 0x2C)? as usize;
+
+    let mut pages = BTreeMap::new();
+    for i in 0..ph_count {
+        let ph = ph_offset + i * ph_entry_size;
+        let kind = read_u32(elf, ph)?;
+        let offset = read_u32(elf, ph + 0x04)? as usize;
+        let paddr = read_u32(elf, ph + 0x0C)?;
+        let file_size = read_u32(elf, ph + 0x10)? as usize;
+
+        if kind != PT_LOAD || file_size == 0 {
+            continue;
+        }
+        if !(FLASH_START..FLASH_END).contains(&paddr) {
+            return Err(anyhow!("Segment at {:#010x} is outside flash", paddr));
+        }
+        if paddr as u64 + file_size as u64 > FLASH_END as u64 {
+            return Err(anyhow!(
+                "Segment at {:#010x} runs past the end of flash",
+                paddr
+            ));
+        }
+
+        let data = elf
+            .get(offset..offset + file_size)
+            .ok_or_else(|| anyhow!("Segment at {:#010x} is truncated", paddr))?;
+        for (i, &byte) in data.iter().enumerate() {
+            let addr = paddr + i as u32;
+            let page = pages
+                .entry(addr - addr % PAGE_SIZE)
+                .or_insert_with(|| vec![0; PAGE_SIZE as usize]);
+            page[(addr % PAGE_SIZE) as usize] = byte;
+        }
+    }
+
+    Ok(pages)
+}
+
+fn read_u32(buf: &[u8], start: usize) -> Result<u32, anyhow::Error> {
+    let bytes = buf
+        .get(start..start + 4)
+        .ok_or_else(|| anyhow!("ELF truncated"))?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u16(buf: &[u8], start: usize) -> Result<u16, anyhow::Error> {
+    let bytes = buf
+        .get(start..start + 2)
+        .ok_or_else(|| anyhow!("ELF truncated"))?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal ELF with a `PT_LOAD` segment of each `(paddr, data)`
+    fn elf(segments: &[(u32, &[u8])]) -> Vec<u8> {
+        const HEADER_SIZE: usize = 0x34;
+        const PH_SIZE: usize = 0x20;
+        let mut elf = vec![0; HEADER_SIZE];
+        elf[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        elf[0x1C..0x20].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&(PH_SIZE as u16).to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        let mut offset = HEADER_SIZE + PH_SIZE * segments.len();
+        for &(paddr, data) in segments {
+            let mut ph = vec![0; PH_SIZE];
+            ph[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+            ph[0x04..0x08].copy_from_slice(&(offset as u32).to_le_bytes());
+            ph[0x0C..0x10].copy_from_slice(&paddr.to_le_bytes());
+            ph[0x10..0x14].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            elf.extend(ph);
+            offset += data.len();
+        }
+        for &(_, data) in segments {
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    fn word(block: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(block[index * 4..index * 4 + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_blocks_are_numbered_and_carry_a_page_each() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let uf2 = elf_to_uf2(&elf(&[(FLASH_START, &data)])).unwrap();
+
+        assert_eq!(uf2.len(), 3 * BLOCK_SIZE);
+        for (i, block) in uf2.chunks(BLOCK_SIZE).enumerate() {
+            assert_eq!(word(block, 0), MAGIC_START_0);
+            assert_eq!(word(block, 1), MAGIC_START_1);
+            assert_eq!(word(block, 2), FLAG_FAMILY_ID_PRESENT);
+            assert_eq!(word(block, 3), FLASH_START + i as u32 * PAGE_SIZE);
+            assert_eq!(word(block, 4), PAGE_SIZE);
+            assert_eq!(word(block, 5), i as u32);
+            assert_eq!(word(block, 6), 3);
+            assert_eq!(word(block, 7), RP2040_FAMILY_ID);
+            assert_eq!(word(block, BLOCK_SIZE / 4 - 1), MAGIC_END);
+        }
+
+        let payload = |block: usize| &uf2[block * BLOCK_SIZE + 32..][..PAGE_SIZE as usize];
+        assert_eq!(payload(0), &data[..256]);
+        assert_eq!(payload(1), &data[256..512]);
+        // The last page is padded with zeroes
+        assert_eq!(&payload(2)[..88], &data[512..]);
+        assert!(payload(2)[88..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_gaps_between_pages_are_skipped() {
+        let uf2 = elf_to_uf2(&elf(&[
+            (FLASH_START + 4, &[1, 2]),
+            (FLASH_START + 0x1000, &[3]),
+        ]))
+        .unwrap();
+
+        assert_eq!(uf2.len(), 2 * BLOCK_SIZE);
+        let first = &uf2[..BLOCK_SIZE];
+        let second = &uf2[BLOCK_SIZE..];
+        assert_eq!(word(first, 3), FLASH_START);
+        assert_eq!(&first[32..38], &[0, 0, 0, 0, 1, 2]);
+        assert_eq!(word(second, 3), FLASH_START + 0x1000);
+        assert_eq!(second[32], 3);
+    }
+
+    #[test]
+    fn test_segments_sharing_a_page_are_merged() {
+        let pages = flash_pages(&elf(&[(FLASH_START, &[1]), (FLASH_START + 2, &[2])])).unwrap();
+
+        assert_eq!(pages.len(), 1);
+        assert_eq!(&pages[&FLASH_START][..3], &[1, 0, 2]);
+    }
+
+    #[test]
+    fn test_rejects_segments_outside_flash() {
+        assert!(elf_to_uf2(&elf(&[(0x2000_0000, &[1])])).is_err());
+    }
+
+    #[test]
+    fn test_rejects_segments_running_past_flash() {
+        assert!(elf_to_uf2(&elf(&[(FLASH_END - 2, &[1, 2, 3])])).is_err());
+        // Right up to the end is fine
+        assert!(elf_to_uf2(&elf(&[(FLASH_END - 2, &[1, 2])])).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_short_and_non_elf_files() {
+        for file in [
+            &b""[..],
+            b"\x7fEL",
+            b"\x7fELF",
+            b"\x7fELF\x01",
+            b"MZ\x90\x00\x03\x00",
+        ] {
+            assert!(elf_to_uf2(file).is_err());
+        }
+        // 64-bit
+        assert!(elf_to_uf2(b"\x7fELF\x02\x01").is_err());
+        assert!(elf_to_uf2(&elf(&[])).is_err());
+    }
+}